@@ -0,0 +1,559 @@
+// src/analysis.rs
+// Analysis of user formulas before running them: the work they repeat, what they cost, and where
+// they are defined.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::functions::{builtin, list_builtin, random_builtin, ELEMENTARY};
+use crate::{Expr, Operator, Polynomial, Statement, UnaryOperator};
+
+// A subtree that appears more than once, and the variable suggested to hold it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonSubexpression {
+    pub expr: Expr,         // The subtree where it first appears
+    pub count: usize,       // How often it appears, including inside other repeated subtrees
+    pub binding: Statement, // `name = expr`, using the bindings before it
+}
+
+// The repeated subtrees worth computing once, innermost first. Subtrees match when they have the
+// same normal form, so `a + b` and `b + a` are the same. Numbers, variables and negated numbers
+// or variables are not reported, nor anything calling a random function. A subtree that would be
+// used only once after binding the others (such as `x + 1` in `f(x + 1) + f(x + 1)`) is left in
+// the binding that uses it.
+pub fn common_subexpressions(expr: &Expr) -> Vec<CommonSubexpression> {
+    let mut candidates = Vec::new();
+    collect(expr, &mut candidates);
+    let mut kept: Vec<Candidate> = candidates.into_iter().filter(|candidate| candidate.count > 1).collect();
+
+    // Inline the least used binding until every one is used at least twice
+    loop {
+        let mut uses = vec![0; kept.len()];
+        count_uses(expr, &kept, None, &mut uses);
+        for (index, candidate) in kept.iter().enumerate() {
+            for child in candidate.expr.children() {
+                count_uses(child, &kept, Some(index), &mut uses);
+            }
+        }
+        let least = (0..kept.len()).rev().min_by_key(|index| uses[*index]);
+        match least {
+            Some(index) if uses[index] < 2 => {
+                kept.remove(index);
+            }
+            _ => break,
+        }
+    }
+
+    let mut taken = HashSet::new();
+    variables(expr, &mut taken);
+    let names: Vec<String> = (1..)
+        .map(|n| format!("t{}", n))
+        .filter(|name| !taken.contains(name))
+        .take(kept.len())
+        .collect();
+    kept.iter()
+        .enumerate()
+        .map(|(index, candidate)| CommonSubexpression {
+            expr: candidate.expr.clone(),
+            count: candidate.count,
+            binding: Statement::Assign {
+                name: names[index].clone(),
+                value: candidate.expr.map_children(|child| rewrite(child, &kept, &names)),
+            },
+        })
+        .collect()
+}
+
+// The expression as statements computing each common subexpression once: the bindings of
+// `common_subexpressions`, then the expression using them. Executed in order, the last
+// statement's value is the expression's.
+pub fn bind_common_subexpressions(expr: &Expr) -> Vec<Statement> {
+    let common = common_subexpressions(expr);
+    let kept: Vec<Candidate> = common
+        .iter()
+        .map(|common| Candidate::new(common.expr.clone()))
+        .collect();
+    let names: Vec<String> = common
+        .iter()
+        .map(|common| match &common.binding {
+            Statement::Assign { name, .. } => name.clone(),
+            Statement::Expr(_) => unreachable!("bindings are assignments"),
+        })
+        .collect();
+    let result = rewrite(expr, &kept, &names);
+    common
+        .into_iter()
+        .map(|common| common.binding)
+        .chain([Statement::Expr(result)])
+        .collect()
+}
+
+struct Candidate {
+    key: Expr, // The normal form
+    expr: Expr,
+    count: usize,
+}
+
+impl Candidate {
+    fn new(expr: Expr) -> Self {
+        Self {
+            key: expr.normal_form(),
+            expr,
+            count: 1,
+        }
+    }
+}
+
+// Whether a subtree is too small to be worth a variable, or must not be computed only once
+fn skipped(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::Scientific { .. } | Expr::Duration(_) | Expr::Money(_) | Expr::Variable(_) | Expr::Text(_) | Expr::Group(_) | Expr::Error => true,
+        Expr::UnaryMinus(operand) => skipped(operand),
+        _ => false,
+    }
+}
+
+fn is_random(expr: &Expr) -> bool {
+    matches!(expr, Expr::Function { name, .. } if random_builtin(name).is_some())
+        || expr.children().into_iter().any(is_random)
+}
+
+// Every subtree worth a variable, counted, in the order they are first completed: inner ones
+// before those around them. Returns whether the subtree calls a random function.
+fn collect(expr: &Expr, candidates: &mut Vec<Candidate>) -> bool {
+    let mut random = matches!(expr, Expr::Function { name, .. } if random_builtin(name).is_some());
+    for child in expr.children() {
+        random |= collect(child, candidates);
+    }
+    if random || skipped(expr) {
+        return random;
+    }
+    let key = expr.normal_form();
+    match candidates.iter_mut().find(|candidate| candidate.key == key) {
+        Some(candidate) => candidate.count += 1,
+        None => candidates.push(Candidate::new(expr.clone())),
+    }
+    false
+}
+
+fn binding(expr: &Expr, kept: &[Candidate]) -> Option<usize> {
+    if skipped(expr) || is_random(expr) {
+        return None;
+    }
+    let key = expr.normal_form();
+    kept.iter().position(|candidate| candidate.key == key)
+}
+
+// Counts the outermost uses of each binding in the expression, not counting the binding `except`
+fn count_uses(expr: &Expr, kept: &[Candidate], except: Option<usize>, uses: &mut [usize]) {
+    match binding(expr, kept) {
+        Some(index) if Some(index) != except => uses[index] += 1,
+        _ => {
+            for child in expr.children() {
+                count_uses(child, kept, except, uses);
+            }
+        }
+    }
+}
+
+// The expression with the outermost subtrees that have bindings replaced by their variables
+fn rewrite(expr: &Expr, kept: &[Candidate], names: &[String]) -> Expr {
+    match binding(expr, kept) {
+        Some(index) => Expr::Variable(names[index].clone()),
+        None => expr.map_children(|child| rewrite(child, kept, names)),
+    }
+}
+
+fn variables(expr: &Expr, names: &mut HashSet<String>) {
+    if let Expr::Variable(name) = expr {
+        names.insert(name.clone());
+    }
+    for child in expr.children() {
+        variables(child, names);
+    }
+}
+
+// Functions whose cost is closer to a power than to an addition, besides the elementary built-ins
+// (sqrt, exp, ln, sin and the rest): gamma, and ones hosts commonly register
+const TRANSCENDENTAL: &[&str] = &["gamma", "pow", "erf"];
+const NUMBER_THEORY: &[&str] = &["isprime", "nextprime", "totient", "factorize", "gcd", "lcm"];
+const MATRIX: &[&str] = &["det", "inv", "solve"];
+
+// What evaluating each kind of node costs, in units of an addition. Numbers and variables are free,
+// as are lists, indexing and parentheses apart from what they contain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostModel {
+    pub arithmetic: f64,       // +, -, *, comparisons, bitwise and logical operators, negation
+    pub division: f64,         // /, modulo and percent
+    pub power: f64,            // ^ and √
+    pub transcendental: f64,   // gamma and the elementary functions such as exp, ln and sin
+    pub number_theory: f64,    // isprime, nextprime, totient, factorize, gcd and lcm
+    pub matrix: f64,           // det, inv and solve
+    pub call: f64,             // Any other built-in function
+    pub unknown_function: f64, // Registered functions and custom operators it has no cost for
+    pub functions: HashMap<String, f64>, // Costs of particular functions, replacing the above
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            arithmetic: 1.0,
+            division: 4.0,
+            power: 20.0,
+            transcendental: 25.0,
+            number_theory: 100.0,
+            matrix: 100.0,
+            call: 2.0,
+            unknown_function: 25.0,
+            functions: HashMap::new(),
+        }
+    }
+}
+
+impl CostModel {
+    // The model with the cost of one function set, such as a registered function known to be slow
+    pub fn with_function(mut self, name: impl Into<String>, cost: f64) -> Self {
+        self.functions.insert(name.into(), cost);
+        self
+    }
+
+    // The cost of evaluating the expression once: the sum of the costs of its nodes
+    pub fn estimate(&self, expr: &Expr) -> f64 {
+        let own = match expr {
+            Expr::BinOp { op, .. } => match op {
+                Operator::Divide | Operator::Modulo => self.division,
+                Operator::Power => self.power,
+                Operator::Custom(_) => self.unknown_function,
+                _ => self.arithmetic,
+            },
+            Expr::Unary { op, .. } => match op {
+                UnaryOperator::Percent => self.division,
+                UnaryOperator::Sqrt => self.power,
+                UnaryOperator::Custom(_) => self.unknown_function,
+                _ => self.arithmetic,
+            },
+            Expr::UnaryMinus(_) => self.arithmetic,
+            Expr::Function { name, .. } => self.function(name),
+            _ => 0.0,
+        };
+        own + expr.children().into_iter().map(|child| self.estimate(child)).sum::<f64>()
+    }
+
+    fn function(&self, name: &str) -> f64 {
+        if let Some(cost) = self.functions.get(name) {
+            *cost
+        } else if TRANSCENDENTAL.contains(&name) || ELEMENTARY.contains(&name) {
+            self.transcendental
+        } else if NUMBER_THEORY.contains(&name) {
+            self.number_theory
+        } else if MATRIX.contains(&name) {
+            self.matrix
+        } else if builtin(name).is_some() || list_builtin(name).is_some() || random_builtin(name).is_some() {
+            self.call
+        } else {
+            self.unknown_function
+        }
+    }
+}
+
+// The cost of evaluating the expression with the default model, for budgeting or rejecting
+// expensive formulas before running them. `1 + 2 * x` costs 2, `x ^ 2` costs 20.
+pub fn estimate_cost(expr: &Expr) -> f64 {
+    CostModel::default().estimate(expr)
+}
+
+// Something that must hold for an expression to be defined
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    NonZero(Expr),     // A divisor
+    NonNegative(Expr), // Under a square root, or raised to a fractional power
+    Positive(Expr),    // The argument of a logarithm, or raised to a negative fractional power
+}
+
+impl Condition {
+    pub fn expr(&self) -> &Expr {
+        match self {
+            Condition::NonZero(expr) | Condition::NonNegative(expr) | Condition::Positive(expr) => expr,
+        }
+    }
+
+    // Whether the condition holds for a value of its expression
+    fn holds(&self, value: f64) -> bool {
+        match self {
+            Condition::NonZero(_) => value != 0.0,
+            Condition::NonNegative(_) => value >= 0.0,
+            Condition::Positive(_) => value > 0.0,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::NonZero(expr) => write!(f, "{} != 0", expr),
+            Condition::NonNegative(expr) => write!(f, "{} >= 0", expr),
+            Condition::Positive(expr) => write!(f, "{} > 0", expr),
+        }
+    }
+}
+
+// A range of values, each end open or closed; the unbounded ends are infinite and open
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lower: f64,
+    pub upper: f64,
+    pub lower_closed: bool,
+    pub upper_closed: bool,
+}
+
+impl Interval {
+    pub fn contains(&self, x: f64) -> bool {
+        let above = x > self.lower || (self.lower_closed && x == self.lower);
+        let below = x < self.upper || (self.upper_closed && x == self.upper);
+        above && below
+    }
+
+    // The values in both, if there are any
+    fn intersect(&self, other: &Interval) -> Option<Interval> {
+        let (lower, lower_closed) = match self.lower.total_cmp(&other.lower) {
+            std::cmp::Ordering::Less => (other.lower, other.lower_closed),
+            std::cmp::Ordering::Greater => (self.lower, self.lower_closed),
+            std::cmp::Ordering::Equal => (self.lower, self.lower_closed && other.lower_closed),
+        };
+        let (upper, upper_closed) = match self.upper.total_cmp(&other.upper) {
+            std::cmp::Ordering::Less => (self.upper, self.upper_closed),
+            std::cmp::Ordering::Greater => (other.upper, other.upper_closed),
+            std::cmp::Ordering::Equal => (self.upper, self.upper_closed && other.upper_closed),
+        };
+        let interval = Interval { lower, upper, lower_closed, upper_closed };
+        (lower < upper || (lower == upper && lower_closed && upper_closed)).then_some(interval)
+    }
+}
+
+// [0, inf), (-inf, 1), [2, 2]
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let open = if self.lower_closed { '[' } else { '(' };
+        let close = if self.upper_closed { ']' } else { ')' };
+        write!(f, "{}{}, {}{}", open, self.lower, self.upper, close)
+    }
+}
+
+// Where an expression is defined in one variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Domain {
+    // Every condition that involves the variable, innermost first
+    pub conditions: Vec<Condition>,
+    // The values of the variable meeting them all, in increasing order, when every condition is a
+    // polynomial of degree up to 3 in the variable alone; None when they cannot be solved exactly
+    pub intervals: Option<Vec<Interval>>,
+}
+
+// (-inf, 1) ∪ (1, inf), or the conditions when they were not solved
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = match &self.intervals {
+            Some(intervals) if intervals.is_empty() => return write!(f, "∅"),
+            Some(intervals) => intervals.iter().map(ToString::to_string).collect(),
+            None => self.conditions.iter().map(ToString::to_string).collect(),
+        };
+        let separator = if self.intervals.is_some() { " ∪ " } else { " and " };
+        write!(f, "{}", parts.join(separator))
+    }
+}
+
+// Where the expression is defined as a function of the variable: divisors nonzero, square roots and
+// fractional powers of nonnegatives, and logarithms (ln, log, log2 and log10) of positives.
+// Conditions that do not involve the variable are left out. Other variables are unknowns, so
+// conditions involving them are reported but not solved.
+pub fn domain(expr: &Expr, variable: &str) -> Domain {
+    let mut conditions = Vec::new();
+    collect_conditions(expr, variable, &mut conditions);
+    let whole = vec![Interval {
+        lower: f64::NEG_INFINITY,
+        upper: f64::INFINITY,
+        lower_closed: false,
+        upper_closed: false,
+    }];
+    let intervals = conditions.iter().try_fold(whole, |intervals, condition| {
+        let solved = solve_condition(condition, variable)?;
+        let mut both: Vec<Interval> =
+            intervals.iter().flat_map(|a| solved.iter().filter_map(|b| a.intersect(b))).collect();
+        both.sort_by(|a, b| a.lower.total_cmp(&b.lower));
+        Some(both)
+    });
+    Domain { conditions, intervals }
+}
+
+fn involves(expr: &Expr, variable: &str) -> bool {
+    match expr {
+        Expr::Variable(name) => name == variable,
+        _ => expr.children().into_iter().any(|child| involves(child, variable)),
+    }
+}
+
+fn collect_conditions(expr: &Expr, variable: &str, conditions: &mut Vec<Condition>) {
+    for child in expr.children() {
+        collect_conditions(child, variable, conditions);
+    }
+    let condition = match expr {
+        Expr::BinOp { op: Operator::Divide | Operator::Modulo, rhs, .. } => Condition::NonZero((**rhs).clone()),
+        Expr::Unary { op: UnaryOperator::Sqrt, operand } => Condition::NonNegative((**operand).clone()),
+        Expr::BinOp { op: Operator::Power, lhs, rhs } => match exponent(rhs) {
+            Some(n) if n.fract() != 0.0 && n < 0.0 => Condition::Positive((**lhs).clone()),
+            Some(n) if n.fract() != 0.0 => Condition::NonNegative((**lhs).clone()),
+            Some(n) if n < 0.0 => Condition::NonZero((**lhs).clone()),
+            _ => return,
+        },
+        Expr::Function { name, args } if args.len() == 1 => match name.as_str() {
+            "ln" | "log" | "log2" | "log10" => Condition::Positive(args[0].clone()),
+            "sqrt" => Condition::NonNegative(args[0].clone()),
+            _ => return,
+        },
+        _ => return,
+    };
+    if involves(condition.expr(), variable) && !conditions.contains(&condition) {
+        conditions.push(condition);
+    }
+}
+
+// The value of a numeric exponent, which the parser writes as a negated literal when negative
+fn exponent(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Literal(n) => Some(*n),
+        Expr::Scientific { base, exponent } => Some(base * 10f64.powi(*exponent)),
+        Expr::UnaryMinus(operand) => exponent(operand).map(|n| -n),
+        Expr::Group(inner) => exponent(inner),
+        _ => None,
+    }
+}
+
+// The values of the variable where a polynomial condition holds, in increasing order
+fn solve_condition(condition: &Condition, variable: &str) -> Option<Vec<Interval>> {
+    let p = Polynomial::from_expr(condition.expr(), variable).ok()?;
+    let roots = if p.degree() == 0 { Vec::new() } else { p.roots()? };
+    // Between and around the roots the sign is constant, so one point decides each stretch
+    let mut bounds = vec![f64::NEG_INFINITY];
+    bounds.extend(&roots);
+    bounds.push(f64::INFINITY);
+    let midpoint = |lower: f64, upper: f64| match (lower.is_finite(), upper.is_finite()) {
+        (true, true) => (lower + upper) / 2.0,
+        (false, true) => upper - 1.0,
+        (true, false) => lower + 1.0,
+        (false, false) => 0.0,
+    };
+    let mut intervals = Vec::new();
+    let mut start: Option<(f64, bool)> = None;
+    for (i, pair) in bounds.windows(2).enumerate() {
+        let (lower, upper) = (pair[0], pair[1]);
+        // The root at the lower end, then the stretch after it
+        if i > 0 {
+            let at_root = condition.holds(0.0);
+            match (start, at_root) {
+                (None, true) => start = Some((lower, true)),
+                (Some((from, closed)), false) => {
+                    intervals.push(Interval { lower: from, upper: lower, lower_closed: closed, upper_closed: false });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        let inside = condition.holds(p.evaluate(midpoint(lower, upper)));
+        match (start, inside) {
+            (None, true) => start = Some((lower, false)),
+            (Some((from, closed)), false) => {
+                intervals.push(Interval { lower: from, upper: lower, lower_closed: closed, upper_closed: true });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((from, closed)) = start {
+        intervals.push(Interval { lower: from, upper: f64::INFINITY, lower_closed: closed, upper_closed: false });
+    }
+    Some(intervals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Evaluator};
+
+    fn bindings(input: &str) -> Vec<String> {
+        let expr = Expr::parse(input).unwrap();
+        bind_common_subexpressions(&expr).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        let expr = Expr::parse("(a + b) * (a + b) + sqrt2((b + a) * (a + b)) / (a + b)").unwrap();
+        let common = common_subexpressions(&expr);
+        let found: Vec<(String, usize)> = common.iter().map(|common| (common.expr.to_string(), common.count)).collect();
+        assert_eq!(found, [("a + b".to_string(), 5), ("(a + b) * (a + b)".to_string(), 2)]);
+        assert_eq!(common[1].binding.to_string(), "t2 = t1 * t1");
+        assert_eq!(bindings("(a + b) * (a + b) + sqrt2((b + a) * (a + b)) / (a + b)"), ["t1 = a + b", "t2 = t1 * t1", "t2 + sqrt2(t2) / t1"]);
+
+        // Only repeated inside another repeated subtree: not worth a variable of its own
+        assert_eq!(bindings("f(x + 1) + f(x + 1) * 2"), ["t1 = f(x + 1)", "t1 + t1 * 2"]);
+        // Names in use are skipped
+        assert_eq!(bindings("t1 * (t1 + 2) - (t1 + 2)"), ["t2 = t1 + 2", "t1 * t2 - t2"]);
+        // Nothing worth binding, and random numbers are drawn each time
+        assert_eq!(bindings("x * 2 + -x + -x"), ["x * 2 + -x + -x"]);
+        assert_eq!(bindings("rand() * 2 + rand() * 2"), ["rand() * 2 + rand() * 2"]);
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let cost = |input: &str| estimate_cost(&Expr::parse(input).unwrap());
+        assert_eq!(cost("1 + 2 * x"), 2.0);
+        assert_eq!(cost("-(a / b) + [x, y][0]"), 6.0);
+        assert_eq!(cost("x ^ 2 + √y"), 41.0);
+        assert_eq!(cost("max(1, 2)"), 2.0);
+        // Transcendental and unknown functions cost more than arithmetic, number theory more still
+        assert!(cost("exp(x)") > cost("x * x * x * x"));
+        assert_eq!(cost("sin(x)"), cost("atan2(y, x)"));
+        assert!(cost("sqrt(x)") > cost("max(x, 1)"));
+        assert_eq!(cost("myfn(x + 1)"), 26.0);
+        assert_eq!(cost("isprime(n)"), 100.0);
+
+        let model = CostModel::default().with_function("myfn", 500.0);
+        assert_eq!(model.estimate(&Expr::parse("myfn(x) + 1").unwrap()), 501.0);
+    }
+
+    #[test]
+    fn test_bindings_keep_the_value() {
+        let mut ctx = Context::new();
+        ctx.set_variable("a", 1.5);
+        ctx.set_variable("b", -4.0);
+        for input in ["(a + b) * (a + b) - max(a + b, a * b) / (a * b)", "[a * b, a * b][0] + (a - b) ^ (a - b)"] {
+            let expr = Expr::parse(input).unwrap();
+            let expected = Evaluator::evaluate_value_with(&expr, &ctx).unwrap();
+            let statements = bind_common_subexpressions(&expr);
+            assert!(statements.len() > 1, "{}", input);
+            let results = Evaluator::execute_all(&statements, &mut ctx.clone()).unwrap();
+            assert_eq!(results.last(), Some(&expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_domain() {
+        let domain = |input: &str| super::domain(&Expr::parse(input).unwrap(), "x").to_string();
+        assert_eq!(domain("1 / (x - 1)"), "(-inf, 1) ∪ (1, inf)");
+        assert_eq!(domain("√(x + 2) / x"), "[-2, 0) ∪ (0, inf)");
+        assert_eq!(domain("ln(4 - x ^ 2)"), "(-2, 2)");
+        assert_eq!(domain("(x ^ 2 - 1) ^ 0.5 + x ^ -1"), "(-inf, -1] ∪ [1, inf)");
+        assert_eq!(domain("√(-(x ^ 2))"), "[0, 0]");
+        assert_eq!(domain("√(-1 - x ^ 2)"), "∅");
+        // Negative exponents are parsed as negated numbers
+        assert_eq!(domain("x ^ -0.5"), "(0, inf)");
+        assert_eq!(domain("(x + 1) ^ (-2)"), "(-inf, -1) ∪ (-1, inf)");
+        assert_eq!(domain("x ^ -1.5e0"), "(0, inf)");
+        assert_eq!(domain("x ^ 2 + y / 2"), "(-inf, inf)");
+        let with_parameter = super::domain(&Expr::parse("1 / (x - a) + √x").unwrap(), "x");
+        assert_eq!(with_parameter.intervals, None);
+        assert_eq!(with_parameter.conditions, vec![
+            Condition::NonZero(Expr::parse("x - a").unwrap()),
+            Condition::NonNegative(Expr::variable("x")),
+        ]);
+        assert_eq!(with_parameter.to_string(), "x - a != 0 and x >= 0");
+    }
+}
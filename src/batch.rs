@@ -0,0 +1,173 @@
+// src/batch.rs
+// CSV batch processing (`mathexpr csv --expr "price * qty" data.csv`): every column becomes a
+// variable named after its header, the expression is evaluated for each row, and the rows are
+// written back with the result in an extra column.
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use mathexpr::{Context, Statement};
+
+use crate::{render_result, CliOptions};
+
+// Arguments of the csv subcommand
+#[derive(Debug, Default, PartialEq)]
+pub struct CsvArgs {
+    expr: String,
+    column: String,         // Name of the result column
+    input: Option<String>,  // Standard input when absent or "-"
+    output: Option<String>, // Standard output when absent
+}
+
+pub const USAGE: &str = "Usage: mathexpr csv --expr <EXPRESSION> [--column <NAME>] [--output <FILE>] [FILE]";
+
+// Parses the arguments following `csv`
+pub fn parse_args(args: &[String]) -> Result<CsvArgs, String> {
+    let mut parsed = CsvArgs {
+        column: "result".to_string(),
+        ..CsvArgs::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "-e" | "--expr" => parsed.expr = value()?,
+            "-c" | "--column" => parsed.column = value()?,
+            "-o" | "--output" => parsed.output = Some(value()?),
+            _ if parsed.input.is_none() => parsed.input = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    if parsed.expr.is_empty() {
+        return Err("--expr is required".to_string());
+    }
+    Ok(parsed)
+}
+
+// Processes the input file. Returns the errors of the rows that could not be evaluated, whose
+// result cells are left empty.
+pub fn run(args: &CsvArgs, options: &CliOptions, ctx: &mut Context) -> Result<Vec<String>, String> {
+    let input: Box<dyn Read> = match args.input.as_deref() {
+        None | Some("-") => Box::new(io::stdin()),
+        Some(path) => Box::new(File::open(path).map_err(|e| format!("cannot read {}: {}", path, e))?),
+    };
+    let output: Box<dyn Write> = match &args.output {
+        None => Box::new(io::stdout()),
+        Some(path) => Box::new(File::create(path).map_err(|e| format!("cannot write {}: {}", path, e))?),
+    };
+    process(input, output, args, options, ctx)
+}
+
+fn process(
+    input: impl Read,
+    output: impl Write,
+    args: &CsvArgs,
+    options: &CliOptions,
+    ctx: &mut Context,
+) -> Result<Vec<String>, String> {
+    let expr = options.config.grammar().parse(&args.expr).map_err(|e| format!("{}: {}", args.expr, e))?;
+    let evaluator = options.config.evaluator();
+    let statement = Statement::Expr(expr.clone()); // For the output base, as in hex(price)
+    let mut reader = csv::Reader::from_reader(input);
+    let mut writer = csv::Writer::from_writer(output);
+
+    let mut headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let names: Vec<String> = headers.iter().map(variable_name).collect();
+    // A result column that already exists is overwritten
+    let result_column = headers.iter().position(|header| header == args.column);
+    if result_column.is_none() {
+        headers.push_field(&args.column);
+    }
+    writer.write_record(&headers).map_err(|e| e.to_string())?;
+
+    let mut errors = Vec::new();
+    for (row, record) in reader.records().enumerate() {
+        let mut record = record.map_err(|e| e.to_string())?;
+        // Cells that are not numbers leave their variable unbound
+        ctx.push_scope();
+        for (name, cell) in names.iter().zip(record.iter()) {
+            if let Ok(value) = cell.trim().parse::<f64>() {
+                ctx.set_variable(name.as_str(), value);
+            }
+        }
+        let result = evaluator.eval_value(&expr, ctx).map_err(|e| e.to_string());
+        ctx.pop_scope();
+
+        let cell = match result.and_then(|value| render_result(&statement, &value, options)) {
+            Ok(text) => text,
+            Err(e) => {
+                // Rows are numbered as in a spreadsheet, after the header row
+                errors.push(format!("row {}: {}", row + 2, e));
+                String::new()
+            }
+        };
+        match result_column {
+            Some(index) => {
+                let mut fields: Vec<&str> = record.iter().collect();
+                fields.resize(fields.len().max(index + 1), "");
+                fields[index] = &cell;
+                writer.write_record(&fields).map_err(|e| e.to_string())?;
+            }
+            None => {
+                record.push_field(&cell);
+                writer.write_record(&record).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(errors)
+}
+
+// The variable name for a column header: characters that cannot appear in a name become '_'
+fn variable_name(header: &str) -> String {
+    let name: String = header
+        .trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_on(input: &str, args: &[&str]) -> (String, Vec<String>) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let args = parse_args(&args).unwrap();
+        let mut output = Vec::new();
+        let mut ctx = Context::new();
+        ctx.set_variable("tax", 0.5);
+        let errors = process(input.as_bytes(), &mut output, &args, &CliOptions::default(), &mut ctx).unwrap();
+        (String::from_utf8(output).unwrap(), errors)
+    }
+
+    #[test]
+    fn test_csv() {
+        let input = "item,price,qty\nbook,10,2\npen,1.5,4\nmug,,1\n";
+        let (output, errors) = run_on(input, &["--expr", "price * qty * (1 + tax)", "data.csv"]);
+        assert_eq!(output, "item,price,qty,result\nbook,10,2,30\npen,1.5,4,9\nmug,,1,\n");
+        assert_eq!(errors, ["row 4: Unknown variable: price"]);
+
+        // Headers that are not names, and an existing result column
+        let input = "unit price,2nd,total\n\"1,5\",2,0\n3,4,0\n";
+        let (output, errors) = run_on(input, &["-e", "unit_price + _2nd", "--column", "total"]);
+        assert_eq!(output, "unit price,2nd,total\n\"1,5\",2,\n3,4,7\n");
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let parse = |args: &[&str]| parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+        let parsed = parse(&["in.csv", "--expr", "a+b", "-o", "out.csv"]).unwrap();
+        assert_eq!(parsed.input.as_deref(), Some("in.csv"));
+        assert_eq!(parsed.output.as_deref(), Some("out.csv"));
+        assert_eq!(parsed.column, "result");
+        assert_eq!(parse(&["in.csv"]), Err("--expr is required".to_string()));
+        assert_eq!(parse(&["--expr"]), Err("--expr requires a value".to_string()));
+        assert!(parse(&["-e", "1", "a.csv", "b.csv"]).is_err());
+    }
+}
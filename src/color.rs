@@ -0,0 +1,100 @@
+// src/color.rs
+// Colored output of the command-line tool: expressions and results are syntax-highlighted,
+// errors are red and the part of the input an error points at is marked. Off with --no-color,
+// when NO_COLOR is set, or when standard output is not a terminal.
+use std::env;
+use std::io::{self, IsTerminal};
+
+use mathexpr::{highlight, Span, TokenClass};
+
+const RESET: &str = "\x1b[0m";
+const NUMBER: &str = "\x1b[36m"; // Cyan
+const OPERATOR: &str = "\x1b[33m"; // Yellow
+const BRACKET: &str = "\x1b[1;34m"; // Bold blue
+const ERROR: &str = "\x1b[1;31m"; // Bold red
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Colors {
+    enabled: bool,
+}
+
+impl Colors {
+    // Colors unless NO_COLOR is set (to anything but an empty string) or the output is redirected
+    pub fn detect() -> Self {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self {
+            enabled: !no_color && io::stdout().is_terminal(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.enabled {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn error(&self, text: &str) -> String {
+        self.paint(ERROR, text)
+    }
+
+    // The text with its numbers, operators and brackets colored. Text that cannot be tokenized
+    // (such as a result in hexadecimal) is left as it is.
+    pub fn highlight(&self, text: &str) -> String {
+        let classes = highlight(text);
+        if !self.enabled || classes.iter().any(|(_, class)| *class == TokenClass::Error) {
+            return text.to_string();
+        }
+        let mut highlighted = String::new();
+        let mut end = 0;
+        for (span, class) in classes {
+            highlighted.push_str(&text[end..span.start]);
+            let source = &text[span.start..span.end];
+            let style = match class {
+                TokenClass::Number => NUMBER,
+                TokenClass::Operator => OPERATOR,
+                TokenClass::Paren => BRACKET,
+                TokenClass::Identifier | TokenClass::Error => "",
+            };
+            match style {
+                "" => highlighted.push_str(source),
+                style => highlighted.push_str(&self.paint(style, source)),
+            }
+            end = span.end;
+        }
+        highlighted.push_str(&text[end..]);
+        highlighted
+    }
+
+    // The input with the part in the span shown as an error
+    pub fn mark(&self, input: &str, span: Span) -> String {
+        let (start, end) = (span.start.min(input.len()), span.end.min(input.len()));
+        format!("{}{}{}", &input[..start], self.error(&input[start..end]), &input[end..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors() {
+        let colors = Colors { enabled: true };
+        assert_eq!(
+            colors.highlight("max(x, 2) + 1"),
+            "max\x1b[1;34m(\x1b[0mx\x1b[33m,\x1b[0m \x1b[36m2\x1b[0m\x1b[1;34m)\x1b[0m \x1b[33m+\x1b[0m \x1b[36m1\x1b[0m"
+        );
+        // Text that is not an expression is not highlighted
+        assert_eq!(colors.highlight("1 $ 2"), "1 $ 2");
+        assert_eq!(colors.mark("1 + 2 3", Span::new(6, 7)), "1 + 2 \x1b[1;31m3\x1b[0m");
+
+        let plain = Colors::default();
+        assert_eq!(plain.highlight("1 + 2"), "1 + 2");
+        assert_eq!(plain.error("oops"), "oops");
+    }
+}
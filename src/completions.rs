@@ -0,0 +1,216 @@
+// src/completions.rs
+// The command-line options and subcommands, and shell completion scripts generated from them
+// (`mathexpr completions bash|zsh|fish`)
+
+// A command-line option
+pub struct Flag {
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub value: Option<&'static str>, // Placeholder of the option's value, shown in the help
+    pub choices: &'static [&'static str], // The values it accepts, if it takes one of a fixed set
+    pub help: &'static str,
+}
+
+const fn flag(short: Option<char>, long: &'static str, help: &'static str) -> Flag {
+    Flag {
+        short,
+        long,
+        value: None,
+        choices: &[],
+        help,
+    }
+}
+
+pub const FLAGS: &[Flag] = &[
+    Flag {
+        value: Some("<auto|sci|eng|si>"),
+        choices: &["auto", "sci", "eng", "si"],
+        ..flag(None, "notation", "Output notation for the result (default: auto)")
+    },
+    Flag {
+        value: Some("<N>"),
+        ..flag(None, "base", "Print the (integer) result in base N, 2 to 36")
+    },
+    Flag {
+        value: Some("<N>"),
+        ..flag(None, "precision", "Print results rounded to N decimal places")
+    },
+    Flag {
+        value: Some("<N>"),
+        ..flag(None, "sig-figs", "Print results rounded to N significant figures")
+    },
+    Flag {
+        value: Some("<FILE>"),
+        ..flag(None, "load", "Load the definitions in FILE (a formula library) before evaluating")
+    },
+    Flag {
+        value: Some("<FILE>"),
+        ..flag(None, "watch", "Evaluate the statements in FILE again whenever it changes")
+    },
+    flag(Some('i'), "interactive", "Read expressions line by line, keeping variables between lines"),
+    flag(None, "time", "Report the time spent tokenizing, parsing and evaluating each input"),
+    flag(None, "no-color", "Print without colors (also when NO_COLOR is set)"),
+    flag(None, "no-config", "Ignore the configuration file"),
+    flag(Some('h'), "help", "Show this help"),
+];
+
+// Subcommands, recognized as the first argument
+pub const SUBCOMMANDS: &[(&str, &str)] = &[
+    ("completions", "Print the completion script for bash, zsh or fish"),
+    ("csv", "Evaluate an expression for every row of a CSV file"),
+    ("fmt", "Format files of formulas in place (--check lists the files that would change)"),
+    ("lint", "Report questionable formulas in files (--deny warnings fails on any)"),
+    ("lsp", "Language server for formula files: diagnostics, hover and completion"),
+    ("serve", "Serve a JSON API for evaluating and parsing expressions over HTTP"),
+    ("tui", "Full-screen calculator with history and variables panes"),
+];
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+impl Flag {
+    // How the option is written in the help, such as "-i, --interactive" or "--base <N>"
+    pub fn usage(&self) -> String {
+        let mut usage = match self.short {
+            Some(short) => format!("-{}, --{}", short, self.long),
+            None => format!("--{}", self.long),
+        };
+        if let Some(value) = self.value {
+            usage = format!("{} {}", usage, value);
+        }
+        usage
+    }
+
+    // Every way of writing the option
+    fn names(&self) -> Vec<String> {
+        let long = format!("--{}", self.long);
+        match self.short {
+            Some(short) => vec![format!("-{}", short), long],
+            None => vec![long],
+        }
+    }
+}
+
+// The completion script for a shell, or None for an unsupported shell
+pub fn script(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash()),
+        "zsh" => Some(zsh()),
+        "fish" => Some(fish()),
+        _ => None,
+    }
+}
+
+fn bash() -> String {
+    let names: Vec<String> = FLAGS.iter().flat_map(Flag::names).collect();
+    let subcommands: Vec<&str> = SUBCOMMANDS.iter().map(|(name, _)| *name).collect();
+    let mut script = String::from("_mathexpr() {\n");
+    script += "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n";
+    script += "    case \"$prev\" in\n";
+    for flag in FLAGS.iter().filter(|flag| flag.value.is_some()) {
+        script += &format!(
+            "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+            flag.names().join("|"),
+            flag.choices.join(" ")
+        );
+    }
+    script += &format!(
+        "        completions) [[ $COMP_CWORD -eq 2 ]] && COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+        SHELLS.join(" ")
+    );
+    script += "    esac\n";
+    script += &format!(
+        "    if [[ $cur == -* ]]; then\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+        names.join(" ")
+    );
+    script += &format!(
+        "    elif [[ $COMP_CWORD -eq 1 ]]; then\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n    fi\n",
+        subcommands.join(" ")
+    );
+    // Files are completed where nothing else applies, such as the input of csv
+    script += "}\ncomplete -o default -F _mathexpr mathexpr\n";
+    script
+}
+
+fn zsh() -> String {
+    let mut script = String::from("#compdef mathexpr\n\n");
+    script += "if [[ $words[2] == completions ]]; then\n";
+    script += &format!("    (( CURRENT == 3 )) && _values 'shell' {}\n    return\nfi\n\n", SHELLS.join(" "));
+    let subcommands: Vec<String> =
+        SUBCOMMANDS.iter().map(|(name, help)| format!("'{}:{}'", name, help)).collect();
+    script += &format!("local -a subcommands=({})\n", subcommands.join(" "));
+    script += "_arguments -s \\\n";
+    for flag in FLAGS {
+        let names = match flag.short {
+            Some(short) => format!("'(-{0} --{1})'{{-{0},--{1}}}", short, flag.long),
+            None => format!("'--{}", flag.long),
+        };
+        let open = if flag.short.is_some() { "'" } else { "" };
+        let value = match flag.value {
+            Some("<FILE>") => format!(":{}:_files", flag.long),
+            Some(_) if flag.choices.is_empty() => format!(":{}: ", flag.long),
+            Some(_) => format!(":{}:({})", flag.long, flag.choices.join(" ")),
+            None => String::new(),
+        };
+        script += &format!("    {}{}[{}]{}' \\\n", names, open, flag.help, value);
+    }
+    script += "    '1:: :{_describe command subcommands}' \\\n";
+    script += "    '*:expression: '\n";
+    script
+}
+
+fn fish() -> String {
+    let mut script = String::from("complete -c mathexpr -f\n");
+    for (name, help) in SUBCOMMANDS {
+        script += &format!("complete -c mathexpr -n __fish_use_subcommand -a {} -d '{}'\n", name, help);
+    }
+    script += &format!(
+        "complete -c mathexpr -n '__fish_seen_subcommand_from completions' -a '{}'\n",
+        SHELLS.join(" ")
+    );
+    script += "complete -c mathexpr -n '__fish_seen_subcommand_from csv' -F\n";
+    for flag in FLAGS {
+        let mut line = String::from("complete -c mathexpr");
+        if let Some(short) = flag.short {
+            line += &format!(" -s {}", short);
+        }
+        line += &format!(" -l {}", flag.long);
+        if flag.value == Some("<FILE>") {
+            line += " -r -F";
+        } else if flag.value.is_some() {
+            line += " -x";
+            if !flag.choices.is_empty() {
+                line += &format!(" -a '{}'", flag.choices.join(" "));
+            }
+        }
+        script += &format!("{} -d '{}'\n", line, flag.help);
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts() {
+        let bash = script("bash").unwrap();
+        assert!(bash.contains("--notation) COMPREPLY=($(compgen -W \"auto sci eng si\" -- \"$cur\")); return ;;"));
+        assert!(bash.contains("-i --interactive --time --no-color --no-config -h --help"));
+        assert!(bash.ends_with("complete -o default -F _mathexpr mathexpr\n"));
+
+        let zsh = script("zsh").unwrap();
+        assert!(zsh.starts_with("#compdef mathexpr\n"));
+        assert!(zsh.contains("    '--notation[Output notation for the result (default: auto)]:notation:(auto sci eng si)' \\\n"));
+        assert!(zsh.contains("    '(-i --interactive)'{-i,--interactive}'[Read expressions"));
+
+        let fish = script("fish").unwrap();
+        assert!(fish.contains("complete -c mathexpr -l base -x -d 'Print the (integer) result in base N, 2 to 36'\n"));
+        assert!(fish.contains("complete -c mathexpr -s h -l help -d 'Show this help'\n"));
+        assert!(fish.contains("complete -c mathexpr -l watch -r -F -d"));
+        assert!(zsh.contains("]:watch:_files' \\\n"));
+        assert!(script("powershell").is_none());
+
+        assert_eq!(FLAGS[0].usage(), "--notation <auto|sci|eng|si>");
+        assert_eq!(FLAGS[6].usage(), "-i, --interactive");
+    }
+}
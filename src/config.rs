@@ -0,0 +1,143 @@
+// src/config.rs
+// Defaults for the command-line tool, read from ~/.config/mathexpr/config.toml at startup so
+// they need not be repeated on every invocation. Command-line flags override them.
+//
+//     notation = "eng"     # auto, sci, eng or si
+//     base = 16            # print integer results in this base
+//     precision = 4        # decimal places
+//     sig_figs = 6         # significant figures, instead of a precision
+//     definitions = ["rate = 0.07", "net = 1 - rate"]
+//     strict = true        # fail on NaN and infinite results
+//     units = true         # read unit names such as km and kg
+//     max_depth = 64       # deepest nesting of input to accept
+//
+//     [constants]
+//     g = 9.80665
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mathexpr::Precision;
+
+use crate::{check_base, check_count, parse_notation, CliOptions, PRECISIONS, SIG_FIGS};
+
+#[derive(Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    notation: Option<String>,
+    base: Option<u32>,
+    precision: Option<u32>,
+    sig_figs: Option<u32>,
+    constants: BTreeMap<String, f64>, // Defined before the definitions
+    definitions: Vec<String>,         // Statements run at startup, in order
+    strict: Option<bool>,
+    units: Option<bool>,
+    max_depth: Option<usize>,
+}
+
+impl Config {
+    // Reads the configuration file. A missing file is an empty configuration.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.message().to_string())
+    }
+
+    // Makes these settings the defaults of the options
+    pub fn apply(self, options: &mut CliOptions) -> Result<(), String> {
+        let config = &mut options.config;
+        if let Some(notation) = &self.notation {
+            config.format.notation = parse_notation(notation)?;
+        }
+        if let Some(base) = self.base {
+            options.base = Some(check_base(base)?);
+        }
+        match (self.precision, self.sig_figs) {
+            (Some(_), Some(_)) => return Err("precision and sig_figs cannot both be set".to_string()),
+            (Some(places), None) => {
+                config.format.precision = Some(Precision::Decimals(check_count("precision", places, PRECISIONS)?))
+            }
+            (None, Some(figures)) => {
+                config.format.precision = Some(Precision::SignificantFigures(check_count("sig_figs", figures, SIG_FIGS)?))
+            }
+            (None, None) => {}
+        }
+        config.evaluation.strict = self.strict.unwrap_or(config.evaluation.strict);
+        config.evaluation.units = self.units.unwrap_or(config.evaluation.units);
+        config.max_depth = self.max_depth.or(config.max_depth);
+        let constants = self.constants.iter().map(|(name, value)| format!("{} = {}", name, value));
+        options.definitions.extend(constants.chain(self.definitions));
+        Ok(())
+    }
+}
+
+// $XDG_CONFIG_HOME/mathexpr/config.toml, falling back to ~/.config
+pub fn config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("mathexpr").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mathexpr::Notation;
+
+    #[test]
+    fn test_config() {
+        let config = Config::parse(
+            r#"
+            notation = "sci"
+            precision = 3
+            definitions = ["r = 2 * g"]
+
+            [constants]
+            g = 9.5
+            "#,
+        )
+        .unwrap();
+        let mut options = CliOptions::default();
+        config.apply(&mut options).unwrap();
+        assert_eq!(options.config.format.notation, Notation::Scientific);
+        assert_eq!(options.config.format.precision, Some(Precision::Decimals(3)));
+        assert_eq!(options.definitions, ["g = 9.5", "r = 2 * g"]);
+        assert_eq!(crate::initial_context(&options).unwrap().variable("r"), Some(19.0));
+
+        assert!(Config::parse("colour = 1").unwrap_err().contains("unknown field"));
+        let sig_figs = Config::parse("sig_figs = 2").unwrap();
+        sig_figs.apply(&mut options).unwrap();
+        assert_eq!(options.config.format.precision, Some(Precision::SignificantFigures(2)));
+        let both = Config::parse("sig_figs = 2\nprecision = 1").unwrap();
+        assert!(both.apply(&mut options).is_err());
+        let bad_base = Config::parse("base = 40").unwrap();
+        assert_eq!(bad_base.apply(&mut options).unwrap_err(), "Invalid base: 40 (expected 2 to 36)");
+        // The same limits as --precision and --sig-figs
+        let bad_precision = Config::parse("precision = 1000").unwrap();
+        assert_eq!(
+            bad_precision.apply(&mut options).unwrap_err(),
+            "Invalid value for precision: 1000 (expected 0 to 100)"
+        );
+        let bad_sig_figs = Config::parse("sig_figs = 0").unwrap();
+        assert_eq!(bad_sig_figs.apply(&mut options).unwrap_err(), "Invalid value for sig_figs: 0 (expected 1 to 100)");
+        assert_eq!(Config::load(Path::new("/nonexistent/config.toml")), Ok(Config::default()));
+
+        // Evaluation settings reach every mode through the options' evaluator configuration
+        let mut options = CliOptions::default();
+        let evaluation = Config::parse("strict = true\nmax_depth = 2\ndefinitions = [\"big = 1e308 * 10\"]").unwrap();
+        evaluation.apply(&mut options).unwrap();
+        assert!(options.config.evaluation.strict);
+        assert_eq!(options.config.max_depth, Some(2));
+        assert!(crate::initial_context(&options).is_err());
+        options.definitions.clear();
+        assert!(options.config.grammar().parse("1 + 2 + 3 + 4").is_err());
+    }
+}
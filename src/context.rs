@@ -0,0 +1,546 @@
+// src/context.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::metrics::Metrics;
+use crate::money::RateProvider;
+use crate::observer::EvalObserver;
+use crate::operators::OperatorTable;
+use crate::random::{Rng, RngCell};
+use crate::units::UnitTable;
+use crate::{Evaluator, Expr, MathError, Result, Value};
+
+// A function callable from expressions. Stored behind an Arc so contexts stay cheap to clone
+// and can be shared across threads.
+pub type Function = Arc<dyn Fn(&[f64]) -> Result<f64> + Send + Sync>;
+
+// Supplies variable values on demand (e.g., from a database, sensor, or config store).
+// Consulted only for names not bound in any scope.
+pub trait VariableResolver: Send + Sync {
+    fn lookup(&self, name: &str) -> Option<f64>;
+}
+
+impl<F> VariableResolver for F
+where
+    F: Fn(&str) -> Option<f64> + Send + Sync,
+{
+    fn lookup(&self, name: &str) -> Option<f64> {
+        self(name)
+    }
+}
+
+// One level of bindings. Inner scopes shadow outer ones. The maps are shared between clones until
+// one of them changes, so snapshots cost a reference count per scope.
+#[derive(Clone, Default)]
+struct Scope {
+    variables: Arc<HashMap<String, Value>>,
+    functions: Arc<HashMap<String, Function>>,
+    definitions: Arc<HashMap<String, FunctionDefinition>>, // Of the functions defined by an expression
+}
+
+// A function defined by an expression of its parameters, such as `net(gross) = gross * 0.9`. Unlike
+// a registered closure, it can be saved and defined again (see `Workspace`).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+// The variables, functions and scopes of a context at one point, to go back to with
+// `Context::restore`. Taking and keeping one is cheap: the first change to the context afterwards
+// copies only the scope it changes.
+#[derive(Clone)]
+pub struct ContextSnapshot {
+    scopes: Vec<Scope>,
+}
+
+impl fmt::Debug for ContextSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ContextSnapshot").field("scope_depth", &(self.scopes.len() - 1)).finish()
+    }
+}
+
+// The variables and functions an expression is evaluated against
+#[derive(Clone)]
+pub struct Context {
+    scopes: Vec<Scope>, // Never empty: scopes[0] is the global scope
+    resolver: Option<Arc<dyn VariableResolver>>,
+    observer: Option<Arc<dyn EvalObserver>>, // Told about every node evaluated against the context
+    metrics: Option<Arc<dyn Metrics>>,       // Told about every evaluation against the context
+    rates: Option<Arc<dyn RateProvider>>,    // Converts money between currencies
+    rng: RngCell, // Drawn from by rand(), uniform(), normal() and randint()
+    operators: OperatorTable,
+    units: UnitTable, // Used by convert() and conversions such as 5 mi in km
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            scopes: vec![Scope::default()],
+            resolver: None,
+            observer: None,
+            metrics: None,
+            rates: None,
+            rng: RngCell::new(Rng::from_entropy()),
+            operators: OperatorTable::new(),
+            units: UnitTable::new(),
+        }
+    }
+}
+
+impl Context {
+    // Creates an empty context
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Opens a new innermost scope. Bindings made until the matching `pop_scope` shadow outer ones.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    // Discards the innermost scope and its bindings. Returns false if only the global scope is left.
+    pub fn pop_scope(&mut self) -> bool {
+        if self.scopes.len() == 1 {
+            return false;
+        }
+        self.scopes.pop();
+        true
+    }
+
+    // Number of scopes pushed on top of the global scope
+    pub fn scope_depth(&self) -> usize {
+        self.scopes.len() - 1
+    }
+
+    // Sets (or replaces) a variable in the innermost scope. Accepts numbers and lists
+    // (e.g., a `Vec<f64>` dataset).
+    pub fn set_variable(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        Arc::make_mut(&mut self.innermost().variables).insert(name.into(), value.into());
+    }
+
+    // Removes a variable from the innermost scope, returning its previous value
+    pub fn remove_variable(&mut self, name: &str) -> Option<Value> {
+        let scope = self.innermost();
+        if !scope.variables.contains_key(name) {
+            return None; // Without copying a map shared with a snapshot
+        }
+        Arc::make_mut(&mut scope.variables).remove(name)
+    }
+
+    // Looks up a variable by name, innermost scope first, then through the resolver
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.variables.get(name).cloned())
+            .or_else(|| self.resolver.as_ref()?.lookup(name).map(Value::Number))
+    }
+
+    // Looks up a numeric variable by name. Returns None for lists; use `value` for those.
+    pub fn variable(&self, name: &str) -> Option<f64> {
+        self.value(name)?.as_number()
+    }
+
+    // Records the variables, functions and scopes, for undo or to try something out and go back.
+    // The resolver, observer, metrics, exchange rates, operators, units and random state are not part of
+    // it.
+    pub fn snapshot(&self) -> ContextSnapshot {
+        ContextSnapshot {
+            scopes: self.scopes.clone(),
+        }
+    }
+
+    // Puts back the variables, functions and scopes recorded in a snapshot of this or another context
+    pub fn restore(&mut self, snapshot: ContextSnapshot) {
+        self.scopes = snapshot.scopes;
+    }
+
+    // Sets the resolver used for variables that are not bound in any scope
+    pub fn set_resolver<R>(&mut self, resolver: R)
+    where
+        R: VariableResolver + 'static,
+    {
+        self.resolver = Some(Arc::new(resolver));
+    }
+
+    // Removes the resolver, so only bound variables are visible
+    pub fn clear_resolver(&mut self) {
+        self.resolver = None;
+    }
+
+    // Sets the exchange rates used to add, subtract, divide and compare amounts of different
+    // currencies
+    pub fn set_rates<R>(&mut self, rates: R)
+    where
+        R: RateProvider + 'static,
+    {
+        self.rates = Some(Arc::new(rates));
+    }
+
+    // Removes the exchange rates, so only amounts of the same currency combine
+    pub fn clear_rates(&mut self) {
+        self.rates = None;
+    }
+
+    pub(crate) fn rates(&self) -> Option<&dyn RateProvider> {
+        self.rates.as_deref()
+    }
+
+    // Sets the observer told about every node evaluated against the context, including in
+    // evaluations started by its functions. Pass an `Arc` to keep a handle on it.
+    pub fn set_observer<O>(&mut self, observer: O)
+    where
+        O: EvalObserver + 'static,
+    {
+        self.observer = Some(Arc::new(observer));
+    }
+
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+
+    pub(crate) fn observer(&self) -> Option<&dyn EvalObserver> {
+        self.observer.as_deref()
+    }
+
+    // Sets the metrics told how long each evaluation against the context took and how it failed.
+    // Pass an `Arc` to keep a handle on them.
+    pub fn set_metrics<M>(&mut self, metrics: M)
+    where
+        M: Metrics + 'static,
+    {
+        self.metrics = Some(Arc::new(metrics));
+    }
+
+    pub fn clear_metrics(&mut self) {
+        self.metrics = None;
+    }
+
+    pub(crate) fn metrics(&self) -> Option<&dyn Metrics> {
+        self.metrics.as_deref()
+    }
+
+    // Registers (or replaces) a function in the innermost scope
+    pub fn register_function<F>(&mut self, name: impl Into<String>, function: F)
+    where
+        F: Fn(&[f64]) -> Result<f64> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        let scope = self.innermost();
+        if scope.definitions.contains_key(&name) {
+            Arc::make_mut(&mut scope.definitions).remove(&name);
+        }
+        Arc::make_mut(&mut scope.functions).insert(name, Arc::new(function));
+    }
+
+    // Defines (or replaces) a function in the innermost scope that evaluates the body with its
+    // parameters bound to the arguments, against what the context defines now
+    pub fn define_function(&mut self, definition: FunctionDefinition) {
+        let FunctionDefinition { name, params, body } = definition.clone();
+        let defined = self.clone();
+        let function = name.clone();
+        self.register_function(name.clone(), move |args: &[f64]| {
+            if args.len() != params.len() {
+                return Err(MathError::ArgumentCount {
+                    function: function.clone(),
+                    expected: params.len().to_string(),
+                    found: args.len(),
+                });
+            }
+            let mut scope = defined.clone();
+            scope.push_scope();
+            for (param, arg) in params.iter().zip(args) {
+                scope.set_variable(param.as_str(), *arg);
+            }
+            Evaluator::evaluate_with(&body, &scope)
+        });
+        Arc::make_mut(&mut self.innermost().definitions).insert(name, definition);
+    }
+
+    // The definition of a function defined by an expression, innermost scope first. None for
+    // registered closures.
+    pub fn function_definition(&self, name: &str) -> Option<&FunctionDefinition> {
+        let scope = self.scopes.iter().rev().find(|scope| scope.functions.contains_key(name))?;
+        scope.definitions.get(name)
+    }
+
+    // Names of the variables bound in any scope, sorted
+    pub fn variable_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.scopes.iter().flat_map(|scope| scope.variables.keys()).map(String::as_str).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    // Names of the functions registered in any scope, sorted
+    pub fn function_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.scopes.iter().flat_map(|scope| scope.functions.keys()).map(String::as_str).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    // Looks up a function by name, innermost scope first
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.functions.get(name))
+    }
+
+    // Sets the custom operators expressions may use. Tokenize with the same table (through
+    // `TokenizerOptions::operators`) so the symbols are recognized.
+    pub fn set_operators(&mut self, operators: OperatorTable) {
+        self.operators = operators;
+    }
+
+    // The custom operators available to expressions
+    pub fn operators(&self) -> &OperatorTable {
+        &self.operators
+    }
+
+    // Sets the units conversions may use: the built-in ones and those defined in the table
+    pub fn set_units(&mut self, units: UnitTable) {
+        self.units = units;
+    }
+
+    // The units available to conversions
+    pub fn units(&self) -> &UnitTable {
+        &self.units
+    }
+
+    // Reseeds the random number generator, making subsequent random draws reproducible
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = RngCell::new(Rng::seeded(seed));
+    }
+
+    // Locks the random number generator for drawing
+    pub(crate) fn rng(&self) -> MutexGuard<'_, Rng> {
+        self.rng.lock()
+    }
+
+    fn innermost(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("context always has a global scope")
+    }
+
+    // Moves this context behind a lock so it can be shared and updated across threads
+    pub fn into_shared(self) -> SharedContext {
+        SharedContext::new(self)
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut variables = HashMap::new();
+        let mut functions = Vec::new();
+        for scope in &self.scopes {
+            variables.extend(scope.variables.iter());
+            functions.extend(scope.functions.keys());
+        }
+        functions.sort();
+        functions.dedup();
+        f.debug_struct("Context")
+            .field("variables", &variables)
+            .field("functions", &functions)
+            .field("scope_depth", &self.scope_depth())
+            .field("has_resolver", &self.resolver.is_some())
+            .field("has_observer", &self.observer.is_some())
+            .field("has_metrics", &self.metrics.is_some())
+            .field("has_rates", &self.rates.is_some())
+            .finish()
+    }
+}
+
+// A context shared between threads.
+//
+// Writers update it through the lock; readers either evaluate against `read()` directly or take
+// a `snapshot()`, an immutable copy that can be handed to workers without holding the lock.
+#[derive(Clone, Default, Debug)]
+pub struct SharedContext {
+    inner: Arc<RwLock<Context>>,
+}
+
+impl SharedContext {
+    // Wraps a context for sharing
+    pub fn new(context: Context) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(context)),
+        }
+    }
+
+    // Borrows the context for reading (e.g., to evaluate against it)
+    pub fn read(&self) -> RwLockReadGuard<'_, Context> {
+        // Context updates never leave it half-written, so a poisoned lock is still usable
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Borrows the context for writing
+    pub fn write(&self) -> RwLockWriteGuard<'_, Context> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Sets (or replaces) a variable visible to all holders of this context
+    pub fn set_variable(&self, name: impl Into<String>, value: impl Into<Value>) {
+        self.write().set_variable(name, value);
+    }
+
+    // Registers (or replaces) a function visible to all holders of this context
+    pub fn register_function<F>(&self, name: impl Into<String>, function: F)
+    where
+        F: Fn(&[f64]) -> Result<f64> + Send + Sync + 'static,
+    {
+        self.write().register_function(name, function);
+    }
+
+    // Takes an immutable copy of the current state
+    pub fn snapshot(&self) -> Arc<Context> {
+        Arc::new(self.read().clone())
+    }
+}
+
+impl From<Context> for SharedContext {
+    fn from(context: Context) -> Self {
+        Self::new(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Evaluator, Parser, Tokenizer};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_context_is_send_sync() {
+        assert_send_sync::<Context>();
+        assert_send_sync::<SharedContext>();
+    }
+
+    #[test]
+    fn test_scopes_shadow_and_restore() {
+        let mut ctx = Context::new();
+        ctx.set_variable("x", 1.0);
+        ctx.set_variable("y", 10.0);
+        ctx.register_function("f", |_: &[f64]| Ok(1.0));
+
+        ctx.push_scope();
+        ctx.set_variable("x", 2.0);
+        ctx.register_function("f", |_: &[f64]| Ok(2.0));
+        assert_eq!(ctx.scope_depth(), 1);
+        assert_eq!(ctx.variable("x"), Some(2.0));
+        assert_eq!(ctx.variable("y"), Some(10.0)); // Outer bindings stay visible
+        assert_eq!(ctx.function("f").unwrap()(&[]).unwrap(), 2.0);
+        ctx.set_variable("a", 3.0);
+        assert_eq!(ctx.variable_names(), ["a", "x", "y"]);
+        assert_eq!(ctx.function_names(), ["f"]);
+
+        // Removing only affects the innermost scope
+        assert_eq!(ctx.remove_variable("y"), None);
+
+        assert!(ctx.pop_scope());
+        assert_eq!(ctx.variable("x"), Some(1.0));
+        assert_eq!(ctx.function("f").unwrap()(&[]).unwrap(), 1.0);
+
+        // The global scope cannot be popped
+        assert!(!ctx.pop_scope());
+        assert_eq!(ctx.variable("y"), Some(10.0));
+    }
+
+    #[test]
+    fn test_resolver_supplies_unbound_variables() {
+        struct Sensors;
+        impl VariableResolver for Sensors {
+            fn lookup(&self, name: &str) -> Option<f64> {
+                match name {
+                    "temperature" => Some(21.5),
+                    _ => None,
+                }
+            }
+        }
+
+        let mut ctx = Context::new();
+        ctx.set_resolver(Sensors);
+        assert_eq!(ctx.variable("temperature"), Some(21.5));
+        assert_eq!(ctx.variable("pressure"), None);
+
+        // Bound variables take priority over the resolver
+        ctx.set_variable("temperature", 0.0);
+        assert_eq!(ctx.variable("temperature"), Some(0.0));
+
+        // Closures work as resolvers too
+        ctx.set_resolver(|name: &str| name.strip_prefix("v").and_then(|n| n.parse().ok()));
+        assert_eq!(ctx.variable("v42"), Some(42.0));
+
+        ctx.clear_resolver();
+        assert_eq!(ctx.variable("v42"), None);
+    }
+
+    #[test]
+    fn test_snapshot_shared_across_threads() {
+        let shared = SharedContext::default();
+        shared.set_variable("x", 2.0);
+        shared.register_function("double", |args: &[f64]| Ok(args[0] * 2.0));
+
+        let tokens = Tokenizer::tokenize("double(x) + 1").unwrap();
+        let expr = Parser::new(tokens).parse().unwrap();
+        let snapshot = shared.snapshot();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let snapshot = Arc::clone(&snapshot);
+                let expr = expr.clone();
+                thread::spawn(move || Evaluator::evaluate_with(&expr, &snapshot).unwrap())
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 5.0);
+        }
+    }
+
+    #[test]
+    fn test_updates_visible_to_clones() {
+        let shared = SharedContext::default();
+        let other = shared.clone();
+        let writer = thread::spawn(move || other.set_variable("rate", 0.5));
+        writer.join().unwrap();
+
+        assert_eq!(shared.read().variable("rate"), Some(0.5));
+        // Snapshots are not affected by later writes
+        let snapshot = shared.snapshot();
+        shared.set_variable("rate", 1.0);
+        assert_eq!(snapshot.variable("rate"), Some(0.5));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut ctx = Context::new();
+        ctx.set_variable("x", 1.0);
+        let before = ctx.snapshot();
+
+        ctx.set_variable("x", 2.0);
+        ctx.register_function("f", |_: &[f64]| Ok(3.0));
+        ctx.push_scope();
+        ctx.set_variable("y", 4.0);
+        let what_if = ctx.snapshot();
+        assert_eq!(ctx.remove_variable("y"), Some(Value::Number(4.0)));
+
+        ctx.restore(what_if.clone());
+        assert_eq!(ctx.variable("y"), Some(4.0));
+        ctx.restore(before);
+        assert_eq!((ctx.variable("x"), ctx.variable("y"), ctx.scope_depth()), (Some(1.0), None, 0));
+        assert!(ctx.function("f").is_none());
+
+        // A snapshot is unchanged by the context it came from
+        ctx.restore(what_if.clone());
+        ctx.set_variable("y", 5.0);
+        ctx.restore(what_if);
+        assert_eq!(ctx.variable("y"), Some(4.0));
+    }
+}
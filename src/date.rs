@@ -0,0 +1,311 @@
+// src/date.rs
+// Calendar dates for business-rule formulas, such as `date(2024, 1, 31) + 30` or
+// `(deadline - today()) / 7`. A date plus or minus a number moves it by that many days, and the
+// difference of two dates is the number of days between them. Dates follow the proleptic
+// Gregorian calendar and display (and serialize) as YYYY-MM-DD.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::duration::format_duration;
+use crate::functions::{expect_args, ListBuiltin};
+use crate::{MathError, Operator, Result, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    days: i64, // Since 1970-01-01
+}
+
+impl Date {
+    // The date of a year, month (1 to 12) and day of the month, if it exists
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Date> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+        // Counting years from March, so the leap day comes last
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month_from_march = (month as i64 + 9) % 12;
+        let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        Some(Date {
+            days: era * 146_097 + day_of_era - 719_468,
+        })
+    }
+
+    // The date a number of days after 1970-01-01 (before it when negative)
+    pub fn from_days(days: i64) -> Date {
+        Date { days }
+    }
+
+    // The current date in UTC
+    pub fn today() -> Date {
+        let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        Date {
+            days: seconds.div_euclid(86_400),
+        }
+    }
+
+    // Days since 1970-01-01
+    pub fn days(self) -> i64 {
+        self.days
+    }
+
+    // The year, month (1 to 12) and day of the month
+    pub fn ymd(self) -> (i64, u32, u32) {
+        let days = self.days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+        let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+
+    // The day of the week, from 1 for Monday to 7 for Sunday
+    pub fn weekday(self) -> u32 {
+        // 1970-01-01 was a Thursday
+        ((self.days + 3).rem_euclid(7) + 1) as u32
+    }
+
+    // The date a number of days later, if it can be represented
+    pub fn add_days(self, days: f64) -> Result<Date> {
+        if days.fract() != 0.0 || !days.is_finite() {
+            return Err(not_whole_days(format!("{} days", days)));
+        }
+        let days = self
+            .days
+            .checked_add(days as i64)
+            .filter(|days| days.abs() < MAX_DAYS)
+            .ok_or_else(|| MathError::Overflow(format!("{} + {} days", self, days)))?;
+        Ok(Date { days })
+    }
+}
+
+// The error for moving a date by part of a day
+fn not_whole_days(amount: String) -> MathError {
+    MathError::InvalidArgument {
+        function: "date".to_string(),
+        reason: format!("dates only move by whole days, not {}", amount),
+    }
+}
+
+// A duration as a number of days, if it is whole days
+fn in_days(seconds: f64) -> Result<f64> {
+    if seconds % 86_400.0 != 0.0 {
+        return Err(not_whole_days(format_duration(seconds)));
+    }
+    Ok(seconds / 86_400.0)
+}
+
+// About a million years either side of 1970, far from overflowing the calendar arithmetic
+const MAX_DAYS: i64 = 365_000_000;
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        if year < 0 {
+            write!(f, "-{:04}-{:02}-{:02}", -year, month, day)
+        } else {
+            write!(f, "{:04}-{:02}-{:02}", year, month, day)
+        }
+    }
+}
+
+impl FromStr for Date {
+    type Err = MathError;
+
+    // Reads YYYY-MM-DD
+    fn from_str(text: &str) -> Result<Date> {
+        let invalid = || MathError::InvalidNumber(text.to_string());
+        let (negative, rest) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let mut parts = rest.splitn(3, '-');
+        let mut part = || {
+            let part = parts.next().ok_or_else(invalid)?;
+            match !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) {
+                true => Ok(part),
+                false => Err(invalid()),
+            }
+        };
+        let (year, month, day) = (part()?, part()?, part()?);
+        let year: i64 = year.parse().map_err(|_| invalid())?;
+        let year = if negative { -year } else { year };
+        let month = month.parse().map_err(|_| invalid())?;
+        let day = day.parse().map_err(|_| invalid())?;
+        Date::from_ymd(year, month, day).ok_or_else(invalid)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Date {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Date {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Date, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Applies a binary operator where at least one operand is a date: moving a date by a number of
+// days or a duration of whole days, the days between two dates, and comparing two dates
+pub(crate) fn apply(op: &Operator, left: &Value, right: &Value) -> Result<Value> {
+    let truth = |condition: bool| Ok(Value::Number(if condition { 1.0 } else { 0.0 }));
+    match (op, left, right) {
+        (Operator::Add, Value::Date(date), Value::Number(days)) | (Operator::Add, Value::Number(days), Value::Date(date)) => {
+            date.add_days(*days).map(Value::Date)
+        }
+        (Operator::Subtract, Value::Date(date), Value::Number(days)) => date.add_days(-days).map(Value::Date),
+        (Operator::Add, Value::Date(date), Value::Duration(seconds)) | (Operator::Add, Value::Duration(seconds), Value::Date(date)) => {
+            date.add_days(in_days(*seconds)?).map(Value::Date)
+        }
+        (Operator::Subtract, Value::Date(date), Value::Duration(seconds)) => date.add_days(-in_days(*seconds)?).map(Value::Date),
+        (Operator::Subtract, Value::Date(a), Value::Date(b)) => Ok(Value::Number((a.days - b.days) as f64)),
+        (Operator::Less, Value::Date(a), Value::Date(b)) => truth(a < b),
+        (Operator::LessEqual, Value::Date(a), Value::Date(b)) => truth(a <= b),
+        (Operator::Greater, Value::Date(a), Value::Date(b)) => truth(a > b),
+        (Operator::GreaterEqual, Value::Date(a), Value::Date(b)) => truth(a >= b),
+        (Operator::Equal, Value::Date(a), Value::Date(b)) => truth(a == b),
+        (Operator::NotEqual, Value::Date(a), Value::Date(b)) => truth(a != b),
+        (_, Value::Date(_), _) => Err(mismatch(left)),
+        _ => Err(mismatch(right)),
+    }
+}
+
+fn mismatch(value: &Value) -> MathError {
+    MathError::TypeMismatch {
+        expected: "number".to_string(),
+        found: value.type_name().to_string(),
+    }
+}
+
+// The date functions: date(y, m, d), today(), days_between(a, b), year(d), month(d), day(d) and
+// weekday(d)
+pub(crate) fn builtin(name: &str) -> Option<ListBuiltin> {
+    let function: ListBuiltin = match name {
+        "date" => |args| {
+            expect_args("date", args, 3)?;
+            let part = |value: &Value| {
+                let n = value.to_number()?;
+                if n.fract() != 0.0 || !n.is_finite() {
+                    return Err(MathError::NotAnInteger(n));
+                }
+                Ok(n)
+            };
+            let (year, month, day) = (part(&args[0])?, part(&args[1])?, part(&args[2])?);
+            let date = (year.abs() < 1e6 && (1.0..=31.0).contains(&day))
+                .then(|| Date::from_ymd(year as i64, month.clamp(0.0, 13.0) as u32, day as u32))
+                .flatten();
+            date.map(Value::Date).ok_or_else(|| MathError::InvalidArgument {
+                function: "date".to_string(),
+                reason: format!("{}-{}-{} is not a date", year, month, day),
+            })
+        },
+        "today" => |args| {
+            expect_args("today", args, 0)?;
+            Ok(Value::Date(Date::today()))
+        },
+        "days_between" => |args| {
+            expect_args("days_between", args, 2)?;
+            let (from, to) = (to_date("days_between", &args[0])?, to_date("days_between", &args[1])?);
+            Ok(Value::Number((to.days - from.days) as f64))
+        },
+        "year" => |args| {
+            expect_args("year", args, 1)?;
+            Ok(Value::Number(to_date("year", &args[0])?.ymd().0 as f64))
+        },
+        "month" => |args| {
+            expect_args("month", args, 1)?;
+            Ok(Value::Number(to_date("month", &args[0])?.ymd().1 as f64))
+        },
+        "day" => |args| {
+            expect_args("day", args, 1)?;
+            Ok(Value::Number(to_date("day", &args[0])?.ymd().2 as f64))
+        },
+        "weekday" => |args| {
+            expect_args("weekday", args, 1)?;
+            Ok(Value::Number(to_date("weekday", &args[0])?.weekday() as f64))
+        },
+        _ => return None,
+    };
+    Some(function)
+}
+
+fn to_date(name: &str, value: &Value) -> Result<Date> {
+    match value {
+        Value::Date(date) => Ok(*date),
+        _ => Err(MathError::InvalidArgument {
+            function: name.to_string(),
+            reason: format!("expected a date, got a {}", value.type_name()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Evaluator, Expr};
+
+    #[test]
+    fn test_calendar() {
+        for (year, month, day, days) in [(1970, 1, 1, 0), (2000, 2, 29, 11_016), (1969, 12, 31, -1), (2024, 3, 1, 19_783)] {
+            let date = Date::from_ymd(year, month, day).unwrap();
+            assert_eq!(date.days(), days);
+            assert_eq!(date.ymd(), (year, month, day));
+        }
+        assert!(Date::from_ymd(2023, 2, 29).is_none());
+        assert!(Date::from_ymd(2024, 13, 1).is_none());
+        assert_eq!(Date::from_ymd(2024, 1, 1).unwrap().weekday(), 1);
+        assert_eq!("2024-02-29".parse::<Date>().unwrap().to_string(), "2024-02-29");
+        assert!("2024-2".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn test_date_arithmetic() {
+        let mut ctx = Context::new();
+        ctx.set_variable("deadline", Value::Date(Date::from_ymd(2024, 3, 15).unwrap()));
+        let value = |input: &str| Evaluator::evaluate_value_with(&Expr::parse(input).unwrap(), &ctx);
+        assert_eq!(value("date(2024, 1, 31) + 30").unwrap().to_string(), "2024-03-01");
+        assert_eq!(value("(deadline - date(2024, 3, 1)) / 7").unwrap(), 2.0);
+        assert_eq!(value("days_between(date(2023, 12, 25), deadline)").unwrap(), 81.0);
+        assert_eq!(value("deadline > date(2024, 1, 1) && month(deadline - 15) == 2").unwrap(), 1.0);
+        assert_eq!(value("today() - today()").unwrap(), 0.0);
+        assert!(matches!(value("deadline * 2"), Err(MathError::TypeMismatch { ref found, .. }) if found == "date"));
+        assert!(matches!(value("date(2023, 2, 29)"), Err(MathError::InvalidArgument { .. })));
+        assert_eq!(
+            value("deadline + 0.5").unwrap_err().to_string(),
+            "Invalid argument to date: dates only move by whole days, not 0.5 days"
+        );
+        assert_eq!(
+            value("deadline + 1e20").unwrap_err().to_string(),
+            "Result out of range: 2024-03-15 + 100000000000000000000 days"
+        );
+    }
+}
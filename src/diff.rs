@@ -0,0 +1,268 @@
+// src/diff.rs
+// Structural differences between two versions of a formula, for review UIs that highlight what
+// changed. Spans are byte ranges of each version as it displays (`expr.to_string()`).
+use std::fmt;
+
+use crate::{Expr, Span};
+
+// One change from the old expression to the new one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    // A node kept in place with another operator, function name, number or variable; the spans
+    // cover just that label
+    Update {
+        old: String,
+        new: String,
+        old_span: Span,
+        new_span: Span,
+    },
+    // A subtree replaced by one of another kind
+    Replace {
+        old: Expr,
+        new: Expr,
+        old_span: Span,
+        new_span: Span,
+    },
+    // An argument or list item only in the new expression
+    Insert { new: Expr, new_span: Span },
+    // An argument or list item only in the old expression
+    Remove { old: Expr, old_span: Span },
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edit::Update {
+                old,
+                new,
+                old_span,
+                new_span,
+            } => write!(f, "update {} -> {} at {} -> {}", old, new, old_span, new_span),
+            Edit::Replace {
+                old,
+                new,
+                old_span,
+                new_span,
+            } => write!(f, "replace {} -> {} at {} -> {}", old, new, old_span, new_span),
+            Edit::Insert { new, new_span } => write!(f, "insert {} at {}", new, new_span),
+            Edit::Remove { old, old_span } => write!(f, "remove {} at {}", old, old_span),
+        }
+    }
+}
+
+// The edits turning `old` into `new`, outermost first and left to right. Subtrees with the same
+// normal form are unchanged, so reordering a sum or regrouping a product is not an edit. Arguments
+// and list items are matched up so that adding or removing one does not change the rest.
+pub fn diff(old: &Expr, new: &Expr) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    node(old, 0, new, 0, &mut edits);
+    edits
+}
+
+fn node(old: &Expr, old_start: usize, new: &Expr, new_start: usize, edits: &mut Vec<Edit>) {
+    if old.normal_form() == new.normal_form() {
+        return;
+    }
+    // Kept parentheses are not part of the structure
+    if let Expr::Group(inner) = old {
+        return node(inner, old_start + 1, new, new_start, edits);
+    }
+    if let Expr::Group(inner) = new {
+        return node(old, old_start, inner, new_start + 1, edits);
+    }
+
+    let same_kind = std::mem::discriminant(old) == std::mem::discriminant(new) || (is_number(old) && is_number(new));
+    let (old_children, new_children) = (child_spans(old, old_start), child_spans(new, new_start));
+    let items = matches!((old, new), (Expr::Function { .. }, Expr::Function { .. }) | (Expr::List(_), Expr::List(_)));
+    if !same_kind || (old_children.len() != new_children.len() && !items) {
+        edits.push(Edit::Replace {
+            old: old.clone(),
+            new: new.clone(),
+            old_span: span(old, old_start),
+            new_span: span(new, new_start),
+        });
+        return;
+    }
+
+    if let (Some((old_label, old_span)), Some((new_label, new_span))) = (label(old, old_start), label(new, new_start)) {
+        if old_label != new_label {
+            edits.push(Edit::Update {
+                old: old_label,
+                new: new_label,
+                old_span,
+                new_span,
+            });
+        }
+    }
+    let old_items: Vec<(&Expr, usize)> = old.children().into_iter().zip(old_children.iter().map(|span| span.start)).collect();
+    let new_items: Vec<(&Expr, usize)> = new.children().into_iter().zip(new_children.iter().map(|span| span.start)).collect();
+    if items {
+        align(&old_items, &new_items, edits);
+    } else {
+        for ((old, old_start), (new, new_start)) in old_items.into_iter().zip(new_items) {
+            node(old, old_start, new, new_start, edits);
+        }
+    }
+}
+
+// Diffs lists of arguments or items: those with the same normal form, as many as possible in
+// order, are kept; between them, old and new items are diffed in pairs and the rest removed or
+// inserted
+fn align(old: &[(&Expr, usize)], new: &[(&Expr, usize)], edits: &mut Vec<Edit>) {
+    let old_forms: Vec<Expr> = old.iter().map(|(expr, _)| expr.normal_form()).collect();
+    let new_forms: Vec<Expr> = new.iter().map(|(expr, _)| expr.normal_form()).collect();
+
+    // Longest common subsequence, from the ends
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old_forms[i] == new_forms[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut inserted) = (Vec::new(), Vec::new());
+    loop {
+        let matched = i < old.len() && j < new.len() && old_forms[i] == new_forms[j];
+        if matched || (i == old.len() && j == new.len()) {
+            gap(&removed, &inserted, edits);
+            removed.clear();
+            inserted.clear();
+            if !matched {
+                return;
+            }
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            removed.push(old[i]);
+            i += 1;
+        } else {
+            inserted.push(new[j]);
+            j += 1;
+        }
+    }
+}
+
+fn gap(removed: &[(&Expr, usize)], inserted: &[(&Expr, usize)], edits: &mut Vec<Edit>) {
+    for (&(old, old_start), &(new, new_start)) in removed.iter().zip(inserted) {
+        node(old, old_start, new, new_start, edits);
+    }
+    for &(old, old_start) in removed.iter().skip(inserted.len()) {
+        edits.push(Edit::Remove {
+            old: old.clone(),
+            old_span: span(old, old_start),
+        });
+    }
+    for &(new, new_start) in inserted.iter().skip(removed.len()) {
+        edits.push(Edit::Insert {
+            new: new.clone(),
+            new_span: span(new, new_start),
+        });
+    }
+}
+
+fn is_number(expr: &Expr) -> bool {
+    matches!(expr, Expr::Literal(_) | Expr::Scientific { .. } | Expr::Duration(_) | Expr::Money(_))
+}
+
+fn span(expr: &Expr, start: usize) -> Span {
+    Span::new(start, start + expr.to_string().len())
+}
+
+// Where each child of an expression displaying at `start` displays
+fn child_spans(expr: &Expr, start: usize) -> Vec<Span> {
+    let text = expr.to_string();
+    let mut from = match expr {
+        Expr::Function { name, .. } => name.len() + 1,
+        Expr::Unary { op, .. } if !op.is_postfix() => op.symbol().len(),
+        Expr::UnaryMinus(_) | Expr::List(_) | Expr::Group(_) => 1,
+        _ => 0,
+    };
+    let mut spans = Vec::new();
+    for (i, child) in expr.children().into_iter().enumerate() {
+        // Skip what separates the second operand from the first, which it could appear in
+        let separator = match expr {
+            Expr::BinOp { op, .. } if i == 1 => op.symbol(),
+            Expr::Index { .. } if i == 1 => "[",
+            _ => "",
+        };
+        if !separator.is_empty() {
+            from += text[from..].find(separator).expect("operands are separated") + separator.len();
+        }
+        let shown = child.to_string();
+        let at = from + text[from..].find(&shown).expect("a child displays within its parent");
+        from = at + shown.len();
+        spans.push(Span::new(start + at, start + from));
+    }
+    spans
+}
+
+// What distinguishes a node from others of its kind, and where it displays: its operator,
+// function name, number or variable name
+fn label(expr: &Expr, start: usize) -> Option<(String, Span)> {
+    let whole = span(expr, start);
+    match expr {
+        Expr::Literal(_) | Expr::Scientific { .. } | Expr::Duration(_) | Expr::Money(_) | Expr::Variable(_) | Expr::Text(_) => Some((expr.to_string(), whole)),
+        Expr::Function { name, .. } => Some((name.clone(), Span::new(start, start + name.len()))),
+        Expr::Unary { op, .. } => {
+            let len = op.symbol().len();
+            let at = if op.is_postfix() { whole.end - len } else { start };
+            Some((op.symbol().to_string(), Span::new(at, at + len)))
+        }
+        Expr::BinOp { op, .. } => {
+            let after_lhs = child_spans(expr, start)[0].end;
+            let text = expr.to_string();
+            let at = after_lhs + text[after_lhs - start..].find(op.symbol()).expect("an operation shows its operator");
+            Some((op.symbol().to_string(), Span::new(at, at + op.symbol().len())))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edits(old: &str, new: &str) -> Vec<String> {
+        diff(&Expr::parse(old).unwrap(), &Expr::parse(new).unwrap()).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(
+            edits("price * (1 + rate) - fee", "price * (1 + tax) - fee * 2"),
+            ["update rate -> tax at 13..17 -> 13..16", "replace fee -> fee * 2 at 21..24 -> 20..27"]
+        );
+        assert_eq!(edits("x + y", "x - y"), ["update + -> - at 2..3 -> 2..3"]);
+        assert_eq!(edits("x xor o", "x xor r"), ["update o -> r at 6..7 -> 6..7"]);
+        // Rewrites that mean the same are not edits
+        assert_eq!(edits("(b + a) * 2", "(a + b) * 3"), ["update 2 -> 3 at 10..11 -> 10..11"]);
+        assert_eq!(edits("x * 1e3", "x * 1000"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_diff_arguments() {
+        assert_eq!(
+            edits("max(a, b, c)", "max(a, c, d + 1)"),
+            ["remove b at 7..8", "insert d + 1 at 10..15"]
+        );
+        assert_eq!(edits("min(a)", "max(x, a)"), ["update min -> max at 0..3 -> 0..3", "insert x at 4..5"]);
+        assert_eq!(edits("[1, 2, 3]", "[1, 5, 3, 4]"), ["update 2 -> 5 at 4..5 -> 4..5", "insert 4 at 10..11"]);
+        // Spans are of the displays
+        let old = Expr::parse("sum([a, b]) - -c").unwrap();
+        let new = Expr::parse("sum([a, b, c]) - -d").unwrap();
+        let text = new.to_string();
+        let spans: Vec<Span> = diff(&old, &new)
+            .into_iter()
+            .map(|edit| match edit {
+                Edit::Insert { new_span, .. } | Edit::Update { new_span, .. } => new_span,
+                edit => panic!("unexpected {}", edit),
+            })
+            .collect();
+        assert_eq!(spans.iter().map(|span| &text[span.start..span.end]).collect::<Vec<_>>(), ["c", "d"]);
+    }
+}
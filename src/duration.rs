@@ -0,0 +1,200 @@
+// src/duration.rs
+// Lengths of time, written as literals of days, hours, minutes and seconds in that order, such as
+// `45s`, `2d`, `1.5h` or `1h30m`, when `ParserBuilder::durations` is on. Durations add to and subtract from each other and from dates
+// (whole days only), scale by numbers, and divide into each other to give a plain number.
+// `days(x)`, `hours(x)`, `minutes(x)` and `seconds(x)` convert both ways: a duration to a number
+// of those units, and a number of those units to a duration.
+use crate::functions::{expect_args, ListBuiltin};
+use crate::{MathError, Operator, Result, Token, Tokenizer, TokenizerOptions, Value};
+
+// The units of a duration literal, largest first, with their length in seconds
+pub(crate) const UNITS: [(char, f64); 4] = [('d', 86_400.0), ('h', 3_600.0), ('m', 60.0), ('s', 1.0)];
+
+// The position of a unit in `UNITS`, if the character is one
+pub(crate) fn unit(ch: char) -> Option<usize> {
+    UNITS.iter().position(|(symbol, _)| *symbol == ch)
+}
+
+// A number of seconds as a duration literal: whole seconds as days, hours, minutes and seconds
+// (5400 => 1h30m), anything else as seconds (1.5 => 1.5s)
+pub fn format_duration(seconds: f64) -> String {
+    if seconds < 0.0 {
+        return format!("-{}", format_duration(-seconds));
+    }
+    if seconds.fract() != 0.0 || !seconds.is_finite() || seconds == 0.0 || seconds >= 1e15 {
+        return format!("{}s", seconds);
+    }
+    let mut rest = seconds;
+    let mut text = String::new();
+    for (symbol, length) in UNITS {
+        let count = (rest / length).floor();
+        if count > 0.0 {
+            text.push_str(&format!("{}{}", count, symbol));
+            rest -= count * length;
+        }
+    }
+    text
+}
+
+// Reads a duration literal such as "1h30m" (or "-1h30m") as a number of seconds
+pub fn parse_duration(text: &str) -> Result<f64> {
+    let (negative, literal) = match text.strip_prefix('-') {
+        Some(literal) => (true, literal),
+        None => (false, text),
+    };
+    let options = TokenizerOptions {
+        durations: true,
+        ..TokenizerOptions::default()
+    };
+    match Tokenizer::tokenize_with(literal, options)?.as_slice() {
+        [Token::Duration(seconds)] if negative => Ok(-seconds),
+        [Token::Duration(seconds)] => Ok(*seconds),
+        _ => Err(MathError::InvalidNumber(text.to_string())),
+    }
+}
+
+// Serializes the seconds of a duration value as its literal, to tell it from a number
+#[cfg(feature = "serde")]
+pub(crate) mod literal {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seconds: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_duration(*seconds))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::parse_duration(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+// Applies a binary operator where at least one operand is a duration and neither is a date
+pub(crate) fn apply(op: &Operator, left: &Value, right: &Value) -> Result<Value> {
+    let truth = |condition: bool| Ok(Value::Number(if condition { 1.0 } else { 0.0 }));
+    match (op, left, right) {
+        (Operator::Add, Value::Duration(a), Value::Duration(b)) => Ok(Value::Duration(a + b)),
+        (Operator::Subtract, Value::Duration(a), Value::Duration(b)) => Ok(Value::Duration(a - b)),
+        (Operator::Multiply, Value::Duration(a), Value::Number(n)) | (Operator::Multiply, Value::Number(n), Value::Duration(a)) => {
+            Ok(Value::Duration(a * n))
+        }
+        (Operator::Divide, Value::Duration(a), Value::Number(n)) => divide(*a, *n).map(Value::Duration),
+        (Operator::Divide, Value::Duration(a), Value::Duration(b)) => divide(*a, *b).map(Value::Number),
+        (Operator::Less, Value::Duration(a), Value::Duration(b)) => truth(a < b),
+        (Operator::LessEqual, Value::Duration(a), Value::Duration(b)) => truth(a <= b),
+        (Operator::Greater, Value::Duration(a), Value::Duration(b)) => truth(a > b),
+        (Operator::GreaterEqual, Value::Duration(a), Value::Duration(b)) => truth(a >= b),
+        (Operator::Equal, Value::Duration(a), Value::Duration(b)) => truth(a == b),
+        (Operator::NotEqual, Value::Duration(a), Value::Duration(b)) => truth(a != b),
+        // Adding to or comparing with a duration needs another one; anything else needs numbers
+        _ => {
+            let (duration, other) = if matches!(left, Value::Duration(_)) { (left, right) } else { (right, left) };
+            let additive = matches!(op, Operator::Add | Operator::Subtract) || op.is_comparison();
+            if additive && !matches!(other, Value::Duration(_)) {
+                Err(mismatch("duration", other))
+            } else {
+                Err(mismatch("number", duration))
+            }
+        }
+    }
+}
+
+fn divide(a: f64, b: f64) -> Result<f64> {
+    if b == 0.0 {
+        return Err(MathError::DivisionByZero);
+    }
+    Ok(a / b)
+}
+
+fn mismatch(expected: &str, value: &Value) -> MathError {
+    MathError::TypeMismatch {
+        expected: expected.to_string(),
+        found: value.type_name().to_string(),
+    }
+}
+
+// The conversions: days(x), hours(x), minutes(x) and seconds(x)
+pub(crate) fn builtin(name: &str) -> Option<ListBuiltin> {
+    let function: ListBuiltin = match name {
+        "days" => |args| convert("days", args, 86_400.0),
+        "hours" => |args| convert("hours", args, 3_600.0),
+        "minutes" => |args| convert("minutes", args, 60.0),
+        "seconds" => |args| convert("seconds", args, 1.0),
+        _ => return None,
+    };
+    Some(function)
+}
+
+// A duration in units of `length` seconds, or that many units as a duration
+fn convert(name: &str, args: &[Value], length: f64) -> Result<Value> {
+    expect_args(name, args, 1)?;
+    match &args[0] {
+        Value::Duration(seconds) => Ok(Value::Number(seconds / length)),
+        Value::Number(count) => Ok(Value::Duration(count * length)),
+        value => Err(MathError::InvalidArgument {
+            function: name.to_string(),
+            reason: format!("expected a duration or a number, got a {}", value.type_name()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Date, Evaluator, Grammar, ImplicitMultiplication, ParserBuilder};
+
+    fn grammar() -> Grammar {
+        ParserBuilder::new().durations(true).build()
+    }
+
+    #[test]
+    fn test_literals() {
+        let tokens = Tokenizer::tokenize_with("1h30m + 45s", grammar().tokenizer_options().clone()).unwrap();
+        assert_eq!(tokens[0], Token::Duration(5_400.0));
+        assert_eq!(parse_duration("2d").unwrap(), 172_800.0);
+        assert_eq!(parse_duration("-1.5h").unwrap(), -5_400.0);
+        for text in ["1h30m", "2d", "45s", "1d1s", "1.5s", "-3m", "0s"] {
+            assert_eq!(format_duration(parse_duration(text).unwrap()), text);
+        }
+        for text in ["1m1h", "1h1h", "1h30", "2min", "1e3s"] {
+            assert!(parse_duration(text).is_err(), "{}", text);
+        }
+        assert_eq!(grammar().parse("2 * 1h30m").unwrap().to_string(), "2 * 1h30m");
+        // Off by default, so a number before m, s, h or d multiplies a variable
+        let implicit = ParserBuilder::new()
+            .implicit_multiplication(ImplicitMultiplication {
+                number_variable: true,
+                ..ImplicitMultiplication::default()
+            })
+            .build();
+        assert_eq!(implicit.parse("2m").unwrap().to_string(), "2 * m");
+        assert_eq!(Tokenizer::tokenize("2s").unwrap(), [Token::Number(2.0), Token::Identifier("s".to_string())]);
+    }
+
+    #[test]
+    fn test_duration_arithmetic() {
+        let mut ctx = Context::new();
+        ctx.set_variable("start", Value::Date(Date::from_ymd(2024, 1, 31).unwrap()));
+        let value = |input: &str| Evaluator::evaluate_value_with(&grammar().parse(input).unwrap(), &ctx);
+        assert_eq!(value("1h30m + 45s").unwrap().to_string(), "1h30m45s");
+        assert_eq!(value("-(2 * 1h30m) / 4").unwrap(), Value::Duration(-2_700.0));
+        assert_eq!(value("1d / 1h").unwrap(), 24.0);
+        assert_eq!(value("hours(90m) + minutes(1.5h)").unwrap(), 91.5);
+        assert_eq!(value("hours(2) == 120m").unwrap(), 1.0);
+        assert_eq!(value("start + 30d").unwrap().to_string(), "2024-03-01");
+        assert_eq!(value("start - 1d").unwrap().to_string(), "2024-01-30");
+        assert_eq!(
+            value("start + 1h").unwrap_err().to_string(),
+            "Invalid argument to date: dates only move by whole days, not 1h"
+        );
+        assert!(matches!(value("1h + 1"), Err(MathError::TypeMismatch { ref found, .. }) if found == "number"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let values = Value::List(vec![Value::Duration(5_400.0), Value::Number(2.0), Value::Date(Date::from_days(0))]);
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(json, r#"["1h30m",2.0,"1970-01-01"]"#);
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), values);
+    }
+}
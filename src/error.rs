@@ -1,15 +1,29 @@
 // src/error.rs
 
 use thiserror::Error;
-use crate::token::Token;
+use crate::token::{Span, Token};
+use crate::Expr;
 
 #[derive(Error, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MathError {
     #[error("Invalid token: {0}")]
     UnexpectedToken(Token),
 
-    #[error("Unmatched parenthesis")]
-    UnmatchedParenthesis,
+    #[error("Unexpected input after the expression: {leftover}")]
+    TrailingInput {
+        leftover: String,   // The unparsed tokens
+        span: Option<Span>, // Where they are in the input, when the parser was given spans
+    },
+
+    #[error("Unmatched '{paren}' ({opened} opened, {closed} closed)")]
+    UnmatchedParenthesis {
+        paren: char,        // '(' or ')'
+        token: usize,       // Index of the unmatched parenthesis among the tokens
+        span: Option<Span>, // Where it is in the input, when the parser was given spans
+        opened: usize,      // Number of '(' in the input
+        closed: usize,      // Number of ')' in the input
+    },
 
     #[error("Invalid number format: {0}")]
     InvalidNumber(String),
@@ -19,6 +33,136 @@ pub enum MathError {
 
     #[error("Invalid operator: {0}")]
     InvalidExpression(String),
+
+    #[error("Unknown variable: {0}")]
+    UnknownVariable(String),
+
+    #[error("Unknown operator: {0}")]
+    UnknownOperator(String),
+
+    #[error("Unknown function: {0}")]
+    UnknownFunction(String),
+
+    #[error("Unknown unit: {0}")]
+    UnknownUnit(String),
+
+    #[error("Dimension mismatch in {operation}: {left} and {right}")]
+    DimensionMismatch { operation: String, left: String, right: String },
+
+    #[error("{function} expects {expected} argument(s), got {found}")]
+    ArgumentCount {
+        function: String,
+        expected: String,
+        found: usize,
+    },
+
+    #[error("Invalid argument to {function}: {reason}")]
+    InvalidArgument { function: String, reason: String },
+
+    #[error("Expected an integer, got {0}")]
+    NotAnInteger(f64),
+
+    #[error("Shift amount out of range: {0}")]
+    InvalidShift(i64),
+
+    #[error("Result out of range: {0}")]
+    Overflow(String), // The operation that overflowed, such as "1 << 63"
+
+    #[error("Type mismatch: expected a {expected}, found a {found}")]
+    TypeMismatch { expected: String, found: String },
+
+    #[error("Shape mismatch: {0}")]
+    ShapeMismatch(String),
+
+    #[error("Index {index} out of range for a list of length {len}")]
+    IndexOutOfRange { index: i64, len: usize },
+
+    #[error("Expression is nested more than {0} levels deep")]
+    TooDeep(usize),
+
+    #[error("Invalid encoded expression: {0}")]
+    InvalidEncoding(String),
+
+    // An error in a file of formulas, on the given line (counted from 1)
+    #[error("line {line}: {error}")]
+    Line { line: usize, error: Box<MathError> },
+
+    // An error in a file loaded with `load_file` or one it includes
+    #[error("{path}: {error}")]
+    File { path: String, error: Box<MathError> },
+
+    #[error("Cannot read {path}: {reason}")]
+    Io { path: String, reason: String },
+
+    #[error("{0} includes itself")]
+    IncludeCycle(String),
+
+    #[error("Placeholder {{{0}}} is not filled")]
+    UnfilledPlaceholder(String),
+
+    #[error("Template has no placeholder {{{0}}}")]
+    UnknownPlaceholder(String),
+
+    #[error("Evaluation took longer than {0:?}")]
+    Timeout(std::time::Duration),
+
+    #[error("Evaluation took more than {0} operations")]
+    OperationLimit(u64),
+
+    #[error("Non-finite result {value} from: {expr}")]
+    NotFinite { value: f64, expr: Expr },
+
+    #[error("No exchange rate from {from} to {to}")]
+    UnknownRate { from: String, to: String },
+}
+
+impl MathError {
+    // Where in the input the error is, when the parser was given spans. For an error on a line of
+    // a file, where in that line.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            MathError::TrailingInput { span, .. } | MathError::UnmatchedParenthesis { span, .. } => *span,
+            MathError::Line { error, .. } | MathError::File { error, .. } => error.span(),
+            _ => None,
+        }
+    }
+
+    // A stable name for the kind of error, for logs and metrics (e.g., "division_by_zero"). An
+    // error on a line of a file has the kind of the error there.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MathError::UnexpectedToken(_) => "unexpected_token",
+            MathError::TrailingInput { .. } => "trailing_input",
+            MathError::UnmatchedParenthesis { .. } => "unmatched_parenthesis",
+            MathError::InvalidNumber(_) => "invalid_number",
+            MathError::DivisionByZero => "division_by_zero",
+            MathError::InvalidExpression(_) => "invalid_expression",
+            MathError::UnknownVariable(_) => "unknown_variable",
+            MathError::UnknownOperator(_) => "unknown_operator",
+            MathError::UnknownFunction(_) => "unknown_function",
+            MathError::UnknownUnit(_) => "unknown_unit",
+            MathError::DimensionMismatch { .. } => "dimension_mismatch",
+            MathError::ArgumentCount { .. } => "argument_count",
+            MathError::InvalidArgument { .. } => "invalid_argument",
+            MathError::NotAnInteger(_) => "not_an_integer",
+            MathError::InvalidShift(_) => "invalid_shift",
+            MathError::Overflow(_) => "overflow",
+            MathError::TypeMismatch { .. } => "type_mismatch",
+            MathError::ShapeMismatch(_) => "shape_mismatch",
+            MathError::IndexOutOfRange { .. } => "index_out_of_range",
+            MathError::TooDeep(_) => "too_deep",
+            MathError::InvalidEncoding(_) => "invalid_encoding",
+            MathError::Line { error, .. } | MathError::File { error, .. } => error.kind(),
+            MathError::Io { .. } => "io",
+            MathError::IncludeCycle(_) => "include_cycle",
+            MathError::UnfilledPlaceholder(_) => "unfilled_placeholder",
+            MathError::UnknownPlaceholder(_) => "unknown_placeholder",
+            MathError::Timeout(_) => "timeout",
+            MathError::OperationLimit(_) => "operation_limit",
+            MathError::NotFinite { .. } => "not_finite",
+            MathError::UnknownRate { .. } => "unknown_rate",
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, MathError>;
@@ -1,53 +1,713 @@
 // src/evaluator.rs
-use crate::{Expr, MathError, Operator, Result};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
 
-pub struct Evaluator;
+use crate::{functions, Context, Expr, MathError, Operator, Quantity, Result, Statement, UnaryOperator, Value};
+use crate::date;
+use crate::duration;
+use crate::matrix;
+use crate::money;
+use crate::solver;
+use crate::trace;
+use crate::units;
+use crate::value::position;
+
+// Evaluates expressions with a set of policies: build one with `Evaluator::builder()` and reuse it,
+// or call the associated functions (`Evaluator::evaluate_with`, ...) that take the policies each time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Evaluator {
+    options: EvalOptions,
+}
+
+// Collects the policies of an evaluator
+#[derive(Debug, Clone, Default)]
+pub struct EvaluatorBuilder {
+    options: EvalOptions,
+}
+
+impl EvaluatorBuilder {
+    // Starts from the default policies
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces all policies at once
+    pub fn options(mut self, options: EvalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // What dividing by zero does (see `DivisionByZero`)
+    pub fn div_by_zero(mut self, policy: DivisionByZero) -> Self {
+        self.options.division_by_zero = policy;
+        self
+    }
+
+    // Reject NaN and infinite results (see `EvalOptions::strict`)
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.options.strict = enabled;
+        self
+    }
+
+    // Bit-identical results on every platform (see `EvalOptions::deterministic`)
+    pub fn deterministic(mut self, enabled: bool) -> Self {
+        self.options.deterministic = enabled;
+        self
+    }
+
+    // How bitwise operators treat operands that are not integers
+    pub fn non_integer(mut self, policy: NonInteger) -> Self {
+        self.options.non_integer = policy;
+        self
+    }
+
+    // How `*` combines two lists
+    pub fn matrix_product(mut self, product: MatrixProduct) -> Self {
+        self.options.matrix_product = product;
+        self
+    }
+
+    // Unit names as quantities (see `EvalOptions::units`)
+    pub fn units(mut self, enabled: bool) -> Self {
+        self.options.units = enabled;
+        self
+    }
+
+    // What `a + b%` and `a - b%` mean (see `Percentage`)
+    pub fn percentage(mut self, percentage: Percentage) -> Self {
+        self.options.percentage = percentage;
+        self
+    }
+
+    // Abort evaluations that take longer (see `EvalOptions::max_duration`)
+    pub fn max_duration(mut self, limit: Duration) -> Self {
+        self.options.max_duration = Some(limit);
+        self
+    }
+
+    pub fn build(self) -> Evaluator {
+        Evaluator { options: self.options }
+    }
+}
+
+// What to do when dividing by zero
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DivisionByZero {
+    // Abort evaluation with MathError::DivisionByZero
+    #[default]
+    Error,
+    // Follow IEEE 754 semantics: x/0 is ±inf and 0/0 is NaN
+    Infinity,
+    // Replace the quotient with the given value
+    Default(f64),
+}
+
+// What bitwise operators do with operands that are not integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NonInteger {
+    // Abort evaluation with MathError::NotAnInteger
+    #[default]
+    Error,
+    // Drop the fractional part (2.7 => 2, -2.7 => -2)
+    Truncate,
+}
+
+// What `*` does when both operands are lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatrixProduct {
+    // Matrix multiplication; two vectors give their dot product
+    #[default]
+    Matrix,
+    // Multiply matching elements, like the other arithmetic operators
+    Elementwise,
+}
+
+// What a percentage added to or subtracted from something means
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Percentage {
+    // x% is always x / 100, so 200 + 10% is 200.1
+    #[default]
+    Fraction,
+    // Like a desk calculator, `a + b%` and `a - b%` add or take off b percent of a, so
+    // 200 + 10% is 220 and 200 - 10% is 180. Elsewhere x% is still x / 100 (200 * 10% is 20).
+    OfLeft,
+}
+
+// A condition holds when it is non-zero (NaN counts as false)
+fn is_true(value: f64) -> bool {
+    value != 0.0 && !value.is_nan()
+}
+
+fn truth(condition: bool) -> f64 {
+    if condition { 1.0 } else { 0.0 }
+}
+
+// Policies controlling how expressions are evaluated
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct EvalOptions {
+    pub division_by_zero: DivisionByZero,
+    // Reject any NaN or ±inf intermediate result with MathError::NotFinite
+    pub strict: bool,
+    // Produce bit-identical results on every platform: powers use the portable libm routines
+    // instead of the platform's, and NaN results are canonicalized. Context functions are
+    // responsible for their own determinism.
+    pub deterministic: bool,
+    // How bitwise operators (&, |, xor, <<, >>, ~) treat non-integer operands
+    pub non_integer: NonInteger,
+    // How `*` combines two lists (vectors and matrices)
+    pub matrix_product: MatrixProduct,
+    // What `a + b%` and `a - b%` mean
+    pub percentage: Percentage,
+    // Read unit names that are not variables as one of that unit (3 km is 3000 m), and keep track
+    // of dimensions: adding a length to a time fails with MathError::DimensionMismatch
+    pub units: bool,
+    // Abort with MathError::Timeout once evaluating has taken this long. Evaluations started by
+    // context functions along the way count toward it and stop with it.
+    pub max_duration: Option<Duration>,
+}
+
+thread_local! {
+    // When the evaluation running on this thread must finish, and the limit it was given. Kept per
+    // thread rather than passed down so evaluations nested in context functions share it.
+    static DEADLINE: Cell<Option<(Instant, Duration)>> = const { Cell::new(None) };
+}
+
+// Sets the deadline of an evaluation with a `max_duration` while it runs, keeping an earlier one
+// of an evaluation around it
+pub(crate) struct Deadline {
+    previous: Option<(Instant, Duration)>,
+}
+
+impl Deadline {
+    fn start(limit: Duration) -> Self {
+        let previous = DEADLINE.get();
+        let deadline = Instant::now().checked_add(limit).map(|at| (at, limit));
+        let earliest = match (previous, deadline) {
+            (Some(previous), Some(deadline)) if previous.0 <= deadline.0 => Some(previous),
+            (previous, None) => previous,
+            (_, deadline) => deadline,
+        };
+        DEADLINE.set(earliest);
+        Self { previous }
+    }
+
+    // Fails once the running evaluation is past its deadline. Built-in functions with long loops
+    // call this too, since the evaluator only checks between nodes.
+    pub(crate) fn check() -> Result<()> {
+        match DEADLINE.get() {
+            Some((at, limit)) if Instant::now() >= at => Err(MathError::Timeout(limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        DEADLINE.set(self.previous);
+    }
+}
 
 impl Evaluator {
+    // An evaluator with the default policies
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builder() -> EvaluatorBuilder {
+        EvaluatorBuilder::new()
+    }
+
+    pub fn options(&self) -> &EvalOptions {
+        &self.options
+    }
+
+    // Evaluates an expression that must produce a number against the context
+    pub fn eval(&self, expr: &Expr, ctx: &Context) -> Result<f64> {
+        Self::evaluate_with_options(expr, ctx, &self.options)
+    }
+
+    // Evaluates an expression that may produce a list against the context
+    pub fn eval_value(&self, expr: &Expr, ctx: &Context) -> Result<Value> {
+        Self::evaluate_value_with_options(expr, ctx, &self.options)
+    }
+
+    // Executes a statement, storing assignments in the context
+    pub fn run(&self, statement: &Statement, ctx: &mut Context) -> Result<Value> {
+        Self::execute_with_options(statement, ctx, &self.options)
+    }
+
+    // Executes statements in order, returning each one's value
+    pub fn run_all(&self, statements: &[Statement], ctx: &mut Context) -> Result<Vec<Value>> {
+        statements.iter().map(|statement| self.run(statement, ctx)).collect()
+    }
+
     // Evaluates an expression tree to produce final result
     pub fn evaluate(expr: &Expr) -> Result<f64> {
+        Self::evaluate_with(expr, &Context::new())
+    }
+
+    // Evaluates an expression tree, resolving variables and functions from the given context
+    pub fn evaluate_with(expr: &Expr, ctx: &Context) -> Result<f64> {
+        Self::evaluate_with_options(expr, ctx, &EvalOptions::default())
+    }
+
+    // Evaluates an expression tree that may produce a list
+    pub fn evaluate_value(expr: &Expr) -> Result<Value> {
+        Self::evaluate_value_with(expr, &Context::new())
+    }
+
+    // Evaluates an expression tree that may produce a list against the given context
+    pub fn evaluate_value_with(expr: &Expr, ctx: &Context) -> Result<Value> {
+        Self::evaluate_value_with_options(expr, ctx, &EvalOptions::default())
+    }
+
+    // Executes a statement, storing assignments in the context. Returns the statement's value.
+    pub fn execute(statement: &Statement, ctx: &mut Context) -> Result<Value> {
+        Self::execute_with_options(statement, ctx, &EvalOptions::default())
+    }
+
+    // Executes a statement using the given policies
+    pub fn execute_with_options(statement: &Statement, ctx: &mut Context, options: &EvalOptions) -> Result<Value> {
+        match statement {
+            Statement::Expr(expr) => Self::evaluate_value_with_options(expr, ctx, options),
+            Statement::Assign { name, value } => {
+                let value = Self::evaluate_value_with_options(value, ctx, options)?;
+                ctx.set_variable(name.clone(), value.clone());
+                Ok(value)
+            }
+        }
+    }
+
+    // Executes statements in order, returning each one's value
+    pub fn execute_all(statements: &[Statement], ctx: &mut Context) -> Result<Vec<Value>> {
+        statements
+            .iter()
+            .map(|statement| Self::execute(statement, ctx))
+            .collect()
+    }
+
+    // Evaluates an expression tree against a context using the given policies. The result
+    // must be a number; use `evaluate_value_with_options` for expressions producing lists.
+    pub fn evaluate_with_options(expr: &Expr, ctx: &Context, options: &EvalOptions) -> Result<f64> {
+        Self::evaluate_value_with_options(expr, ctx, options)?.to_number()
+    }
+
+    // Evaluates an expression tree that may produce a list, using the given policies
+    pub fn evaluate_value_with_options(expr: &Expr, ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        let _deadline = options.max_duration.map(Deadline::start);
+        let Some(metrics) = ctx.metrics() else {
+            return trace::evaluate(expr, || Self::value(expr, ctx, options));
+        };
+        let start = Instant::now();
+        let result = trace::evaluate(expr, || Self::value(expr, ctx, options));
+        metrics.evaluated(start.elapsed(), result.as_ref().err().map(MathError::kind));
+        result
+    }
+
+    // Evaluates an expression or one of its children, checking the result against the policies
+    fn value(expr: &Expr, ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        let value = Self::evaluate_node(expr, ctx, options)?;
+        if !options.deterministic && !options.strict {
+            return Ok(value);
+        }
+        let check = |mut value: f64| {
+            if options.deterministic && value.is_nan() {
+                value = f64::NAN;
+            }
+            if options.strict && !value.is_finite() {
+                return Err(MathError::NotFinite {
+                    value,
+                    expr: expr.clone(),
+                });
+            }
+            Ok(value)
+        };
+        match value {
+            // Every element of a list result is checked, including ones computed by broadcasting
+            Value::Number(_) | Value::List(_) => matrix::map(&value, &check),
+            Value::Quantity(quantity) => {
+                Ok(Value::Quantity(Quantity::new(check(quantity.value)?, quantity.dimensions)))
+            }
+            // Dates, durations and money are never NaN or infinite
+            _ => Ok(value),
+        }
+    }
+
+    // Evaluates a child expression that must produce a number
+    fn number(expr: &Expr, ctx: &Context, options: &EvalOptions) -> Result<f64> {
+        Self::value(expr, ctx, options)?.to_number()
+    }
+
+    // Evaluates a single node, recursing through `value` for its children.
+    // Checks the deadline first, and tells the context's observer.
+    fn evaluate_node(expr: &Expr, ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        Deadline::check()?;
+        let Some(observer) = ctx.observer() else {
+            return Self::node_value(expr, ctx, options);
+        };
+        observer.enter(expr)?;
+        let start = Instant::now();
+        let result = Self::node_value(expr, ctx, options);
+        observer.exit(expr, start.elapsed(), &result);
+        result
+    }
+
+    // The value of a node, without the checks and hooks around it
+    fn node_value(expr: &Expr, ctx: &Context, options: &EvalOptions) -> Result<Value> {
         match expr {
             // Return the literal value
-            Expr::Literal(value) => Ok(*value),
+            Expr::Literal(value) => Ok(Value::Number(*value)),
+
+            Expr::Duration(seconds) => Ok(Value::Duration(*seconds)),
+
+            Expr::Money(money) => Ok(Value::Money(money.clone())),
 
             // Evaluate the base value multiplied by 10 raised to the power of the exponent
-            Expr::Scientific { base, exponent } => Ok(base * (10f64.powi(*exponent))),
+            Expr::Scientific { base, exponent } => {
+                if options.deterministic {
+                    // Parsing is correctly rounded, so it gives the same bits everywhere
+                    format!("{}e{}", base, exponent)
+                        .parse::<f64>()
+                        .map(Value::Number)
+                        .map_err(|_| MathError::InvalidNumber(expr.to_string()))
+                } else {
+                    Ok(Value::Number(base * (10f64.powi(*exponent))))
+                }
+            }
 
             // Evaluate the expression inside the parentheses and return the result
             // Expr::Parenthesized(expr) => Self::evaluate(expr),
             Expr::UnaryMinus(expr) => {
-                let value = Self::evaluate(expr)?;
-                Ok(-value)
+                match Self::value(expr, ctx, options)? {
+                    Value::Duration(seconds) => Ok(Value::Duration(-seconds)),
+                    Value::Money(money) => Ok(Value::Money(-money)),
+                    value => matrix::map(&value, &|value| Ok(-value)),
+                }
             }
 
             // Evaluate the left and right expressions and apply the operator
             Expr::BinOp { op, lhs, rhs } => {
-                let left = Self::evaluate(lhs)?;
-                let right = Self::evaluate(rhs)?;
-
-                match op {
-                    // Apply the operator to the left and right values
-                    Operator::Add => Ok(left + right),
-                    Operator::Subtract => Ok(left - right),
-                    Operator::Multiply => Ok(left * right),
-                    Operator::Divide => {
-                        if right == 0.0 {
-                            Err(MathError::DivisionByZero)
-                        } else {
-                            Ok(left / right)
-                        }
+                let left = Self::value(lhs, ctx, options)?;
+                // && and || skip their right operand once a scalar left operand decides the result
+                match (op, &left) {
+                    (Operator::And, Value::Number(l)) if !is_true(*l) => return Ok(Value::Number(0.0)),
+                    (Operator::Or, Value::Number(l)) if is_true(*l) => return Ok(Value::Number(1.0)),
+                    _ => {}
+                }
+                // a + b% and a - b% as a calculator reads them: b percent of a, worked out as a * b / 100
+                let percent = match &**rhs {
+                    Expr::Unary { op: UnaryOperator::Percent, operand } => Some(operand),
+                    _ => None,
+                };
+                if let (Operator::Add | Operator::Subtract, Some(operand)) = (op, percent) {
+                    if options.percentage == Percentage::OfLeft {
+                        let rate = Self::value(operand, ctx, options)?;
+                        let part = Self::combine(&Operator::Multiply, &left, &rate, ctx, options)?;
+                        let part = Self::combine(&Operator::Divide, &part, &Value::Number(100.0), ctx, options)?;
+                        return Self::combine(op, &left, &part, ctx, options);
+                    }
+                }
+                let right = Self::value(rhs, ctx, options)?;
+                Self::combine(op, &left, &right, ctx, options)
+            }
+
+            // Evaluate the operand and apply the prefix operator
+            Expr::Unary { op, operand } => {
+                let value = Self::value(operand, ctx, options)?;
+                let apply = |value: f64| match op {
+                    UnaryOperator::BitNot => Ok(!Self::to_integer(value, options)? as f64),
+                    UnaryOperator::Percent => Ok(value / 100.0),
+                    UnaryOperator::Sqrt => Ok(value.sqrt()),
+                    UnaryOperator::Abs => Ok(value.abs()),
+                    UnaryOperator::Not => Ok(truth(!is_true(value))),
+                    UnaryOperator::Custom(custom) => match ctx.operators().unary_function(&custom.symbol, custom.fixity) {
+                        Some(function) => function(value),
+                        None => Err(MathError::UnknownOperator(custom.symbol.clone())),
+                    },
+                };
+                match &value {
+                    Value::Quantity(quantity) => units::unary(op, quantity, apply),
+                    _ => matrix::map(&value, &apply),
+                }
+            }
+
+            // A quoted name only means something to the function it is passed to
+            Expr::Text(_) => Err(MathError::TypeMismatch {
+                expected: "number".to_string(),
+                found: "quoted name".to_string(),
+            }),
+
+            // Look up the variable in the context, then among the units when they are enabled
+            Expr::Variable(name) => ctx
+                .value(name)
+                .or_else(|| functions::constant(name).map(Value::Number))
+                .or_else(|| options.units.then(|| ctx.units().quantity(name)).flatten())
+                .ok_or_else(|| MathError::UnknownVariable(name.clone())),
+
+            // Evaluate each element in order
+            Expr::List(items) => items
+                .iter()
+                .map(|item| Self::value(item, ctx, options))
+                .collect::<Result<Vec<Value>>>()
+                .map(Value::List),
+
+            // Read one element of a list
+            Expr::Index { target, index } => {
+                let target = Self::value(target, ctx, options)?;
+                let items = target.to_list()?;
+                let index = Self::number(index, ctx, options)?;
+                Ok(items[position(index, items.len())?].clone())
+            }
+
+            Expr::Group(expr) => Self::value(expr, ctx, options),
+
+            // A placeholder left by a recovering parse
+            Expr::Error => Err(MathError::InvalidExpression("Cannot evaluate an incomplete expression".to_string())),
+
+            // Evaluate the arguments and call the context function, falling back to the built-ins
+            Expr::Function { name, args } => {
+                if ctx.function(name).is_none() {
+                    if let Some(result) = Self::special_form(name, args, ctx, options) {
+                        return result;
+                    }
+                }
+                let values = args
+                    .iter()
+                    .map(|arg| Self::value(arg, ctx, options))
+                    .collect::<Result<Vec<Value>>>()?;
+                let number = |value: &Value| match value {
+                    Value::Quantity(quantity) => Err(units::not_dimensionless(name, quantity)),
+                    value => value.to_number(),
+                };
+                let numbers = || values.iter().map(number).collect::<Result<Vec<f64>>>();
+                if let Some(function) = ctx.function(name) {
+                    function(&numbers()?).map(Value::Number)
+                } else if name == "mod" && values.len() == 2 {
+                    // Divides like `%`, so it follows the division-by-zero policy
+                    let numbers = numbers()?;
+                    Self::divide(numbers[0], numbers[1], options, functions::floored_mod).map(Value::Number)
+                } else if let Some(function) = functions::builtin(name) {
+                    function(&numbers()?).map(Value::Number)
+                } else if let Some(function) = functions::list_builtin(name)
+                    .or_else(|| date::builtin(name))
+                    .or_else(|| duration::builtin(name)) {
+                    function(&values)
+                } else if let Some(function) = functions::random_builtin(name) {
+                    // The arguments are already evaluated: they may draw from the generator too
+                    function(&mut ctx.rng(), &numbers()?).map(Value::Number)
+                } else {
+                    Err(MathError::UnknownFunction(name.clone()))
+                }
+            }
+        }
+    }
+
+    // Applies a binary operator to two values. Lists are combined element by element, except for a
+    // matrix product; dates, durations, money and quantities have their own rules.
+    fn combine(op: &Operator, left: &Value, right: &Value, ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        match (op, left, right) {
+            (Operator::Multiply, Value::List(_), Value::List(_)) if options.matrix_product == MatrixProduct::Matrix => {
+                matrix::matmul(left, right)
+            }
+            (_, Value::Date(_), _) | (_, _, Value::Date(_)) => date::apply(op, left, right),
+            (_, Value::Duration(_), _) | (_, _, Value::Duration(_)) => duration::apply(op, left, right),
+            (_, Value::Money(_), _) | (_, _, Value::Money(_)) => money::apply(op, left, right, ctx),
+            (_, Value::Quantity(_), _) | (_, _, Value::Quantity(_)) => {
+                units::apply(op, left, right, |op, left, right| Self::apply(op, left, right, ctx, options))
+            }
+            _ => matrix::elementwise(left, right, &|left, right| Self::apply(op, left, right, ctx, options)),
+        }
+    }
+
+    // Applies a binary operator to two numbers
+    fn apply(op: &Operator, left: f64, right: f64, ctx: &Context, options: &EvalOptions) -> Result<f64> {
+        match op {
+            // Apply the operator to the left and right values
+            Operator::Add => Ok(left + right),
+            Operator::Subtract => Ok(left - right),
+            Operator::Multiply => Ok(left * right),
+            Operator::Divide => Self::divide(left, right, options, |l, r| l / r),
+            // Remainder with the sign of the dividend (like C and Rust)
+            Operator::Modulo => Self::divide(left, right, options, |l, r| l % r),
+            Operator::BitAnd => Self::bitwise(left, right, options, |a, b| Ok(a & b)),
+            Operator::BitOr => Self::bitwise(left, right, options, |a, b| Ok(a | b)),
+            Operator::BitXor => Self::bitwise(left, right, options, |a, b| Ok(a ^ b)),
+            // checked_shl only checks the shift amount, so bits shifted out (or into the sign bit)
+            // are caught by shifting back
+            Operator::ShiftLeft => Self::bitwise(left, right, options, |a, b| {
+                let shifted = u32::try_from(b)
+                    .ok()
+                    .and_then(|shift| a.checked_shl(shift).map(|shifted| (shift, shifted)))
+                    .ok_or(MathError::InvalidShift(b))?;
+                match shifted {
+                    (shift, shifted) if shifted >> shift == a && (shifted < 0) == (a < 0) => Ok(shifted),
+                    _ => Err(MathError::Overflow(format!("{} << {}", a, b))),
+                }
+            }),
+            Operator::ShiftRight => Self::bitwise(left, right, options, |a, b| {
+                u32::try_from(b)
+                    .ok()
+                    .and_then(|b| a.checked_shr(b))
+                    .ok_or(MathError::InvalidShift(b))
+            }),
+            Operator::Power => {
+                // Raise left to the power of right
+                if options.deterministic {
+                    Ok(libm::pow(left, right))
+                } else {
+                    Ok(left.powf(right))
+                }
+            }
+            // Comparisons give 1 for true and 0 for false; any comparison with NaN is false
+            Operator::Less => Ok(truth(left < right)),
+            Operator::LessEqual => Ok(truth(left <= right)),
+            Operator::Greater => Ok(truth(left > right)),
+            Operator::GreaterEqual => Ok(truth(left >= right)),
+            Operator::Equal => Ok(truth(left == right)),
+            Operator::NotEqual => Ok(truth(left != right)),
+            Operator::And => Ok(truth(is_true(left) && is_true(right))),
+            Operator::Or => Ok(truth(is_true(left) || is_true(right))),
+            Operator::Custom(custom) => match ctx.operators().binary_function(&custom.symbol) {
+                Some(function) => function(left, right),
+                None => Err(MathError::UnknownOperator(custom.symbol.clone())),
+            },
+        }
+    }
+
+    // Evaluates a built-in whose arguments are evaluated lazily, if `name` is one.
+    // Context functions shadow these like any other built-in.
+    fn special_form(name: &str, args: &[Expr], ctx: &Context, options: &EvalOptions) -> Option<Result<Value>> {
+        match name {
+            "if" => Some(Self::conditional(args, ctx, options)),
+            "piecewise" => Some(Self::piecewise(args, ctx, options)),
+            "convert" => Some(Self::convert(args, ctx, options)),
+            "solve" if solver::is_system(args) => Some(Self::solve(args, ctx, options)),
+            _ => None,
+        }
+    }
+
+    // if(condition, a, b): a when the condition holds, otherwise b. Only the taken branch is
+    // evaluated, so if(x == 0, 0, 1 / x) never divides by zero.
+    fn conditional(args: &[Expr], ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        let [condition, then, otherwise] = args else {
+            return Err(MathError::ArgumentCount {
+                function: "if".to_string(),
+                expected: "3".to_string(),
+                found: args.len(),
+            });
+        };
+        let branch = if is_true(Self::number(condition, ctx, options)?) { then } else { otherwise };
+        Self::value(branch, ctx, options)
+    }
+
+    // piecewise((cond1, value1), (cond2, value2), ..., [otherwise]): the value of the first branch
+    // whose condition holds. Only that condition's predecessors and its value are evaluated.
+    fn piecewise(args: &[Expr], ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        let invalid = |reason: String| MathError::InvalidArgument {
+            function: "piecewise".to_string(),
+            reason,
+        };
+        for (i, arg) in args.iter().enumerate() {
+            match arg {
+                Expr::List(branch) if branch.len() == 2 => {
+                    if is_true(Self::number(&branch[0], ctx, options)?) {
+                        return Self::value(&branch[1], ctx, options);
                     }
-                    Operator::Power => Ok(left.powf(right)), // Raise left to the power of right
                 }
+                // A bare last argument is the value when no condition holds
+                _ if i == args.len() - 1 => return Self::value(arg, ctx, options),
+                _ => return Err(invalid(format!("expected a (condition, value) pair, got {}", arg))),
             }
         }
+        Err(invalid("no condition holds and there is no otherwise value".to_string()))
+    }
+
+    // convert(x, "from", "to"): x in the unit `from` as a number of the unit `to`, with the
+    // context's units. Written inline as x from in to.
+    fn convert(args: &[Expr], ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        let [value, Expr::Text(from), Expr::Text(to)] = args else {
+            if args.len() == 3 {
+                return Err(MathError::InvalidArgument {
+                    function: "convert".to_string(),
+                    reason: "expected quoted unit names, as in convert(5, \"mi\", \"km\")".to_string(),
+                });
+            }
+            return Err(MathError::ArgumentCount {
+                function: "convert".to_string(),
+                expected: "3".to_string(),
+                found: args.len(),
+            });
+        };
+        let value = Self::value(value, ctx, options)?;
+        matrix::map(&value, &|value| ctx.units().convert(value, from, to))
+    }
+
+    // solve([equations], [unknowns]): the unknowns' values, as a list. The unknowns are not looked
+    // up, while everything else in the equations is evaluated as usual.
+    fn solve(args: &[Expr], ctx: &Context, options: &EvalOptions) -> Result<Value> {
+        let [Expr::List(equations), Expr::List(unknowns)] = args else {
+            unreachable!("solver::is_system checks the arguments");
+        };
+        let names: Vec<&str> = unknowns
+            .iter()
+            .filter_map(|u| match u {
+                Expr::Variable(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        let solution = solver::solve_linear_by(equations, &names, &|expr| Self::number(expr, ctx, options))?;
+        Ok(Value::from(solution))
+    }
+
+    // Applies a division-like operation, honouring the division-by-zero policy
+    fn divide<F>(left: f64, right: f64, options: &EvalOptions, op: F) -> Result<f64>
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        if right != 0.0 {
+            return Ok(op(left, right));
+        }
+        match options.division_by_zero {
+            DivisionByZero::Error => Err(MathError::DivisionByZero),
+            DivisionByZero::Infinity => Ok(op(left, right)),
+            DivisionByZero::Default(value) => Ok(value),
+        }
+    }
+
+    // Converts an operand of a bitwise operator to an integer according to the options
+    fn to_integer(value: f64, options: &EvalOptions) -> Result<i64> {
+        let value = match options.non_integer {
+            NonInteger::Error if value.fract() != 0.0 => return Err(MathError::NotAnInteger(value)),
+            NonInteger::Error => value,
+            NonInteger::Truncate => value.trunc(),
+        };
+        // Also rejects NaN and infinities
+        if !(value >= i64::MIN as f64 && value < i64::MAX as f64) {
+            return Err(MathError::NotAnInteger(value));
+        }
+        Ok(value as i64)
+    }
+
+    // Applies a bitwise operation to two operands
+    fn bitwise<F>(left: f64, right: f64, options: &EvalOptions, op: F) -> Result<f64>
+    where
+        F: Fn(i64, i64) -> Result<i64>,
+    {
+        let left = Self::to_integer(left, options)?;
+        let right = Self::to_integer(right, options)?;
+        Ok(op(left, right)? as f64)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*; // Import names from the parent module
-    use crate::{Parser, Tokenizer};
+    use crate::{Parser, Tokenizer, TokenizerOptions};
 
     fn eval_str(input: &str) -> Result<f64> {
         let tokens = Tokenizer::tokenize(input)?;
@@ -126,6 +786,170 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_division_by_zero_policies() {
+        let expr = Parser::new(Tokenizer::tokenize("1 / (2 - 2)").unwrap()).parse().unwrap();
+        let nan = Parser::new(Tokenizer::tokenize("0 / 0").unwrap()).parse().unwrap();
+        let ctx = Context::new();
+
+        let infinity = EvalOptions {
+            division_by_zero: DivisionByZero::Infinity,
+            ..EvalOptions::default()
+        };
+        assert_eq!(Evaluator::evaluate_with_options(&expr, &ctx, &infinity).unwrap(), f64::INFINITY);
+        assert!(Evaluator::evaluate_with_options(&nan, &ctx, &infinity).unwrap().is_nan());
+
+        let default = EvalOptions {
+            division_by_zero: DivisionByZero::Default(0.0),
+            ..EvalOptions::default()
+        };
+        assert_eq!(Evaluator::evaluate_with_options(&expr, &ctx, &default).unwrap(), 0.0);
+
+        // mod() divides too
+        let modulo = Expr::parse("mod(7, 2 - 2)").unwrap();
+        assert!(matches!(Evaluator::evaluate_with(&modulo, &ctx), Err(MathError::DivisionByZero)));
+        assert_eq!(Evaluator::evaluate_with_options(&modulo, &ctx, &default).unwrap(), 0.0);
+        assert!(Evaluator::evaluate_with_options(&modulo, &ctx, &infinity).unwrap().is_nan());
+        assert_eq!(Evaluator::evaluate_with_options(&Expr::parse("mod(-7, 3)").unwrap(), &ctx, &default).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_strict_non_finite_detection() {
+        let strict = EvalOptions {
+            division_by_zero: DivisionByZero::Infinity,
+            strict: true,
+            ..EvalOptions::default()
+        };
+        let eval_strict = |input: &str| {
+            let expr = Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+            Evaluator::evaluate_with_options(&expr, &Context::new(), &strict)
+        };
+
+        // The error names the innermost offending sub-expression
+        match eval_strict("1 + 0 ^ -1") {
+            Err(MathError::NotFinite { value, expr }) => {
+                assert_eq!(value, f64::INFINITY);
+                assert_eq!(expr.to_string(), "0 ^ -1");
+            }
+            other => panic!("expected NotFinite, got {:?}", other),
+        }
+        assert!(matches!(eval_strict("10 ^ 400"), Err(MathError::NotFinite { .. })));
+        assert!(matches!(eval_strict("1 / 0"), Err(MathError::NotFinite { .. })));
+        assert_eq!(eval_strict("2 ^ 10").unwrap(), 1024.0);
+
+        // Without strict mode the value propagates silently
+        assert_eq!(eval_str("0 ^ -1").unwrap(), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_non_finite_literals() {
+        assert_eq!(eval_str("-inf").unwrap(), f64::NEG_INFINITY);
+        assert_eq!(eval_str("1 / inf").unwrap(), 0.0);
+        assert!(eval_str("inf - inf").unwrap().is_nan());
+        assert!(eval_str("nan + 1").unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_deterministic_mode() {
+        let deterministic = EvalOptions {
+            deterministic: true,
+            ..EvalOptions::default()
+        };
+        let eval_deterministic = |input: &str| {
+            let expr = Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+            Evaluator::evaluate_with_options(&expr, &Context::new(), &deterministic).unwrap()
+        };
+
+        // Scientific literals are parsed exactly rather than scaled by a computed power of ten
+        assert_eq!(eval_deterministic("1.23e-4").to_bits(), 1.23e-4_f64.to_bits());
+        assert_eq!(eval_deterministic("2 ^ 0.5").to_bits(), libm::pow(2.0, 0.5).to_bits());
+        assert_eq!(eval_deterministic("inf - inf + nan").to_bits(), f64::NAN.to_bits());
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        assert_eq!(eval_str("12 & 10").unwrap(), 8.0);
+        assert_eq!(eval_str("12 | 3").unwrap(), 15.0);
+        assert_eq!(eval_str("12 xor 10").unwrap(), 6.0);
+        assert_eq!(eval_str("1 << 4").unwrap(), 16.0);
+        assert_eq!(eval_str("-16 >> 2").unwrap(), -4.0);
+        assert_eq!(eval_str("~5").unwrap(), -6.0);
+
+        // Precedence: | below xor below & below shifts below arithmetic
+        assert_eq!(eval_str("1 | 2 & 3").unwrap(), 3.0);
+        assert_eq!(eval_str("1 << 1 + 1").unwrap(), 4.0);
+        assert_eq!(eval_str("6 & 3 xor 1").unwrap(), 3.0);
+
+        assert!(matches!(eval_str("2.5 & 1"), Err(MathError::NotAnInteger(_))));
+        assert!(matches!(eval_str("1 << 64"), Err(MathError::InvalidShift(64))));
+        // Bits shifted out, or into the sign bit, overflow
+        assert_eq!(eval_str("1 << 62").unwrap(), 4611686018427387904.0);
+        assert_eq!(eval_str("-1 << 63").unwrap(), -9223372036854775808.0);
+        assert_eq!(eval_str("1 << 63").unwrap_err().to_string(), "Result out of range: 1 << 63");
+        assert!(matches!(eval_str("3 << 62"), Err(MathError::Overflow(_))));
+        assert!(matches!(eval_str("-3 << 62"), Err(MathError::Overflow(_))));
+        assert!(matches!(eval_str("1 << -1"), Err(MathError::InvalidShift(-1))));
+
+        let truncate = EvalOptions {
+            non_integer: NonInteger::Truncate,
+            ..EvalOptions::default()
+        };
+        let expr = Parser::new(Tokenizer::tokenize("7.9 & -2.5").unwrap()).parse().unwrap();
+        assert_eq!(Evaluator::evaluate_with_options(&expr, &Context::new(), &truncate).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn test_percent_and_modulo() {
+        assert_eq!(eval_str("200 * 15%").unwrap(), 30.0);
+        assert_eq!(eval_str("50%").unwrap(), 0.5);
+        assert_eq!(eval_str("(10 + 10)% * 5").unwrap(), 1.0);
+
+        let modulo = TokenizerOptions {
+            percent_sign: crate::PercentSign::Modulo,
+            ..TokenizerOptions::default()
+        };
+        let eval_modulo = |input: &str| {
+            let tokens = Tokenizer::tokenize_with(input, modulo.clone()).unwrap();
+            Evaluator::evaluate(&Parser::new(tokens).parse().unwrap())
+        };
+        assert_eq!(eval_modulo("7 % 3").unwrap(), 1.0);
+        assert_eq!(eval_modulo("-7 % 3").unwrap(), -1.0);
+        assert_eq!(eval_modulo("1 + 7 % 3 * 2").unwrap(), 3.0);
+        assert!(matches!(eval_modulo("7 % 0"), Err(MathError::DivisionByZero)));
+    }
+
+    #[test]
+    fn test_calculator_percentage() {
+        let calculator = Evaluator::builder().percentage(Percentage::OfLeft).build();
+        let eval = |input: &str| calculator.eval_value(&Expr::parse(input).unwrap(), &Context::new());
+        assert_eq!(eval("200 + 10%").unwrap(), 220.0);
+        assert_eq!(eval("200 - 10%").unwrap(), 180.0);
+        assert_eq!(eval("50 + 20 + 10%").unwrap(), 77.0);
+        assert_eq!(eval("200 * 10%").unwrap(), 20.0);
+        assert_eq!(eval("10%").unwrap(), 0.1);
+        assert_eq!(eval("[100, 200] + 5%").unwrap().to_string(), "[105, 210]");
+        assert_eq!(eval("19.99 EUR + 20%").unwrap().to_string(), "23.988 EUR");
+        // Without the dialect, the percentage is a fraction
+        assert_eq!(eval_str("200 + 10%").unwrap(), 200.1);
+    }
+
+    #[test]
+    fn test_sqrt_and_absolute_value() {
+        assert_eq!(eval_str("√9").unwrap(), 3.0);
+        assert_eq!(eval_str("√(7 + 9) * 2").unwrap(), 8.0);
+        assert_eq!(eval_str("|2 - 5|").unwrap(), 3.0);
+        assert_eq!(eval_str("||-1| - 3| + 1").unwrap(), 3.0);
+        assert_eq!(eval_str("|-4| | 1").unwrap(), 5.0);
+        assert!(eval_str("√-1").unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_superscripts() {
+        assert_eq!(eval_str("3² + 2⁻¹").unwrap(), 9.5);
+        assert_eq!(eval_str("-2²").unwrap(), -4.0);
+        assert_eq!(eval_str("2 * 10³").unwrap(), 2000.0);
+    }
+
     #[test]
     fn test_invalid_expression() {
         assert!(matches!(
@@ -138,7 +962,7 @@ mod tests {
         ));
         assert!(matches!(
             eval_str("1 + 2 * (3 - 4"),
-            Err(MathError::InvalidExpression(_))
+            Err(MathError::UnmatchedParenthesis { paren: '(', .. })
         ));
         assert!(matches!(
             eval_str("1 + 2 * (3 - 4) +"),
@@ -150,6 +974,351 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_variables_and_functions() {
+        let mut ctx = Context::new();
+        ctx.set_variable("x", 3.0);
+        ctx.register_function("sum", |args: &[f64]| Ok(args.iter().sum()));
+
+        let tokens = Tokenizer::tokenize("sum(x, 2, 1) * x").unwrap();
+        let expr = Parser::new(tokens).parse().unwrap();
+        assert_eq!(Evaluator::evaluate_with(&expr, &ctx).unwrap(), 18.0);
+
+        assert!(matches!(eval_str("y + 1"), Err(MathError::UnknownVariable(_))));
+        assert!(matches!(eval_str("f(1)"), Err(MathError::UnknownFunction(_))));
+    }
+
+    #[test]
+    fn test_statements() {
+        let tokens = Tokenizer::tokenize("a = 2; b = 3; a^b").unwrap();
+        let statements = Parser::new(tokens).parse_statements().unwrap();
+        let mut ctx = Context::new();
+        assert_eq!(Evaluator::execute_all(&statements, &mut ctx).unwrap(), vec![2.0, 3.0, 8.0]);
+        assert_eq!(ctx.variable("b"), Some(3.0));
+
+        // A failing statement stops execution
+        let tokens = Tokenizer::tokenize("c = 1 / 0; d = 1").unwrap();
+        let statements = Parser::new(tokens).parse_statements().unwrap();
+        assert!(Evaluator::execute_all(&statements, &mut ctx).is_err());
+        assert_eq!(ctx.variable("d"), None);
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        assert_eq!(eval_str("max(1, 2 * 3, 4) - min(5, 2)").unwrap(), 4.0);
+        assert_eq!(eval_str("clamp(lerp(0, 10, 1.5), 0, 12)").unwrap(), 12.0);
+
+        // Context functions shadow built-ins
+        let mut ctx = Context::new();
+        ctx.register_function("max", |_: &[f64]| Ok(-1.0));
+        let expr = Parser::new(Tokenizer::tokenize("max(1, 2)").unwrap()).parse().unwrap();
+        assert_eq!(Evaluator::evaluate_with(&expr, &ctx).unwrap(), -1.0);
+    }
+
+    #[test]
+    fn test_lists() {
+        let value = |input: &str, ctx: &Context| {
+            let expr = Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+            Evaluator::evaluate_value_with(&expr, ctx)
+        };
+        let mut ctx = Context::new();
+        ctx.set_variable("data", vec![3.0, 1.0, 4.0, 1.0, 5.0]);
+
+        assert_eq!(value("data[0] + data[-1]", &ctx).unwrap(), 8.0);
+        assert_eq!(value("sum(data) / len(data)", &ctx).unwrap(), 2.8);
+        assert_eq!(value("slice(data, 1, 3)", &ctx).unwrap(), Value::from(vec![1.0, 4.0]));
+        assert_eq!(value("[[1, 2], [3, 2 * 2]][1][1]", &ctx).unwrap(), 4.0);
+        assert_eq!(value("[1 + 1, len([])]", &ctx).unwrap(), Value::from(vec![2.0, 0.0]));
+        assert_eq!(ctx.variable("data"), None); // Not a number
+
+        assert!(matches!(value("data[5]", &ctx), Err(MathError::IndexOutOfRange { index: 5, len: 5 })));
+        assert!(matches!(value("data[1.5]", &ctx), Err(MathError::NotAnInteger(_))));
+        assert!(matches!(value("max(data)", &ctx), Err(MathError::TypeMismatch { .. })));
+        assert!(matches!(value("2[0]", &ctx), Err(MathError::TypeMismatch { .. })));
+
+        // Scripts can bind lists
+        let tokens = Tokenizer::tokenize("xs = [2, 3]; xs[0] ^ xs[1]").unwrap();
+        let statements = Parser::new(tokens).parse_statements().unwrap();
+        assert_eq!(Evaluator::execute_all(&statements, &mut ctx).unwrap()[1], 8.0);
+    }
+
+    #[test]
+    fn test_matrix_arithmetic() {
+        let value = |input: &str, options: &EvalOptions| {
+            let expr = Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+            Evaluator::evaluate_value_with_options(&expr, &Context::new(), options)
+        };
+        let matrix = |rows: Vec<Vec<f64>>| Value::List(rows.into_iter().map(Value::from).collect());
+        let options = EvalOptions::default();
+
+        assert_eq!(
+            value("[[1, 2], [3, 4]] * [[5, 6], [7, 8]]", &options).unwrap(),
+            matrix(vec![vec![19.0, 22.0], vec![43.0, 50.0]])
+        );
+        assert_eq!(value("[1, 2, 3] * [4, 5, 6]", &options).unwrap(), 32.0);
+        assert_eq!(
+            value("2 * [[1, 2], [3, 4]] - 1", &options).unwrap(),
+            matrix(vec![vec![1.0, 3.0], vec![5.0, 7.0]])
+        );
+        assert_eq!(value("-[1, 4]^2 / 2", &options).unwrap(), Value::from(vec![0.5, 8.0]));
+        assert_eq!(value("√[4, 9] + |[-1, 1]|", &options).unwrap(), Value::from(vec![3.0, 4.0]));
+        assert_eq!(
+            value("transpose([[1, 2], [3, 4]])[0]", &options).unwrap(),
+            Value::from(vec![1.0, 3.0])
+        );
+        assert!(matches!(value("[1, 2] + [1, 2, 3]", &options), Err(MathError::ShapeMismatch(_))));
+        assert!(matches!(value("[[1, 2]] * [[1, 2]]", &options), Err(MathError::ShapeMismatch(_))));
+        assert!(matches!(value("[1, 0] / 0", &options), Err(MathError::DivisionByZero)));
+
+        let elementwise = EvalOptions {
+            matrix_product: MatrixProduct::Elementwise,
+            ..EvalOptions::default()
+        };
+        assert_eq!(
+            value("[[1, 2], [3, 4]] * [[5, 6], [7, 8]]", &elementwise).unwrap(),
+            matrix(vec![vec![5.0, 12.0], vec![21.0, 32.0]])
+        );
+
+        // Strict mode checks every element
+        let strict = EvalOptions {
+            strict: true,
+            division_by_zero: DivisionByZero::Infinity,
+            ..EvalOptions::default()
+        };
+        assert!(matches!(value("[1, 0] / 0", &strict), Err(MathError::NotFinite { .. })));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(eval_str("1 + 1 == 2").unwrap(), 1.0);
+        assert_eq!(eval_str("3 < 2").unwrap(), 0.0);
+        assert_eq!(eval_str("2 <= 2 + (1 >= 1)").unwrap(), 1.0);
+        assert_eq!(eval_str("nan != nan").unwrap(), 1.0);
+        assert_eq!(eval_str("nan == nan").unwrap(), 0.0);
+        assert_eq!(
+            Evaluator::evaluate_value(&Parser::new(Tokenizer::tokenize("[1, 5, 3] > 2").unwrap()).parse().unwrap())
+                .unwrap(),
+            Value::from(vec![0.0, 1.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn test_custom_operators() {
+        use crate::{Associativity, OperatorTable};
+
+        let mut operators = OperatorTable::new();
+        let additive = Operator::Add.precedence();
+        operators.register_binary("⊕", additive, Associativity::Left, |a, b| Ok(a.hypot(b)));
+        operators.register_binary("↑", Operator::Power.precedence(), Associativity::Right, |a, b| Ok(a.powf(b)));
+        let options = TokenizerOptions {
+            operators: operators.clone(),
+            ..TokenizerOptions::default()
+        };
+        let parse = |input: &str| Parser::new(Tokenizer::tokenize_with(input, options.clone()).unwrap()).parse().unwrap();
+        let mut ctx = Context::new();
+        ctx.set_operators(operators);
+
+        assert_eq!(Evaluator::evaluate_with(&parse("3 ⊕ 2 * 2"), &ctx).unwrap(), 5.0);
+        // Right-associative: 2 ↑ 3 ↑ 2 is 2 ↑ 9
+        let tower = parse("2 ↑ 3 ↑ 2");
+        assert_eq!(Evaluator::evaluate_with(&tower, &ctx).unwrap(), 512.0);
+        assert_eq!(tower.to_string(), "2 ↑ 3 ↑ 2");
+        assert_eq!(parse("(2 ↑ 3) ↑ 2").to_string(), "(2 ↑ 3) ↑ 2");
+        assert_eq!(Evaluator::evaluate_value_with(&parse("[3, 0] ⊕ 4"), &ctx).unwrap(), Value::from(vec![5.0, 4.0]));
+
+        // Unary operators, including one sharing its symbol with a built-in
+        let mut operators = ctx.operators().clone();
+        operators.register_postfix("!", |x| Ok(libm::tgamma(x + 1.0)));
+        operators.register_prefix("∛", |x| Ok(x.cbrt()));
+        let options = TokenizerOptions {
+            operators: operators.clone(),
+            ..TokenizerOptions::default()
+        };
+        let parse = |input: &str| Parser::new(Tokenizer::tokenize_with(input, options.clone()).unwrap()).parse().unwrap();
+        ctx.set_operators(operators);
+        let expr = parse("∛8 + 3!^2 + !0");
+        assert_eq!(Evaluator::evaluate_with(&expr, &ctx).unwrap(), 39.0);
+        assert_eq!(expr.to_string(), "∛8 + 3! ^ 2 + !0");
+        assert_eq!(Evaluator::evaluate_value_with(&parse("[2, 3]!"), &ctx).unwrap(), Value::from(vec![2.0, 6.0]));
+
+        // Evaluating without the table fails cleanly
+        assert!(matches!(
+            Evaluator::evaluate(&parse("1 ⊕ 1")),
+            Err(MathError::UnknownOperator(symbol)) if symbol == "⊕"
+        ));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert_eq!(eval_str("1 < 2 && 2 < 3").unwrap(), 1.0);
+        assert_eq!(eval_str("1 > 2 || 0").unwrap(), 0.0);
+        assert_eq!(eval_str("0 or 0.5").unwrap(), 1.0);
+        assert_eq!(eval_str("not 2 + !0").unwrap(), 1.0);
+        assert_eq!(eval_str("!nan").unwrap(), 1.0);
+        // && binds tighter than ||
+        assert_eq!(eval_str("1 || 0 && 0").unwrap(), 1.0);
+        assert_eq!(eval_str("(1 || 0) and 0").unwrap(), 0.0);
+        // Bars still nest
+        assert_eq!(eval_str("||-2| - 3|").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_short_circuit_evaluation() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut ctx = Context::new();
+        let counter = Arc::clone(&calls);
+        ctx.register_function("tick", move |_: &[f64]| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(1.0)
+        });
+        let eval = |input: &str, ctx: &Context| {
+            let expr = Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+            Evaluator::evaluate_with(&expr, ctx)
+        };
+
+        // Skipped operands are never evaluated, so they cannot fail or have side effects
+        assert_eq!(eval("0 && 1 / 0", &ctx).unwrap(), 0.0);
+        assert_eq!(eval("1 || undefined_variable", &ctx).unwrap(), 1.0);
+        assert_eq!(eval("0 && tick() || 2 > 1 || tick()", &ctx).unwrap(), 1.0);
+        assert_eq!(eval("if(1, 2, tick()) + piecewise((0, tick()), (1, 3), tick())", &ctx).unwrap(), 5.0);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // Operands that are needed are evaluated exactly once
+        assert_eq!(eval("1 && tick()", &ctx).unwrap(), 1.0);
+        assert_eq!(eval("0 || tick()", &ctx).unwrap(), 1.0);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(matches!(eval("1 && 1 / 0", &ctx), Err(MathError::DivisionByZero)));
+
+        // Skipped random draws leave the generator untouched
+        ctx.set_seed(9);
+        let expected = eval("rand()", &ctx).unwrap();
+        ctx.set_seed(9);
+        eval("0 && rand()", &ctx).unwrap();
+        assert_eq!(eval("rand()", &ctx).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_if() {
+        let mut ctx = Context::new();
+        let eval = |input: &str, ctx: &Context| {
+            let expr = Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+            Evaluator::evaluate_with(&expr, ctx)
+        };
+        ctx.set_variable("x", 0.0);
+        assert_eq!(eval("if(x == 0, 0, 1 / x)", &ctx).unwrap(), 0.0);
+        ctx.set_variable("x", 4.0);
+        assert_eq!(eval("if(x == 0, 0, 1 / x)", &ctx).unwrap(), 0.25);
+        assert_eq!(eval("if(nan, 1, 2) + if(-1, 10, 20)", &ctx).unwrap(), 12.0);
+        // The untaken branch may even reference unknown names
+        assert_eq!(eval("if(1, 5, undefined_variable)", &ctx).unwrap(), 5.0);
+
+        assert!(matches!(eval("if(1, 2)", &ctx), Err(MathError::ArgumentCount { .. })));
+
+        // A context function named `if` replaces the lazy built-in
+        ctx.register_function("if", |args: &[f64]| Ok(args.iter().sum()));
+        assert_eq!(eval("if(1, 2, 3)", &ctx).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn test_piecewise() {
+        let mut ctx = Context::new();
+        let eval = |input: &str, ctx: &Context| {
+            let expr = Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+            Evaluator::evaluate_with(&expr, ctx)
+        };
+        let abs = "piecewise((x < 0, -x), (x >= 0, x))";
+        ctx.set_variable("x", -3.0);
+        assert_eq!(eval(abs, &ctx).unwrap(), 3.0);
+        ctx.set_variable("x", 2.0);
+        assert_eq!(eval(abs, &ctx).unwrap(), 2.0);
+
+        // Tax brackets with an otherwise value; untaken branches are never evaluated
+        let tax = "piecewise([income <= 10000, 0], [income <= 40000, (income - 10000) * 0.2], 6000 + (income - 40000) * 0.4)";
+        ctx.set_variable("income", 25000.0);
+        assert_eq!(eval(tax, &ctx).unwrap(), 3000.0);
+        ctx.set_variable("income", 50000.0);
+        assert_eq!(eval(tax, &ctx).unwrap(), 10000.0);
+        ctx.set_variable("x", 0.0);
+        assert_eq!(eval("piecewise((x == 0, 0), (x != 0, 1 / x))", &ctx).unwrap(), 0.0);
+
+        assert!(matches!(eval("piecewise((x > 1, 1))", &ctx), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(eval("piecewise(1, (x > 1, 1))", &ctx), Err(MathError::InvalidArgument { .. })));
+    }
+
+    #[test]
+    fn test_seeded_random_functions() {
+        let expr = Parser::new(Tokenizer::tokenize("rand() + uniform(1, 2) + normal(0, 1) + randint(1, 6)").unwrap())
+            .parse()
+            .unwrap();
+        let mut ctx = Context::new();
+        ctx.set_seed(2024);
+        let first = Evaluator::evaluate_with(&expr, &ctx).unwrap();
+        let second = Evaluator::evaluate_with(&expr, &ctx).unwrap();
+        assert_ne!(first, second); // Each evaluation advances the generator
+
+        // Reseeding (or cloning) replays the same draws
+        ctx.set_seed(2024);
+        let replay = ctx.clone();
+        assert_eq!(Evaluator::evaluate_with(&expr, &ctx).unwrap(), first);
+        assert_eq!(Evaluator::evaluate_with(&expr, &replay).unwrap(), first);
+    }
+
+    #[test]
+    fn test_max_duration() {
+        // Each call sleeps past the short limit, so the timeouts below do not depend on timing,
+        // and the long limit leaves room for a slow machine
+        let mut ctx = Context::new();
+        ctx.register_function("slow", |args: &[f64]| {
+            std::thread::sleep(Duration::from_millis(60));
+            Ok(args[0])
+        });
+        let inner = ctx.clone();
+        // Evaluations inside context functions stop at the same deadline
+        ctx.register_function("nested", move |_: &[f64]| Evaluator::evaluate_with(&Expr::parse("slow(1) + slow(2)")?, &inner));
+        let options = |millis| EvalOptions {
+            max_duration: Some(Duration::from_millis(millis)),
+            ..EvalOptions::default()
+        };
+        let eval = |input: &str, options: &EvalOptions| {
+            Evaluator::evaluate_with_options(&Expr::parse(input).unwrap(), &ctx, options)
+        };
+        assert_eq!(eval("slow(1) + slow(2)", &options(60_000)).unwrap(), 3.0);
+        let error = eval("slow(1) + slow(2) + slow(3)", &options(50)).unwrap_err();
+        assert_eq!(error.to_string(), "Evaluation took longer than 50ms");
+        assert!(matches!(eval("1 + nested(0) + 1", &options(50)), Err(MathError::Timeout(_))));
+        // Built-in functions with long loops stop at the deadline too
+        assert!(matches!(eval("binom(0.5, slow(1000000))", &options(50)), Err(MathError::Timeout(_))));
+        assert!(matches!(eval("totient(slow(4294967291 * 65537))", &options(50)), Err(MathError::Timeout(_))));
+        // The deadline ends with the evaluation
+        assert_eq!(eval("slow(1) + slow(2) + slow(3)", &EvalOptions::default()).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn test_evaluator_builder() {
+        let evaluator = Evaluator::builder()
+            .div_by_zero(DivisionByZero::Infinity)
+            .non_integer(NonInteger::Truncate)
+            .build();
+        let ctx = Context::new();
+        let eval = |input: &str| evaluator.eval(&Expr::parse(input).unwrap(), &ctx);
+        assert_eq!(eval("1 / 0").unwrap(), f64::INFINITY);
+        assert_eq!(eval("5.7 & 3").unwrap(), 1.0);
+        assert_eq!(evaluator.options().division_by_zero, DivisionByZero::Infinity);
+
+        let strict = Evaluator::builder().options(evaluator.options().clone()).strict(true).build();
+        assert!(matches!(strict.eval(&Expr::parse("1 / 0").unwrap(), &ctx), Err(MathError::NotFinite { .. })));
+        let mut ctx = Context::new();
+        let statements = crate::Grammar::default().parse_statements("a = [1, 2]; a * 2");
+        assert_eq!(
+            Evaluator::new().run_all(&statements.unwrap(), &mut ctx).unwrap().last(),
+            Some(&Value::List(vec![Value::Number(2.0), Value::Number(4.0)]))
+        );
+        assert_eq!(Evaluator::new(), Evaluator::builder().build());
+    }
+
     #[test]
     fn test_parentheses() {
         assert_eq!(eval_str("(1 + 2) * 3").unwrap(), 9.0);
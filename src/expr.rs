@@ -1,7 +1,10 @@
 //src/expr.rs
-use crate::Operator;
+use std::str::FromStr;
+
+use crate::{MathError, Money, Operator, Parser, Result, UnaryOperator};
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     // A literal number value
     Literal(f64),
@@ -28,22 +31,143 @@ pub enum Expr {
 
     // A binary operation (e.g., addition, subtraction, etc.)
     BinOp {
-        op: Operator,   // The operator to apply
+        op: Operator, // The operator to apply
+        #[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))]
         lhs: Box<Expr>, // The left-hand side of the operation
+        #[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))]
         rhs: Box<Expr>, // The right-hand side of the operation
     },
 
     // A unary minus operation (e.g., -5)
-    UnaryMinus(Box<Expr>),
+    UnaryMinus(#[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))] Box<Expr>),
+
+    // Any other prefix operation (e.g., ~5)
+    Unary {
+        op: UnaryOperator, // The operator to apply
+        #[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))]
+        operand: Box<Expr>, // The expression it applies to
+    },
 
     // Scientific notation (e.g., 1e3)
     Scientific {
         base: f64,     // The base value
         exponent: i32, // The exponent value
     },
+
+    // A named variable resolved from the evaluation context (e.g., x)
+    Variable(String),
+
+    // A function call resolved from the evaluation context (e.g., max(1, 2))
+    Function {
+        name: String, // The function name
+        #[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))]
+        args: Vec<Expr>, // The argument expressions
+    },
+
+    // A list literal (e.g., [1, 2, 3])
+    List(#[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))] Vec<Expr>),
+
+    // An element of a list, counted from 0; negative indices count from the end (e.g., xs[0])
+    Index {
+        #[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))]
+        target: Box<Expr>, // The list being indexed
+        #[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))]
+        index: Box<Expr>, // The position to read
+    },
+
+    // Parentheses written in the input, kept when parsing with `ParserOptions::preserve_notation`
+    // so the expression displays as it was written; evaluates to the inner expression
+    Group(#[cfg_attr(feature = "binary", serde(deserialize_with = "nested"))] Box<Expr>),
+
+    // Stands in for a missing or malformed part after `Parser::parse_recovering`; cannot be evaluated
+    Error,
+
+    // A duration literal, in seconds (e.g., 1h30m)
+    Duration(f64),
+
+    // An amount of money (e.g., 100 USD)
+    Money(Money),
+
+    // A quoted name, such as a unit in convert(5, "mi", "km"); only meaningful as an argument
+    Text(String),
 }
 
 impl Expr {
+    // Parses an expression with the default syntax (same as `Parser::parse_str`)
+    pub fn parse(input: &str) -> Result<Self> {
+        Parser::parse_str(input)
+    }
+
+    // Whether the expression displays as a single unit, needing no parentheses after a prefix
+    // operator (e.g., -x, √2, !f(x))
+    pub fn is_atomic(&self) -> bool {
+        match self {
+            Expr::Literal(value) => value.is_sign_positive(),
+            Expr::Scientific { base, .. } => base.is_sign_positive(),
+            Expr::Duration(seconds) => seconds.is_sign_positive(),
+            Expr::Money(money) => money.amount() >= 0.0,
+            Expr::Variable(_)
+            | Expr::Text(_)
+            | Expr::Function { .. }
+            | Expr::List(_)
+            | Expr::Index { .. }
+            | Expr::Group(_)
+            | Expr::Error => true,
+            Expr::BinOp { .. } | Expr::UnaryMinus(_) | Expr::Unary { .. } => false,
+        }
+    }
+
+    // Levels of nesting in the tree: 1 for a number or variable, plus one for each operation,
+    // call, list or group around it (e.g., 2 for 1 + 2, 3 for -(1 + 2))
+    pub fn depth(&self) -> usize {
+        let inner = match self {
+            Expr::Literal(_) | Expr::Scientific { .. } | Expr::Duration(_) | Expr::Money(_) | Expr::Variable(_) | Expr::Text(_) | Expr::Error => 0,
+            Expr::BinOp { lhs, rhs, .. } => lhs.depth().max(rhs.depth()),
+            Expr::UnaryMinus(operand) | Expr::Unary { operand, .. } | Expr::Group(operand) => operand.depth(),
+            Expr::Function { args: items, .. } | Expr::List(items) => items.iter().map(Expr::depth).max().unwrap_or(0),
+            Expr::Index { target, index } => target.depth().max(index.depth()),
+        };
+        inner + 1
+    }
+
+    // The direct children: operands, arguments, items, or the target and the index
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::BinOp { lhs, rhs, .. } => vec![lhs, rhs],
+            Expr::UnaryMinus(operand) | Expr::Unary { operand, .. } | Expr::Group(operand) => vec![operand],
+            Expr::Function { args: items, .. } | Expr::List(items) => items.iter().collect(),
+            Expr::Index { target, index } => vec![target, index],
+            Expr::Literal(_) | Expr::Scientific { .. } | Expr::Duration(_) | Expr::Money(_) | Expr::Variable(_) | Expr::Text(_) | Expr::Error => Vec::new(),
+        }
+    }
+
+    // The expression with each direct child (operand, argument, item, ...) replaced by `f` of it
+    pub fn map_children(&self, mut f: impl FnMut(&Expr) -> Expr) -> Expr {
+        match self {
+            Expr::BinOp { op, lhs, rhs } => Expr::BinOp {
+                op: op.clone(),
+                lhs: Box::new(f(lhs)),
+                rhs: Box::new(f(rhs)),
+            },
+            Expr::UnaryMinus(operand) => Expr::UnaryMinus(Box::new(f(operand))),
+            Expr::Unary { op, operand } => Expr::Unary {
+                op: op.clone(),
+                operand: Box::new(f(operand)),
+            },
+            Expr::Group(inner) => Expr::Group(Box::new(f(inner))),
+            Expr::Function { name, args } => Expr::Function {
+                name: name.clone(),
+                args: args.iter().map(f).collect(),
+            },
+            Expr::List(items) => Expr::List(items.iter().map(f).collect()),
+            Expr::Index { target, index } => Expr::Index {
+                target: Box::new(f(target)),
+                index: Box::new(f(index)),
+            },
+            Expr::Literal(_) | Expr::Scientific { .. } | Expr::Duration(_) | Expr::Money(_) | Expr::Variable(_) | Expr::Text(_) | Expr::Error => self.clone(),
+        }
+    }
+
     // Creates a new literal expression
     pub fn literal(value: f64) -> Self {
         Expr::Literal(value)
@@ -63,8 +187,163 @@ impl Expr {
         Expr::UnaryMinus(Box::new(expr))
     }
 
+    // Creates a new prefix operation expression
+    pub fn unary(op: UnaryOperator, operand: Expr) -> Self {
+        Expr::Unary {
+            op,
+            operand: Box::new(operand),
+        }
+    }
+
     // Creates a new scientific notation expression
     pub fn scientific(base: f64, exponent: i32) -> Self {
         Expr::Scientific { base, exponent }
     }
+
+    // Creates a new variable expression
+    pub fn variable(name: impl Into<String>) -> Self {
+        Expr::Variable(name.into())
+    }
+
+    // Creates a new function call expression
+    pub fn function(name: impl Into<String>, args: Vec<Expr>) -> Self {
+        Expr::Function {
+            name: name.into(),
+            args,
+        }
+    }
+
+    // Creates a new list literal expression
+    pub fn list(items: Vec<Expr>) -> Self {
+        Expr::List(items)
+    }
+
+    // Creates a new indexing expression
+    pub fn index(target: Expr, index: Expr) -> Self {
+        Expr::Index {
+            target: Box::new(target),
+            index: Box::new(index),
+        }
+    }
+}
+
+// Version of the binary encoding, stored in its first byte
+#[cfg(feature = "binary")]
+const BINARY_FORMAT: u8 = 1;
+
+// Deepest nesting `from_bytes` decodes, since decoding recurses once per level
+#[cfg(feature = "binary")]
+const MAX_DECODE_DEPTH: usize = 256;
+
+#[cfg(feature = "binary")]
+thread_local! {
+    // The nesting of the tree being decoded on this thread, and whether it went past the limit
+    static DECODING: std::cell::Cell<(usize, bool)> = const { std::cell::Cell::new((0, false)) };
+}
+
+// Decodes the operands of an operation, one level deeper than the operation itself
+#[cfg(feature = "binary")]
+fn nested<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    let (depth, _) = DECODING.get();
+    if depth >= MAX_DECODE_DEPTH {
+        DECODING.set((depth, true));
+        return Err(serde::de::Error::custom(MathError::TooDeep(MAX_DECODE_DEPTH)));
+    }
+    DECODING.set((depth + 1, false));
+    let decoded = T::deserialize(deserializer);
+    DECODING.set((depth, DECODING.get().1));
+    decoded
+}
+
+#[cfg(feature = "binary")]
+impl Expr {
+    // Encodes the tree compactly (varint postcard encoding behind a version byte), for save files
+    // and network protocols where JSON is too bulky
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![BINARY_FORMAT];
+        // Every part of a tree is encodable, so this cannot fail
+        bytes.extend(postcard::to_allocvec(self).expect("expression trees are always encodable"));
+        bytes
+    }
+
+    // Decodes a tree written by `to_bytes`. Trees nested more than 256 levels deep are rejected
+    // with `MathError::TooDeep`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| MathError::InvalidEncoding(reason.to_string());
+        match bytes.split_first() {
+            Some((&BINARY_FORMAT, encoded)) => {
+                DECODING.set((0, false));
+                let (expr, rest) = postcard::take_from_bytes(encoded).map_err(|e| match DECODING.get() {
+                    (_, true) => MathError::TooDeep(MAX_DECODE_DEPTH),
+                    _ => invalid(&e.to_string()),
+                })?;
+                if rest.is_empty() {
+                    Ok(expr)
+                } else {
+                    Err(invalid("trailing bytes after the expression"))
+                }
+            }
+            Some((version, _)) => Err(invalid(&format!("unsupported format version {}", version))),
+            None => Err(invalid("no data")),
+        }
+    }
+}
+
+// Lets expressions be parsed with `str::parse`
+impl FromStr for Expr {
+    type Err = MathError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Parser::parse_str(input)
+    }
+}
+
+// Lets numbers stand wherever an expression is expected, as when filling a `Template`
+impl From<f64> for Expr {
+    fn from(value: f64) -> Self {
+        Expr::Literal(value)
+    }
+}
+
+// One statement of a script such as `a = 2; b = 3; a^b`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Statement {
+    // An expression whose value is the statement's result
+    Expr(Expr),
+
+    // Binds a variable in the context; its result is the assigned value
+    Assign { name: String, value: Expr },
+}
+
+#[cfg(all(test, feature = "binary"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_round_trip() {
+        let expr = Expr::parse("sum([1, 2.5e-3, x]) * -(y ^ 2) + 1/3 >= 0 || !z").unwrap();
+        let bytes = expr.to_bytes();
+        assert_eq!(Expr::from_bytes(&bytes).unwrap(), expr);
+        // Much smaller than the JSON form
+        assert!(bytes.len() * 2 < serde_json::to_vec(&expr).unwrap().len());
+
+        let invalid = |bytes: &[u8]| matches!(Expr::from_bytes(bytes), Err(MathError::InvalidEncoding(_)));
+        assert!(invalid(&[]));
+        assert!(invalid(&[2, 0]));
+        assert!(invalid(&bytes[..bytes.len() - 1]));
+        assert!(invalid(&[bytes.as_slice(), &[0]].concat()));
+
+        // Deep trees are refused rather than overflowing the stack
+        let nest = |levels: usize| (0..levels).fold(Expr::literal(1.0), |expr, _| Expr::unary_minus(expr));
+        assert_eq!(Expr::from_bytes(&nest(256).to_bytes()).unwrap(), nest(256));
+        assert!(matches!(Expr::from_bytes(&nest(257).to_bytes()), Err(MathError::TooDeep(256))));
+        let mut deep = vec![BINARY_FORMAT];
+        deep.extend(std::iter::repeat_n(2, 1_000_000)); // UnaryMinus, a million times over
+        assert!(matches!(Expr::from_bytes(&deep), Err(MathError::TooDeep(256))));
+    }
 }
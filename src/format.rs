@@ -0,0 +1,924 @@
+// src/format.rs
+// Formatting of evaluation results for display
+use std::fmt;
+
+use crate::duration::format_duration;
+use crate::functions::{factorize, gcd};
+use crate::{Expr, Value};
+
+// How many digits to keep when formatting a result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Precision {
+    // A fixed number of digits after the decimal point (e.g., 2 => 3.14)
+    Decimals(u32),
+    // A fixed number of significant figures (e.g., 3 => 3.14, 0.00123, 1.23e10)
+    SignificantFigures(u32),
+}
+
+// How to round the digits that are dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoundingMode {
+    // Ties go away from zero (2.5 => 3, -2.5 => -3)
+    #[default]
+    HalfUp,
+    // Ties go toward zero (2.5 => 2)
+    HalfDown,
+    // Ties go to the even neighbour (2.5 => 2, 3.5 => 4)
+    HalfEven,
+    // Always toward zero (truncate)
+    Down,
+    // Always away from zero
+    Up,
+    // Always toward +inf
+    Ceiling,
+    // Always toward -inf
+    Floor,
+}
+
+// The overall shape of a printed number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Notation {
+    // Positional notation, switching to an exponent only when needed (e.g., 1500)
+    #[default]
+    Auto,
+    // One integer digit and an exponent (e.g., 1.5e3)
+    Scientific,
+    // One to three integer digits and an exponent that is a multiple of three (e.g., 15e3)
+    Engineering,
+    // Engineering notation with an SI prefix instead of the exponent (e.g., 15k, 4.7u)
+    SiPrefix,
+}
+
+// Separators used when printing numbers for a particular audience
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct Locale {
+    pub decimal_separator: char,
+    pub group_separator: Option<char>, // Inserted between groups of three integer digits
+}
+
+impl Locale {
+    // Plain machine-readable output: 1234567.89
+    pub const PLAIN: Locale = Locale {
+        decimal_separator: '.',
+        group_separator: None,
+    };
+    // English: 1,234,567.89
+    pub const EN: Locale = Locale {
+        decimal_separator: '.',
+        group_separator: Some(','),
+    };
+    // German, Italian, Spanish and others: 1.234.567,89
+    pub const DE: Locale = Locale {
+        decimal_separator: ',',
+        group_separator: Some('.'),
+    };
+    // French: 1 234 567,89 (narrow no-break space)
+    pub const FR: Locale = Locale {
+        decimal_separator: ',',
+        group_separator: Some('\u{202F}'),
+    };
+    // Swiss: 1'234'567.89
+    pub const CH: Locale = Locale {
+        decimal_separator: '.',
+        group_separator: Some('\''),
+    };
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::PLAIN
+    }
+}
+
+// Options controlling how a result is printed
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct FormatOptions {
+    pub notation: Notation,
+    pub locale: Locale,
+    pub precision: Option<Precision>, // None prints the shortest round-trip representation
+    pub rounding: RoundingMode,
+    pub trim_trailing_zeros: bool, // Print 2.5 instead of 2.500
+}
+
+impl FormatOptions {
+    // Round to a number of decimal places
+    pub fn decimals(places: u32) -> Self {
+        Self {
+            precision: Some(Precision::Decimals(places)),
+            ..Self::default()
+        }
+    }
+
+    // Round to a number of significant figures
+    pub fn significant_figures(figures: u32) -> Self {
+        Self {
+            precision: Some(Precision::SignificantFigures(figures)),
+            ..Self::default()
+        }
+    }
+
+    // Use the given notation
+    pub fn notation(mut self, notation: Notation) -> Self {
+        self.notation = notation;
+        self
+    }
+
+    // Use the given locale's separators
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    // Use the given rounding mode
+    pub fn rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    // Drop zeros after the last significant fractional digit
+    pub fn trim_trailing_zeros(mut self, trim: bool) -> Self {
+        self.trim_trailing_zeros = trim;
+        self
+    }
+}
+
+// The decimal digits of a finite value: 0.d0 d1 d2 ... scaled so that d0 sits at 10^exponent
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Decimal {
+    pub negative: bool,
+    pub digits: Vec<u8>, // Most significant first, no leading zeros (empty for zero)
+    pub exponent: i32,   // Power of ten of digits[0]
+}
+
+impl Decimal {
+    // Decomposes a finite value using its shortest round-trip representation, so 2.675 rounds
+    // the way a person reading "2.675" expects
+    pub fn from_f64(value: f64) -> Self {
+        let text = format!("{:e}", value.abs());
+        let (mantissa, exponent) = text.split_once('e').expect("LowerExp always has an exponent");
+        let exponent: i32 = exponent.parse().expect("LowerExp exponent is an integer");
+        let mut digits: Vec<u8> = mantissa
+            .bytes()
+            .filter(u8::is_ascii_digit)
+            .map(|b| b - b'0')
+            .collect();
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        Self {
+            negative: value.is_sign_negative(),
+            digits,
+            exponent,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    // Rounds so that the last kept digit sits at 10^place
+    pub fn round_at(&mut self, place: i32, mode: RoundingMode) {
+        if self.is_zero() {
+            return;
+        }
+        let keep = self.exponent - place + 1;
+        if keep >= self.digits.len() as i32 {
+            return;
+        }
+
+        // Everything being dropped: pad with zeros so the rounding position exists
+        if keep < 0 {
+            let mut padded = vec![0; (-keep) as usize];
+            padded.extend_from_slice(&self.digits);
+            self.digits = padded;
+            self.exponent += -keep;
+        }
+        let keep = keep.max(0) as usize;
+
+        let dropped = self.digits.split_off(keep);
+        let first = dropped[0];
+        let rest_nonzero = dropped[1..].iter().any(|&d| d != 0);
+        let any_nonzero = first != 0 || rest_nonzero;
+        let last_kept_odd = self.digits.last().is_some_and(|d| d % 2 == 1);
+
+        let round_away = match mode {
+            RoundingMode::HalfUp => first >= 5,
+            RoundingMode::HalfDown => first > 5 || (first == 5 && rest_nonzero),
+            RoundingMode::HalfEven => first > 5 || (first == 5 && (rest_nonzero || last_kept_odd)),
+            RoundingMode::Down => false,
+            RoundingMode::Up => any_nonzero,
+            RoundingMode::Ceiling => any_nonzero && !self.negative,
+            RoundingMode::Floor => any_nonzero && self.negative,
+        };
+
+        if round_away {
+            self.increment();
+        }
+        while self.digits.last() == Some(&0) {
+            self.digits.pop();
+        }
+        if self.is_zero() {
+            self.negative = false;
+        }
+    }
+
+    // Adds one unit in the last kept place
+    fn increment(&mut self) {
+        for digit in self.digits.iter_mut().rev() {
+            if *digit == 9 {
+                *digit = 0;
+            } else {
+                *digit += 1;
+                return;
+            }
+        }
+        // Carried past the most significant digit (also covers the empty case)
+        self.digits.insert(0, 1);
+        self.exponent += 1;
+    }
+
+    // The digit at 10^place
+    pub fn digit_at(&self, place: i32) -> u8 {
+        let index = self.exponent - place;
+        if index < 0 {
+            return 0;
+        }
+        self.digits.get(index as usize).copied().unwrap_or(0)
+    }
+
+    // Integer and fraction digits in positional notation with the given number of fraction digits
+    pub fn fixed_parts(&self, decimals: u32) -> (String, String) {
+        let top = if self.is_zero() { 0 } else { self.exponent.max(0) };
+        let integer: String = (0..=top)
+            .rev()
+            .map(|place| char::from(b'0' + self.digit_at(place)))
+            .collect();
+        let fraction: String = (1..=decimals as i32)
+            .map(|place| char::from(b'0' + self.digit_at(-place)))
+            .collect();
+        (integer, fraction)
+    }
+}
+
+// Rounds a value to the given precision, returning the rounded number
+pub fn round_to(value: f64, precision: Precision, mode: RoundingMode) -> f64 {
+    if !value.is_finite() {
+        return value;
+    }
+    let options = FormatOptions {
+        precision: Some(precision),
+        rounding: mode,
+        trim_trailing_zeros: true,
+        ..FormatOptions::default()
+    };
+    format_number(value, &options).parse().unwrap_or(value)
+}
+
+// Formats a result according to the options
+pub fn format_number(value: f64, options: &FormatOptions) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    if options.notation == Notation::Auto && options.precision.is_none() {
+        return localize(&value.to_string(), &options.locale);
+    }
+
+    let mut decimal = Decimal::from_f64(value);
+    let text = match options.notation {
+        Notation::Auto => match options.precision {
+            Some(Precision::Decimals(places)) => {
+                decimal.round_at(-(places as i32), options.rounding);
+                fixed(&decimal, 0, options.precision)
+            }
+            Some(Precision::SignificantFigures(figures)) => {
+                let figures = figures.max(1);
+                decimal.round_at(decimal.exponent - figures as i32 + 1, options.rounding);
+                // Like printf's %g: switch to scientific notation for very large or small values
+                if !decimal.is_zero() && (decimal.exponent < -4 || decimal.exponent >= figures as i32) {
+                    with_exponent(&decimal, decimal.exponent, options.precision)
+                } else {
+                    fixed(&decimal, 0, options.precision)
+                }
+            }
+            None => unreachable!("handled above"),
+        },
+        Notation::Scientific => {
+            round_mantissa(&mut decimal, options, |exponent| exponent);
+            with_exponent(&decimal, decimal.exponent, options.precision)
+        }
+        Notation::Engineering => {
+            round_mantissa(&mut decimal, options, engineering_exponent);
+            with_exponent(&decimal, engineering_exponent(decimal.exponent), options.precision)
+        }
+        Notation::SiPrefix => {
+            round_mantissa(&mut decimal, options, engineering_exponent);
+            let exponent = engineering_exponent(decimal.exponent);
+            match si_prefix(exponent) {
+                Some(prefix) => format!("{}{}", fixed(&decimal, exponent, options.precision), prefix),
+                None => with_exponent(&decimal, exponent, options.precision),
+            }
+        }
+    };
+
+    let text = if options.trim_trailing_zeros {
+        trim_zeros(&text)
+    } else {
+        text
+    };
+    let text = localize(&text, &options.locale);
+    if decimal.negative {
+        format!("-{}", text)
+    } else {
+        text
+    }
+}
+
+// Formats a number, date, duration, money, quantity or list result. List elements are separated by
+// ", ", or by "; " when the locale's decimal separator is a comma.
+pub fn format_value(value: &Value, options: &FormatOptions) -> String {
+    match value {
+        Value::Number(n) => format_number(*n, options),
+        Value::List(items) => {
+            let separator = if options.locale.decimal_separator == ',' { "; " } else { ", " };
+            let items: Vec<String> = items.iter().map(|item| format_value(item, options)).collect();
+            format!("[{}]", items.join(separator))
+        }
+        Value::Date(date) => date.to_string(),
+        Value::Duration(seconds) => format_duration(*seconds),
+        Value::Money(money) => money.to_string(),
+        Value::Quantity(quantity) => format!("{} {}", format_number(quantity.value, options), quantity.units()),
+    }
+}
+
+// Pads rendered text to the formatter's width, fill and alignment, never truncating it
+pub(crate) fn pad(f: &mut fmt::Formatter, rendered: &str) -> fmt::Result {
+    let len = rendered.chars().count();
+    let padding = f.width().map_or(0, |width| width.saturating_sub(len));
+    let (before, after) = match f.align() {
+        Some(fmt::Alignment::Right) => (padding, 0),
+        Some(fmt::Alignment::Center) => (padding / 2, padding - padding / 2),
+        Some(fmt::Alignment::Left) | None => (0, padding),
+    };
+    let fill = f.fill().to_string();
+    write!(f, "{}{}{}", fill.repeat(before), rendered, fill.repeat(after))
+}
+
+// Applies the locale's digit grouping and decimal separator to a formatted number
+fn localize(text: &str, locale: &Locale) -> String {
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text),
+    };
+    let integer_len = unsigned
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(unsigned.len());
+    let (integer, rest) = unsigned.split_at(integer_len);
+
+    let mut localized = String::from(sign);
+    for (i, digit) in integer.chars().enumerate() {
+        if let Some(separator) = locale.group_separator {
+            if i > 0 && (integer_len - i) % 3 == 0 {
+                localized.push(separator);
+            }
+        }
+        localized.push(digit);
+    }
+    match rest.strip_prefix('.') {
+        Some(fraction) => {
+            localized.push(locale.decimal_separator);
+            localized.push_str(fraction);
+        }
+        None => localized.push_str(rest),
+    }
+    localized
+}
+
+// Rounds for a notation whose mantissa is scaled by 10^scale(exponent)
+fn round_mantissa(decimal: &mut Decimal, options: &FormatOptions, scale: fn(i32) -> i32) {
+    match options.precision {
+        Some(Precision::Decimals(places)) => {
+            let place = scale(decimal.exponent) - places as i32;
+            decimal.round_at(place, options.rounding);
+        }
+        Some(Precision::SignificantFigures(figures)) => {
+            let place = decimal.exponent - figures.max(1) as i32 + 1;
+            decimal.round_at(place, options.rounding);
+        }
+        None => {}
+    }
+}
+
+// The exponent to use in engineering notation: the nearest multiple of three at or below
+fn engineering_exponent(exponent: i32) -> i32 {
+    exponent.div_euclid(3) * 3
+}
+
+// The SI prefix for a power of ten that is a multiple of three
+fn si_prefix(exponent: i32) -> Option<&'static str> {
+    let prefix = match exponent {
+        -24 => "y",
+        -21 => "z",
+        -18 => "a",
+        -15 => "f",
+        -12 => "p",
+        -9 => "n",
+        -6 => "u",
+        -3 => "m",
+        0 => "",
+        3 => "k",
+        6 => "M",
+        9 => "G",
+        12 => "T",
+        15 => "P",
+        18 => "E",
+        21 => "Z",
+        24 => "Y",
+        _ => return None,
+    };
+    Some(prefix)
+}
+
+// Positional notation of the value divided by 10^shift, without the sign. With no precision,
+// prints as many fraction digits as the value has.
+fn fixed(decimal: &Decimal, shift: i32, precision: Option<Precision>) -> String {
+    let shifted = Decimal {
+        exponent: decimal.exponent - shift,
+        ..decimal.clone()
+    };
+    let places = match precision {
+        Some(Precision::Decimals(places)) => places as i32,
+        Some(Precision::SignificantFigures(figures)) => figures.max(1) as i32 - 1 - shifted.exponent,
+        None => shifted.digits.len() as i32 - 1 - shifted.exponent,
+    };
+    let (integer, fraction) = shifted.fixed_parts(places.max(0) as u32);
+    if fraction.is_empty() {
+        integer
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+// Mantissa-and-exponent notation without the sign (e.g., 1.50e3)
+fn with_exponent(decimal: &Decimal, exponent: i32, precision: Option<Precision>) -> String {
+    format!("{}e{}", fixed(decimal, exponent, precision), exponent)
+}
+
+// The closest fraction p/q to a value with 1 <= q <= max_denominator, as (numerator, denominator).
+// Returns None if even that fraction is not within a relative tolerance of 1e-9.
+pub fn to_fraction(value: f64, max_denominator: u64) -> Option<(i64, u64)> {
+    let (numerator, denominator) = closest_fraction(value, max_denominator)?;
+    let approximation = numerator as f64 / denominator as f64;
+    if (approximation - value).abs() > 1e-9 * value.abs().max(1.0) {
+        return None;
+    }
+    Some((numerator, denominator))
+}
+
+// The closest fraction with a denominator up to max_denominator, however far away it is
+fn closest_fraction(value: f64, max_denominator: u64) -> Option<(i64, u64)> {
+    // Beyond 2^53 every f64 is an integer and the numerator could overflow
+    if !value.is_finite() || value.abs() > 9007199254740992.0 || max_denominator == 0 {
+        return None;
+    }
+
+    // Walk the continued fraction expansion, tracking the last two convergents h/k. Numerators
+    // must fit in an i64, and a convergent that would overflow ends the walk like a denominator
+    // over the limit.
+    let target = value.abs();
+    let next = |a: u64, x1: u64, x0: u64| a.checked_mul(x1).and_then(|x| x.checked_add(x0));
+    let (mut h0, mut h1) = (0u64, 1u64);
+    let (mut k0, mut k1) = (1u64, 0u64);
+    let mut x = target;
+    for _ in 0..64 {
+        let a = x.floor() as u64;
+        match (next(a, h1, h0), next(a, k1, k0)) {
+            (Some(h2), Some(k2)) if h2 <= i64::MAX as u64 && k2 <= max_denominator => {
+                (h0, h1, k0, k1) = (h1, h2, k1, k2);
+            }
+            _ => {
+                // The next convergent is out of range: the best semiconvergent may still beat
+                // the last convergent
+                let t = ((max_denominator - k0) / k1).min(a);
+                if let (Some(hs), Some(ks)) = (next(t, h1, h0), next(t, k1, k0)) {
+                    let error = |h: u64, k: u64| (target - h as f64 / k as f64).abs();
+                    if hs <= i64::MAX as u64 && error(hs, ks) < error(h1, k1) {
+                        (h1, k1) = (hs, ks);
+                    }
+                }
+                break;
+            }
+        }
+        let fraction = x - a as f64;
+        if fraction < 1e-12 {
+            break;
+        }
+        x = 1.0 / fraction;
+    }
+
+    let numerator = h1 as i64;
+    Some((if value < 0.0 { -numerator } else { numerator }, k1))
+}
+
+// Formats a value as a fraction such as 3/4 or -1/3 (whole numbers print without a denominator).
+// Returns None if no fraction with a denominator up to max_denominator is close enough.
+pub fn format_as_fraction(value: f64, max_denominator: u64) -> Option<String> {
+    let (numerator, denominator) = to_fraction(value, max_denominator)?;
+    if denominator == 1 {
+        Some(numerator.to_string())
+    } else {
+        Some(format!("{}/{}", numerator, denominator))
+    }
+}
+
+// The continued fraction [a0; a1, a2, ...] of a value, with at most max_terms terms: a0 is the
+// floor and the rest are positive. It stops early when the remainder is within rounding error, so
+// 0.75 is [0; 1, 3] and the square root of 2 is [1; 2, 2, 2, ...].
+pub fn continued_fraction(value: f64, max_terms: usize) -> Vec<i64> {
+    let mut terms = Vec::new();
+    if !value.is_finite() {
+        return terms;
+    }
+    let mut x = value;
+    while terms.len() < max_terms && x.abs() < 9007199254740992.0 {
+        let a = x.floor();
+        terms.push(a as i64);
+        let fraction = x - a;
+        if fraction < 1e-9 {
+            break;
+        }
+        x = 1.0 / fraction;
+    }
+    terms
+}
+
+// Formats a continued fraction as [a0; a1, a2], or [a0] for a whole number
+pub fn format_continued_fraction(value: f64, max_terms: usize) -> Option<String> {
+    let terms = continued_fraction(value, max_terms);
+    let (first, rest) = terms.split_first()?;
+    let rest: Vec<String> = rest.iter().map(|a| a.to_string()).collect();
+    if rest.is_empty() {
+        Some(format!("[{}]", first))
+    } else {
+        Some(format!("[{}; {}]", first, rest.join(", ")))
+    }
+}
+
+// A closed form recognised for a number, such as √2 for 1.4142135623730951
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExactForm {
+    pub text: String,    // The form: 3/4, √2/2, 3π/4, 2e
+    pub value: f64,      // The value of the form
+    pub confidence: f64, // From 0 to 1: how unlikely the match is to be a coincidence
+}
+
+// Denominators up to this are tried for exact forms
+const EXACT_MAX_DENOMINATOR: u64 = 1000;
+
+// The simplest likely closed form of a value: a fraction, a fraction times π or e, or a fraction
+// times a square root. The confidence counts the digits the form matches, less the digits its
+// denominator could match by chance, as a share of the digits in an f64. A value rounded from the
+// form scores near 1, and 355/113 for π only 0.2. Returns the most confident form with at least
+// min_confidence, preferring the simpler kind on ties.
+pub fn exact_form(value: f64, min_confidence: f64) -> Option<ExactForm> {
+    if !value.is_finite() {
+        return None;
+    }
+    let mut candidates = Vec::new();
+    for (symbol, constant) in [("", 1.0), ("π", std::f64::consts::PI), ("e", std::f64::consts::E)] {
+        if let Some((p, q)) = closest_fraction(value / constant, EXACT_MAX_DENOMINATOR) {
+            candidates.push((times(p, q, symbol), p as f64 / q as f64 * constant, q));
+        }
+    }
+    // value^2 = p/q, so value = √(pq)/q; square factors of pq move in front of the root
+    if let Some((p, q)) = closest_fraction(value * value, EXACT_MAX_DENOMINATOR) {
+        let (square, radicand) = square_part(p.unsigned_abs() * q);
+        if radicand > 1 {
+            let divisor = gcd(square.into(), q.into()) as u64;
+            let (p, q) = (square / divisor, q / divisor);
+            let sign = if value < 0.0 { -1 } else { 1 };
+            let form = (sign * p as i64) as f64 / q as f64 * (radicand as f64).sqrt();
+            candidates.push((times(sign * p as i64, q, &format!("√{}", radicand)), form, q));
+        }
+    }
+    let digits = -f64::EPSILON.log10();
+    candidates
+        .into_iter()
+        .map(|(text, form, q)| {
+            let error = ((form - value).abs() / value.abs().max(f64::MIN_POSITIVE)).max(f64::EPSILON);
+            let confidence = ((-error.log10() - 2.0 * (q as f64).log10()) / digits).clamp(0.0, 1.0);
+            ExactForm { text, value: form, confidence }
+        })
+        .fold(None, |best: Option<ExactForm>, form| match best {
+            Some(best) if best.confidence >= form.confidence => Some(best),
+            _ => Some(form),
+        })
+        .filter(|form| form.confidence >= min_confidence)
+}
+
+// p/q times a symbol: 3π/4, -π, √2/2, or 3/4 with no symbol
+fn times(p: i64, q: u64, symbol: &str) -> String {
+    let sign = if p < 0 { "-" } else { "" };
+    let numerator = match (p.unsigned_abs(), symbol) {
+        (n, "") => n.to_string(),
+        (1, symbol) => symbol.to_string(),
+        (n, symbol) => format!("{}{}", n, symbol),
+    };
+    match (p, q) {
+        (0, _) => "0".to_string(),
+        (_, 1) => format!("{}{}", sign, numerator),
+        _ => format!("{}{}/{}", sign, numerator, q),
+    }
+}
+
+// n as s^2 * r with r square-free, as (s, r), from its prime factors so that large n stay quick
+fn square_part(n: u64) -> (u64, u64) {
+    let factors = factorize(n);
+    let mut square = 1;
+    let mut i = 0;
+    while i < factors.len() {
+        if factors.get(i + 1) == Some(&factors[i]) {
+            square *= factors[i];
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    (square, n / (square * square))
+}
+
+// Formats an integer-valued result in the given radix (2 to 36). Bases 2, 8 and 16 get a 0b, 0o
+// or 0x prefix. Returns None for non-integers, out-of-range values, or an unsupported radix.
+pub fn format_radix(value: f64, radix: u32) -> Option<String> {
+    if !(2..=36).contains(&radix) || value.fract() != 0.0 || value.abs() >= 2f64.powi(127) {
+        return None;
+    }
+
+    let mut magnitude = value.abs() as u128;
+    let mut digits = Vec::new();
+    loop {
+        let digit = (magnitude % radix as u128) as u32;
+        digits.push(std::char::from_digit(digit, radix).expect("digit is below the radix"));
+        magnitude /= radix as u128;
+        if magnitude == 0 {
+            break;
+        }
+    }
+
+    let prefix = match radix {
+        2 => "0b",
+        8 => "0o",
+        16 => "0x",
+        _ => "",
+    };
+    let sign = if value < 0.0 { "-" } else { "" };
+    Some(format!("{}{}{}", sign, prefix, digits.iter().rev().collect::<String>()))
+}
+
+// The radix requested by a top-level hex(...), bin(...) or oct(...) call, if any
+pub fn output_radix(expr: &Expr) -> Option<u32> {
+    match expr {
+        Expr::Function { name, args } if args.len() == 1 => match name.as_str() {
+            "hex" => Some(16),
+            "bin" => Some(2),
+            "oct" => Some(8),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Removes trailing fractional zeros (and a dangling point), keeping any exponent suffix
+fn trim_zeros(text: &str) -> String {
+    let (number, suffix) = match text.find('e') {
+        Some(index) => text.split_at(index),
+        None => (text, ""),
+    };
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    };
+    format!("{}{}", number, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimal_places() {
+        assert_eq!(format_number(0.1 + 0.2, &FormatOptions::decimals(2)), "0.30");
+        assert_eq!(format_number(2.675, &FormatOptions::decimals(2)), "2.68");
+        assert_eq!(format_number(-1.005, &FormatOptions::decimals(2)), "-1.01");
+        assert_eq!(format_number(999.96, &FormatOptions::decimals(1)), "1000.0");
+        assert_eq!(format_number(1234.5, &FormatOptions::decimals(0)), "1235");
+        assert_eq!(format_number(0.0004, &FormatOptions::decimals(2)), "0.00");
+        assert_eq!(format_number(-0.0004, &FormatOptions::decimals(2)), "0.00");
+        assert_eq!(format_number(0.5, &FormatOptions::decimals(0)), "1");
+    }
+
+    #[test]
+    fn test_significant_figures() {
+        let three = FormatOptions::significant_figures(3);
+        assert_eq!(format_number(1.23456, &three), "1.23");
+        assert_eq!(format_number(0.00123456, &three), "0.00123");
+        assert_eq!(format_number(1.2, &three), "1.20");
+        assert_eq!(format_number(123456.0, &three), "1.23e5");
+        assert_eq!(format_number(0.0000123456, &three), "1.23e-5");
+        assert_eq!(format_number(99.96, &three), "100");
+        assert_eq!(format_number(0.0, &three), "0.00");
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let at = |value: f64, mode: RoundingMode| {
+            format_number(value, &FormatOptions::decimals(0).rounding(mode))
+        };
+        assert_eq!(at(2.5, RoundingMode::HalfUp), "3");
+        assert_eq!(at(2.5, RoundingMode::HalfDown), "2");
+        assert_eq!(at(2.5, RoundingMode::HalfEven), "2");
+        assert_eq!(at(3.5, RoundingMode::HalfEven), "4");
+        assert_eq!(at(2.7, RoundingMode::Down), "2");
+        assert_eq!(at(2.1, RoundingMode::Up), "3");
+        assert_eq!(at(-2.1, RoundingMode::Up), "-3");
+        assert_eq!(at(-2.1, RoundingMode::Ceiling), "-2");
+        assert_eq!(at(-2.1, RoundingMode::Floor), "-3");
+        assert_eq!(at(0.2, RoundingMode::Up), "1");
+    }
+
+    #[test]
+    fn test_trim_and_special_values() {
+        let trimmed = FormatOptions::decimals(4).trim_trailing_zeros(true);
+        assert_eq!(format_number(2.5, &trimmed), "2.5");
+        assert_eq!(format_number(3.0, &trimmed), "3");
+        assert_eq!(format_number(f64::INFINITY, &trimmed), "inf");
+        assert!(format_number(f64::NAN, &trimmed).eq_ignore_ascii_case("nan"));
+        assert_eq!(format_number(0.1 + 0.2, &FormatOptions::default()), "0.30000000000000004");
+    }
+
+    #[test]
+    fn test_scientific_notation() {
+        let scientific = FormatOptions::default().notation(Notation::Scientific);
+        assert_eq!(format_number(1500.0, &scientific), "1.5e3");
+        assert_eq!(format_number(-0.024, &scientific), "-2.4e-2");
+        assert_eq!(format_number(7.0, &scientific), "7e0");
+
+        let two_places = FormatOptions::decimals(2).notation(Notation::Scientific);
+        assert_eq!(format_number(123456.0, &two_places), "1.23e5");
+        assert_eq!(format_number(9.999, &two_places), "1.00e1");
+
+        let sig = FormatOptions::significant_figures(2).notation(Notation::Scientific);
+        assert_eq!(format_number(0.000123, &sig), "1.2e-4");
+    }
+
+    #[test]
+    fn test_engineering_notation() {
+        let engineering = FormatOptions::default().notation(Notation::Engineering);
+        assert_eq!(format_number(1500.0, &engineering), "1.5e3");
+        assert_eq!(format_number(15000.0, &engineering), "15e3");
+        assert_eq!(format_number(0.0047, &engineering), "4.7e-3");
+        assert_eq!(format_number(0.0, &engineering), "0e0");
+
+        let rounded = FormatOptions::decimals(1).notation(Notation::Engineering);
+        assert_eq!(format_number(999.96, &rounded), "1.0e3");
+
+        let si = FormatOptions::default().notation(Notation::SiPrefix);
+        assert_eq!(format_number(4700.0, &si), "4.7k");
+        assert_eq!(format_number(0.0000033, &si), "3.3u");
+        assert_eq!(format_number(-220e-9, &si), "-220n");
+        assert_eq!(format_number(12.0, &si), "12");
+        assert_eq!(format_number(1e30, &si), "1e30");
+    }
+
+    #[test]
+    fn test_locales() {
+        let german = FormatOptions::decimals(2).locale(Locale::DE);
+        assert_eq!(format_number(1234567.891, &german), "1.234.567,89");
+        assert_eq!(format_number(-1234.5, &german), "-1.234,50");
+        assert_eq!(format_number(999.0, &german), "999,00");
+
+        let english = FormatOptions::default().locale(Locale::EN);
+        assert_eq!(format_number(1234567.25, &english), "1,234,567.25");
+        assert_eq!(format_number(-100000.0, &english), "-100,000");
+
+        let swiss = FormatOptions::default().locale(Locale::CH);
+        assert_eq!(format_number(1e6, &swiss), "1'000'000");
+
+        // Exponent and prefix suffixes are left alone
+        let scientific = FormatOptions::default().notation(Notation::Scientific).locale(Locale::DE);
+        assert_eq!(format_number(1500.0, &scientific), "1,5e3");
+        let custom = Locale {
+            decimal_separator: ',',
+            group_separator: Some(' '),
+        };
+        assert_eq!(format_number(4700.5, &FormatOptions::default().locale(custom)), "4 700,5");
+    }
+
+    #[test]
+    fn test_fractions() {
+        assert_eq!(format_as_fraction(0.75, 100), Some("3/4".to_string()));
+        assert_eq!(format_as_fraction(1.0 / 3.0, 100), Some("1/3".to_string()));
+        assert_eq!(format_as_fraction(-2.5, 100), Some("-5/2".to_string()));
+        assert_eq!(format_as_fraction(4.0, 100), Some("4".to_string()));
+        assert_eq!(format_as_fraction(0.1 + 0.2, 100), Some("3/10".to_string()));
+        assert_eq!(format_as_fraction(355.0 / 113.0, 1000), Some("355/113".to_string()));
+
+        // Not representable within the denominator limit
+        assert_eq!(format_as_fraction(std::f64::consts::PI, 1000), None);
+        assert_eq!(format_as_fraction(1.0 / 3.0, 2), None);
+        assert_eq!(format_as_fraction(f64::NAN, 100), None);
+
+        // Any denominator limit, stopping at the last convergent that fits
+        assert_eq!(to_fraction(0.75, u64::MAX), Some((3, 4)));
+        let (p, q) = to_fraction(std::f64::consts::PI, u64::MAX).unwrap();
+        assert!((p as f64 / q as f64 - std::f64::consts::PI).abs() < 1e-15);
+        assert_eq!(to_fraction(-1e-300, u64::MAX), Some((0, 1)));
+        assert!(to_fraction(9007199254740991.5, u64::MAX).is_some());
+        assert!(to_fraction(1.0 / 3.0 + 1e-17, u64::MAX).is_some());
+    }
+
+    #[test]
+    fn test_exact_forms() {
+        use std::f64::consts::{E, PI};
+        assert_eq!(continued_fraction(0.75, 10), vec![0, 1, 3]);
+        assert_eq!(continued_fraction(2f64.sqrt(), 5), vec![1, 2, 2, 2, 2]);
+        assert_eq!(continued_fraction(-1.5, 10), vec![-2, 2]);
+        assert_eq!(format_continued_fraction(PI, 4), Some("[3; 7, 15, 1]".to_string()));
+        assert_eq!(format_continued_fraction(7.0, 4), Some("[7]".to_string()));
+
+        let text = |value: f64| exact_form(value, 0.8).map(|form| form.text);
+        assert_eq!(text(2f64.sqrt()), Some("√2".to_string()));
+        assert_eq!(text(PI), Some("π".to_string()));
+        assert_eq!(text(-3.0 * PI / 4.0), Some("-3π/4".to_string()));
+        assert_eq!(text(3f64.sqrt() / 2.0), Some("√3/2".to_string()));
+        assert_eq!(text(6.0 * 5f64.sqrt() / 7.0), Some("6√5/7".to_string()));
+        assert_eq!(text(2.0 * E), Some("2e".to_string()));
+        assert_eq!(text(0.1 + 0.2), Some("3/10".to_string()));
+        assert_eq!(text(0.0), Some("0".to_string()));
+        assert_eq!(text(1.2345678901234), None);
+        // Large denominators match by chance more often
+        assert_eq!(text(355.0 / 113.0), None);
+        assert_eq!(exact_form(355.0 / 113.0, 0.5).unwrap().text, "355/113");
+        let rounded = exact_form((PI * 1e4).round() / 1e4, 0.0).unwrap();
+        assert_eq!(rounded.text, "π");
+        assert!(rounded.confidence < 0.5);
+
+        assert_eq!(square_part(72), (6, 2));
+        assert_eq!(square_part(0), (1, 0));
+        assert_eq!(square_part(u64::MAX), (1, u64::MAX));
+        assert_eq!(square_part(4_294_967_291 * 4_294_967_291), (4_294_967_291, 1));
+        assert_eq!(text(12_345_677f64.sqrt()), Some("√12345677".to_string()));
+    }
+
+    #[test]
+    fn test_radix() {
+        assert_eq!(format_radix(255.0, 16), Some("0xff".to_string()));
+        assert_eq!(format_radix(5.0, 2), Some("0b101".to_string()));
+        assert_eq!(format_radix(-8.0, 8), Some("-0o10".to_string()));
+        assert_eq!(format_radix(0.0, 2), Some("0b0".to_string()));
+        assert_eq!(format_radix(35.0, 36), Some("z".to_string()));
+        assert_eq!(format_radix(2.5, 16), None);
+        assert_eq!(format_radix(f64::INFINITY, 16), None);
+        assert_eq!(format_radix(10.0, 1), None);
+
+        let call = |name: &str| Expr::function(name, vec![Expr::literal(1.0)]);
+        assert_eq!(output_radix(&call("hex")), Some(16));
+        assert_eq!(output_radix(&call("bin")), Some(2));
+        assert_eq!(output_radix(&Expr::binary(crate::Operator::Add, call("hex"), call("hex"))), None);
+    }
+
+    #[test]
+    fn test_format_value() {
+        let value = Value::List(vec![Value::from(vec![1.5, 2.0]), Value::Number(1234.5)]);
+        assert_eq!(format_value(&value, &FormatOptions::default()), "[[1.5, 2], 1234.5]");
+        let options = FormatOptions::decimals(1).locale(Locale::DE);
+        assert_eq!(format_value(&value, &options), "[[1,5; 2,0]; 1.234,5]");
+    }
+
+    #[test]
+    fn test_round_to() {
+        assert_eq!(round_to(2.675, Precision::Decimals(2), RoundingMode::HalfUp), 2.68);
+        assert_eq!(round_to(123456.0, Precision::SignificantFigures(2), RoundingMode::HalfUp), 120000.0);
+    }
+
+    #[test]
+    fn test_display_flags() {
+        let expr = Expr::parse("1 + 2 * 3 - 4 / x").unwrap();
+        assert_eq!(format!("{:#}", expr), "(1 + (2 * 3)) - (4 / x)");
+        assert_eq!(format!("{:#}", Expr::parse("2 ^ 3 ^ 2").unwrap()), "(2 ^ 3) ^ 2");
+        assert_eq!(format!("{:.2}", Expr::parse("1.5 * max(2, 1/3)").unwrap()), "1.50 * max(2.00, 1.00 / 3.00)");
+        assert_eq!(format!("[{:>8}]", Expr::parse("x+1").unwrap()), "[   x + 1]");
+        assert_eq!(format!("[{:*^#11.1}]", Expr::parse("1+2*3").unwrap()), "[1.0 + (2.0 * 3.0)]");
+        assert_eq!(format!("[{:-^9}]", Expr::parse("1+2").unwrap()), "[--1 + 2--]");
+        let statement = crate::Statement::Assign {
+            name: "a".to_string(),
+            value: Expr::parse("2*3").unwrap(),
+        };
+        assert_eq!(format!("{:<10}|{:.1}", statement, statement), "a = 2 * 3 |a = 2.0 * 3.0");
+    }
+}
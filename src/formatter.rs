@@ -0,0 +1,109 @@
+// src/formatter.rs
+// Formatting files of formulas (`mathexpr fmt [--check] [FILE]...`) with `format_source`: files
+// are rewritten in place, or standard input is formatted to standard output. With --check
+// nothing is written and the files that would change are listed instead.
+use std::fs;
+use std::io::{self, Read};
+
+use mathexpr::format_source;
+
+use crate::Failure;
+
+// Arguments of the fmt subcommand
+#[derive(Debug, Default, PartialEq)]
+pub struct FmtArgs {
+    check: bool,
+    files: Vec<String>, // Standard input when empty
+}
+
+pub const USAGE: &str = "Usage: mathexpr fmt [--check] [FILE]...";
+
+// Parses the arguments following `fmt`
+pub fn parse_args(args: &[String]) -> Result<FmtArgs, String> {
+    let mut parsed = FmtArgs::default();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => parsed.check = true,
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("Unexpected argument: {}", arg)),
+            _ => parsed.files.push(arg.clone()),
+        }
+    }
+    Ok(parsed)
+}
+
+pub fn run(args: &FmtArgs) -> Result<(), Failure> {
+    if args.files.is_empty() || args.files == ["-"] {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Error: cannot read standard input: {}", e);
+            return Err(Failure::Usage);
+        }
+        let formatted = format(&source, "<stdin>")?;
+        if args.check {
+            return if formatted == source { Ok(()) } else { Err(Failure::Check) };
+        }
+        print!("{}", formatted);
+        return Ok(());
+    }
+
+    // Every file is handled before failing, so all problems are reported at once
+    let mut result = Ok(());
+    for path in &args.files {
+        result = result.and(format_file(path, args.check));
+    }
+    result
+}
+
+fn format_file(path: &str, check: bool) -> Result<(), Failure> {
+    let source = fs::read_to_string(path).map_err(|e| {
+        eprintln!("Error: cannot read {}: {}", path, e);
+        Failure::Usage
+    })?;
+    let formatted = format(&source, path)?;
+    if formatted == source {
+        return Ok(());
+    }
+    if check {
+        println!("{}", path);
+        return Err(Failure::Check);
+    }
+    fs::write(path, formatted).map_err(|e| {
+        eprintln!("Error: cannot write {}: {}", path, e);
+        Failure::Usage
+    })
+}
+
+fn format(source: &str, path: &str) -> Result<String, Failure> {
+    format_source(source).map_err(|e| {
+        eprintln!("Error in {}, {}", path, e);
+        Failure::Parse
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fmt_files() {
+        let dir = std::env::temp_dir().join(format!("mathexpr-fmt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let messy = dir.join("messy.mexpr");
+        let tidy = dir.join("tidy.mexpr");
+        fs::write(&messy, "a=1+(2*3)\n").unwrap();
+        fs::write(&tidy, "a = 1 + 2 * 3\n").unwrap();
+        let files = vec![messy.display().to_string(), tidy.display().to_string()];
+
+        let check = FmtArgs { check: true, files: files.clone() };
+        assert_eq!(run(&check), Err(Failure::Check));
+        assert_eq!(fs::read_to_string(&messy).unwrap(), "a=1+(2*3)\n");
+
+        assert_eq!(run(&FmtArgs { check: false, files }), Ok(()));
+        assert_eq!(fs::read_to_string(&messy).unwrap(), "a = 1 + 2 * 3\n");
+        assert_eq!(run(&check), Ok(()));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(parse_args(&["--check".to_string(), "-".to_string()]).unwrap(), FmtArgs { check: true, files: vec!["-".to_string()] });
+        assert!(parse_args(&["--write".to_string()]).is_err());
+    }
+}
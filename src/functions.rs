@@ -0,0 +1,1053 @@
+// src/functions.rs
+// The standard library of built-in functions, available in every evaluation.
+// Context functions with the same name take precedence.
+use std::convert::Infallible;
+
+use crate::evaluator::Deadline;
+use crate::format::{round_to, Precision, RoundingMode};
+use crate::matrix;
+use crate::random::Rng;
+use crate::{MathError, Result, Value};
+
+// A built-in function over evaluated arguments
+pub type Builtin = fn(&[f64]) -> Result<f64>;
+
+// Looks up a built-in function by name
+pub fn builtin(name: &str) -> Option<Builtin> {
+    let function: Builtin = match name {
+        "hex" => |args| integer_identity("hex", args),
+        "bin" => |args| integer_identity("bin", args),
+        "oct" => |args| integer_identity("oct", args),
+        "min" => |args| {
+            expect_min_args("min", args, 1)?;
+            Ok(args.iter().copied().fold(f64::INFINITY, f64::min))
+        },
+        "max" => |args| {
+            expect_min_args("max", args, 1)?;
+            Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+        },
+        "clamp" => clamp,
+        "ncr" => |args| {
+            expect_args("ncr", args, 2)?;
+            let (n, k) = (natural("ncr", args[0])?, natural("ncr", args[1])?);
+            Ok(combinations(n, k))
+        },
+        "npr" => |args| {
+            expect_args("npr", args, 2)?;
+            let (n, k) = (natural("npr", args[0])?, natural("npr", args[1])?);
+            Ok(permutations(n, k))
+        },
+        "binom" => binom,
+        "gamma" => |args| {
+            expect_args("gamma", args, 1)?;
+            Ok(libm::tgamma(args[0]))
+        },
+        "gcd" => |args| {
+            expect_min_args("gcd", args, 1)?;
+            let values = integers("gcd", args)?;
+            Ok(values.into_iter().fold(0, |divisor, value| gcd(divisor, value) as i128) as f64)
+        },
+        "lcm" => |args| {
+            expect_min_args("lcm", args, 1)?;
+            let values = integers("lcm", args)?;
+            let multiple = values.iter().try_fold(1, |multiple, &value| lcm(multiple, value));
+            multiple
+                .map(|multiple| multiple as f64)
+                .ok_or_else(|| MathError::Overflow(format!("lcm of {} values", values.len())))
+        },
+        "floor" => |args| {
+            expect_args("floor", args, 1)?;
+            Ok(args[0].floor())
+        },
+        "ceil" => |args| {
+            expect_args("ceil", args, 1)?;
+            Ok(args[0].ceil())
+        },
+        "trunc" => |args| {
+            expect_args("trunc", args, 1)?;
+            Ok(args[0].trunc())
+        },
+        "round" => round,
+        "sign" => |args| {
+            expect_args("sign", args, 1)?;
+            let x = args[0];
+            Ok(if x > 0.0 { 1.0 } else if x < 0.0 { -1.0 } else { x * 0.0 })
+        },
+        "mod" => |args| {
+            expect_args("mod", args, 2)?;
+            // The evaluator calls `floored_mod` itself, following its division-by-zero policy
+            if args[1] == 0.0 {
+                return Err(MathError::DivisionByZero);
+            }
+            Ok(floored_mod(args[0], args[1]))
+        },
+        "isprime" => |args| {
+            expect_args("isprime", args, 1)?;
+            let n = natural("isprime", args[0])?;
+            Ok(if is_prime(n) { 1.0 } else { 0.0 })
+        },
+        "nextprime" => |args| {
+            expect_args("nextprime", args, 1)?;
+            let n = natural("nextprime", args[0])?;
+            next_prime_until(n, &Deadline::check)?.map(|p| p as f64).ok_or_else(|| MathError::InvalidArgument {
+                function: "nextprime".to_string(),
+                reason: format!("no prime above {} fits in 64 bits", n),
+            })
+        },
+        "totient" => |args| {
+            expect_args("totient", args, 1)?;
+            let n = natural("totient", args[0])?;
+            let mut factors = factorize_until(n, &Deadline::check)?;
+            factors.dedup();
+            Ok(factors.into_iter().fold(n, |phi, p| phi / p * (p - 1)) as f64)
+        },
+        "lerp" => |args| {
+            expect_args("lerp", args, 3)?;
+            let (a, b, t) = (args[0], args[1], args[2]);
+            Ok(a + (b - a) * t)
+        },
+        // Roots, exponentials, logarithms, and trigonometric functions of angles in radians
+        "sqrt" => |args| elementary("sqrt", args, libm::sqrt),
+        "cbrt" => |args| elementary("cbrt", args, libm::cbrt),
+        "exp" => |args| elementary("exp", args, libm::exp),
+        "ln" => |args| elementary("ln", args, libm::log),
+        "log" => log,
+        "log2" => |args| elementary("log2", args, libm::log2),
+        "log10" => |args| elementary("log10", args, libm::log10),
+        "sin" => |args| elementary("sin", args, libm::sin),
+        "cos" => |args| elementary("cos", args, libm::cos),
+        "tan" => |args| elementary("tan", args, libm::tan),
+        "asin" => |args| elementary("asin", args, libm::asin),
+        "acos" => |args| elementary("acos", args, libm::acos),
+        "atan" => |args| elementary("atan", args, libm::atan),
+        "atan2" => |args| {
+            expect_args("atan2", args, 2)?;
+            Ok(libm::atan2(args[0], args[1]))
+        },
+        "sinh" => |args| elementary("sinh", args, libm::sinh),
+        "cosh" => |args| elementary("cosh", args, libm::cosh),
+        "tanh" => |args| elementary("tanh", args, libm::tanh),
+        _ => return None,
+    };
+    Some(function)
+}
+
+// The roots, exponentials, logarithms and trigonometric functions among the built-ins. Their
+// results are rarely exact, so simplification only computes the whole ones.
+pub(crate) const ELEMENTARY: &[&str] = &[
+    "sqrt", "cbrt", "exp", "ln", "log", "log2", "log10", "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "sinh",
+    "cosh", "tanh",
+];
+
+// A function of one number. These use the portable libm routines, so they give the same results
+// on every platform. Outside their domain they give NaN, as ln(-1) does.
+fn elementary(name: &str, args: &[f64], f: fn(f64) -> f64) -> Result<f64> {
+    expect_args(name, args, 1)?;
+    Ok(f(args[0]))
+}
+
+// log(x) is the base-10 logarithm, and log(x, b) the base-b one
+fn log(args: &[f64]) -> Result<f64> {
+    match args {
+        [x] => Ok(libm::log10(*x)),
+        [x, base] => Ok(libm::log(*x) / libm::log(*base)),
+        _ => Err(MathError::ArgumentCount {
+            function: "log".to_string(),
+            expected: "1 or 2".to_string(),
+            found: args.len(),
+        }),
+    }
+}
+
+// The built-in constants, read like variables when the context does not define the name. They stay
+// names in the tree, so simplification keeps pi * 2 exact and only evaluation rounds them.
+pub fn constant(name: &str) -> Option<f64> {
+    match name {
+        "pi" | "π" => Some(std::f64::consts::PI),
+        "tau" | "τ" => Some(std::f64::consts::TAU),
+        "e" => Some(std::f64::consts::E),
+        _ => None,
+    }
+}
+
+// Checks that a function received exactly `expected` arguments
+pub(crate) fn expect_args<T>(name: &str, args: &[T], expected: usize) -> Result<()> {
+    if args.len() != expected {
+        return Err(MathError::ArgumentCount {
+            function: name.to_string(),
+            expected: expected.to_string(),
+            found: args.len(),
+        });
+    }
+    Ok(())
+}
+
+// Checks that a variadic function received at least `minimum` arguments
+pub(crate) fn expect_min_args<T>(name: &str, args: &[T], minimum: usize) -> Result<()> {
+    if args.len() < minimum {
+        return Err(MathError::ArgumentCount {
+            function: name.to_string(),
+            expected: format!("at least {}", minimum),
+            found: args.len(),
+        });
+    }
+    Ok(())
+}
+
+// clamp(x, lo, hi): x limited to the range [lo, hi]
+fn clamp(args: &[f64]) -> Result<f64> {
+    expect_args("clamp", args, 3)?;
+    let (x, lo, hi) = (args[0], args[1], args[2]);
+    if lo > hi || lo.is_nan() || hi.is_nan() {
+        return Err(MathError::InvalidArgument {
+            function: "clamp".to_string(),
+            reason: format!("empty range [{}, {}]", lo, hi),
+        });
+    }
+    Ok(x.clamp(lo, hi))
+}
+
+// Converts an argument that must be a non-negative integer
+pub(crate) fn natural(name: &str, value: f64) -> Result<u64> {
+    if value < 0.0 || value.fract() != 0.0 || value >= u64::MAX as f64 || value.is_nan() {
+        return Err(MathError::InvalidArgument {
+            function: name.to_string(),
+            reason: format!("{} is not a non-negative integer", value),
+        });
+    }
+    Ok(value as u64)
+}
+
+// Converts arguments that must all be integers
+fn integers(name: &str, args: &[f64]) -> Result<Vec<i128>> {
+    args.iter()
+        .map(|&value| {
+            if value.fract() != 0.0 || !value.is_finite() || value.abs() >= 2f64.powi(63) {
+                return Err(MathError::InvalidArgument {
+                    function: name.to_string(),
+                    reason: format!("{} is not an integer", value),
+                });
+            }
+            Ok(value as i128)
+        })
+        .collect()
+}
+
+// Greatest common divisor, as an unsigned number so that gcd(i128::MIN, 0) fits
+pub(crate) fn gcd(a: i128, b: i128) -> u128 {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+// Least common multiple, always non-negative (0 if either is 0), or None if it does not fit
+fn lcm(a: i128, b: i128) -> Option<i128> {
+    if a == 0 || b == 0 {
+        return Some(0);
+    }
+    (a / gcd(a, b) as i128).checked_mul(b)?.checked_abs()
+}
+
+// round(x) or round(x, digits): ties away from zero, decided on the decimal value as written
+// (round(2.675, 2) is 2.68). Negative digits round to tens, hundreds, and so on.
+fn round(args: &[f64]) -> Result<f64> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(MathError::ArgumentCount {
+            function: "round".to_string(),
+            expected: "1 or 2".to_string(),
+            found: args.len(),
+        });
+    }
+    let x = args[0];
+    let digits = match args.get(1) {
+        Some(&digits) if digits.fract() == 0.0 && digits.abs() <= 308.0 => digits as i32,
+        Some(&digits) => {
+            return Err(MathError::InvalidArgument {
+                function: "round".to_string(),
+                reason: format!("{} is not a valid number of digits", digits),
+            })
+        }
+        None => 0,
+    };
+
+    if digits >= 0 {
+        Ok(round_to(x, Precision::Decimals(digits as u32), RoundingMode::HalfUp))
+    } else {
+        let scale = 10f64.powi(-digits);
+        Ok((x / scale).round() * scale)
+    }
+}
+
+// A built-in function over lists as well as numbers
+pub type ListBuiltin = fn(&[Value]) -> Result<Value>;
+
+// Looks up a list built-in function by name
+pub fn list_builtin(name: &str) -> Option<ListBuiltin> {
+    let function: ListBuiltin = match name {
+        "len" => |args| {
+            expect_args("len", args, 1)?;
+            Ok(Value::Number(args[0].to_list()?.len() as f64))
+        },
+        "sum" => |args| {
+            expect_args("sum", args, 1)?;
+            let items = args[0].to_list()?;
+            items.iter().try_fold(0.0, |total, item| Ok(total + item.to_number()?)).map(Value::Number)
+        },
+        "slice" => slice,
+        "transpose" => |args| {
+            expect_args("transpose", args, 1)?;
+            matrix::transpose(&args[0])
+        },
+        "det" => |args| {
+            expect_args("det", args, 1)?;
+            Ok(Value::Number(matrix::determinant(square_matrix("det", &args[0])?)))
+        },
+        "inv" => |args| {
+            expect_args("inv", args, 1)?;
+            let a = square_matrix("inv", &args[0])?;
+            let identity = matrix::identity(a.len());
+            matrix::solve(a, identity).map(matrix::from_matrix).ok_or_else(|| singular("inv"))
+        },
+        "solve" => |args| {
+            expect_args("solve", args, 2)?;
+            let a = square_matrix("solve", &args[0])?;
+            // b is either a vector (one right-hand side) or a matrix of column right-hand sides
+            let (b, is_vector) = match (matrix::as_vector(&args[1]), matrix::as_matrix(&args[1])) {
+                (Some(b), _) => (b.into_iter().map(|x| vec![x]).collect(), true),
+                (None, Some(b)) => (b, false),
+                (None, None) => return Err(not_a("solve", "vector or matrix", &args[1])),
+            };
+            if b.len() != a.len() {
+                return Err(MathError::ShapeMismatch(format!(
+                    "cannot solve a {0}x{0} system with {1} right-hand side rows",
+                    a.len(),
+                    b.len()
+                )));
+            }
+            let x = matrix::solve(a, b).ok_or_else(|| singular("solve"))?;
+            Ok(if is_vector {
+                Value::from(x.into_iter().map(|row| row[0]).collect::<Vec<f64>>())
+            } else {
+                matrix::from_matrix(x)
+            })
+        },
+        "dot" => |args| {
+            expect_args("dot", args, 2)?;
+            let (a, b) = (vector("dot", &args[0])?, vector("dot", &args[1])?);
+            if a.len() != b.len() {
+                return Err(MathError::ShapeMismatch(format!(
+                    "cannot take the dot product of vectors of length {} and {}",
+                    a.len(),
+                    b.len()
+                )));
+            }
+            Ok(Value::Number(matrix::dot(&a, &b)))
+        },
+        "cross" => |args| {
+            expect_args("cross", args, 2)?;
+            let (a, b) = (vector("cross", &args[0])?, vector("cross", &args[1])?);
+            let [a1, a2, a3] = a[..] else { return Err(not_a("cross", "3-vector", &args[0])) };
+            let [b1, b2, b3] = b[..] else { return Err(not_a("cross", "3-vector", &args[1])) };
+            Ok(Value::from(vec![a2 * b3 - a3 * b2, a3 * b1 - a1 * b3, a1 * b2 - a2 * b1]))
+        },
+        // Euclidean norm of a vector, Frobenius norm of a matrix
+        "norm" => |args| {
+            expect_args("norm", args, 1)?;
+            let entries = match (matrix::as_vector(&args[0]), matrix::as_matrix(&args[0])) {
+                (Some(v), _) => v,
+                (None, Some(m)) => m.concat(),
+                (None, None) => return Err(not_a("norm", "vector or matrix", &args[0])),
+            };
+            Ok(Value::Number(entries.iter().fold(0.0, |acc: f64, x| acc.hypot(*x))))
+        },
+        "factorize" => |args| {
+            expect_args("factorize", args, 1)?;
+            let n = natural("factorize", args[0].to_number()?)?;
+            let factors = factorize_until(n, &Deadline::check)?;
+            Ok(Value::from(factors.into_iter().map(|p| p as f64).collect::<Vec<_>>()))
+        },
+        _ => return None,
+    };
+    Some(function)
+}
+
+// The numbers of a vector argument
+fn vector(name: &str, value: &Value) -> Result<Vec<f64>> {
+    matrix::as_vector(value).ok_or_else(|| not_a(name, "vector", value))
+}
+
+// The rows of a square matrix argument
+fn square_matrix(name: &str, value: &Value) -> Result<Vec<Vec<f64>>> {
+    match matrix::as_matrix(value) {
+        Some(rows) if rows.len() == rows[0].len() => Ok(rows),
+        _ => Err(not_a(name, "square matrix", value)),
+    }
+}
+
+fn not_a(name: &str, expected: &str, value: &Value) -> MathError {
+    MathError::InvalidArgument {
+        function: name.to_string(),
+        reason: format!("expected a {}, got a {}", expected, matrix::shape(value)),
+    }
+}
+
+fn singular(name: &str) -> MathError {
+    MathError::InvalidArgument {
+        function: name.to_string(),
+        reason: "matrix is singular".to_string(),
+    }
+}
+
+// slice(xs, start) or slice(xs, start, end): the elements from start up to (not including) end.
+// Negative bounds count from the end, and bounds past either end are clamped, as in Python.
+fn slice(args: &[Value]) -> Result<Value> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(MathError::ArgumentCount {
+            function: "slice".to_string(),
+            expected: "2 or 3".to_string(),
+            found: args.len(),
+        });
+    }
+    let items = args[0].to_list()?;
+    let len = items.len() as i64;
+    let bound = |value: &Value| -> Result<usize> {
+        let value = value.to_number()?;
+        if value.fract() != 0.0 || value.is_nan() {
+            return Err(MathError::NotAnInteger(value));
+        }
+        let index = value.clamp(-(len as f64), len as f64) as i64;
+        Ok(if index < 0 { index + len } else { index } as usize)
+    };
+    let start = bound(&args[1])?;
+    let end = args.get(2).map(bound).transpose()?.unwrap_or(items.len());
+    Ok(Value::List(items[start..end.max(start)].to_vec()))
+}
+
+// A built-in function that draws from the context's random number generator
+pub type RandomBuiltin = fn(&mut Rng, &[f64]) -> Result<f64>;
+
+// Looks up a random built-in function by name
+pub fn random_builtin(name: &str) -> Option<RandomBuiltin> {
+    let function: RandomBuiltin = match name {
+        "rand" => |rng, args| {
+            expect_args("rand", args, 0)?;
+            Ok(rng.next_f64())
+        },
+        "uniform" => |rng, args| {
+            expect_args("uniform", args, 2)?;
+            let (a, b) = range("uniform", args[0], args[1])?;
+            Ok(a + (b - a) * rng.next_f64())
+        },
+        "normal" => |rng, args| {
+            expect_args("normal", args, 2)?;
+            let (mu, sigma) = (args[0], args[1]);
+            if !(sigma >= 0.0 && sigma.is_finite() && mu.is_finite()) {
+                return Err(MathError::InvalidArgument {
+                    function: "normal".to_string(),
+                    reason: format!("needs a finite mean and a non-negative deviation, got {} and {}", mu, sigma),
+                });
+            }
+            Ok(mu + sigma * rng.next_normal())
+        },
+        "randint" => |rng, args| {
+            expect_args("randint", args, 2)?;
+            let bounds = integers("randint", args)?;
+            let (a, b) = (bounds[0], bounds[1]);
+            if a > b {
+                return Err(MathError::InvalidArgument {
+                    function: "randint".to_string(),
+                    reason: format!("empty range {}..={}", a, b),
+                });
+            }
+            // Both bounds are below 2^63 in magnitude, so the span fits in a u64
+            let span = (b - a) as u64;
+            let offset = if span == u64::MAX { rng.next_u64() } else { rng.below(span + 1) };
+            Ok((a + offset as i128) as f64)
+        },
+        _ => return None,
+    };
+    Some(function)
+}
+
+// Validates a finite, non-empty real range
+fn range(name: &str, a: f64, b: f64) -> Result<(f64, f64)> {
+    if !(a <= b && a.is_finite() && b.is_finite()) {
+        return Err(MathError::InvalidArgument {
+            function: name.to_string(),
+            reason: format!("{}..{} is not a valid range", a, b),
+        });
+    }
+    Ok((a, b))
+}
+
+// Deterministic Miller-Rabin; these bases are exact for every 64-bit n
+pub fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    const BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    for &p in &BASES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+
+    let mut d = n - 1;
+    let mut s = 0;
+    while d.is_multiple_of(2) {
+        d /= 2;
+        s += 1;
+    }
+
+    'witness: for &a in &BASES {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// The smallest prime strictly greater than n, if it fits in a u64
+pub fn next_prime(n: u64) -> Option<u64> {
+    let Ok(prime) = next_prime_until::<Infallible>(n, &|| Ok(()));
+    prime
+}
+
+// next_prime, stopping with the error of `check`, which runs before each candidate
+fn next_prime_until<E>(n: u64, check: &dyn Fn() -> std::result::Result<(), E>) -> std::result::Result<Option<u64>, E> {
+    let Some(mut candidate) = n.checked_add(1) else { return Ok(None) };
+    while !is_prime(candidate) {
+        check()?;
+        let Some(next) = candidate.checked_add(1) else { return Ok(None) };
+        candidate = next;
+    }
+    Ok(Some(candidate))
+}
+
+// Prime factors of n in ascending order, repeated by multiplicity (empty for 0 and 1)
+pub fn factorize(n: u64) -> Vec<u64> {
+    let Ok(factors) = factorize_until::<Infallible>(n, &|| Ok(()));
+    factors
+}
+
+// factorize, stopping with the error of `check`, which runs at each step of the search for divisors
+fn factorize_until<E>(mut n: u64, check: &dyn Fn() -> std::result::Result<(), E>) -> std::result::Result<Vec<u64>, E> {
+    let mut factors = Vec::new();
+    if n < 2 {
+        return Ok(factors);
+    }
+    let mut pending = vec![];
+    for p in [2, 3, 5] {
+        while n.is_multiple_of(p) {
+            factors.push(p);
+            n /= p;
+        }
+    }
+    if n > 1 {
+        pending.push(n);
+    }
+    while let Some(m) = pending.pop() {
+        if is_prime(m) {
+            factors.push(m);
+        } else {
+            let d = pollard_rho(m, check)?;
+            pending.push(d);
+            pending.push(m / d);
+        }
+    }
+    factors.sort_unstable();
+    Ok(factors)
+}
+
+// Euler's totient: how many of 1..=n are coprime to n
+pub fn totient(n: u64) -> u64 {
+    let mut factors = factorize(n);
+    factors.dedup();
+    factors.into_iter().fold(n, |phi, p| phi / p * (p - 1))
+}
+
+fn add_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 + b as u128) % m as u128) as u64
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut result = 1;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+// Finds a non-trivial divisor of an odd composite n (Pollard's rho with Floyd's cycle detection)
+fn pollard_rho<E>(n: u64, check: &dyn Fn() -> std::result::Result<(), E>) -> std::result::Result<u64, E> {
+    let mut c = 1;
+    loop {
+        let f = |x: u64| add_mod(mul_mod(x, x, n), c, n);
+        let (mut x, mut y, mut d) = (2, 2, 1);
+        while d == 1 {
+            check()?;
+            x = f(x);
+            y = f(f(y));
+            d = gcd(x.abs_diff(y) as i128, n as i128) as u64;
+        }
+        if d != n {
+            return Ok(d);
+        }
+        c += 1;
+    }
+}
+
+// Floored remainder: the result takes the sign of the divisor, so mod(-7, 3) is 2. A zero
+// divisor gives NaN, as with `%`.
+pub(crate) fn floored_mod(a: f64, b: f64) -> f64 {
+    let r = a % b;
+    if r != 0.0 && (r < 0.0) != (b < 0.0) { r + b } else { r }
+}
+
+// ln(n!) computed through the log-gamma function
+fn ln_factorial(n: u64) -> f64 {
+    libm::lgamma(n as f64 + 1.0)
+}
+
+// C(n, k): exact while the result fits in 128 bits, otherwise computed in log space
+fn combinations(n: u64, k: u64) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    let k = k.min(n - k);
+    let mut result: u128 = 1;
+    for i in 0..k {
+        // result * (n - i) is always divisible by i + 1 since it is C(n, i + 1) * (i + 1)
+        match result.checked_mul((n - i) as u128) {
+            Some(product) => result = product / (i + 1) as u128,
+            None => return (ln_factorial(n) - ln_factorial(k) - ln_factorial(n - k)).exp().round(),
+        }
+    }
+    result as f64
+}
+
+// P(n, k) = n! / (n - k)!: exact while the result fits in 128 bits, otherwise computed in log space
+fn permutations(n: u64, k: u64) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    let mut result: u128 = 1;
+    for i in 0..k {
+        match result.checked_mul((n - i) as u128) {
+            Some(product) => result = product,
+            None => return (ln_factorial(n) - ln_factorial(n - k)).exp().round(),
+        }
+    }
+    result as f64
+}
+
+// The most factors binom multiplies out for a non-integer x
+const MAX_BINOM_TERMS: u64 = 1_000_000;
+
+// binom(x, k): the binomial coefficient generalized to any real x and integer k >= 0,
+// x (x - 1) ... (x - k + 1) / k!. Equal to ncr for non-negative integers.
+fn binom(args: &[f64]) -> Result<f64> {
+    expect_args("binom", args, 2)?;
+    let (x, k) = (args[0], natural("binom", args[1])?);
+    if x >= 0.0 && x.fract() == 0.0 {
+        return Ok(combinations(x as u64, k));
+    }
+    if x.fract() == 0.0 {
+        // binom(-n, k) = (-1)^k C(n + k - 1, k)
+        let sign = if k.is_multiple_of(2) { 1.0 } else { -1.0 };
+        return Ok(sign * combinations(natural("binom", k as f64 - x - 1.0)?, k));
+    }
+    if k > MAX_BINOM_TERMS {
+        return Err(MathError::InvalidArgument {
+            function: "binom".to_string(),
+            reason: format!("k above {} needs an integer x, got {}", MAX_BINOM_TERMS, x),
+        });
+    }
+    let mut result = 1.0;
+    for i in 0..k {
+        if i.is_multiple_of(4096) {
+            Deadline::check()?;
+        }
+        result = result * (x - i as f64) / (i + 1) as f64;
+    }
+    Ok(result)
+}
+
+// hex(x), bin(x), oct(x): the value itself, which must be an integer. The radix only affects how
+// the result is displayed (see format::output_radix).
+fn integer_identity(name: &str, args: &[f64]) -> Result<f64> {
+    expect_args(name, args, 1)?;
+    let value = args[0];
+    if value.fract() != 0.0 || !value.is_finite() {
+        return Err(MathError::InvalidArgument {
+            function: name.to_string(),
+            reason: format!("{} is not an integer", value),
+        });
+    }
+    Ok(value)
+}
+
+// Documentation of a built-in function, for help and completion in interactive tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionInfo {
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub description: &'static str,
+}
+
+const fn info(name: &'static str, signature: &'static str, description: &'static str) -> FunctionInfo {
+    FunctionInfo {
+        name,
+        signature,
+        description,
+    }
+}
+
+// Every built-in function, in alphabetical order
+pub const FUNCTIONS: &[FunctionInfo] = &[
+    info("acos", "acos(x)", "Arccosine of x, in radians from 0 to pi"),
+    info("asin", "asin(x)", "Arcsine of x, in radians from -pi/2 to pi/2"),
+    info("atan", "atan(x)", "Arctangent of x, in radians from -pi/2 to pi/2"),
+    info("atan2", "atan2(y, x)", "Angle of the point (x, y) from the x axis, in radians from -pi to pi"),
+    info("bin", "bin(x)", "x itself, displayed in binary"),
+    info("binom", "binom(x, k)", "Binomial coefficient for any real x and integer k >= 0"),
+    info("cbrt", "cbrt(x)", "Cube root of x"),
+    info("ceil", "ceil(x)", "Smallest integer not below x"),
+    info("clamp", "clamp(x, lo, hi)", "x limited to the range [lo, hi]"),
+    info("convert", "convert(x, \"from\", \"to\")", "x in the unit from as a number of the unit to; also written x from in to"),
+    info("cos", "cos(x)", "Cosine of an angle in radians"),
+    info("cosh", "cosh(x)", "Hyperbolic cosine"),
+    info("cross", "cross(a, b)", "Cross product of two 3-vectors"),
+    info("date", "date(year, month, day)", "The date of a day; adding n moves it n days, and two dates subtract to the days between"),
+    info("day", "day(date)", "Day of the month of a date"),
+    info("days", "days(x)", "A duration in days, or x days as a duration"),
+    info("days_between", "days_between(from, to)", "Days from one date to another"),
+    info("det", "det(m)", "Determinant of a square matrix"),
+    info("dot", "dot(a, b)", "Dot product of two vectors of the same length"),
+    info("exp", "exp(x)", "e raised to the power x"),
+    info("factorize", "factorize(n)", "Prime factors of n, with repetition, in ascending order"),
+    info("floor", "floor(x)", "Largest integer not above x"),
+    info("gamma", "gamma(x)", "The gamma function; gamma(n + 1) is n!"),
+    info("gcd", "gcd(a, b, ...)", "Greatest common divisor of integers"),
+    info("hex", "hex(x)", "x itself, displayed in hexadecimal"),
+    info("hours", "hours(x)", "A duration in hours, or x hours as a duration"),
+    info("if", "if(condition, then, otherwise)", "then if condition is non-zero, otherwise otherwise; only one branch is evaluated"),
+    info("inv", "inv(m)", "Inverse of a square matrix"),
+    info("isprime", "isprime(n)", "1 if n is prime, 0 otherwise"),
+    info("lcm", "lcm(a, b, ...)", "Least common multiple of integers"),
+    info("len", "len(list)", "Number of items in a list"),
+    info("lerp", "lerp(a, b, t)", "Linear interpolation a + (b - a) t"),
+    info("ln", "ln(x)", "Natural logarithm"),
+    info("log", "log(x, base)", "Logarithm in the base, 10 when omitted"),
+    info("log10", "log10(x)", "Base-10 logarithm"),
+    info("log2", "log2(x)", "Base-2 logarithm"),
+    info("max", "max(x, ...)", "Largest argument"),
+    info("min", "min(x, ...)", "Smallest argument"),
+    info("minutes", "minutes(x)", "A duration in minutes, or x minutes as a duration"),
+    info("mod", "mod(a, b)", "Floored remainder, with the sign of b"),
+    info("month", "month(date)", "Month of a date, from 1 to 12"),
+    info("ncr", "ncr(n, k)", "Number of ways to choose k of n items"),
+    info("nextprime", "nextprime(n)", "Smallest prime above n"),
+    info("norm", "norm(v)", "Euclidean norm of a vector, Frobenius norm of a matrix"),
+    info("normal", "normal(mu, sigma)", "Random number from a normal distribution"),
+    info("npr", "npr(n, k)", "Number of ordered arrangements of k of n items"),
+    info("oct", "oct(x)", "x itself, displayed in octal"),
+    info("piecewise", "piecewise([condition, value], ..., otherwise)", "Value of the first branch whose condition holds"),
+    info("rand", "rand()", "Random number in [0, 1)"),
+    info("randint", "randint(a, b)", "Random integer in [a, b]"),
+    info("round", "round(x, digits)", "x rounded half away from zero, to 0 digits when omitted"),
+    info("seconds", "seconds(x)", "A duration in seconds, or x seconds as a duration"),
+    info("sign", "sign(x)", "-1, 0 or 1 by the sign of x"),
+    info("sin", "sin(x)", "Sine of an angle in radians"),
+    info("sinh", "sinh(x)", "Hyperbolic sine"),
+    info("slice", "slice(list, start, end)", "Items from start up to end (the end of the list when omitted)"),
+    info("solve", "solve(a, b)", "Solution x of the linear system a x = b, or of equations in unknowns"),
+    info("sqrt", "sqrt(x)", "Square root of x, as √x"),
+    info("sum", "sum(list)", "Sum of the items of a list"),
+    info("tan", "tan(x)", "Tangent of an angle in radians"),
+    info("tanh", "tanh(x)", "Hyperbolic tangent"),
+    info("today", "today()", "The current date (UTC)"),
+    info("totient", "totient(n)", "Euler's totient: integers in [1, n] coprime to n"),
+    info("transpose", "transpose(m)", "Transpose of a matrix"),
+    info("trunc", "trunc(x)", "x with its fractional part removed"),
+    info("uniform", "uniform(a, b)", "Random number in [a, b)"),
+    info("weekday", "weekday(date)", "Day of the week of a date, from 1 for Monday to 7 for Sunday"),
+    info("year", "year(date)", "Year of a date"),
+];
+
+// Looks up the documentation of a built-in function
+pub fn function_info(name: &str) -> Option<&'static FunctionInfo> {
+    FUNCTIONS.iter().find(|info| info.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_info() {
+        for info in FUNCTIONS {
+            let known = builtin(info.name).is_some()
+                || list_builtin(info.name).is_some()
+                || random_builtin(info.name).is_some()
+                || crate::date::builtin(info.name).is_some()
+                || crate::duration::builtin(info.name).is_some()
+                || ["convert", "if", "piecewise"].contains(&info.name);
+            assert!(known, "{} is not a function", info.name);
+            assert!(info.signature.starts_with(&format!("{}(", info.name)));
+        }
+        assert!(FUNCTIONS.windows(2).all(|pair| pair[0].name < pair[1].name));
+        assert_eq!(function_info("lerp").unwrap().signature, "lerp(a, b, t)");
+        assert!(function_info("nope").is_none());
+    }
+
+    #[test]
+    fn test_radix_functions() {
+        let hex = builtin("hex").unwrap();
+        assert_eq!(hex(&[255.0]).unwrap(), 255.0);
+        assert!(matches!(hex(&[2.5]), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(
+            builtin("bin").unwrap()(&[1.0, 2.0]),
+            Err(MathError::ArgumentCount { found: 2, .. })
+        ));
+        assert!(builtin("nope").is_none());
+    }
+
+    #[test]
+    fn test_combinatorics() {
+        let call = |name: &str, args: &[f64]| builtin(name).unwrap()(args);
+        assert_eq!(call("ncr", &[5.0, 2.0]).unwrap(), 10.0);
+        assert_eq!(call("ncr", &[5.0, 7.0]).unwrap(), 0.0);
+        assert_eq!(call("npr", &[5.0, 2.0]).unwrap(), 20.0);
+        assert_eq!(call("ncr", &[0.0, 0.0]).unwrap(), 1.0);
+
+        // Exact where the integer result is representable, and no overflow beyond that
+        assert_eq!(call("ncr", &[60.0, 30.0]).unwrap(), 118264581564861424.0);
+        let huge = call("ncr", &[1000.0, 500.0]).unwrap();
+        assert!((huge / 2.702882409454366e299 - 1.0).abs() < 1e-9);
+        assert!(call("npr", &[200.0, 100.0]).unwrap().is_finite());
+        assert!(matches!(call("ncr", &[2.5, 1.0]), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(call("npr", &[-1.0, 1.0]), Err(MathError::InvalidArgument { .. })));
+
+        assert_eq!(call("binom", &[6.0, 3.0]).unwrap(), 20.0);
+        assert_eq!(call("binom", &[0.5, 2.0]).unwrap(), -0.125);
+        assert_eq!(call("binom", &[-1.0, 3.0]).unwrap(), -1.0);
+
+        // Huge k is exact for integer x and refused rather than multiplied out otherwise
+        assert!(matches!(call("binom", &[0.5, 1e15]), Err(MathError::InvalidArgument { .. })));
+        assert_eq!(call("binom", &[-1.0, 1e15]).unwrap(), 1.0);
+        assert_eq!(call("binom", &[-3.0, 1e15]).unwrap(), 500000000000001500000000000001.0);
+        assert!(call("binom", &[0.5, 1e6]).unwrap().abs() < 1e-9);
+
+        assert_eq!(call("gamma", &[5.0]).unwrap(), 24.0);
+        assert!((call("gamma", &[0.5]).unwrap() - std::f64::consts::PI.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_integer_utilities() {
+        let call = |name: &str, args: &[f64]| builtin(name).unwrap()(args);
+        assert_eq!(call("gcd", &[12.0, -18.0]).unwrap(), 6.0);
+        assert_eq!(call("gcd", &[12.0, 18.0, 8.0]).unwrap(), 2.0);
+        assert_eq!(call("gcd", &[0.0, 0.0]).unwrap(), 0.0);
+        assert_eq!(call("lcm", &[4.0, -6.0]).unwrap(), 12.0);
+        assert_eq!(call("lcm", &[0.0, 5.0]).unwrap(), 0.0);
+        // Large coprime integers whose product does not fit
+        let primes = [9007199254740881.0, 9007199254740847.0, 9007199254740997.0];
+        assert!(matches!(call("lcm", &primes), Err(MathError::Overflow(_))));
+        assert!(matches!(call("gcd", &[1.5, 3.0]), Err(MathError::InvalidArgument { .. })));
+
+        assert_eq!(call("floor", &[-2.5]).unwrap(), -3.0);
+        assert_eq!(call("ceil", &[-2.5]).unwrap(), -2.0);
+        assert_eq!(call("trunc", &[-2.5]).unwrap(), -2.0);
+
+        assert_eq!(call("round", &[2.5]).unwrap(), 3.0);
+        assert_eq!(call("round", &[-2.5]).unwrap(), -3.0);
+        assert_eq!(call("round", &[2.675, 2.0]).unwrap(), 2.68);
+        assert_eq!(call("round", &[1234.5, -2.0]).unwrap(), 1200.0);
+        assert!(matches!(call("round", &[1.0, 0.5]), Err(MathError::InvalidArgument { .. })));
+
+        assert_eq!(call("sign", &[-4.0]).unwrap(), -1.0);
+        assert_eq!(call("sign", &[0.0]).unwrap(), 0.0);
+        assert_eq!(call("sign", &[3.0]).unwrap(), 1.0);
+
+        assert_eq!(call("mod", &[7.0, 3.0]).unwrap(), 1.0);
+        assert_eq!(call("mod", &[-7.0, 3.0]).unwrap(), 2.0);
+        assert_eq!(call("mod", &[7.0, -3.0]).unwrap(), -2.0);
+        assert_eq!(call("mod", &[-7.0, -3.0]).unwrap(), -1.0);
+        assert_eq!(call("mod", &[5.5, 2.0]).unwrap(), 1.5);
+        assert!(matches!(call("mod", &[1.0, 0.0]), Err(MathError::DivisionByZero)));
+    }
+
+    #[test]
+    fn test_number_theory() {
+        let call = |name: &str, args: &[f64]| builtin(name).unwrap()(args);
+        assert_eq!(call("isprime", &[97.0]).unwrap(), 1.0);
+        assert_eq!(call("isprime", &[1.0]).unwrap(), 0.0);
+        assert_eq!(call("isprime", &[91.0]).unwrap(), 0.0);
+        assert_eq!(call("nextprime", &[13.0]).unwrap(), 17.0);
+        assert_eq!(call("nextprime", &[0.0]).unwrap(), 2.0);
+        assert_eq!(call("totient", &[36.0]).unwrap(), 12.0);
+        assert_eq!(call("totient", &[1.0]).unwrap(), 1.0);
+        assert!(matches!(call("isprime", &[-7.0]), Err(MathError::InvalidArgument { .. })));
+        // 2^64 does not fit, and the float just below it does
+        assert!(matches!(call("isprime", &[2f64.powi(64)]), Err(MathError::InvalidArgument { .. })));
+        assert_eq!(natural("isprime", 2f64.powi(64) - 2048.0).unwrap(), u64::MAX - 2047);
+
+        // Mersenne prime 2^61 - 1 and a product of two large primes
+        assert!(is_prime((1 << 61) - 1));
+        assert!(!is_prime(4_294_967_291 * 4_294_967_279));
+        assert_eq!(factorize(360), vec![2, 2, 2, 3, 3, 5]);
+        assert_eq!(factorize(4_294_967_291 * 4_294_967_279), vec![4_294_967_279, 4_294_967_291]);
+        // Near 2^64, where the steps of the divisor search must not overflow
+        assert_eq!(factorize(u64::MAX - 2), vec![13, 3889, 364_870_227_143_809]);
+        assert_eq!(factorize(u64::MAX - 32), vec![827, 3_894_899, 5_726_879_071]);
+        assert_eq!(add_mod(u64::MAX - 1, 5, u64::MAX), 4);
+        assert_eq!(factorize(1), Vec::<u64>::new());
+        assert_eq!(next_prime(u64::MAX - 1), None);
+    }
+
+    #[test]
+    fn test_list_functions() {
+        let call = |name: &str, args: &[Value]| list_builtin(name).unwrap()(args);
+        let xs = Value::from(vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(call("len", std::slice::from_ref(&xs)).unwrap(), Value::Number(4.0));
+        assert_eq!(call("sum", std::slice::from_ref(&xs)).unwrap(), Value::Number(10.0));
+        assert_eq!(call("slice", &[xs.clone(), 1.0.into()]).unwrap(), Value::from(vec![2.0, 3.0, 4.0]));
+        assert_eq!(call("slice", &[xs.clone(), 1.0.into(), (-1.0).into()]).unwrap(), Value::from(vec![2.0, 3.0]));
+        assert_eq!(call("slice", &[xs.clone(), 3.0.into(), 1.0.into()]).unwrap(), Value::List(vec![]));
+        assert_eq!(call("slice", &[xs.clone(), (-10.0).into(), 10.0.into()]).unwrap(), xs);
+        assert_eq!(call("factorize", &[360.0.into()]).unwrap(), Value::from(vec![2.0, 2.0, 2.0, 3.0, 3.0, 5.0]));
+
+        assert!(matches!(call("len", &[2.0.into()]), Err(MathError::TypeMismatch { .. })));
+        assert!(matches!(call("sum", &[Value::List(vec![xs.clone()])]), Err(MathError::TypeMismatch { .. })));
+        assert!(matches!(call("slice", &[xs.clone(), 0.5.into()]), Err(MathError::NotAnInteger(_))));
+        assert!(matches!(call("slice", &[xs]), Err(MathError::ArgumentCount { .. })));
+        assert_eq!(
+            call("transpose", &[Value::from(vec![Value::from(vec![1.0, 2.0])])]).unwrap(),
+            Value::from(vec![Value::from(vec![1.0]), Value::from(vec![2.0])])
+        );
+    }
+
+    #[test]
+    fn test_linear_algebra() {
+        let call = |name: &str, args: &[Value]| list_builtin(name).unwrap()(args);
+        let m = |rows: Vec<Vec<f64>>| matrix::from_matrix(rows);
+        let a = m(vec![vec![4.0, 7.0], vec![2.0, 6.0]]);
+
+        assert_eq!(call("det", std::slice::from_ref(&a)).unwrap(), 10.0);
+        let inverse = call("inv", std::slice::from_ref(&a)).unwrap();
+        let expected = [[0.6, -0.7], [-0.2, 0.4]];
+        for (row, expected) in matrix::as_matrix(&inverse).unwrap().iter().zip(expected) {
+            for (x, e) in row.iter().zip(expected) {
+                assert!((x - e).abs() < 1e-12);
+            }
+        }
+        let x = call("solve", &[a.clone(), Value::from(vec![18.0, 14.0])]).unwrap();
+        assert_eq!(x, Value::from(vec![1.0, 2.0]));
+        let x = call("solve", &[a.clone(), m(vec![vec![18.0], vec![14.0]])]).unwrap();
+        assert_eq!(x, m(vec![vec![1.0], vec![2.0]]));
+
+        assert_eq!(call("dot", &[Value::from(vec![1.0, 2.0]), Value::from(vec![3.0, 4.0])]).unwrap(), 11.0);
+        assert_eq!(
+            call("cross", &[Value::from(vec![1.0, 0.0, 0.0]), Value::from(vec![0.0, 1.0, 0.0])]).unwrap(),
+            Value::from(vec![0.0, 0.0, 1.0])
+        );
+        assert_eq!(call("norm", &[Value::from(vec![3.0, 4.0])]).unwrap(), 5.0);
+        assert_eq!(call("norm", &[m(vec![vec![1.0, 1.0], vec![1.0, 1.0]])]).unwrap(), 2.0);
+
+        let singular = m(vec![vec![1.0, 2.0], vec![2.0, 4.0]]);
+        assert!(matches!(call("inv", &[singular]), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(call("det", &[m(vec![vec![1.0, 2.0]])]), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(call("cross", &[Value::from(vec![1.0, 2.0]), Value::from(vec![1.0, 2.0])]), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(call("solve", &[a, Value::from(vec![1.0])]), Err(MathError::ShapeMismatch(_))));
+    }
+
+    #[test]
+    fn test_random_functions() {
+        let call = |rng: &mut Rng, name: &str, args: &[f64]| random_builtin(name).unwrap()(rng, args);
+        let mut rng = Rng::seeded(1);
+        for _ in 0..200 {
+            let x = call(&mut rng, "uniform", &[-2.0, 3.0]).unwrap();
+            assert!((-2.0..3.0).contains(&x));
+            let n = call(&mut rng, "randint", &[1.0, 6.0]).unwrap();
+            assert!((1.0..=6.0).contains(&n) && n.fract() == 0.0);
+        }
+        assert_eq!(call(&mut rng, "randint", &[4.0, 4.0]).unwrap(), 4.0);
+        assert_eq!(call(&mut rng, "normal", &[5.0, 0.0]).unwrap(), 5.0);
+
+        assert!(matches!(call(&mut rng, "rand", &[1.0]), Err(MathError::ArgumentCount { .. })));
+        assert!(matches!(call(&mut rng, "uniform", &[3.0, 1.0]), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(call(&mut rng, "randint", &[3.0, 1.0]), Err(MathError::InvalidArgument { .. })));
+        assert!(matches!(call(&mut rng, "normal", &[0.0, -1.0]), Err(MathError::InvalidArgument { .. })));
+    }
+
+    #[test]
+    fn test_min_max_clamp_lerp() {
+        let call = |name: &str, args: &[f64]| builtin(name).unwrap()(args);
+        assert_eq!(call("min", &[3.0, -1.0, 2.0]).unwrap(), -1.0);
+        assert_eq!(call("max", &[3.0, -1.0, 2.0]).unwrap(), 3.0);
+        assert_eq!(call("max", &[7.0]).unwrap(), 7.0);
+        assert!(matches!(call("min", &[]), Err(MathError::ArgumentCount { .. })));
+
+        assert_eq!(call("clamp", &[5.0, 0.0, 1.0]).unwrap(), 1.0);
+        assert_eq!(call("clamp", &[-5.0, 0.0, 1.0]).unwrap(), 0.0);
+        assert_eq!(call("clamp", &[0.5, 0.0, 1.0]).unwrap(), 0.5);
+        assert!(matches!(call("clamp", &[0.5, 1.0, 0.0]), Err(MathError::InvalidArgument { .. })));
+
+        assert_eq!(call("lerp", &[10.0, 20.0, 0.25]).unwrap(), 12.5);
+        assert_eq!(call("lerp", &[10.0, 20.0, 1.0]).unwrap(), 20.0);
+        assert!(matches!(call("lerp", &[1.0, 2.0]), Err(MathError::ArgumentCount { .. })));
+    }
+
+    #[test]
+    fn test_elementary_functions() {
+        use std::f64::consts::{E, FRAC_PI_2, FRAC_PI_4, PI};
+        let call = |name: &str, args: &[f64]| builtin(name).unwrap()(args);
+        let close = |name: &str, args: &[f64], expected: f64| {
+            let value = call(name, args).unwrap();
+            assert!((value - expected).abs() < 1e-12, "{}{:?} is {}, not {}", name, args, value, expected);
+        };
+        close("sqrt", &[2.0], 2f64.sqrt());
+        close("cbrt", &[-27.0], -3.0);
+        close("exp", &[1.0], E);
+        close("ln", &[E], 1.0);
+        close("log", &[1000.0], 3.0);
+        close("log", &[8.0, 2.0], 3.0);
+        close("log2", &[0.125], -3.0);
+        close("log10", &[0.01], -2.0);
+        // Angles are in radians
+        close("sin", &[FRAC_PI_2], 1.0);
+        close("cos", &[PI], -1.0);
+        close("tan", &[FRAC_PI_4], 1.0);
+        close("asin", &[1.0], FRAC_PI_2);
+        close("acos", &[-1.0], PI);
+        close("atan", &[1.0], FRAC_PI_4);
+        close("atan2", &[1.0, -1.0], 3.0 * FRAC_PI_4);
+        close("sinh", &[0.0], 0.0);
+        close("cosh", &[0.0], 1.0);
+        close("tanh", &[1e3], 1.0);
+        assert!(call("ln", &[-1.0]).unwrap().is_nan());
+        assert!(matches!(call("sin", &[]), Err(MathError::ArgumentCount { .. })));
+        assert!(matches!(call("log", &[1.0, 2.0, 3.0]), Err(MathError::ArgumentCount { .. })));
+        assert!(ELEMENTARY.iter().all(|name| builtin(name).is_some()));
+    }
+}
@@ -0,0 +1,121 @@
+// src/generators.rs
+// Random expression trees, for fuzzing the parser and printer and for generating practice problems.
+// Every tree displays as text that parses back to the same tree.
+use crate::random::Rng;
+use crate::{Expr, Operator};
+
+// The chance that a node below the root is a number or variable before the depth runs out
+const LEAF_CHANCE: f64 = 0.3;
+
+// The chance that a leaf is a variable, when there are variables to choose from
+const VARIABLE_CHANCE: f64 = 0.3;
+
+// What random expressions may contain
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    pub max_depth: usize,               // Levels of operations and calls, not counting signs of numbers
+    pub operators: Vec<Operator>,       // Binary operators to choose from, equally often
+    pub functions: Vec<(String, usize)>, // Functions to call, with how many arguments
+    pub variables: Vec<String>,         // Names leaves may be, besides numbers
+    pub min_value: f64,                 // Numbers are drawn from min_value..max_value
+    pub max_value: f64,
+    pub decimals: u32, // Digits after the decimal point of numbers; 0 for whole numbers
+}
+
+impl Default for GeneratorConfig {
+    // Arithmetic on whole numbers from 0 to 9, up to three operations deep
+    fn default() -> Self {
+        Self {
+            max_depth: 4,
+            operators: vec![Operator::Add, Operator::Subtract, Operator::Multiply, Operator::Divide, Operator::Power],
+            functions: Vec::new(),
+            variables: Vec::new(),
+            min_value: 0.0,
+            max_value: 10.0,
+            decimals: 0,
+        }
+    }
+}
+
+// A random expression: an operation or call at the root when the config has any and allows more
+// than one level, with operands that stop at a number or variable at random or when `max_depth`
+// is reached. The same config and generator state give the same expression.
+pub fn random_expr(config: &GeneratorConfig, rng: &mut Rng) -> Expr {
+    node(config, rng, config.max_depth.max(1), true)
+}
+
+fn node(config: &GeneratorConfig, rng: &mut Rng, depth: usize, root: bool) -> Expr {
+    let choices = config.operators.len() + config.functions.len();
+    if depth <= 1 || choices == 0 || (!root && rng.next_f64() < LEAF_CHANCE) {
+        return leaf(config, rng);
+    }
+    let choice = rng.below(choices as u64) as usize;
+    match config.operators.get(choice) {
+        Some(op) => {
+            let lhs = node(config, rng, depth - 1, false);
+            let rhs = node(config, rng, depth - 1, false);
+            Expr::binary(op.clone(), lhs, rhs)
+        }
+        None => {
+            let (name, arity) = &config.functions[choice - config.operators.len()];
+            let args = (0..*arity).map(|_| node(config, rng, depth - 1, false)).collect();
+            Expr::function(name.clone(), args)
+        }
+    }
+}
+
+fn leaf(config: &GeneratorConfig, rng: &mut Rng) -> Expr {
+    if !config.variables.is_empty() && rng.next_f64() < VARIABLE_CHANCE {
+        let index = rng.below(config.variables.len() as u64) as usize;
+        return Expr::variable(config.variables[index].clone());
+    }
+    let scale = 10f64.powi(config.decimals as i32);
+    let value = config.min_value + rng.next_f64() * (config.max_value - config.min_value);
+    let value = (value * scale).floor() / scale;
+    // Written with a minus sign, a negative number parses as a negation
+    if value < 0.0 {
+        Expr::unary_minus(Expr::literal(-value))
+    } else {
+        Expr::literal(value.abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let config = GeneratorConfig {
+            max_depth: 6,
+            operators: vec![Operator::Add, Operator::Subtract, Operator::Power, Operator::Less, Operator::And, Operator::BitXor],
+            functions: vec![("max".to_string(), 2), ("f".to_string(), 1), ("rand".to_string(), 0)],
+            variables: vec!["x".to_string(), "rate".to_string()],
+            min_value: -50.0,
+            max_value: 50.0,
+            decimals: 2,
+        };
+        let mut rng = Rng::seeded(1);
+        for _ in 0..500 {
+            let expr = random_expr(&config, &mut rng);
+            assert!(expr.depth() <= config.max_depth + 1, "{}", expr);
+            assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_practice_problems() {
+        let config = GeneratorConfig::default();
+        let problems: Vec<Expr> = (0..20).map(|_| random_expr(&config, &mut Rng::seeded(7))).collect();
+        assert!(problems.iter().all(|problem| *problem == problems[0]));
+
+        let mut rng = Rng::seeded(7);
+        for _ in 0..100 {
+            let problem = random_expr(&config, &mut rng);
+            assert!(matches!(problem, Expr::BinOp { .. }));
+            assert!((2..=4).contains(&problem.depth()));
+        }
+        let single = GeneratorConfig { max_depth: 1, ..config };
+        assert!(matches!(random_expr(&single, &mut rng), Expr::Literal(value) if value.fract() == 0.0 && value < 10.0));
+    }
+}
@@ -0,0 +1,190 @@
+// src/grammar.rs
+// Configures the syntax of an expression language once and parses any number of inputs with it
+use crate::operators::OperatorTable;
+use crate::parser::{ImplicitMultiplication, PartialParse};
+use crate::{Expr, Parser, ParserOptions, PercentSign, Result, Statement, Token, Tokenizer, TokenizerOptions};
+
+// Collects the operators, literal syntax and optional features of a dialect
+#[derive(Debug, Clone, Default)]
+pub struct ParserBuilder {
+    tokenizer: TokenizerOptions,
+    parser: ParserOptions,
+}
+
+impl ParserBuilder {
+    // Starts from the default syntax
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces all tokenizer settings at once
+    pub fn tokenizer_options(mut self, options: TokenizerOptions) -> Self {
+        self.tokenizer = options;
+        self
+    }
+
+    // Replaces all parser settings at once
+    pub fn parser_options(mut self, options: ParserOptions) -> Self {
+        self.parser = options;
+        self
+    }
+
+    // Custom operators, on top of the built-in ones
+    pub fn operators(mut self, operators: OperatorTable) -> Self {
+        self.tokenizer.operators = operators;
+        self
+    }
+
+    // Read ',' as the decimal point (see `TokenizerOptions::decimal_comma`)
+    pub fn decimal_comma(mut self, enabled: bool) -> Self {
+        self.tokenizer.decimal_comma = enabled;
+        self
+    }
+
+    // Accept this character between groups of three integer digits
+    pub fn thousands_separator(mut self, separator: Option<char>) -> Self {
+        self.tokenizer.thousands_separator = separator;
+        self
+    }
+
+    // Accept SI magnitude suffixes on numbers, such as 4.7k
+    pub fn si_suffixes(mut self, enabled: bool) -> Self {
+        self.tokenizer.si_suffixes = enabled;
+        self
+    }
+
+    // Accept duration literals such as 1h30m
+    pub fn durations(mut self, enabled: bool) -> Self {
+        self.tokenizer.durations = enabled;
+        self
+    }
+
+    // Whether '%' is the postfix percent or the remainder operator
+    pub fn percent_sign(mut self, percent_sign: PercentSign) -> Self {
+        self.tokenizer.percent_sign = percent_sign;
+        self
+    }
+
+    // Accept comparison operators
+    pub fn comparisons(mut self, enabled: bool) -> Self {
+        self.parser.comparisons = enabled;
+        self
+    }
+
+    // Accept function calls
+    pub fn functions(mut self, enabled: bool) -> Self {
+        self.parser.functions = enabled;
+        self
+    }
+
+    // Where writing operands side by side multiplies them, and how tightly that binds
+    pub fn implicit_multiplication(mut self, policy: ImplicitMultiplication) -> Self {
+        self.parser.implicit_multiplication = policy;
+        self
+    }
+
+    // Keep the input's parentheses so expressions display as written
+    pub fn preserve_notation(mut self, enabled: bool) -> Self {
+        self.parser.preserve_notation = enabled;
+        self
+    }
+
+    // Reject input nested more than `depth` levels deep (see `ParserOptions::max_depth`)
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.parser.max_depth = Some(depth);
+        self
+    }
+
+    pub fn build(self) -> Grammar {
+        Grammar {
+            tokenizer: self.tokenizer,
+            parser: self.parser,
+        }
+    }
+}
+
+// A configured syntax that creates parsers for inputs. Cheap to clone and reusable.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Grammar {
+    tokenizer: TokenizerOptions,
+    parser: ParserOptions,
+}
+
+impl Grammar {
+    pub fn tokenizer_options(&self) -> &TokenizerOptions {
+        &self.tokenizer
+    }
+
+    pub fn parser_options(&self) -> &ParserOptions {
+        &self.parser
+    }
+
+    // The custom operators; give these to `Context::set_operators` to evaluate them
+    pub fn operators(&self) -> &OperatorTable {
+        &self.tokenizer.operators
+    }
+
+    pub fn tokenize(&self, input: &str) -> Result<Vec<Token>> {
+        Tokenizer::tokenize_with(input, self.tokenizer.clone())
+    }
+
+    // A parser over the tokens of the input
+    pub fn parser(&self, input: &str) -> Result<Parser> {
+        let tokens = Tokenizer::tokenize_spanned(input, self.tokenizer.clone())?;
+        Ok(Parser::with_spans(tokens, self.parser))
+    }
+
+    // Parses the input as a single expression
+    pub fn parse(&self, input: &str) -> Result<Expr> {
+        self.parser(input)?.parse()
+    }
+
+    // Parses the input without stopping at the first error (see `Parser::parse_recovering`). Input
+    // that cannot even be tokenized gives a lone `Expr::Error`.
+    pub fn parse_recovering(&self, input: &str) -> PartialParse {
+        match self.parser(input) {
+            Ok(mut parser) => parser.parse_recovering(),
+            Err(error) => PartialParse {
+                expr: Expr::Error,
+                errors: vec![error],
+            },
+        }
+    }
+
+    // Parses the input as ';'-separated statements
+    pub fn parse_statements(&self, input: &str) -> Result<Vec<Statement>> {
+        self.parser(input)?.parse_statements()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Associativity, Context, Evaluator, MathError};
+
+    #[test]
+    fn test_configured_dialect() {
+        let mut operators = OperatorTable::new();
+        operators.register_binary("⊕", 8, Associativity::Left, |a, b| Ok(a.hypot(b)));
+        let grammar = ParserBuilder::new()
+            .decimal_comma(true)
+            .operators(operators)
+            .comparisons(false)
+            .build();
+
+        let mut ctx = Context::new();
+        ctx.set_operators(grammar.operators().clone());
+        let expr = grammar.parse("1,5 * 2 ⊕ 4").unwrap();
+        assert_eq!(Evaluator::evaluate_with(&expr, &ctx).unwrap(), 5.0);
+        assert_eq!(grammar.parse_statements("a = max(1; 2); a").unwrap().len(), 2);
+
+        assert!(matches!(grammar.parse("1 < 2"), Err(MathError::InvalidExpression(_))));
+        // The same grammar is reused for every input
+        assert!(grammar.parse("1 ⊕ 2").is_ok());
+
+        let no_functions = ParserBuilder::new().functions(false).build();
+        assert!(matches!(no_functions.parse("max(1, 2)"), Err(MathError::InvalidExpression(_))));
+        assert_eq!(no_functions.parse("max").unwrap(), Expr::Variable("max".to_string()));
+        assert_eq!(Grammar::default(), ParserBuilder::new().build());
+    }
+}
@@ -0,0 +1,287 @@
+// src/incremental.rs
+// Incremental parsing for editors that evaluate as the user types. An `IncrementalParser` keeps
+// the tokens and the parsed statements of its input; after an edit it tokenizes again only from
+// the token before the edit until the new tokens line up with the old ones, and parses again only
+// the ';'-separated statements whose tokens changed. Everything else is kept.
+use std::ops::Range;
+
+use crate::token::{ends_operand, paren_depth_after};
+use crate::{Grammar, MathError, Parser, Result, Span, Statement, Token, Tokenizer};
+
+pub struct IncrementalParser {
+    grammar: Grammar,
+    source: String,
+    tokens: Vec<(Token, Span)>,         // Empty when the input cannot be tokenized
+    tokenize_error: Option<MathError>,  // Why the input cannot be tokenized
+    segments: Vec<Range<usize>>,        // The tokens of each statement, without the ';' between them
+    statements: Vec<Result<Statement>>, // Each statement, parsed on its own
+}
+
+impl IncrementalParser {
+    // Tokenizes and parses the whole input with the grammar
+    pub fn new(grammar: Grammar, source: impl Into<String>) -> Self {
+        let mut parser = Self {
+            grammar,
+            source: source.into(),
+            tokens: Vec::new(),
+            tokenize_error: None,
+            segments: Vec::new(),
+            statements: Vec::new(),
+        };
+        parser.reparse_all();
+        parser
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn tokens(&self) -> &[(Token, Span)] {
+        &self.tokens
+    }
+
+    // Every statement of the input, or the error that kept it from parsing. Empty when the input
+    // cannot be tokenized.
+    pub fn statements(&self) -> &[Result<Statement>] {
+        &self.statements
+    }
+
+    // Why the input cannot be tokenized, or else the first statement that failed to parse
+    pub fn error(&self) -> Option<&MathError> {
+        self.tokenize_error
+            .as_ref()
+            .or_else(|| self.statements.iter().find_map(|statement| statement.as_ref().err()))
+    }
+
+    // Replaces the text in the span with `text`, then brings the tokens and statements up to date.
+    // Returns the indices of the statements that were parsed again; those before and after are
+    // unchanged. Panics if the span does not fall on character boundaries within the input, as
+    // `String::replace_range` does.
+    pub fn edit(&mut self, span: Span, text: &str) -> Range<usize> {
+        self.source.replace_range(span.start..span.end, text);
+        if self.tokenize_error.is_some() {
+            // Nothing is known to be reusable
+            self.reparse_all();
+            return 0..self.statements.len();
+        }
+        let delta = text.len() as isize - (span.end - span.start) as isize;
+        let shifted = |old: Span| Span::new(old.start.wrapping_add_signed(delta), old.end.wrapping_add_signed(delta));
+
+        // Start after the last token that ends before the edit: what follows it up to the edit is
+        // whitespace or comments, so the edit cannot join onto it
+        let first = self.tokens.partition_point(|(_, old)| old.end < span.start);
+        let restart = first.checked_sub(1).map_or(0, |previous| self.tokens[previous].1.end);
+        let depth = self.tokens[..first].iter().fold(0, |depth, (token, _)| paren_depth_after(depth, token));
+        let after_operand = first > 0 && ends_operand(&self.tokens[first - 1].0);
+        let mut tokenizer =
+            Tokenizer::resume(&self.source[restart..], self.grammar.tokenizer_options().clone(), depth, after_operand);
+
+        // Tokenize until a new token matches an old one after the edit, in the same place (and
+        // inside the same parentheses, when that matters): from there on the old tokens are the same
+        let nesting_matters = tokenizer.nesting_matters();
+        let mut replacement = Vec::new();
+        let (mut new_depth, mut old_depth) = (depth, depth);
+        let mut resync = first;
+        loop {
+            let (token, at) = match tokenizer.next_spanned() {
+                Ok(Some((token, at))) => (token, Span::new(at.start + restart, at.end + restart)),
+                Ok(None) => {
+                    resync = self.tokens.len();
+                    break;
+                }
+                Err(error) => {
+                    self.fail(error);
+                    return 0..0;
+                }
+            };
+            while let Some((old, old_span)) = self.tokens.get(resync) {
+                if old_span.start >= span.end && shifted(*old_span).start >= at.start {
+                    break;
+                }
+                old_depth = paren_depth_after(old_depth, old);
+                resync += 1;
+            }
+            if let Some((old, old_span)) = self.tokens.get(resync) {
+                if old_span.start >= span.end && *old == token
+                    && shifted(*old_span) == at
+                    && (!nesting_matters || old_depth == new_depth)
+                {
+                    break;
+                }
+            }
+            new_depth = paren_depth_after(new_depth, &token);
+            replacement.push((token, at));
+        }
+
+        let reused = first + replacement.len(); // Where the old tokens from `resync` on now start
+        let after: Vec<(Token, Span)> = self.tokens.drain(resync..).map(|(token, old)| (token, shifted(old))).collect();
+        self.tokens.truncate(first);
+        self.tokens.extend(replacement);
+        self.tokens.extend(after);
+
+        // A statement is kept when its tokens and the ';' that ends it (or the ';' before it) are
+        // all outside the tokens that changed. Failed statements are parsed again, since their
+        // errors hold spans.
+        let old_segments = std::mem::replace(&mut self.segments, segments(&self.tokens));
+        let mut old_statements: Vec<Option<Result<Statement>>> = self.statements.drain(..).map(Some).collect();
+        let mut reparsed: Option<Range<usize>> = None;
+        for (index, segment) in self.segments.iter().enumerate() {
+            let old_segment = if segment.end < first {
+                Some(segment.clone())
+            } else if segment.start > reused {
+                Some(segment.start - reused + resync..segment.end - reused + resync)
+            } else {
+                None
+            };
+            let old_index = old_segment.as_ref().and_then(|old| {
+                let i = old_segments.binary_search_by_key(&old.start, |segment| segment.start).ok()?;
+                (old_segments[i] == *old).then_some(i)
+            });
+            let kept = old_index.and_then(|i| old_statements[i].take()).filter(Result::is_ok);
+            let statement = match kept {
+                Some(statement) => statement,
+                None => {
+                    if old_segment.is_none() {
+                        reparsed = Some(reparsed.map_or(index, |reparsed| reparsed.start)..index + 1);
+                    }
+                    parse_segment(&self.grammar, &self.tokens, segment.clone())
+                }
+            };
+            self.statements.push(statement);
+        }
+        // Empty when the edit only removed statements or changed whitespace
+        reparsed.unwrap_or(0..0)
+    }
+
+    fn reparse_all(&mut self) {
+        match Tokenizer::tokenize_spanned(&self.source, self.grammar.tokenizer_options().clone()) {
+            Ok(tokens) => {
+                self.tokens = tokens;
+                self.tokenize_error = None;
+                self.segments = segments(&self.tokens);
+                self.statements = self
+                    .segments
+                    .iter()
+                    .map(|segment| parse_segment(&self.grammar, &self.tokens, segment.clone()))
+                    .collect();
+            }
+            Err(error) => self.fail(error),
+        }
+    }
+
+    fn fail(&mut self, error: MathError) {
+        self.tokens.clear();
+        self.segments.clear();
+        self.statements.clear();
+        self.tokenize_error = Some(error);
+    }
+}
+
+// The token ranges of the statements, split at ';' and skipping empty ones
+pub(crate) fn segments(tokens: &[(Token, Span)]) -> Vec<Range<usize>> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        if *token == Token::Semicolon {
+            if start < index {
+                segments.push(start..index);
+            }
+            start = index + 1;
+        }
+    }
+    if start < tokens.len() {
+        segments.push(start..tokens.len());
+    }
+    segments
+}
+
+fn parse_segment(grammar: &Grammar, tokens: &[(Token, Span)], segment: Range<usize>) -> Result<Statement> {
+    let offset = segment.start;
+    let mut statements = Parser::with_spans(tokens[segment].to_vec(), *grammar.parser_options())
+        .parse_statements()
+        .map_err(|error| match error {
+            // Count the parenthesis among all the tokens, not just the statement's
+            MathError::UnmatchedParenthesis { paren, token, span, opened, closed } => MathError::UnmatchedParenthesis {
+                paren,
+                token: token + offset,
+                span,
+                opened,
+                closed,
+            },
+            error => error,
+        })?;
+    statements
+        .pop()
+        .ok_or_else(|| MathError::InvalidExpression("Empty statement".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that the parser agrees with tokenizing and parsing the edited input from scratch
+    fn assert_fresh(parser: &IncrementalParser) {
+        let grammar = parser.grammar.clone();
+        match Tokenizer::tokenize_spanned(parser.source(), grammar.tokenizer_options().clone()) {
+            Ok(tokens) => {
+                assert_eq!(parser.tokens(), tokens.as_slice(), "{}", parser.source());
+                let fresh = IncrementalParser::new(grammar, parser.source());
+                let statements = |parser: &IncrementalParser| -> Vec<_> {
+                    parser.statements().iter().map(|statement| statement.as_ref().ok().cloned()).collect()
+                };
+                assert_eq!(statements(parser), statements(&fresh), "{}", parser.source());
+            }
+            Err(_) => assert!(parser.tokenize_error.is_some(), "{}", parser.source()),
+        }
+    }
+
+    #[test]
+    fn test_edits_match_full_parse() {
+        let mut parser = IncrementalParser::new(Grammar::default(), "a = 12; b = a * (3 + 4)  # total\nc = max(a, b)");
+        assert_fresh(&parser);
+        let edits = [
+            (Span::new(6, 6), "3"),    // Joins the number: a = 123
+            (Span::new(14, 14), "("),  // Unbalanced parentheses
+            (Span::new(14, 15), ""),   // And balanced again
+            (Span::new(7, 8), " + 1"), // Removes a ';', merging two statements
+            (Span::new(0, 0), "# "),   // Comments out the first line
+            (Span::new(0, 2), ""),
+            (Span::new(4, 4), "/* "),  // Cannot be tokenized: an open comment
+            (Span::new(4, 7), ""),     // Until it is removed again
+            (Span::new(2, 2), "²"),
+        ];
+        for (span, text) in edits {
+            parser.edit(span, text);
+            assert_fresh(&parser);
+        }
+        assert_eq!(parser.source(), "a ²= 123 + 1 b = a * (3 + 4)  # total\nc = max(a, b)");
+
+        // With decimal commas a ';' inside parentheses separates arguments, so opening or closing
+        // one changes the tokens after it
+        let grammar = crate::ParserBuilder::new().decimal_comma(true).build();
+        let mut parser = IncrementalParser::new(grammar, "x = 1,5; max(x; 2); y = 3");
+        for (span, text) in [(Span::new(9, 13), ""), (Span::new(0, 0), "(")] {
+            parser.edit(span, text);
+            assert_fresh(&parser);
+        }
+        assert_eq!(parser.source(), "(x = 1,5; x; 2); y = 3");
+    }
+
+    #[test]
+    fn test_only_edited_statements_are_reparsed() {
+        let mut parser = IncrementalParser::new(Grammar::default(), "a = 1; b = a * 2; c = b + 3");
+        let tokens_before = parser.tokens().len();
+        assert_eq!(parser.edit(Span::new(15, 16), "20"), 1..2);
+        assert_eq!(parser.source(), "a = 1; b = a * 20; c = b + 3");
+        assert_eq!(parser.tokens().len(), tokens_before);
+        assert_eq!(parser.statements()[1].as_ref().unwrap().to_string(), "b = a * 20");
+        assert_fresh(&parser);
+
+        // A failed statement is reported, and parsed again once fixed
+        assert_eq!(parser.edit(Span::new(0, 5), "a = (1"), 0..1);
+        assert!(matches!(parser.error(), Some(MathError::UnmatchedParenthesis { token: 2, .. })));
+        assert_eq!(parser.edit(Span::new(6, 6), ")"), 0..1);
+        assert!(parser.error().is_none());
+        assert_fresh(&parser);
+    }
+}
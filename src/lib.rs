@@ -6,10 +6,68 @@ pub mod error;
 pub mod parser;
 pub mod expr;
 pub mod evaluator;
+pub mod context;
+pub mod format;
+pub mod functions;
+pub mod random;
+pub mod value;
+pub mod date;
+pub mod duration;
+pub mod money;
+pub mod units;
+mod matrix;
+pub mod operators;
+pub mod grammar;
+pub mod incremental;
+pub mod source;
+pub mod lint;
+pub mod loader;
+pub mod template;
+pub mod simplify;
+pub mod normal;
+pub mod polynomial;
+pub mod limits;
+pub mod solver;
+pub mod analysis;
+pub mod diff;
+pub mod generators;
+pub mod testing;
+pub mod observer;
+pub mod metrics;
+pub mod settings;
+pub mod workspace;
+mod trace;
 
 // Re-export commonly used types for easier access
-pub use crate::token::{Token, Operator, Tokenizer};
+pub use crate::token::{
+    highlight, Operator, PercentSign, Span, Token, TokenClass, Tokenizer, TokenizerOptions, Tokens, UnaryOperator,
+};
 pub use crate::error::{MathError, Result};
-pub use crate::parser::Parser;
-pub use crate::expr::Expr;
-pub use crate::evaluator::Evaluator;
\ No newline at end of file
+pub use crate::parser::{ImplicitMultiplication, ImplicitPrecedence, Parser, ParserOptions, PartialParse};
+pub use crate::grammar::{Grammar, ParserBuilder};
+pub use crate::incremental::IncrementalParser;
+pub use crate::source::{format_source, format_source_with};
+pub use crate::lint::{lint_source, Lint, LintCode, Warning};
+pub use crate::loader::{load_file, load_file_into, load_source_into};
+pub use crate::template::Template;
+pub use crate::simplify::SimplifyOptions;
+pub use crate::polynomial::Polynomial;
+pub use crate::analysis::{estimate_cost, CostModel};
+pub use crate::diff::{diff, Edit};
+pub use crate::expr::{Expr, Statement};
+pub use crate::evaluator::{DivisionByZero, EvalOptions, Evaluator, EvaluatorBuilder, MatrixProduct, NonInteger, Percentage};
+pub use crate::context::{Context, ContextSnapshot, FunctionDefinition, SharedContext, VariableResolver};
+pub use crate::observer::{EvalObserver, OperationCounter};
+pub use crate::metrics::{EvalStats, Metrics, StatsSnapshot};
+pub use crate::settings::EvaluatorConfig;
+pub use crate::workspace::Workspace;
+pub use crate::value::Value;
+pub use crate::date::Date;
+pub use crate::duration::{format_duration, parse_duration};
+pub use crate::money::{Money, RateProvider};
+pub use crate::units::{Dimension, Dimensions, Quantity, Unit, UnitTable};
+pub use crate::operators::{Associativity, CustomOperator, CustomUnaryOperator, Fixity, OperatorTable};
+pub use crate::format::{
+    continued_fraction, exact_form, format_as_fraction, format_continued_fraction, format_number, format_radix,
+    format_value, ExactForm, FormatOptions, Locale, Notation, Precision, RoundingMode,
+};
\ No newline at end of file
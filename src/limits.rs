@@ -0,0 +1,410 @@
+// src/limits.rs
+// Limits of expressions in one variable, worked out symbolically. Rational functions (sums,
+// products, quotients and whole powers of the variable and numbers) are exact: common factors of
+// (x - a) cancel, so (x^2 - 1) / (x - 1) tends to 2 at 1, and degrees decide the limit at
+// infinity. Other expressions are taken apart with the rules for limits of sums, products and
+// quotients, which leave indeterminate forms such as inf - inf undetermined. Built-in functions
+// such as sin and exp are continuous where defined, and the standard forms sin(u) / u,
+// (exp(u) - 1) / u and their kin tend to 1 where u tends to 0.
+use std::f64::consts::FRAC_PI_2;
+use std::fmt;
+
+use crate::functions::builtin;
+use crate::polynomial::MAX_DEGREE;
+use crate::{Expr, Operator, Polynomial, UnaryOperator};
+
+// Where the variable tends to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Point {
+    Finite(f64),
+    PositiveInfinity,
+    NegativeInfinity,
+}
+
+// f64::INFINITY and f64::NEG_INFINITY are the infinite points
+impl From<f64> for Point {
+    fn from(x: f64) -> Self {
+        match x {
+            f64::INFINITY => Point::PositiveInfinity,
+            f64::NEG_INFINITY => Point::NegativeInfinity,
+            x => Point::Finite(x),
+        }
+    }
+}
+
+// What an expression tends to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Finite(f64),
+    PositiveInfinity,
+    NegativeInfinity,
+    // No limit, a limit that differs from either side (1 / x at 0), or one these rules cannot find
+    Undetermined,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Finite(x) => write!(f, "{}", x),
+            Limit::PositiveInfinity => write!(f, "inf"),
+            Limit::NegativeInfinity => write!(f, "-inf"),
+            Limit::Undetermined => write!(f, "undetermined"),
+        }
+    }
+}
+
+// The limit of the expression as `variable` tends to the point (a number, or f64::INFINITY or
+// f64::NEG_INFINITY). Other variables are unknown, so limits that depend on them are undetermined.
+pub fn limit(expr: &Expr, variable: &str, point: impl Into<Point>) -> Limit {
+    limit_at(expr, variable, point.into())
+}
+
+fn limit_at(expr: &Expr, variable: &str, point: Point) -> Limit {
+    if let Some(rational) = Rational::from_expr(expr, variable) {
+        return rational.limit(point);
+    }
+    let at = |expr: &Expr| limit_at(expr, variable, point);
+    match expr {
+        Expr::Group(inner) => at(inner),
+        Expr::UnaryMinus(operand) => negate(at(operand)),
+        Expr::Unary { op, operand } => match (op, at(operand)) {
+            (UnaryOperator::Percent, Limit::Finite(x)) => Limit::Finite(x / 100.0),
+            (UnaryOperator::Percent, limit) => limit,
+            (UnaryOperator::Abs, Limit::Finite(x)) => Limit::Finite(x.abs()),
+            (UnaryOperator::Abs, Limit::PositiveInfinity | Limit::NegativeInfinity) => Limit::PositiveInfinity,
+            (UnaryOperator::Sqrt, Limit::Finite(x)) if x >= 0.0 => Limit::Finite(x.sqrt()),
+            (UnaryOperator::Sqrt, Limit::PositiveInfinity) => Limit::PositiveInfinity,
+            _ => Limit::Undetermined,
+        },
+        Expr::Function { name, args } if args.len() == 1 => function(name, at(&args[0])),
+        Expr::BinOp { op: Operator::Divide, lhs, rhs } if standard_form(lhs, rhs, variable, point) => {
+            Limit::Finite(1.0)
+        }
+        Expr::BinOp { op, lhs, rhs } => {
+            let (left, right) = (at(lhs), at(rhs));
+            match op {
+                Operator::Add => add(left, right),
+                Operator::Subtract => add(left, negate(right)),
+                Operator::Multiply => multiply(left, right),
+                Operator::Divide => divide(left, right),
+                Operator::Power => match (left, right) {
+                    (Limit::Finite(base), Limit::Finite(exponent)) => finite(base.powf(exponent)),
+                    _ => Limit::Undetermined,
+                },
+                // Comparisons, remainders and bitwise operators jump, so they have no limit rule
+                _ => Limit::Undetermined,
+            }
+        }
+        _ => Limit::Undetermined,
+    }
+}
+
+// The limit of a built-in function of something with the given limit
+fn function(name: &str, limit: Limit) -> Limit {
+    let value = |x: f64| builtin(name).and_then(|f| f(&[x]).ok()).map_or(Limit::Undetermined, finite);
+    match (name, limit) {
+        (
+            "sin" | "cos" | "atan" | "exp" | "sinh" | "cosh" | "tanh" | "cbrt" | "sqrt" | "ln" | "log" | "log2" | "log10",
+            Limit::Finite(x),
+        ) if x > 0.0 || !matches!(name, "sqrt" | "ln" | "log" | "log2" | "log10") => value(x),
+        ("sqrt", Limit::Finite(0.0)) => Limit::Finite(0.0), // As for √x
+        ("asin" | "acos", Limit::Finite(x)) if x.abs() < 1.0 => value(x),
+        // Away from the poles at odd multiples of pi / 2
+        ("tan", Limit::Finite(x)) if libm::cos(x).abs() > 1e-9 => value(x),
+        ("exp" | "sinh" | "cosh" | "cbrt" | "sqrt" | "ln" | "log" | "log2" | "log10", Limit::PositiveInfinity) => {
+            Limit::PositiveInfinity
+        }
+        ("sinh" | "cbrt", Limit::NegativeInfinity) => Limit::NegativeInfinity,
+        ("cosh", Limit::NegativeInfinity) => Limit::PositiveInfinity,
+        ("exp", Limit::NegativeInfinity) => Limit::Finite(0.0),
+        ("atan", Limit::PositiveInfinity) => Limit::Finite(FRAC_PI_2),
+        ("atan", Limit::NegativeInfinity) => Limit::Finite(-FRAC_PI_2),
+        ("tanh", Limit::PositiveInfinity) => Limit::Finite(1.0),
+        ("tanh", Limit::NegativeInfinity) => Limit::Finite(-1.0),
+        _ => Limit::Undetermined,
+    }
+}
+
+// Whether the quotient is one of the standard forms that tend to 1, or its reciprocal: sin(u) / u,
+// with tan, asin, atan, sinh or tanh in place of sin, (exp(u) - 1) / u and ln(1 + u) / u, for a u
+// that tends to 0
+fn standard_form(numerator: &Expr, denominator: &Expr, variable: &str, point: Point) -> bool {
+    let is_one = |expr: &Expr| matches!(ungroup(expr), Expr::Literal(n) if *n == 1.0);
+    let tends_to_one = |f: &Expr, u: &Expr| {
+        let u = ungroup(u);
+        let form = match ungroup(f) {
+            Expr::BinOp { op: Operator::Subtract, lhs, rhs } if is_one(rhs) => argument(lhs, &["exp"]) == Some(u),
+            f => match argument(f, &["ln"]) {
+                Some(Expr::BinOp { op: Operator::Add, lhs, rhs }) => {
+                    (is_one(lhs) && ungroup(rhs) == u) || (is_one(rhs) && ungroup(lhs) == u)
+                }
+                _ => argument(f, &["sin", "tan", "asin", "atan", "sinh", "tanh"]) == Some(u),
+            },
+        };
+        form && limit_at(u, variable, point) == Limit::Finite(0.0)
+    };
+    tends_to_one(numerator, denominator) || tends_to_one(denominator, numerator)
+}
+
+// The argument of a call of one of the functions
+fn argument<'a>(expr: &'a Expr, names: &[&str]) -> Option<&'a Expr> {
+    match ungroup(expr) {
+        Expr::Function { name, args } if args.len() == 1 && names.contains(&name.as_str()) => Some(ungroup(&args[0])),
+        _ => None,
+    }
+}
+
+fn ungroup(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Group(inner) => ungroup(inner),
+        expr => expr,
+    }
+}
+
+fn finite(x: f64) -> Limit {
+    if x.is_finite() { Limit::Finite(x) } else { Limit::Undetermined }
+}
+
+// The limit of infinities with the sign of x
+fn infinity(sign: f64) -> Limit {
+    if sign > 0.0 { Limit::PositiveInfinity } else { Limit::NegativeInfinity }
+}
+
+// 1 for a positive limit, -1 for a negative one, 0 for zero
+fn sign(limit: Limit) -> Option<f64> {
+    match limit {
+        Limit::Finite(0.0) => Some(0.0),
+        Limit::Finite(x) => Some(x.signum()),
+        Limit::PositiveInfinity => Some(1.0),
+        Limit::NegativeInfinity => Some(-1.0),
+        Limit::Undetermined => None,
+    }
+}
+
+fn negate(limit: Limit) -> Limit {
+    match limit {
+        Limit::Finite(x) => Limit::Finite(-x),
+        Limit::PositiveInfinity => Limit::NegativeInfinity,
+        Limit::NegativeInfinity => Limit::PositiveInfinity,
+        Limit::Undetermined => Limit::Undetermined,
+    }
+}
+
+fn add(left: Limit, right: Limit) -> Limit {
+    match (left, right) {
+        (Limit::Finite(a), Limit::Finite(b)) => finite(a + b),
+        (Limit::Undetermined, _) | (_, Limit::Undetermined) => Limit::Undetermined,
+        (Limit::Finite(_), infinite) | (infinite, Limit::Finite(_)) => infinite,
+        (a, b) if a == b => a,
+        _ => Limit::Undetermined, // inf - inf
+    }
+}
+
+fn multiply(left: Limit, right: Limit) -> Limit {
+    match (left, right) {
+        (Limit::Finite(a), Limit::Finite(b)) => finite(a * b),
+        _ => match (sign(left), sign(right)) {
+            (Some(a), Some(b)) if a * b != 0.0 => infinity(a * b),
+            _ => Limit::Undetermined, // 0 * inf
+        },
+    }
+}
+
+fn divide(left: Limit, right: Limit) -> Limit {
+    match (left, right) {
+        // The sign of a denominator tending to 0 is not known here
+        (_, Limit::Finite(0.0)) => Limit::Undetermined,
+        (Limit::Finite(a), Limit::Finite(b)) => finite(a / b),
+        (Limit::Finite(_), Limit::PositiveInfinity | Limit::NegativeInfinity) => Limit::Finite(0.0),
+        (infinite, Limit::Finite(b)) => multiply(infinite, Limit::Finite(b.signum())),
+        _ => Limit::Undetermined, // inf / inf
+    }
+}
+
+// A quotient of polynomials in the variable
+#[derive(Debug, Clone, PartialEq)]
+struct Rational {
+    numerator: Polynomial,
+    denominator: Polynomial,
+}
+
+// Powers above this are not expanded
+const MAX_POWER: f64 = 64.0;
+
+impl Rational {
+    fn new(numerator: Polynomial, denominator: Polynomial) -> Self {
+        Self { numerator, denominator }
+    }
+
+    // The expression as a rational function of the variable, if it is one whose numerator and
+    // denominator have at most MAX_DEGREE between them
+    fn from_expr(expr: &Expr, variable: &str) -> Option<Rational> {
+        let of = |expr: &Expr| Rational::from_expr(expr, variable);
+        match expr {
+            Expr::Group(inner) => of(inner),
+            Expr::UnaryMinus(operand) => {
+                let r = of(operand)?;
+                Some(Rational::new(-&r.numerator, r.denominator))
+            }
+            Expr::BinOp { op, lhs, rhs } => {
+                let (a, b) = (of(lhs)?, of(rhs)?);
+                // Every result but a power's has a degree of at most the sum of the operands'
+                if !matches!(op, Operator::Power) && a.degree() + b.degree() > MAX_DEGREE {
+                    return None;
+                }
+                match op {
+                    Operator::Add => Some(Rational::new(
+                        &(&a.numerator * &b.denominator) + &(&b.numerator * &a.denominator),
+                        &a.denominator * &b.denominator,
+                    )),
+                    Operator::Subtract => Some(Rational::new(
+                        &(&a.numerator * &b.denominator) - &(&b.numerator * &a.denominator),
+                        &a.denominator * &b.denominator,
+                    )),
+                    Operator::Multiply => Some(Rational::new(
+                        &a.numerator * &b.numerator,
+                        &a.denominator * &b.denominator,
+                    )),
+                    Operator::Divide => Some(Rational::new(
+                        &a.numerator * &b.denominator,
+                        &a.denominator * &b.numerator,
+                    )),
+                    Operator::Power => a.power(b.constant()?),
+                    _ => None,
+                }
+            }
+            // Numbers and the variable itself
+            _ => {
+                let numerator = Polynomial::from_expr(expr, variable).ok()?;
+                Some(Rational::new(numerator, Polynomial::constant(variable, 1.0)))
+            }
+        }
+    }
+
+    // The degrees of the numerator and denominator together
+    fn degree(&self) -> usize {
+        self.numerator.degree() + self.denominator.degree()
+    }
+
+    // The value, if the function is a constant
+    fn constant(&self) -> Option<f64> {
+        match (self.numerator.degree(), self.denominator.degree()) {
+            (0, 0) if !self.denominator.is_zero() => Some(self.numerator.leading() / self.denominator.leading()),
+            _ => None,
+        }
+    }
+
+    // Raised to a whole power
+    fn power(&self, exponent: f64) -> Option<Rational> {
+        if exponent.fract() != 0.0 || exponent.abs() > MAX_POWER {
+            return None;
+        }
+        let n = exponent.abs() as u32;
+        if self.degree() * n as usize > MAX_DEGREE {
+            return None;
+        }
+        let mut result = Rational::new(self.numerator.powi(n), self.denominator.powi(n));
+        if exponent < 0.0 {
+            result = Rational::new(result.denominator, result.numerator);
+        }
+        Some(result)
+    }
+
+    fn limit(&self, point: Point) -> Limit {
+        if self.denominator.is_zero() {
+            return Limit::Undetermined;
+        }
+        if self.numerator.is_zero() {
+            return Limit::Finite(0.0);
+        }
+        match point {
+            Point::Finite(a) => {
+                let (zeros, numerator) = self.numerator.clone().factor_out(a);
+                let (poles, denominator) = self.denominator.clone().factor_out(a);
+                let ratio = numerator.evaluate(a) / denominator.evaluate(a);
+                match zeros.cmp(&poles) {
+                    std::cmp::Ordering::Greater => Limit::Finite(0.0),
+                    std::cmp::Ordering::Equal => finite(ratio),
+                    // An odd number of extra poles changes sign across the point
+                    std::cmp::Ordering::Less if (poles - zeros) % 2 == 1 => Limit::Undetermined,
+                    std::cmp::Ordering::Less => infinity(ratio),
+                }
+            }
+            Point::PositiveInfinity | Point::NegativeInfinity => {
+                let ratio = self.numerator.leading() / self.denominator.leading();
+                let (p, q) = (self.numerator.degree(), self.denominator.degree());
+                match p.cmp(&q) {
+                    std::cmp::Ordering::Less => Limit::Finite(0.0),
+                    std::cmp::Ordering::Equal => finite(ratio),
+                    // x^n for odd n is negative toward negative infinity
+                    std::cmp::Ordering::Greater if point == Point::NegativeInfinity && (p - q) % 2 == 1 => {
+                        infinity(-ratio)
+                    }
+                    std::cmp::Ordering::Greater => infinity(ratio),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit_of(input: &str, point: f64) -> Limit {
+        limit(&Expr::parse(input).unwrap(), "x", point)
+    }
+
+    #[test]
+    fn test_rational_limits() {
+        assert_eq!(limit_of("(x^2 - 1) / (x - 1)", 1.0), Limit::Finite(2.0));
+        assert_eq!(limit_of("(x^3 - 8) / (x - 2)", 2.0), Limit::Finite(12.0));
+        assert_eq!(limit_of("(x - 1)^2 / (x - 1)^3 * (x - 1)", 1.0), Limit::Finite(1.0));
+        assert_eq!(limit_of("x^2 / x", 0.0), Limit::Finite(0.0));
+        assert_eq!(limit_of("1 / x^2", 0.0), Limit::PositiveInfinity);
+        assert_eq!(limit_of("-3 / (x - 1)^2", 1.0), Limit::NegativeInfinity);
+        assert_eq!(limit_of("1 / x", 0.0), Limit::Undetermined);
+        assert_eq!(limit_of("(3 * x^2 + 1) / (x^2 - 5)", f64::INFINITY), Limit::Finite(3.0));
+        assert_eq!(limit_of("x^3 / (x + 1)", f64::NEG_INFINITY), Limit::PositiveInfinity);
+        assert_eq!(limit_of("2 * x^3 - x", f64::NEG_INFINITY), Limit::NegativeInfinity);
+        assert_eq!(limit_of("(x + 1) / x^2", f64::INFINITY), Limit::Finite(0.0));
+
+        // The degree is bounded overall, not just each exponent
+        assert_eq!(limit_of("(x^16)^32 / (x^50)^10", 0.0), Limit::Finite(0.0));
+        let start = std::time::Instant::now();
+        assert_eq!(limit_of("((x^64)^64)^64", 2.0), Limit::Undetermined);
+        assert_eq!(limit_of("((x^64)^64)^64 * ((x^64)^64)^64", f64::INFINITY), Limit::Undetermined);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_limit_rules() {
+        assert_eq!(limit_of("√(x + 3) * 2", 1.0), Limit::Finite(4.0));
+        assert_eq!(limit_of("√x + x", f64::INFINITY), Limit::PositiveInfinity);
+        assert_eq!(limit_of("√x - x", f64::INFINITY), Limit::Undetermined);
+        assert_eq!(limit_of("|x| / x", 0.0), Limit::Undetermined);
+        assert_eq!(limit_of("1 / √x", f64::INFINITY), Limit::Finite(0.0));
+        assert_eq!(limit_of("y / x", 1.0), Limit::Undetermined);
+        assert_eq!(limit_of("floor(x)", 1.0), Limit::Undetermined);
+
+        // Built-in functions
+        assert_eq!(limit_of("cos(x) + exp(x - 1)", 1.0), Limit::Finite(libm::cos(1.0) + 1.0));
+        assert_eq!(limit_of("exp(-x)", f64::INFINITY), Limit::Finite(0.0));
+        assert_eq!(limit_of("ln(x ^ 2)", f64::NEG_INFINITY), Limit::PositiveInfinity);
+        assert_eq!(limit_of("atan(x)", f64::NEG_INFINITY), Limit::Finite(-FRAC_PI_2));
+        assert_eq!(limit_of("ln(x)", 0.0), Limit::Undetermined);
+        assert_eq!(limit_of("tan(x)", FRAC_PI_2), Limit::Undetermined);
+        assert_eq!(limit_of("asin(x)", 1.0), Limit::Undetermined);
+
+        // Standard forms
+        assert_eq!(limit_of("sin(x) / x", 0.0), Limit::Finite(1.0));
+        assert_eq!(limit_of("x / tan(x)", 0.0), Limit::Finite(1.0));
+        assert_eq!(limit_of("(exp(2 * x) - 1) / (2 * x)", 0.0), Limit::Finite(1.0));
+        assert_eq!(limit_of("ln(1 + (x - 1)) / (x - 1)", 1.0), Limit::Finite(1.0));
+        assert_eq!(limit_of("3 * (sinh(x) / x)", 0.0), Limit::Finite(3.0));
+        assert_eq!(limit_of("sin(x) / x", 1.0), Limit::Finite(libm::sin(1.0)));
+        assert_eq!(limit_of("sin(x) / x", f64::INFINITY), Limit::Undetermined);
+        assert_eq!(limit_of("sin(2 * x) / x", 0.0), Limit::Undetermined);
+        assert_eq!(Limit::NegativeInfinity.to_string(), "-inf");
+    }
+}
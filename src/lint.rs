@@ -0,0 +1,348 @@
+// src/lint.rs
+// Warnings about files of formulas (see `source`): code that is valid but probably not what was
+// meant, such as a variable read before it is assigned or a division by a literal zero. Each
+// warning has a code naming the check, the line it is on and the span within that line. Lines
+// are read like the loader reads formula libraries, so includes and function definitions are
+// accepted; the body of a function is checked with its parameters defined.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::functions::{constant, function_info};
+use crate::incremental::segments;
+use crate::loader::{include_path, parse_definition, Definition};
+use crate::source::ends_in_comment;
+use crate::{Context, Grammar, MathError, Operator, Parser, Span, Statement, Token, Tokenizer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintCode {
+    SyntaxError,          // The line does not tokenize or parse; the only error
+    UndefinedVariable,    // Read before any assignment, and not set in the context
+    OverwrittenVariable,  // Assigned again before the earlier value was read
+    ShadowedFunction,     // A variable named like a function
+    DivisionByZero,       // Divided (or taken modulo) by a literal zero
+    RedundantParentheses, // Parentheses that do not change the expression
+}
+
+impl LintCode {
+    // The name reported with the warning, and accepted by `Lint::allow`
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintCode::SyntaxError => "syntax-error",
+            LintCode::UndefinedVariable => "undefined-variable",
+            LintCode::OverwrittenVariable => "overwritten-variable",
+            LintCode::ShadowedFunction => "shadowed-function",
+            LintCode::DivisionByZero => "division-by-zero",
+            LintCode::RedundantParentheses => "redundant-parentheses",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|code| code.name() == name)
+    }
+
+    // Whether the file is wrong rather than questionable
+    pub fn is_error(&self) -> bool {
+        *self == LintCode::SyntaxError
+    }
+
+    pub const ALL: [LintCode; 6] = [
+        LintCode::SyntaxError,
+        LintCode::UndefinedVariable,
+        LintCode::OverwrittenVariable,
+        LintCode::ShadowedFunction,
+        LintCode::DivisionByZero,
+        LintCode::RedundantParentheses,
+    ];
+}
+
+impl fmt::Display for LintCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub code: LintCode,
+    pub message: String,
+    pub line: usize, // Counted from 1
+    pub span: Span,  // Byte offsets within the line
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.code.is_error() { "error" } else { "warning" };
+        write!(f, "line {}: {}[{}]: {}", self.line, kind, self.code, self.message)
+    }
+}
+
+// The checks to run over a file, and what is already defined when it runs
+pub struct Lint<'a> {
+    grammar: Grammar,
+    ctx: Option<&'a Context>,
+    allowed: Vec<LintCode>,
+}
+
+impl Default for Lint<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Lint<'a> {
+    // Every check, with the default syntax and nothing defined beforehand
+    pub fn new() -> Self {
+        Self {
+            grammar: Grammar::default(),
+            ctx: None,
+            allowed: Vec::new(),
+        }
+    }
+
+    pub fn grammar(mut self, grammar: Grammar) -> Self {
+        self.grammar = grammar;
+        self
+    }
+
+    // Variables and functions of the context count as defined
+    pub fn context(mut self, ctx: &'a Context) -> Self {
+        self.ctx = Some(ctx);
+        self
+    }
+
+    // Skips a check. Syntax errors are always reported.
+    pub fn allow(mut self, code: LintCode) -> Self {
+        if !code.is_error() {
+            self.allowed.push(code);
+        }
+        self
+    }
+
+    // The warnings for a file of formulas, in order of line and then position. Lines that do not
+    // parse are reported as syntax errors and otherwise skipped; lines inside block comments that
+    // span several lines are not checked.
+    pub fn check(&self, source: &str) -> Vec<Warning> {
+        let mut checker = Checker {
+            lint: self,
+            warnings: Vec::new(),
+            assigned: HashMap::new(),
+            unread: HashMap::new(),
+        };
+        let mut in_comment = false;
+        for (number, line) in source.lines().enumerate() {
+            let open = in_comment;
+            in_comment = ends_in_comment(line, open);
+            if !open && !in_comment {
+                checker.check_line(number + 1, line);
+            }
+        }
+        let mut warnings = checker.warnings;
+        warnings.retain(|warning| !self.allowed.contains(&warning.code));
+        warnings.sort_by_key(|warning| (warning.line, warning.span.start));
+        warnings
+    }
+}
+
+// Runs every check over a file of formulas, with the default syntax
+pub fn lint_source(source: &str, ctx: &Context) -> Vec<Warning> {
+    Lint::new().context(ctx).check(source)
+}
+
+struct Checker<'a, 'b> {
+    lint: &'b Lint<'a>,
+    warnings: Vec<Warning>,
+    assigned: HashMap<String, usize>,       // Variables assigned so far, and on which line
+    unread: HashMap<String, (usize, Span)>, // Assignments whose value has not been read yet
+}
+
+impl Checker<'_, '_> {
+    fn warn(&mut self, code: LintCode, message: String, line: usize, span: Span) {
+        self.warnings.push(Warning { code, message, line, span });
+    }
+
+    fn check_line(&mut self, line: usize, text: &str) {
+        match include_path(text) {
+            Ok(Some(_)) => return,
+            Ok(None) => {}
+            Err(error) => return self.syntax_error(line, text, error),
+        }
+        let options = self.lint.grammar.tokenizer_options().clone();
+        let tokens = match Tokenizer::tokenize_spanned(text, options) {
+            Ok(tokens) => tokens,
+            Err(error) => return self.syntax_error(line, text, error),
+        };
+        let definitions = segments(&tokens)
+            .into_iter()
+            .map(|segment| Ok((parse_definition(&tokens[segment.clone()], &self.lint.grammar)?, segment)))
+            .collect::<Result<Vec<_>, MathError>>();
+        let definitions = match definitions {
+            Ok(definitions) => definitions,
+            Err(error) => return self.syntax_error(line, text, error),
+        };
+        for (definition, segment) in definitions {
+            let tokens = &tokens[segment];
+            let (tokens, statement, params) = match definition {
+                Definition::Statement(statement) => (tokens, statement, Vec::new()),
+                // Only the body, where the parameters are defined
+                Definition::Function(function) => {
+                    let assign = tokens.iter().position(|(token, _)| *token == Token::Assign).expect("a definition has '='");
+                    (&tokens[assign + 1..], Statement::Expr(function.body), function.params)
+                }
+                Definition::Include(_) => continue,
+            };
+            self.check_variables(line, tokens, &statement, &params);
+            self.check_division(line, tokens);
+            self.check_parentheses(line, tokens, &statement);
+        }
+    }
+
+    fn syntax_error(&mut self, line: usize, text: &str, error: MathError) {
+        let span = error.span().unwrap_or(Span::new(0, text.len()));
+        self.warn(LintCode::SyntaxError, error.to_string(), line, span);
+    }
+
+    // Reads come before the assignment, as in `x = x + 1`. Names followed by '(' are calls.
+    fn check_variables(&mut self, line: usize, tokens: &[(Token, Span)], statement: &Statement, params: &[String]) {
+        let target = matches!(statement, Statement::Assign { .. }) as usize;
+        for (index, (token, span)) in tokens.iter().enumerate().skip(target) {
+            let Token::Identifier(name) = token else { continue };
+            if tokens.get(index + 1).is_some_and(|(next, _)| *next == Token::LParen) || params.contains(name) {
+                continue;
+            }
+            self.unread.remove(name);
+            let defined = self.assigned.contains_key(name)
+                || constant(name).is_some()
+                || self.lint.ctx.is_some_and(|ctx| ctx.value(name).is_some());
+            if !defined {
+                let message = format!("`{}` is read before it is assigned", name);
+                self.warn(LintCode::UndefinedVariable, message, line, *span);
+            }
+        }
+
+        let Statement::Assign { name, .. } = statement else { return };
+        let span = tokens[0].1;
+        if let Some((earlier, earlier_span)) = self.unread.remove(name) {
+            let message = format!("`{}` is assigned again on line {} before this value is read", name, line);
+            self.warn(LintCode::OverwrittenVariable, message, earlier, earlier_span);
+        }
+        let function = function_info(name).is_some() || self.lint.ctx.is_some_and(|ctx| ctx.function(name).is_some());
+        if function && !self.assigned.contains_key(name) {
+            let message = format!("`{}` is also the name of a function", name);
+            self.warn(LintCode::ShadowedFunction, message, line, span);
+        }
+        self.assigned.insert(name.clone(), line);
+        self.unread.insert(name.clone(), (line, span));
+    }
+
+    fn check_division(&mut self, line: usize, tokens: &[(Token, Span)]) {
+        for pair in tokens.windows(2) {
+            let [(Token::Operator(op @ (Operator::Divide | Operator::Modulo)), op_span), (divisor, span)] = pair else {
+                continue;
+            };
+            let zero = match divisor {
+                Token::Number(value) | Token::Scientific { base: value, .. } => *value == 0.0,
+                _ => false,
+            };
+            if zero {
+                let what = if *op == Operator::Divide { "Division" } else { "Modulo" };
+                self.warn(LintCode::DivisionByZero, format!("{} by zero", what), line, Span::new(op_span.start, span.end));
+            }
+        }
+    }
+
+    // A pair of parentheses is redundant when the statement parses the same without it
+    fn check_parentheses(&mut self, line: usize, tokens: &[(Token, Span)], statement: &Statement) {
+        let mut open = Vec::new();
+        let mut reported = None; // The last pair reported
+        for (index, (token, _)) in tokens.iter().enumerate() {
+            match token {
+                Token::LParen => open.push(index),
+                Token::RParen => {
+                    let Some(start) = open.pop() else { continue };
+                    let call = start > 0 && matches!(tokens[start - 1].0, Token::Identifier(_));
+                    let doubled = reported == Some((start + 1, index - 1));
+                    if call || doubled || !self.same_without(tokens, start, index, statement) {
+                        continue;
+                    }
+                    reported = Some((start, index));
+                    let span = Span::new(tokens[start].1.start, tokens[index].1.end);
+                    self.warn(LintCode::RedundantParentheses, "Unnecessary parentheses".to_string(), line, span);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn same_without(&self, tokens: &[(Token, Span)], open: usize, close: usize, statement: &Statement) -> bool {
+        let rest: Vec<(Token, Span)> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != open && *index != close)
+            .map(|(_, token)| token.clone())
+            .collect();
+        let parsed = Parser::with_spans(rest, *self.lint.grammar.parser_options()).parse_statements();
+        matches!(parsed.as_deref(), Ok([reparsed]) if reparsed == statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(source: &str) -> Vec<(LintCode, usize, Span)> {
+        let mut ctx = Context::new();
+        ctx.set_variable("rate", 0.05);
+        lint_source(source, &ctx)
+            .into_iter()
+            .map(|warning| (warning.code, warning.line, warning.span))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let source = "price = 10  # from the context\ntotal = price * (1 + rate) + tax\nprice = 12\n\
+                      x = (price) / 0; y = max(x, 1) / 0.0\nmax = 1 /* inline */\n/* open\ntotal = */ (2";
+        assert_eq!(
+            codes(source),
+            vec![
+                (LintCode::UndefinedVariable, 2, Span::new(29, 32)),
+                (LintCode::RedundantParentheses, 4, Span::new(4, 11)),
+                (LintCode::DivisionByZero, 4, Span::new(12, 15)),
+                (LintCode::DivisionByZero, 4, Span::new(31, 36)),
+                (LintCode::ShadowedFunction, 5, Span::new(0, 3)),
+            ]
+        );
+
+        // An assignment nothing reads before the next one is reported where it was made
+        let warnings = lint_source("a = 1\nb = 2\na = b", &Context::new());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].to_string(), "line 1: warning[overwritten-variable]: `a` is assigned again on line 3 before this value is read");
+        // Only the innermost of doubled parentheses is reported
+        let redundant = |span| (LintCode::RedundantParentheses, 1, span);
+        assert_eq!(codes("n = 1; n = n + 1; (n - 2)^2; -(2)^2; ((1 + 2)) * 3"), vec![redundant(Span::new(30, 33)), redundant(Span::new(38, 45))]);
+    }
+
+    #[test]
+    fn test_formula_libraries() {
+        let source = "include \"common.mexpr\" # shared\nnet(gross) = gross * (1 - rate) - fee / 0\nfee = 2; area(w, h) = (w * h)";
+        assert_eq!(
+            codes(source),
+            vec![
+                (LintCode::UndefinedVariable, 2, Span::new(34, 37)),
+                (LintCode::DivisionByZero, 2, Span::new(38, 41)),
+                (LintCode::RedundantParentheses, 3, Span::new(22, 29)),
+            ]
+        );
+        assert_eq!(codes("include \"x\" y")[0].0, LintCode::SyntaxError);
+    }
+
+    #[test]
+    fn test_syntax_errors_and_allow() {
+        let warnings = Lint::new().allow(LintCode::UndefinedVariable).allow(LintCode::SyntaxError).check("1 + (2\nx / 0\ny $");
+        let found: Vec<(LintCode, usize)> = warnings.iter().map(|warning| (warning.code, warning.line)).collect();
+        assert_eq!(found, vec![(LintCode::SyntaxError, 1), (LintCode::DivisionByZero, 2), (LintCode::SyntaxError, 3)]);
+        assert_eq!(warnings[0].span, Span::new(4, 5));
+        assert_eq!(LintCode::from_name("division-by-zero"), Some(LintCode::DivisionByZero));
+        assert_eq!(LintCode::from_name("other"), None);
+    }
+}
@@ -0,0 +1,137 @@
+// src/linter.rs
+// Checking files of formulas (`mathexpr lint [--deny warnings] [--allow CODE] FILE...`) with
+// `Lint`: every warning is listed as `path:line:column: warning[code]: message` followed by the
+// line with the span marked. Syntax errors fail the run; warnings only fail it with
+// --deny warnings, for use in CI.
+use std::fs;
+
+use mathexpr::{Context, Lint, LintCode, Warning};
+
+use crate::color::Colors;
+use crate::Failure;
+
+// Arguments of the lint subcommand
+#[derive(Debug, Default, PartialEq)]
+pub struct LintArgs {
+    deny_warnings: bool,
+    allowed: Vec<LintCode>,
+    files: Vec<String>,
+}
+
+pub const USAGE: &str = "Usage: mathexpr lint [--deny warnings] [--allow <CODE>]... FILE...";
+
+// Parses the arguments following `lint`
+pub fn parse_args(args: &[String]) -> Result<LintArgs, String> {
+    let mut parsed = LintArgs::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--deny" => match value()?.as_str() {
+                "warnings" => parsed.deny_warnings = true,
+                other => return Err(format!("Only warnings can be denied, not {}", other)),
+            },
+            "--allow" => {
+                let name = value()?;
+                let code = LintCode::from_name(name).ok_or(format!("Unknown lint: {}", name))?;
+                parsed.allowed.push(code);
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unexpected argument: {}", arg)),
+            _ => parsed.files.push(arg.clone()),
+        }
+    }
+    if parsed.files.is_empty() {
+        return Err("No files to check".to_string());
+    }
+    Ok(parsed)
+}
+
+pub fn run(args: &LintArgs, ctx: &Context, colors: Colors) -> Result<(), Failure> {
+    let lint = args.allowed.iter().fold(Lint::new().context(ctx), |lint, code| lint.allow(*code));
+    let (mut errors, mut warnings) = (0, 0);
+    for path in &args.files {
+        let source = fs::read_to_string(path).map_err(|e| {
+            eprintln!("Error: cannot read {}: {}", path, e);
+            Failure::Usage
+        })?;
+        let lines: Vec<&str> = source.lines().collect();
+        for warning in lint.check(&source) {
+            println!("{}", report(path, lines[warning.line - 1], &warning, colors));
+            if warning.code.is_error() {
+                errors += 1;
+            } else {
+                warnings += 1;
+            }
+        }
+    }
+    if errors + warnings > 0 {
+        println!("{} errors, {} warnings", errors, warnings);
+    }
+    if errors > 0 {
+        Err(Failure::Parse)
+    } else if warnings > 0 && args.deny_warnings {
+        Err(Failure::Check)
+    } else {
+        Ok(())
+    }
+}
+
+// The warning with its location (the column counted in characters from 1) and the marked line
+fn report(path: &str, line: &str, warning: &Warning, colors: Colors) -> String {
+    let column = line[..warning.span.start.min(line.len())].chars().count() + 1;
+    let kind = if warning.code.is_error() { colors.error("error") } else { "warning".to_string() };
+    format!(
+        "{}:{}:{}: {}[{}]: {}\n    {}",
+        path,
+        warning.line,
+        column,
+        kind,
+        warning.code,
+        warning.message,
+        colors.mark(line, warning.span)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mathexpr::Span;
+
+    #[test]
+    fn test_lint_files() {
+        let dir = std::env::temp_dir().join(format!("mathexpr-lint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("formulas.mexpr");
+        fs::write(&path, "rate = 0.05\ntotal = (price) * rate\n").unwrap();
+        let path = path.display().to_string();
+
+        let args = |flags: &[&str]| {
+            let mut args: Vec<String> = flags.iter().map(|s| s.to_string()).collect();
+            args.push(path.clone());
+            parse_args(&args).unwrap()
+        };
+        let mut ctx = Context::new();
+        assert_eq!(run(&args(&["--deny", "warnings"]), &ctx, Colors::default()), Err(Failure::Check));
+        assert_eq!(run(&args(&[]), &ctx, Colors::default()), Ok(()));
+        ctx.set_variable("price", 2.0);
+        let allowed = args(&["--deny", "warnings", "--allow", "redundant-parentheses"]);
+        assert_eq!(run(&allowed, &ctx, Colors::default()), Ok(()));
+        fs::write(&path, "total = (1\n").unwrap();
+        assert_eq!(run(&args(&[]), &ctx, Colors::default()), Err(Failure::Parse));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let warning = Warning {
+            code: LintCode::DivisionByZero,
+            message: "Division by zero".to_string(),
+            line: 3,
+            span: Span::new(7, 10),
+        };
+        assert_eq!(
+            report("f.mexpr", "π² = x / 0", &warning, Colors::default()),
+            "f.mexpr:3:6: warning[division-by-zero]: Division by zero\n    π² = x / 0"
+        );
+        assert!(parse_args(&["--deny".to_string(), "all".to_string(), "f".to_string()]).is_err());
+        assert!(parse_args(&["--allow".to_string(), "other".to_string(), "f".to_string()]).is_err());
+        assert!(parse_args(&[]).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+// src/loader.rs
+// Formula libraries: `.mexpr` files of named definitions, loaded into a context so teams can keep
+// shared rates, constants and formulas in one place.
+//
+//     # Shared by every report
+//     include "common.mexpr"
+//     rate = 0.05; fee = 2.5
+//     net(gross) = gross * (1 - rate) - fee   /* a function of its parameters */
+//
+// Each line holds definitions separated by ';': a variable (`name = expression`) or a function
+// (`name(a, b) = expression`), or an `include` of another file, relative to the including one. A
+// definition sees everything defined above it, including what earlier files defined. Comments are
+// '#' to the end of the line and '/* ... */', which may span lines. A file included twice is only
+// loaded the first time. `parse_line` reads a line the way the loader does, for the tools that
+// check and format these files.
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::incremental::segments;
+use crate::{Context, Evaluator, Expr, FunctionDefinition, Grammar, MathError, Parser, Result, Span, Statement, Token, Tokenizer};
+
+// One entry of a line of a formula file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Definition {
+    Include(String),              // include "path"
+    Function(FunctionDefinition), // name(params) = body
+    Statement(Statement),         // name = value; the loader accepts no other statement
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Definition::Include(path) => write!(f, "include \"{}\"", path),
+            Definition::Function(function) => write!(f, "{}({}) = {}", function.name, function.params.join(", "), function.body),
+            Definition::Statement(statement) => write!(f, "{}", statement),
+        }
+    }
+}
+
+// The entries of a line with the grammar: an include, or definitions and statements separated by
+// ';'. Comments may be '#' to the end of the line or '/* ... */' within it.
+pub fn parse_line(code: &str, grammar: &Grammar) -> Result<Vec<Definition>> {
+    if let Some(path) = include_path(code)? {
+        return Ok(vec![Definition::Include(path.to_string())]);
+    }
+    let tokens = Tokenizer::tokenize_spanned(code, grammar.tokenizer_options().clone())?;
+    segments(&tokens)
+        .into_iter()
+        .map(|segment| parse_definition(&tokens[segment], grammar))
+        .collect()
+}
+
+// Loads a file of definitions into a new context
+pub fn load_file(path: impl AsRef<Path>) -> Result<Context> {
+    let mut ctx = Context::new();
+    load_file_into(path, &mut ctx)?;
+    Ok(ctx)
+}
+
+// Loads a file of definitions into the context, replacing variables and functions of the same
+// name. Errors name the file and line they are on, through every include that led there.
+pub fn load_file_into(path: impl AsRef<Path>, ctx: &mut Context) -> Result<()> {
+    Loader::default().file(path.as_ref(), ctx)
+}
+
+// Loads definitions from a string, with includes relative to the directory `dir`
+pub fn load_source_into(source: &str, dir: impl AsRef<Path>, ctx: &mut Context) -> Result<()> {
+    Loader::default().source(source, dir.as_ref(), ctx)
+}
+
+#[derive(Default)]
+struct Loader {
+    loading: Vec<PathBuf>,   // The files being loaded, each included by the one before it
+    loaded: HashSet<PathBuf>, // Every file loaded or being loaded
+}
+
+impl Loader {
+    fn file(&mut self, path: &Path, ctx: &mut Context) -> Result<()> {
+        let in_file = |error| MathError::File {
+            path: path.display().to_string(),
+            error: Box::new(error),
+        };
+        let io = |e: std::io::Error| MathError::Io {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        };
+        let canonical = fs::canonicalize(path).map_err(io)?;
+        if self.loading.contains(&canonical) {
+            return Err(MathError::IncludeCycle(path.display().to_string()));
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(());
+        }
+        let source = fs::read_to_string(path).map_err(io)?;
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.loading.push(canonical);
+        let result = self.source(&source, &dir, ctx);
+        self.loading.pop();
+        result.map_err(in_file)
+    }
+
+    fn source(&mut self, source: &str, dir: &Path, ctx: &mut Context) -> Result<()> {
+        let mut open = false; // Inside a block comment that started on an earlier line
+        for (number, line) in source.lines().enumerate() {
+            let (code, still_open) = line_code(line, open);
+            open = still_open;
+            self.line(code, dir, ctx).map_err(|error| MathError::Line {
+                line: number + 1,
+                error: Box::new(error),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, code: &str, dir: &Path, ctx: &mut Context) -> Result<()> {
+        for definition in parse_line(code, &Grammar::default())? {
+            match definition {
+                Definition::Include(path) => self.file(&dir.join(path), ctx)?,
+                Definition::Function(function) => ctx.define_function(function),
+                Definition::Statement(statement @ Statement::Assign { .. }) => {
+                    Evaluator::execute(&statement, ctx)?;
+                }
+                Definition::Statement(_) => {
+                    return Err(MathError::InvalidExpression(
+                        "Expected a definition such as `name = value` or `name(x) = expression`".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The code of a line, up to a block comment that stays open past its end and after one that was
+// open at its start. Also returns whether a block comment is open at the end of the line.
+fn line_code(line: &str, open: bool) -> (&str, bool) {
+    let code = if open {
+        match line.find("*/") {
+            Some(end) => &line[end + 2..],
+            None => return ("", true),
+        }
+    } else {
+        line
+    };
+    let mut from = 0;
+    loop {
+        let rest = &code[from..];
+        match (rest.find("/*"), rest.find('#')) {
+            (Some(start), hash) if hash.is_none_or(|hash| start < hash) => {
+                let start = from + start;
+                match code[start + 2..].find("*/") {
+                    Some(end) => from = start + 2 + end + 2,
+                    None => return (&code[..start], true),
+                }
+            }
+            _ => return (code, false),
+        }
+    }
+}
+
+// The file named by an `include "path"` line, or None when the line is not an include
+pub(crate) fn include_path(code: &str) -> Result<Option<&str>> {
+    let code = code.trim();
+    let Some(rest) = code.strip_prefix("include") else { return Ok(None) };
+    let rest = rest.trim_start();
+    if !rest.starts_with('"') {
+        return Ok(None);
+    }
+    let invalid = || MathError::InvalidExpression(format!("Expected include \"FILE\", found: {}", code));
+    let (path, after) = rest[1..].split_once('"').ok_or_else(invalid)?;
+    let after = after.trim_start();
+    if path.is_empty() || !(after.is_empty() || after.starts_with('#')) {
+        return Err(invalid());
+    }
+    Ok(Some(path))
+}
+
+// One ';'-separated entry of a line: `name(params) = body`, or otherwise a statement
+pub(crate) fn parse_definition(tokens: &[(Token, Span)], grammar: &Grammar) -> Result<Definition> {
+    let parse = |tokens: &[(Token, Span)]| Parser::with_spans(tokens.to_vec(), *grammar.parser_options());
+    let assign = tokens.iter().position(|(token, _)| *token == Token::Assign);
+    if let (Some(assign), [(Token::Identifier(_), _), (Token::LParen, _), ..]) = (assign, tokens) {
+        let Expr::Function { name, args } = parse(&tokens[..assign]).parse()? else {
+            return Err(MathError::InvalidExpression("Expected name(parameters) before '='".to_string()));
+        };
+        let params = args
+            .into_iter()
+            .map(|arg| match arg {
+                Expr::Variable(param) => Ok(param),
+                arg => Err(MathError::InvalidExpression(format!("Parameters of {} must be names, found: {}", name, arg))),
+            })
+            .collect::<Result<Vec<String>>>()?;
+        let body = parse(&tokens[assign + 1..]).parse()?;
+        return Ok(Definition::Function(FunctionDefinition { name, params, body }));
+    }
+    let mut statements = parse(tokens).parse_statements()?;
+    match statements.pop() {
+        Some(statement) if statements.is_empty() => Ok(Definition::Statement(statement)),
+        _ => Err(MathError::InvalidExpression("Expected one statement".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str, ctx: &Context) -> Result<f64> {
+        Evaluator::evaluate_with(&Expr::parse(input)?, ctx)
+    }
+
+    #[test]
+    fn test_load_files() {
+        let dir = std::env::temp_dir().join(format!("mathexpr-loader-{}", std::process::id()));
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(dir.join("shared/common.mexpr"), "# Shared\nrate = 0.05\nsquare(x) = x^2\n").unwrap();
+        fs::write(
+            dir.join("report.mexpr"),
+            "include \"shared/common.mexpr\"\ninclude \"shared/common.mexpr\" # only once\n\
+             fee = 2.5; net(gross) = gross * (1 - rate) - fee /* spans\n  lines */ area(w, h) = w * h\n",
+        )
+        .unwrap();
+        let ctx = load_file(dir.join("report.mexpr")).unwrap();
+        assert_eq!(ctx.variable("rate"), Some(0.05));
+        assert_eq!(eval("net(100)", &ctx).unwrap(), 92.5);
+        assert_eq!(eval("area(2, 3) + square(4)", &ctx).unwrap(), 22.0);
+        assert!(matches!(eval("area(2)", &ctx), Err(MathError::ArgumentCount { found: 1, .. })));
+
+        // Errors name the file and line, through the includes
+        fs::write(dir.join("shared/common.mexpr"), "rate = 0.05\ninclude \"../report.mexpr\"\n").unwrap();
+        let error = load_file(dir.join("report.mexpr")).unwrap_err();
+        let report = dir.join("report.mexpr").display().to_string();
+        assert!(error.to_string().ends_with(&format!("line 2: {} includes itself", dir.join("shared/../report.mexpr").display())));
+        assert!(error.to_string().starts_with(&format!("{}: line 1: ", report)));
+        fs::write(dir.join("shared/common.mexpr"), "rate = 0.05\n\ntotal = price * rate\n").unwrap();
+        let error = load_file(dir.join("report.mexpr")).unwrap_err();
+        assert!(error.to_string().ends_with("common.mexpr: line 3: Unknown variable: price"));
+        assert!(matches!(load_file(dir.join("missing.mexpr")), Err(MathError::Io { .. })));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_definitions() {
+        let mut ctx = Context::new();
+        ctx.set_variable("base", 10.0);
+        load_source_into("a = base * 2; f(x) = x + a\na = 0 # f keeps the a it was defined with", ".", &mut ctx).unwrap();
+        assert_eq!(eval("f(1) + a", &ctx).unwrap(), 21.0);
+
+        let error = |source: &str| load_source_into(source, ".", &mut Context::new()).unwrap_err().to_string();
+        assert_eq!(error("x = 1\n1 + 2"), "line 2: Invalid operator: Expected a definition such as `name = value` or `name(x) = expression`");
+        assert_eq!(error("f(x, 2) = x"), "line 1: Invalid operator: Parameters of f must be names, found: 2");
+        assert_eq!(error("include \"a.mexpr\" x"), "line 1: Invalid operator: Expected include \"FILE\", found: include \"a.mexpr\" x");
+        assert!(error("include \"missing.mexpr\"").starts_with("line 1: Cannot read ./missing.mexpr: "));
+    }
+
+    #[test]
+    fn test_line_code() {
+        assert_eq!(line_code("a = 1 /* open", false), ("a = 1 ", true));
+        assert_eq!(line_code("still open", true), ("", true));
+        assert_eq!(line_code("*/ b = 2 /* c */ # /*", true), (" b = 2 /* c */ # /*", false));
+        assert_eq!(include_path("include \"x.mexpr\"  # note").unwrap(), Some("x.mexpr"));
+        assert_eq!(include_path("included = 1").unwrap(), None);
+        assert_eq!(include_path("include").unwrap(), None);
+    }
+}
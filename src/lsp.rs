@@ -0,0 +1,352 @@
+// src/lsp.rs
+// Language server for formula files (`mathexpr lsp`), speaking the Language Server Protocol on
+// standard input and output. A document is read like a watch-mode file: statements one or more
+// per line, each line evaluated after the ones before it, and lines of formula libraries (see
+// `mathexpr::loader`) too, with includes relative to the document. Editors get an error diagnostic for
+// every line that fails (at the offending token when the error has a span), hover showing a
+// function's documentation, a variable's value or what the line evaluates to, and completion of
+// function and variable names.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse, Diagnostic,
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    Documentation, Hover, HoverContents, HoverParams, HoverProviderCapability, MarkupContent, MarkupKind, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+use mathexpr::functions::{function_info, FUNCTIONS};
+use mathexpr::loader::{parse_line, Definition};
+use mathexpr::{load_file_into, Context, Expr, MathError, Span, Statement, Token, Tokenizer};
+
+use crate::{render_result, CliOptions};
+
+// Serves the editor on standard input and output until it shuts the server down
+pub fn run(options: &CliOptions, ctx: &Context) -> Result<(), String> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection, options, ctx)?;
+    drop(connection); // Closes the channels, so the threads writing and reading can finish
+    io_threads.join().map_err(|e| e.to_string())
+}
+
+fn serve(connection: &Connection, options: &CliOptions, ctx: &Context) -> Result<(), String> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..ServerCapabilities::default()
+    };
+    let capabilities = serde_json::to_value(capabilities).map_err(|e| e.to_string())?;
+    connection.initialize(capabilities).map_err(|e| e.to_string())?;
+
+    // The text of every open document, by URI
+    let mut documents: HashMap<String, String> = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request).map_err(|e| e.to_string())? {
+                    return Ok(());
+                }
+                let response = answer(request, &documents, options, ctx);
+                connection.sender.send(Message::Response(response)).map_err(|e| e.to_string())?;
+            }
+            Message::Notification(notification) => {
+                if let Some((uri, text)) = update(notification, &mut documents) {
+                    let dir = document_dir(&uri);
+                    let params = PublishDiagnosticsParams {
+                        uri,
+                        diagnostics: text.map(|text| diagnostics(&text, &dir, options, ctx)).unwrap_or_default(),
+                        version: None,
+                    };
+                    let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+                    connection.sender.send(Message::Notification(notification)).map_err(|e| e.to_string())?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+// Applies a document being opened, changed or closed. Returns the document and its new text
+// (none once closed), whose diagnostics are then published again.
+fn update(notification: Notification, documents: &mut HashMap<String, String>) -> Option<(Uri, Option<String>)> {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            let document = params.text_document;
+            documents.insert(document.uri.as_str().to_string(), document.text.clone());
+            Some((document.uri, Some(document.text)))
+        }
+        DidChangeTextDocument::METHOD => {
+            // With full synchronization the last change holds the whole text
+            let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            let text = params.content_changes.into_iter().last()?.text;
+            documents.insert(params.text_document.uri.as_str().to_string(), text.clone());
+            Some((params.text_document.uri, Some(text)))
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            documents.remove(params.text_document.uri.as_str());
+            Some((params.text_document.uri, None))
+        }
+        _ => None,
+    }
+}
+
+fn answer(request: Request, documents: &HashMap<String, String>, options: &CliOptions, ctx: &Context) -> Response {
+    let text = |uri: &Uri| documents.get(uri.as_str()).map(String::as_str).unwrap_or_default();
+    match request.method.as_str() {
+        HoverRequest::METHOD => match serde_json::from_value::<HoverParams>(request.params) {
+            Ok(params) => {
+                let at = params.text_document_position_params;
+                let uri = &at.text_document.uri;
+                let hover = hover(text(uri), &document_dir(uri), at.position, options, ctx).map(|value| Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value,
+                    }),
+                    range: None,
+                });
+                Response::new_ok(request.id, hover)
+            }
+            Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+        },
+        Completion::METHOD => match serde_json::from_value::<CompletionParams>(request.params) {
+            Ok(params) => {
+                let at = params.text_document_position;
+                let uri = &at.text_document.uri;
+                let items = completions(text(uri), &document_dir(uri), at.position, options, ctx);
+                Response::new_ok(request.id, CompletionResponse::Array(items))
+            }
+            Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+        },
+        method => Response::new_err(request.id, ErrorCode::MethodNotFound as i32, format!("Unsupported: {}", method)),
+    }
+}
+
+// Why a line could not be evaluated, and where in the line when known
+struct LineError {
+    message: String,
+    span: Option<Span>,
+}
+
+impl From<MathError> for LineError {
+    fn from(error: MathError) -> Self {
+        Self {
+            span: error.span(),
+            message: error.to_string(),
+        }
+    }
+}
+
+// The directory includes in a document are relative to: the file's own, or the working directory
+// for documents that are not files
+fn document_dir(uri: &Uri) -> PathBuf {
+    let path = uri.as_str().strip_prefix("file://").map(Path::new);
+    path.and_then(Path::parent).map(Path::to_path_buf).unwrap_or_default()
+}
+
+// Evaluates the statements of a line, returning their results as printed by the command line.
+// Includes are loaded and functions defined, without results.
+fn check_line(line: &str, dir: &Path, ctx: &mut Context, options: &CliOptions) -> Result<Vec<String>, LineError> {
+    let mut results = Vec::new();
+    for definition in parse_line(line, &options.config.grammar())? {
+        match definition {
+            Definition::Include(path) => load_file_into(dir.join(path), ctx)?,
+            Definition::Function(function) => ctx.define_function(function),
+            Definition::Statement(statement) => {
+                let value = options.config.evaluator().run(&statement, ctx)?;
+                let text = render_result(&statement, &value, options).map_err(|message| LineError { message, span: None })?;
+                results.push(text);
+            }
+        }
+    }
+    Ok(results)
+}
+
+// The context after evaluating the lines before `line`
+fn context_before(text: &str, dir: &Path, line: usize, options: &CliOptions, ctx: &Context) -> Context {
+    let mut ctx = ctx.clone();
+    for source in text.lines().take(line) {
+        let _ = check_line(source, dir, &mut ctx, options);
+    }
+    ctx
+}
+
+fn diagnostics(text: &str, dir: &Path, options: &CliOptions, ctx: &Context) -> Vec<Diagnostic> {
+    let mut ctx = ctx.clone();
+    let mut diagnostics = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if let Err(error) = check_line(line, dir, &mut ctx, options) {
+            // Errors found while evaluating have no span, so they cover the whole line
+            let span = error.span.unwrap_or(Span::new(0, line.len()));
+            let range = Range::new(position(line, number, span.start), position(line, number, span.end));
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("mathexpr".to_string()),
+                message: error.message,
+                ..Diagnostic::default()
+            });
+        }
+    }
+    diagnostics
+}
+
+// Markdown shown over the token at `at`: the signature and description of a function, the value
+// of a variable, or otherwise the results of the line
+fn hover(text: &str, dir: &Path, at: Position, options: &CliOptions, ctx: &Context) -> Option<String> {
+    let line = text.lines().nth(at.line as usize)?;
+    let offset = byte_offset(line, at.character);
+    let tokens = Tokenizer::tokenize_spanned(line, options.config.grammar().tokenizer_options().clone()).ok()?;
+    let (token, _) = tokens.iter().find(|(_, span)| span.start <= offset && offset <= span.end)?;
+    if let Token::Identifier(name) = token {
+        if let Some(info) = function_info(name) {
+            return Some(format!("```\n{}\n```\n{}", info.signature, info.description));
+        }
+    }
+
+    // Evaluate the line itself too, so an assignment on it shows its value
+    let mut ctx = context_before(text, dir, at.line as usize, options, ctx);
+    let results = check_line(line, dir, &mut ctx, options).ok()?;
+    match token {
+        Token::Identifier(name) => {
+            let value = ctx.value(name)?;
+            let text = render_result(&Statement::Expr(Expr::variable(name.as_str())), &value, options).ok()?;
+            Some(format!("`{}` = {}", name, text))
+        }
+        _ if results.is_empty() => None,
+        _ => Some(results.iter().map(|result| format!("= {}", result)).collect::<Vec<_>>().join("\n\n")),
+    }
+}
+
+// Every function, and the variables defined before the line being edited
+fn completions(text: &str, dir: &Path, at: Position, options: &CliOptions, ctx: &Context) -> Vec<CompletionItem> {
+    let ctx = context_before(text, dir, at.line as usize, options, ctx);
+    let functions = FUNCTIONS.iter().map(|info| CompletionItem {
+        label: info.name.to_string(),
+        kind: Some(CompletionItemKind::FUNCTION),
+        detail: Some(info.signature.to_string()),
+        documentation: Some(Documentation::String(info.description.to_string())),
+        ..CompletionItem::default()
+    });
+    let variables = ctx.variable_names().into_iter().map(|name| CompletionItem {
+        label: name.to_string(),
+        kind: Some(CompletionItemKind::VARIABLE),
+        detail: ctx.value(name).map(|value| value.to_string()),
+        ..CompletionItem::default()
+    });
+    functions.chain(variables).collect()
+}
+
+// Positions count UTF-16 code units within a line, as the protocol does by default
+fn position(line: &str, number: usize, byte: usize) -> Position {
+    let character = line[..byte.min(line.len())].encode_utf16().count();
+    Position::new(number as u32, character as u32)
+}
+
+fn byte_offset(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (offset, c) in line.char_indices() {
+        if units >= character as usize {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = "price = 2.5\ntotal = price * 4\nmax(total, 1) + (2\n√x + 1";
+
+    #[test]
+    fn test_diagnostics() {
+        let diagnostics = diagnostics(DOCUMENT, Path::new(""), &CliOptions::default(), &Context::new());
+        let found: Vec<(Range, &str)> = diagnostics.iter().map(|d| (d.range, d.message.as_str())).collect();
+        assert_eq!(
+            found,
+            [
+                // The unclosed parenthesis, counted in UTF-16 units
+                (Range::new(Position::new(2, 16), Position::new(2, 17)), "Unmatched '(' (2 opened, 1 closed)"),
+                (Range::new(Position::new(3, 0), Position::new(3, 6)), "Unknown variable: x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_formula_libraries() {
+        let dir = std::env::temp_dir().join(format!("mathexpr-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("common.mexpr"), "rate = 0.05\n").unwrap();
+        let document = "include \"common.mexpr\"\nnet(gross) = gross * (1 - rate)\nnet(100)";
+        assert_eq!(diagnostics(document, &dir, &CliOptions::default(), &Context::new()), []);
+        let hover = hover(document, &dir, Position::new(2, 7), &CliOptions::default(), &Context::new());
+        assert_eq!(hover, Some("= 95".to_string()));
+        let uri: Uri = format!("file://{}/prices.mexpr", dir.display()).parse().unwrap();
+        assert_eq!(document_dir(&uri), dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hover() {
+        let hover = |line, character| {
+            hover(DOCUMENT, Path::new(""), Position::new(line, character), &CliOptions::default(), &Context::new())
+        };
+        assert_eq!(hover(1, 9), Some("`price` = 2.5".to_string()));
+        assert_eq!(hover(1, 1), Some("`total` = 10".to_string()));
+        assert_eq!(hover(1, 14), Some("= 10".to_string()));
+        assert!(hover(2, 1).unwrap().starts_with("```\nmax("));
+        assert_eq!(hover(3, 1), None); // The line fails
+        assert_eq!(byte_offset("√x + 1", 1), "√".len());
+    }
+
+    #[test]
+    fn test_completions() {
+        let items = completions(DOCUMENT, Path::new(""), Position::new(1, 0), &CliOptions::default(), &Context::new());
+        let price = items.iter().find(|item| item.label == "price").unwrap();
+        assert_eq!((price.kind, price.detail.as_deref()), (Some(CompletionItemKind::VARIABLE), Some("2.5")));
+        // Only what is defined before the line
+        assert!(items.iter().all(|item| item.label != "total"));
+        assert!(items.iter().any(|item| item.label == "sum" && item.kind == Some(CompletionItemKind::FUNCTION)));
+    }
+
+    #[test]
+    fn test_protocol() {
+        let (server, client) = Connection::memory();
+        let handle = std::thread::spawn(move || serve(&server, &CliOptions::default(), &Context::new()));
+        let request = |id: i32, method: &str, params: serde_json::Value| {
+            client.sender.send(Message::Request(Request::new(id.into(), method.to_string(), params))).unwrap();
+            match client.receiver.recv().unwrap() {
+                Message::Response(response) => response.result,
+                message => panic!("unexpected {:?}", message),
+            }
+        };
+
+        let initialized = request(1, "initialize", serde_json::json!({ "capabilities": {} })).unwrap();
+        assert_eq!(initialized["capabilities"]["hoverProvider"], true);
+        client.sender.send(Message::Notification(Notification::new("initialized".to_string(), serde_json::json!({})))).unwrap();
+
+        let document = serde_json::json!({ "uri": "file:///prices.mexpr", "languageId": "mexpr", "version": 1, "text": "1 +" });
+        let open = Notification::new(DidOpenTextDocument::METHOD.to_string(), serde_json::json!({ "textDocument": document }));
+        client.sender.send(Message::Notification(open)).unwrap();
+        let Message::Notification(published) = client.receiver.recv().unwrap() else { panic!("expected diagnostics") };
+        assert_eq!(published.method, PublishDiagnostics::METHOD);
+        assert_eq!(published.params["diagnostics"].as_array().unwrap().len(), 1);
+
+        let position = serde_json::json!({ "textDocument": { "uri": "file:///prices.mexpr" }, "position": { "line": 0, "character": 0 } });
+        assert_eq!(request(2, HoverRequest::METHOD, position), Some(serde_json::Value::Null));
+
+        assert_eq!(request(3, "shutdown", serde_json::Value::Null), Some(serde_json::Value::Null));
+        client.sender.send(Message::Notification(Notification::new("exit".to_string(), serde_json::Value::Null))).unwrap();
+        handle.join().unwrap().unwrap();
+    }
+}
@@ -5,68 +5,547 @@
 //! This program tokenizes, parses, and evaluates mathematical expressions.
 
 //src/main.rs
-use mathexpr::{Evaluator, Parser, Tokenizer};
+use mathexpr::format::{format_radix, output_radix};
+use mathexpr::{
+    load_file_into, Context, EvaluatorConfig, Notation, Parser, Precision, Span, Statement, Tokenizer, Value,
+};
 use std::env;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "csv")]
+mod batch;
+mod color;
+mod completions;
+mod formatter;
+mod linter;
+#[cfg(feature = "config")]
+mod config;
+#[cfg(feature = "repl")]
+mod repl;
+#[cfg(feature = "lsp")]
+mod lsp;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "tui")]
+mod tui;
+mod watch;
 
-fn process_expression(input: &str) {
-    println!("Input: {}", input);
+// Settings collected from the configuration file and the command-line flags
+#[derive(Default)]
+struct CliOptions {
+    config: EvaluatorConfig, // How every mode parses, evaluates and prints
+    base: Option<u32>,
+    help: bool,
+    interactive: bool,
+    time: bool, // Report how long each stage took
+    colors: color::Colors,
+    definitions: Vec<String>, // Statements run before the expressions, such as "g = 9.81"
+    libraries: Vec<String>,   // Files of definitions loaded before those
+    watch: Option<String>,    // File to re-evaluate whenever it changes
+    expressions: Vec<String>,
+}
+
+// Parses the command-line arguments (excluding the program name) on top of the defaults
+fn parse_args(args: &[String], mut options: CliOptions) -> Result<CliOptions, String> {
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => options.help = true,
+            "-i" | "--interactive" => options.interactive = true,
+            "--time" => options.time = true,
+            "--no-color" => options.colors = color::Colors::default(),
+            "--no-config" => {} // Handled before the configuration is read
+            // The later of --precision and --sig-figs wins
+            "--precision" => {
+                let value = args.next().ok_or("--precision requires a value")?;
+                let places = parse_count("--precision", value, PRECISIONS)?;
+                options.config.format.precision = Some(Precision::Decimals(places));
+            }
+            "--sig-figs" => {
+                let value = args.next().ok_or("--sig-figs requires a value")?;
+                let figures = parse_count("--sig-figs", value, SIG_FIGS)?;
+                options.config.format.precision = Some(Precision::SignificantFigures(figures));
+            }
+            "--load" => {
+                let path = args.next().ok_or("--load requires a file")?;
+                options.libraries.push(path.clone());
+            }
+            "--watch" => {
+                let path = args.next().ok_or("--watch requires a file")?;
+                options.watch = Some(path.clone());
+            }
+            "--notation" => {
+                let value = args.next().ok_or("--notation requires a value")?;
+                options.config.format.notation = parse_notation(value)?;
+            }
+            "--base" => {
+                let value = args.next().ok_or("--base requires a value")?;
+                let base = value.parse::<u32>().map_err(|_| format!("Invalid base: {} (expected 2 to 36)", value))?;
+                options.base = Some(check_base(base)?);
+            }
+            _ => options.expressions.push(arg.clone()),
+        }
+    }
+
+    Ok(options)
+}
+
+// A number of digits given to a flag
+// The decimal places and significant figures results can be shown with
+const PRECISIONS: RangeInclusive<u32> = 0..=100;
+const SIG_FIGS: RangeInclusive<u32> = 1..=100;
+
+fn parse_count(flag: &str, value: &str, range: RangeInclusive<u32>) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(count) => check_count(flag, count, range),
+        Err(_) => Err(format!("Invalid value for {}: {} (expected {} to {})", flag, value, range.start(), range.end())),
+    }
+}
+
+fn check_count(name: &str, count: u32, range: RangeInclusive<u32>) -> Result<u32, String> {
+    match range.contains(&count) {
+        true => Ok(count),
+        false => Err(format!("Invalid value for {}: {} (expected {} to {})", name, count, range.start(), range.end())),
+    }
+}
+
+fn check_base(base: u32) -> Result<u32, String> {
+    match base {
+        2..=36 => Ok(base),
+        _ => Err(format!("Invalid base: {} (expected 2 to 36)", base)),
+    }
+}
+
+// A context holding the definitions of the loaded files, then the variables of the configured
+// definitions
+fn initial_context(options: &CliOptions) -> Result<Context, String> {
+    let mut ctx = Context::new();
+    for path in &options.libraries {
+        load_file_into(path, &mut ctx).map_err(|e| e.to_string())?;
+    }
+    let (grammar, evaluator) = (options.config.grammar(), options.config.evaluator());
+    for definition in &options.definitions {
+        let statements = grammar.parse_statements(definition).map_err(|e| format!("{}: {}", definition, e))?;
+        for statement in &statements {
+            evaluator.run(statement, &mut ctx).map_err(|e| format!("{}: {}", definition, e))?;
+        }
+    }
+    Ok(ctx)
+}
+
+fn parse_notation(value: &str) -> Result<Notation, String> {
+    match value {
+        "auto" => Ok(Notation::Auto),
+        "sci" | "scientific" => Ok(Notation::Scientific),
+        "eng" | "engineering" => Ok(Notation::Engineering),
+        "si" => Ok(Notation::SiPrefix),
+        _ => Err(format!("Unknown notation: {} (expected auto, sci, eng or si)", value)),
+    }
+}
+
+
+// Time spent in each stage of processing an input, reported with --time
+#[derive(Debug, Default)]
+struct Timings {
+    tokenize: Duration,
+    parse: Duration,
+    evaluate: Duration, // Of all the statements
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.tokenize + self.parse + self.evaluate;
+        write!(
+            f,
+            "tokenize {:?}, parse {:?}, evaluate {:?}, total {:?}",
+            self.tokenize, self.parse, self.evaluate, total
+        )
+    }
+}
+
+// Runs `stage` and adds the time it took to `elapsed`
+fn timed<T>(elapsed: &mut Duration, stage: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = stage();
+    *elapsed += start.elapsed();
+    result
+}
+
+// Prints an error. When it has a span and colors are on, the input follows with the erroneous
+// part marked.
+fn print_error(message: &str, input: &str, span: Option<Span>, options: &CliOptions) {
+    println!("{}", options.colors.error(message));
+    if let (Some(span), true) = (span, options.colors.enabled()) {
+        println!("  {}", options.colors.mark(input, span));
+    }
+}
+
+fn process_expression(input: &str, options: &CliOptions, ctx: &Context) -> Result<(), Failure> {
+    println!("Input: {}", options.colors.highlight(input));
+    let mut timings = Timings::default();
+    let grammar = options.config.grammar();
 
     // First tokenize
-    match Tokenizer::tokenize(input) {
+    let outcome = match timed(&mut timings.tokenize, || Tokenizer::tokenize_spanned(input, grammar.tokenizer_options().clone())) {
         Ok(tokens) => {
-            println!("\nTokens: {:#?}", tokens);
+            println!("\nTokens: {:#?}", tokens.iter().map(|(token, _)| token).collect::<Vec<_>>());
             
             // Then parse
-            let mut parser = Parser::new(tokens);
-            match parser.parse() {
-                Ok(expr) => {
-                    println!("\nParsed Expression: {}", expr);
-                    println!("\nExpression Tree: {:#?}", expr);
-
-                    // Finally evaluate
-                    match Evaluator::evaluate(&expr) {
-                        Ok(result) => println!("\nResult: {}", result),
-                        Err(e) => println!("Evaluation Error: {}", e),
-                    }
+            let mut parser = Parser::with_spans(tokens, *grammar.parser_options());
+            match timed(&mut timings.parse, || parser.parse_statements()) {
+                Ok(statements) => {
+                    // Assignments in earlier statements are visible to later ones
+                    let mut ctx = ctx.clone();
+                    statements
+                        .iter()
+                        .try_for_each(|statement| process_statement(statement, &mut ctx, options, &mut timings))
+                }
+                Err(e) => {
+                    print_error(&format!("Parsing Error: {}", e), input, e.span(), options);
+                    Err(Failure::Parse)
                 }
-                Err(e) => println!("Parsing Error: {}", e),
             }
         }
-        Err(e) => println!("Tokenization Error: {}", e),
+        Err(e) => {
+            print_error(&format!("Tokenization Error: {}", e), input, e.span(), options);
+            Err(Failure::Tokenize)
+        }
+    };
+
+    if options.time {
+        println!("\nTime: {}", timings);
+    }
+    outcome
+}
+
+// Evaluates ';'-separated statements, printing just their results, one per line
+fn evaluate_line(line: &str, ctx: &mut Context, options: &CliOptions) -> Result<(), Failure> {
+    let mut timings = Timings::default();
+    let grammar = options.config.grammar();
+    let tokens = timed(&mut timings.tokenize, || Tokenizer::tokenize_spanned(line, grammar.tokenizer_options().clone()))
+        .map_err(|e| {
+            print_error(&format!("Error: {}", e), line, e.span(), options);
+            Failure::Tokenize
+        })?;
+    let parsed = timed(&mut timings.parse, || Parser::with_spans(tokens, *grammar.parser_options()).parse_statements());
+    let statements = parsed.map_err(|e| {
+        print_error(&format!("Error: {}", e), line, e.span(), options);
+        Failure::Parse
+    })?;
+    let evaluator = options.config.evaluator();
+    for statement in &statements {
+        let result = timed(&mut timings.evaluate, || evaluator.run(statement, ctx)).map_err(|e| e.to_string());
+        match result.and_then(|result| render_result(statement, &result, options)) {
+            Ok(text) => println!("{}", options.colors.highlight(&text)),
+            Err(e) => {
+                println!("{}", options.colors.error(&format!("Error: {}", e)));
+                return Err(Failure::Evaluate);
+            }
+        }
+    }
+    if options.time {
+        println!("Time: {}", timings);
+    }
+    Ok(())
+}
+
+// Evaluates and prints one statement
+fn process_statement(
+    statement: &Statement,
+    ctx: &mut Context,
+    options: &CliOptions,
+    timings: &mut Timings,
+) -> Result<(), Failure> {
+    println!("\nParsed Expression: {}", options.colors.highlight(&statement.to_string()));
+    match statement {
+        Statement::Expr(expr) => println!("\nExpression Tree: {:#?}", expr),
+        Statement::Assign { .. } => println!("\nExpression Tree: {:#?}", statement),
+    }
+
+    // Finally evaluate
+    let evaluator = options.config.evaluator();
+    let result = timed(&mut timings.evaluate, || evaluator.run(statement, ctx)).map_err(|e| e.to_string());
+    match result.and_then(|result| render_result(statement, &result, options)) {
+        Ok(text) => {
+            println!("\nResult: {}", options.colors.highlight(&text));
+            Ok(())
+        }
+        Err(e) => {
+            println!("{}", options.colors.error(&format!("Evaluation Error: {}", e)));
+            Err(Failure::Evaluate)
+        }
+    }
+}
+
+// The text shown for a statement's result, in the requested notation or base
+fn render_result(statement: &Statement, result: &Value, options: &CliOptions) -> Result<String, String> {
+    let radix = match statement {
+        Statement::Expr(expr) => options.base.or_else(|| output_radix(expr)),
+        Statement::Assign { .. } => options.base,
+    };
+    match (radix, result.as_number()) {
+        (Some(base), Some(result)) => {
+            format_radix(result, base).ok_or_else(|| format!("{} cannot be shown in base {}", result, base))
+        }
+        _ => Ok(options.config.format(result)),
     }
 }
 
 
 fn print_usage() {
-    println!("Usage: mathexpr [EXPRESSION]");
+    println!("Usage: mathexpr [OPTIONS] [EXPRESSION]...");
+    println!("       mathexpr <COMMAND> [ARGS]");
+    println!("\nAn expression may contain several ';'-separated statements, such as \"a = 2; b = 3; a^b\".");
+    println!("\nOptions:");
+    for flag in completions::FLAGS {
+        println!("  {:<28}  {}", flag.usage(), flag.help);
+    }
+    println!("\nCommands:");
+    for (name, help) in completions::SUBCOMMANDS {
+        println!("  {:<28}  {}", name, help);
+    }
     println!("\nExamples:");
     println!("  mathexpr \"2 + 3 * 4\"");
     println!("  mathexpr \"1.5e3 + 2 * (3.7 - 4)^2\"");
     println!("  mathexpr \"(2 + 3) * 4\"");
-    println!("\nIf no expression is provided, a default example will be used.");
+    println!("  mathexpr \"hex(255)\"");
+    println!("\nA single expression is shown step by step. Several are evaluated in order, sharing");
+    println!("assignments, with one result per line. If no expression is provided, a default example");
+    println!("will be used.");
+    println!("\nExit status: 0 on success, 1 for invalid arguments, configuration or files, 2 for");
+    println!("tokenization errors, 3 for parse errors and 4 for evaluation errors.");
+    println!("\nDefaults for the options, constants and definitions can be set in");
+    println!("~/.config/mathexpr/config.toml (see src/config.rs for the keys).");
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    
-    match args.len() {
-        // No arguments provided - use default example
-        1 => process_expression("1.5e3 + 2 * (3.7 - 4)^2"),
-        
-        // Expression provided as argument
-        2 => {
-            if args[1] == "-h" || args[1] == "--help" {
-                print_usage();
-            } else {
-                process_expression(&args[1]);
+// Runs the csv subcommand, reporting the rows that failed on standard error
+#[cfg(feature = "csv")]
+fn run_csv(args: &[String], options: &CliOptions) -> Result<(), Failure> {
+    let result = batch::parse_args(args).and_then(|args| {
+        let mut ctx = initial_context(options)?;
+        batch::run(&args, options, &mut ctx)
+    });
+    match result {
+        Ok(errors) if errors.is_empty() => Ok(()),
+        Ok(errors) => {
+            for e in errors {
+                eprintln!("Evaluation Error in {}", e);
             }
+            Err(Failure::Evaluate)
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("{}", batch::USAGE);
+            Err(Failure::Usage)
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+fn run_server(args: &[String], options: &CliOptions) -> Result<(), Failure> {
+    let result = server::parse_args(args).and_then(|args| {
+        let ctx = initial_context(options)?;
+        server::run(&args, &options.config, &ctx)
+    });
+    result.map_err(|e| {
+        eprintln!("Error: {}", e);
+        eprintln!("{}", server::USAGE);
+        Failure::Usage
+    })
+}
+
+#[cfg(not(feature = "server"))]
+fn run_server(_args: &[String], _options: &CliOptions) -> Result<(), Failure> {
+    println!("Error: the HTTP server needs the server feature");
+    Err(Failure::Usage)
+}
+
+fn run_lint(args: &[String], options: &CliOptions) -> Result<(), Failure> {
+    let args = linter::parse_args(args).map_err(|e| {
+        eprintln!("Error: {}", e);
+        eprintln!("{}", linter::USAGE);
+        Failure::Usage
+    })?;
+    let ctx = initial_context(options).map_err(|e| {
+        eprintln!("Error in definition {}", e);
+        Failure::Usage
+    })?;
+    linter::run(&args, &ctx, options.colors)
+}
+
+// The protocol uses standard output, so errors go to standard error
+#[cfg(feature = "lsp")]
+fn run_lsp(options: &CliOptions) -> Result<(), Failure> {
+    let result = initial_context(options).and_then(|ctx| lsp::run(options, &ctx));
+    result.map_err(|e| {
+        eprintln!("Error: {}", e);
+        Failure::Usage
+    })
+}
+
+#[cfg(not(feature = "lsp"))]
+fn run_lsp(_options: &CliOptions) -> Result<(), Failure> {
+    println!("Error: the language server needs the lsp feature");
+    Err(Failure::Usage)
+}
+
+// The options set in the configuration file, unless --no-config is given
+#[cfg(feature = "config")]
+fn configured_defaults(args: &[String]) -> Result<CliOptions, String> {
+    let mut options = CliOptions::default();
+    if args.iter().any(|arg| arg == "--no-config") {
+        return Ok(options);
+    }
+    if let Some(path) = config::config_path() {
+        config::Config::load(&path)
+            .and_then(|config| config.apply(&mut options))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(options)
+}
+
+#[cfg(not(feature = "config"))]
+fn configured_defaults(_: &[String]) -> Result<CliOptions, String> {
+    Ok(CliOptions::default())
+}
+
+// Why the tool failed, reported through its exit status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Usage = 1, // Invalid arguments, configuration or files
+    Tokenize = 2,
+    Parse = 3,
+    Evaluate = 4,
+    Check = 5, // A check such as `fmt --check` or `lint --deny warnings` found something to fix
+}
+
+impl From<Failure> for ExitCode {
+    fn from(failure: Failure) -> Self {
+        ExitCode::from(failure as u8)
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => failure.into(),
+    }
+}
+
+fn run() -> Result<(), Failure> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some("completions") = args.first().map(String::as_str) {
+        let Some(script) = args.get(1).and_then(|shell| completions::script(shell)) else {
+            println!("Usage: mathexpr completions <bash|zsh|fish>");
+            return Err(Failure::Usage);
+        };
+        print!("{}", script);
+        return Ok(());
+    }
+    let defaults = match configured_defaults(&args) {
+        Ok(defaults) => CliOptions {
+            colors: color::Colors::detect(),
+            ..defaults
         },
-        
-        // Too many arguments
-        _ => {
-            println!("Error: Too many arguments provided.");
+        Err(e) => {
+            println!("Error in {}", e);
+            return Err(Failure::Usage);
+        }
+    };
+    #[cfg(feature = "csv")]
+    if let Some("csv") = args.first().map(String::as_str) {
+        return run_csv(&args[1..], &defaults);
+    }
+    if let Some("serve") = args.first().map(String::as_str) {
+        return run_server(&args[1..], &defaults);
+    }
+    if let Some("fmt") = args.first().map(String::as_str) {
+        let args = formatter::parse_args(&args[1..]).map_err(|e| {
+            eprintln!("Error: {}", e);
+            eprintln!("{}", formatter::USAGE);
+            Failure::Usage
+        })?;
+        return formatter::run(&args);
+    }
+    if let Some("lint") = args.first().map(String::as_str) {
+        return run_lint(&args[1..], &defaults);
+    }
+    if let Some("lsp") = args.first().map(String::as_str) {
+        return run_lsp(&defaults);
+    }
+    // The full-screen calculator takes the same options as the other modes
+    let tui = args.first().is_some_and(|arg| arg == "tui");
+    let args = if tui { &args[1..] } else { &args[..] };
+    let options = match parse_args(args, defaults) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
             print_usage();
+            return Err(Failure::Usage);
+        }
+    };
+
+    if options.help {
+        print_usage();
+        return Ok(());
+    }
+
+    let ctx = match initial_context(&options) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            println!("Error in definition {}", e);
+            return Err(Failure::Usage);
+        }
+    };
+
+    if let Some(path) = &options.watch {
+        return watch::run(path, &options, &ctx).map_err(|e| {
+            println!("Error: {}", e);
+            Failure::Usage
+        });
+    }
+
+    if tui {
+        #[cfg(feature = "tui")]
+        return tui::run(&options, ctx).map_err(|e| {
+            println!("Error: {}", e);
+            Failure::Usage
+        });
+        #[cfg(not(feature = "tui"))]
+        {
+            println!("Error: the full-screen calculator needs the tui feature");
+            return Err(Failure::Usage);
+        }
+    }
+
+    if options.interactive {
+        #[cfg(feature = "repl")]
+        return repl::run(&options, ctx).map_err(|e| {
+            println!("Error: {}", e);
+            Failure::Usage
+        });
+        #[cfg(not(feature = "repl"))]
+        {
+            println!("Error: interactive mode needs the repl feature");
+            return Err(Failure::Usage);
         }
     }
-}
\ No newline at end of file
+
+    match options.expressions.as_slice() {
+        // No expression provided - use default example
+        [] => process_expression("1.5e3 + 2 * (3.7 - 4)^2", &options, &ctx),
+
+        // Expression provided as argument
+        [expression] => process_expression(expression, &options, &ctx),
+
+        // Several expressions: evaluated in order, sharing assignments, up to the first error
+        expressions => {
+            let mut ctx = ctx;
+            expressions.iter().try_for_each(|expression| evaluate_line(expression, &mut ctx, &options))
+        }
+    }
+}
@@ -0,0 +1,272 @@
+// src/matrix.rs
+// Arithmetic over list values. A flat list of numbers is a vector and a list of equally long
+// vectors is a matrix (one inner list per row).
+use crate::{MathError, Result, Value};
+
+// Combines two values element by element. A number is broadcast against every element of a
+// list; two lists must have the same shape.
+pub(crate) fn elementwise<F>(left: &Value, right: &Value, op: &F) -> Result<Value>
+where
+    F: Fn(f64, f64) -> Result<f64>,
+{
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => op(*l, *r).map(Value::Number),
+        (Value::List(items), Value::Number(_)) => items
+            .iter()
+            .map(|item| elementwise(item, right, op))
+            .collect::<Result<Vec<Value>>>()
+            .map(Value::List),
+        (Value::Number(_), Value::List(items)) => items
+            .iter()
+            .map(|item| elementwise(left, item, op))
+            .collect::<Result<Vec<Value>>>()
+            .map(Value::List),
+        (Value::List(l), Value::List(r)) => {
+            if l.len() != r.len() {
+                return Err(shape_mismatch("combine", left, right));
+            }
+            l.iter()
+                .zip(r)
+                .map(|(l, r)| elementwise(l, r, op))
+                .collect::<Result<Vec<Value>>>()
+                .map(Value::List)
+        }
+        (Value::Date(_) | Value::Duration(_) | Value::Money(_) | Value::Quantity(_), _) => Err(not_a_number(left)),
+        (_, Value::Date(_) | Value::Duration(_) | Value::Money(_) | Value::Quantity(_)) => Err(not_a_number(right)),
+    }
+}
+
+// Applies a function to every number in a value
+pub(crate) fn map<F>(value: &Value, op: &F) -> Result<Value>
+where
+    F: Fn(f64) -> Result<f64>,
+{
+    match value {
+        Value::Number(n) => op(*n).map(Value::Number),
+        Value::List(items) => items
+            .iter()
+            .map(|item| map(item, op))
+            .collect::<Result<Vec<Value>>>()
+            .map(Value::List),
+        Value::Date(_) | Value::Duration(_) | Value::Money(_) | Value::Quantity(_) => Err(not_a_number(value)),
+    }
+}
+
+fn not_a_number(value: &Value) -> MathError {
+    MathError::TypeMismatch {
+        expected: "number".to_string(),
+        found: value.type_name().to_string(),
+    }
+}
+
+// Matrix product. Vectors act as a row on the left and as a column on the right, so
+// vector * vector is the dot product and matrix * vector is a vector.
+pub(crate) fn matmul(left: &Value, right: &Value) -> Result<Value> {
+    let mismatch = || shape_mismatch("multiply", left, right);
+    match (as_vector(left), as_vector(right)) {
+        (Some(l), Some(r)) if l.len() == r.len() => Ok(Value::Number(dot(&l, &r))),
+        (Some(_), Some(_)) => Err(mismatch()),
+        (Some(l), None) => {
+            let r = as_matrix(right).ok_or_else(mismatch)?;
+            let product = multiply(&[l], &r).ok_or_else(mismatch)?;
+            Ok(Value::from(product.into_iter().next().unwrap_or_default()))
+        }
+        (None, Some(r)) => {
+            let l = as_matrix(left).ok_or_else(mismatch)?;
+            let column: Vec<Vec<f64>> = r.into_iter().map(|x| vec![x]).collect();
+            let product = multiply(&l, &column).ok_or_else(mismatch)?;
+            Ok(Value::from(product.into_iter().map(|row| row[0]).collect::<Vec<f64>>()))
+        }
+        (None, None) => {
+            let l = as_matrix(left).ok_or_else(mismatch)?;
+            let r = as_matrix(right).ok_or_else(mismatch)?;
+            multiply(&l, &r).map(from_matrix).ok_or_else(mismatch)
+        }
+    }
+}
+
+// Swaps rows and columns. A vector becomes a single column.
+pub(crate) fn transpose(value: &Value) -> Result<Value> {
+    if let Some(vector) = as_vector(value) {
+        return Ok(Value::List(vector.into_iter().map(|x| Value::from(vec![x])).collect()));
+    }
+    let rows = as_matrix(value).ok_or_else(|| MathError::InvalidArgument {
+        function: "transpose".to_string(),
+        reason: format!("expected a vector or matrix, got {}", shape(value)),
+    })?;
+    let columns = rows.first().map_or(0, Vec::len);
+    Ok(from_matrix(
+        (0..columns).map(|j| rows.iter().map(|row| row[j]).collect()).collect(),
+    ))
+}
+
+// The numbers of a flat list
+pub(crate) fn as_vector(value: &Value) -> Option<Vec<f64>> {
+    value.as_list()?.iter().map(Value::as_number).collect()
+}
+
+// The rows of a rectangular list of vectors
+pub(crate) fn as_matrix(value: &Value) -> Option<Vec<Vec<f64>>> {
+    let rows = value.as_list()?;
+    let rows: Vec<Vec<f64>> = rows.iter().map(as_vector).collect::<Option<_>>()?;
+    let columns = rows.first()?.len();
+    rows.iter().all(|row| row.len() == columns).then_some(rows)
+}
+
+pub(crate) fn from_matrix(rows: Vec<Vec<f64>>) -> Value {
+    Value::List(rows.into_iter().map(Value::from).collect())
+}
+
+// A value's shape for error messages, such as "2x3 matrix" or "number"
+pub(crate) fn shape(value: &Value) -> String {
+    if let Some(vector) = as_vector(value) {
+        format!("vector of length {}", vector.len())
+    } else if let Some(rows) = as_matrix(value) {
+        format!("{}x{} matrix", rows.len(), rows[0].len())
+    } else {
+        value.type_name().to_string()
+    }
+}
+
+pub(crate) fn dot(l: &[f64], r: &[f64]) -> f64 {
+    l.iter().zip(r).map(|(a, b)| a * b).sum()
+}
+
+// Determinant of a square matrix, by Gaussian elimination with partial pivoting
+pub(crate) fn determinant(mut a: Vec<Vec<f64>>) -> f64 {
+    let n = a.len();
+    let mut det = 1.0;
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap_or(col);
+        if a[pivot][col] == 0.0 {
+            return 0.0;
+        }
+        if pivot != col {
+            a.swap(pivot, col);
+            det = -det;
+        }
+        det *= a[col][col];
+        let pivot_row = a[col].clone();
+        for row in &mut a[col + 1..] {
+            let factor = row[col] / pivot_row[col];
+            subtract_scaled(row, &pivot_row, factor);
+        }
+    }
+    det
+}
+
+// Solves A X = B for X by Gauss-Jordan elimination with partial pivoting. Returns None when A
+// is singular (to within rounding error).
+pub(crate) fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let n = a.len();
+    let scale = a.iter().flatten().fold(0.0f64, |max, x| max.max(x.abs()));
+    let tolerance = scale * n as f64 * f64::EPSILON;
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= tolerance || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(pivot, col);
+        b.swap(pivot, col);
+
+        let p = a[col][col];
+        a[col].iter_mut().for_each(|x| *x /= p);
+        b[col].iter_mut().for_each(|x| *x /= p);
+        let (pivot_a, pivot_b) = (a[col].clone(), b[col].clone());
+        for row in 0..n {
+            let factor = a[row][col];
+            if row != col && factor != 0.0 {
+                subtract_scaled(&mut a[row], &pivot_a, factor);
+                subtract_scaled(&mut b[row], &pivot_b, factor);
+            }
+        }
+    }
+    Some(b)
+}
+
+// row -= factor * pivot
+fn subtract_scaled(row: &mut [f64], pivot: &[f64], factor: f64) {
+    for (x, p) in row.iter_mut().zip(pivot) {
+        *x -= factor * p;
+    }
+}
+
+pub(crate) fn identity(n: usize) -> Vec<Vec<f64>> {
+    (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect()
+}
+
+fn multiply(l: &[Vec<f64>], r: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let inner = r.len();
+    if l.first()?.len() != inner {
+        return None;
+    }
+    let columns = r.first()?.len();
+    Some(
+        l.iter()
+            .map(|row| (0..columns).map(|j| (0..inner).map(|k| row[k] * r[k][j]).sum()).collect())
+            .collect(),
+    )
+}
+
+fn shape_mismatch(action: &str, left: &Value, right: &Value) -> MathError {
+    MathError::ShapeMismatch(format!("cannot {} a {} with a {}", action, shape(left), shape(right)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(rows: &[&[f64]]) -> Value {
+        from_matrix(rows.iter().map(|row| row.to_vec()).collect())
+    }
+
+    #[test]
+    fn test_elementwise_broadcasting() {
+        let add = |l: f64, r: f64| Ok(l + r);
+        let m = matrix(&[&[1.0, 2.0], &[3.0, 4.0]]);
+        assert_eq!(elementwise(&m, &Value::Number(1.0), &add).unwrap(), matrix(&[&[2.0, 3.0], &[4.0, 5.0]]));
+        assert_eq!(elementwise(&m, &m, &add).unwrap(), matrix(&[&[2.0, 4.0], &[6.0, 8.0]]));
+        assert!(matches!(
+            elementwise(&m, &Value::from(vec![1.0, 2.0, 3.0]), &add),
+            Err(MathError::ShapeMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = matrix(&[&[1.0, 2.0], &[3.0, 4.0]]);
+        let b = matrix(&[&[5.0, 6.0], &[7.0, 8.0]]);
+        assert_eq!(matmul(&a, &b).unwrap(), matrix(&[&[19.0, 22.0], &[43.0, 50.0]]));
+        let v = Value::from(vec![1.0, 1.0]);
+        assert_eq!(matmul(&a, &v).unwrap(), Value::from(vec![3.0, 7.0]));
+        assert_eq!(matmul(&v, &a).unwrap(), Value::from(vec![4.0, 6.0]));
+        assert_eq!(matmul(&v, &v).unwrap(), Value::Number(2.0));
+
+        let wide = matrix(&[&[1.0, 2.0, 3.0]]);
+        match matmul(&wide, &wide) {
+            Err(MathError::ShapeMismatch(message)) => {
+                assert_eq!(message, "cannot multiply a 1x3 matrix with a 1x3 matrix")
+            }
+            other => panic!("expected a shape mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_determinant_and_solve() {
+        assert_eq!(determinant(vec![vec![1.0, 2.0], vec![3.0, 4.0]]), -2.0);
+        assert_eq!(determinant(vec![vec![0.0, 1.0], vec![1.0, 0.0]]), -1.0);
+        assert_eq!(determinant(vec![vec![1.0, 2.0], vec![2.0, 4.0]]), 0.0);
+
+        let x = solve(vec![vec![2.0, 1.0], vec![1.0, 3.0]], vec![vec![3.0], vec![5.0]]).unwrap();
+        assert!((x[0][0] - 0.8).abs() < 1e-12 && (x[1][0] - 1.4).abs() < 1e-12);
+        assert_eq!(solve(vec![vec![1.0, 2.0], vec![2.0, 4.0]], identity(2)), None);
+    }
+
+    #[test]
+    fn test_transpose() {
+        let m = matrix(&[&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]]);
+        assert_eq!(transpose(&m).unwrap(), matrix(&[&[1.0, 4.0], &[2.0, 5.0], &[3.0, 6.0]]));
+        assert_eq!(transpose(&Value::from(vec![1.0, 2.0])).unwrap(), matrix(&[&[1.0], &[2.0]]));
+        assert!(transpose(&Value::List(vec![Value::from(vec![1.0]), Value::from(vec![1.0, 2.0])])).is_err());
+    }
+}
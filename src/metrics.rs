@@ -0,0 +1,106 @@
+// src/metrics.rs
+// Metrics of evaluations for hosts to export to Prometheus, StatsD or the like, without this crate
+// depending on any of them: a callback set on a context with `Context::set_metrics` hears about
+// every evaluation against it, with how long it took and the kind of error it failed with.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub trait Metrics: Send + Sync {
+    // Called when an evaluation finishes, with the error's `MathError::kind` when it failed.
+    // Evaluations started by context functions are reported too, before the one around them.
+    fn evaluated(&self, duration: Duration, error: Option<&'static str>);
+}
+
+impl<F> Metrics for F
+where
+    F: Fn(Duration, Option<&'static str>) + Send + Sync,
+{
+    fn evaluated(&self, duration: Duration, error: Option<&'static str>) {
+        self(duration, error)
+    }
+}
+
+// Lets a host keep a handle on the metrics it set, to read them
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn evaluated(&self, duration: Duration, error: Option<&'static str>) {
+        (**self).evaluated(duration, error)
+    }
+}
+
+// Totals of the evaluations reported to an `EvalStats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub evaluations: u64,                   // Including those that failed
+    pub errors: BTreeMap<&'static str, u64>, // Failed evaluations by error kind
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+}
+
+// Metrics kept in memory, for hosts that scrape them periodically rather than forward each one
+#[derive(Debug, Default)]
+pub struct EvalStats(Mutex<StatsSnapshot>);
+
+impl EvalStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The totals so far
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.lock().clone()
+    }
+
+    // The totals so far, starting again from zero
+    pub fn take(&self) -> StatsSnapshot {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StatsSnapshot> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Metrics for EvalStats {
+    fn evaluated(&self, duration: Duration, error: Option<&'static str>) {
+        let mut stats = self.lock();
+        stats.evaluations += 1;
+        if let Some(kind) = error {
+            *stats.errors.entry(kind).or_default() += 1;
+        }
+        stats.total_duration += duration;
+        stats.max_duration = stats.max_duration.max(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Evaluator, Expr};
+
+    #[test]
+    fn test_eval_stats() {
+        let stats = Arc::new(EvalStats::new());
+        let mut ctx = Context::new();
+        ctx.set_metrics(stats.clone());
+        for input in ["1 + 2", "1 / 0", "x", "y", "2 ^ 10"] {
+            let _ = Evaluator::evaluate_with(&Expr::parse(input).unwrap(), &ctx);
+        }
+        let snapshot = stats.take();
+        assert_eq!(snapshot.evaluations, 5);
+        assert_eq!(snapshot.errors, BTreeMap::from([("division_by_zero", 1), ("unknown_variable", 2)]));
+        assert!(snapshot.max_duration <= snapshot.total_duration);
+        assert_eq!(stats.snapshot(), StatsSnapshot::default());
+    }
+
+    #[test]
+    fn test_callback() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let seen = errors.clone();
+        let mut ctx = Context::new();
+        ctx.set_metrics(move |_: Duration, error: Option<&'static str>| seen.lock().unwrap().push(error));
+        let _ = Evaluator::evaluate_with(&Expr::parse("gcd(1.5, 2)").unwrap(), &ctx);
+        let _ = Evaluator::evaluate_with(&Expr::parse("gcd(15, 20)").unwrap(), &ctx);
+        assert_eq!(*errors.lock().unwrap(), [Some("invalid_argument"), None]);
+    }
+}
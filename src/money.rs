@@ -0,0 +1,304 @@
+// src/money.rs
+// Amounts of money for invoicing and finance formulas, written as a number followed by a
+// ISO 4217 currency code: `100 USD + 50 EUR`. Amounts are exact decimals to the millionth, so
+// `0.10 USD + 0.20 USD` is 0.30 USD. Amounts in different currencies are converted into the
+// currency of the left operand, at the rate given by the `RateProvider` set with
+// `Context::set_rates`.
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Neg;
+use std::str::FromStr;
+
+use crate::{Context, MathError, Operator, Result, Value};
+
+// Supplies exchange rates: how many units of `to` one unit of `from` buys
+pub trait RateProvider: Send + Sync {
+    fn rate(&self, from: &str, to: &str) -> Option<f64>;
+}
+
+impl<F> RateProvider for F
+where
+    F: Fn(&str, &str) -> Option<f64> + Send + Sync,
+{
+    fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        self(from, to)
+    }
+}
+
+// Millionths in a unit of currency
+const SCALE: i128 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Money {
+    micros: i128, // The amount in millionths
+    currency: String,
+}
+
+impl Money {
+    // An amount of a currency, rounded half to even to the millionth
+    pub fn new(amount: f64, currency: impl Into<String>) -> Result<Money> {
+        let currency = currency.into();
+        if !is_currency_code(&currency) {
+            return Err(MathError::InvalidArgument {
+                function: "money".to_string(),
+                reason: format!("{} is not an ISO 4217 currency code", currency),
+            });
+        }
+        let micros = scale(SCALE, amount).ok_or_else(|| MathError::InvalidNumber(format!("{} {}", amount, currency)))?;
+        Ok(Money { micros, currency })
+    }
+
+    // The amount, as the nearest number
+    pub fn amount(&self) -> f64 {
+        self.micros as f64 / SCALE as f64
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    // The amount in another currency, at the context's rate
+    pub fn convert(&self, currency: &str, ctx: &Context) -> Result<Money> {
+        if self.currency == currency {
+            return Ok(self.clone());
+        }
+        let rate = ctx
+            .rates()
+            .and_then(|rates| rates.rate(&self.currency, currency))
+            .ok_or_else(|| MathError::UnknownRate {
+                from: self.currency.clone(),
+                to: currency.to_string(),
+            })?;
+        Ok(Money {
+            micros: self.scaled(rate)?,
+            currency: currency.to_string(),
+        })
+    }
+
+    // The amount multiplied by a number, rounded half to even to the millionth
+    fn scaled(&self, factor: f64) -> Result<i128> {
+        scale(self.micros, factor).ok_or_else(|| MathError::InvalidNumber(format!("{} * {}", self, factor)))
+    }
+
+    fn with_micros(&self, micros: Option<i128>) -> Result<Value> {
+        let micros = micros.ok_or_else(|| MathError::InvalidNumber(format!("{} overflows", self)))?;
+        Ok(Value::Money(Money {
+            micros,
+            currency: self.currency.clone(),
+        }))
+    }
+}
+
+// The ISO 4217 currency codes in use, in alphabetical order
+const CURRENCY_CODES: [&str; 160] = [
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT", "BGN", "BHD", "BIF",
+    "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD", "CDF", "CHF", "CLP", "CNY", "COP", "CRC",
+    "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS",
+    "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD",
+    "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL",
+    "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MYR", "MZN", "NAD",
+    "NGN", "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD",
+    "RUB", "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SOS", "SRD", "SSP", "STN", "SVC", "SYP",
+    "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "UYU", "UZS", "VES",
+    "VND", "VUV", "WST", "XAF", "XAG", "XAU", "XCD", "XCG", "XOF", "XPD", "XPF", "XPT", "YER", "ZAR", "ZMW", "ZWG",
+];
+
+// Whether a name is an ISO 4217 currency code, such as USD. Other names, such as ABC, stay
+// variables, so `2 ABC` is not money.
+pub fn is_currency_code(name: &str) -> bool {
+    CURRENCY_CODES.binary_search(&name).is_ok()
+}
+
+// `n * x`, rounded half to even, where x is taken as the decimal it displays as (0.1 is 1/10)
+fn scale(n: i128, x: f64) -> Option<i128> {
+    let (digits, places) = decimal(x)?;
+    let product = n.checked_mul(digits)?;
+    match 10i128.checked_pow(places) {
+        Some(divisor) => Some(round_div(product, divisor)),
+        None => Some(0), // x is so small that the product rounds to nothing
+    }
+}
+
+// A finite number as `digits / 10^places`, from its shortest decimal form
+pub(crate) fn decimal(x: f64) -> Option<(i128, u32)> {
+    if !x.is_finite() {
+        return None;
+    }
+    let text = format!("{}", x.abs());
+    let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let digits: i128 = format!("{}{}", whole, fraction).parse().ok()?;
+    Some((if x < 0.0 { -digits } else { digits }, fraction.len() as u32))
+}
+
+// `n / d` rounded half to even, for a positive d
+fn round_div(n: i128, d: i128) -> i128 {
+    let (quotient, remainder) = (n.div_euclid(d), n.rem_euclid(d));
+    match (2 * remainder).cmp(&d) {
+        Ordering::Less => quotient,
+        Ordering::Greater => quotient + 1,
+        Ordering::Equal => quotient + (quotient & 1),
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money {
+            micros: -self.micros,
+            currency: self.currency,
+        }
+    }
+}
+
+// At least two decimal places, and up to six when needed: 150.00 USD, 0.125 EUR
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.micros < 0 { "-" } else { "" };
+        let micros = self.micros.unsigned_abs();
+        let fraction = format!("{:06}", micros % SCALE as u128);
+        let fraction = fraction.trim_end_matches('0');
+        write!(f, "{}{}.{:0<2} {}", sign, micros / SCALE as u128, fraction, self.currency)
+    }
+}
+
+impl FromStr for Money {
+    type Err = MathError;
+
+    // Reads an amount and a currency code, such as "150.00 USD"
+    fn from_str(text: &str) -> Result<Money> {
+        let invalid = || MathError::InvalidNumber(text.to_string());
+        let (amount, currency) = text.trim().split_once(' ').ok_or_else(invalid)?;
+        let amount: f64 = amount.parse().map_err(|_| invalid())?;
+        Money::new(amount, currency.trim()).map_err(|_| invalid())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Money {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Money {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Money, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Applies a binary operator where at least one operand is money: sums and differences in the left
+// operand's currency, scaling by numbers, ratios of two amounts and comparisons
+pub(crate) fn apply(op: &Operator, left: &Value, right: &Value, ctx: &Context) -> Result<Value> {
+    let truth = |condition: bool| Ok(Value::Number(if condition { 1.0 } else { 0.0 }));
+    match (op, left, right) {
+        (Operator::Add, Value::Money(a), Value::Money(b)) => {
+            let b = b.convert(&a.currency, ctx)?;
+            a.with_micros(a.micros.checked_add(b.micros))
+        }
+        (Operator::Subtract, Value::Money(a), Value::Money(b)) => {
+            let b = b.convert(&a.currency, ctx)?;
+            a.with_micros(a.micros.checked_sub(b.micros))
+        }
+        (Operator::Multiply, Value::Money(a), Value::Number(n)) | (Operator::Multiply, Value::Number(n), Value::Money(a)) => {
+            a.with_micros(Some(a.scaled(*n)?))
+        }
+        (Operator::Divide, Value::Money(a), Value::Number(n)) => {
+            if *n == 0.0 {
+                return Err(MathError::DivisionByZero);
+            }
+            let (digits, places) = decimal(*n).ok_or_else(|| MathError::InvalidNumber(n.to_string()))?;
+            let micros = 10i128.checked_pow(places).and_then(|power| a.micros.checked_mul(power));
+            a.with_micros(micros.map(|micros| round_div(micros * digits.signum(), digits.abs())))
+        }
+        (Operator::Divide, Value::Money(a), Value::Money(b)) => {
+            let b = b.convert(&a.currency, ctx)?;
+            if b.micros == 0 {
+                return Err(MathError::DivisionByZero);
+            }
+            Ok(Value::Number(a.micros as f64 / b.micros as f64))
+        }
+        (op, Value::Money(a), Value::Money(b)) if op.is_comparison() => {
+            let ordering = a.micros.cmp(&b.convert(&a.currency, ctx)?.micros);
+            match op {
+                Operator::Less => truth(ordering.is_lt()),
+                Operator::LessEqual => truth(ordering.is_le()),
+                Operator::Greater => truth(ordering.is_gt()),
+                Operator::GreaterEqual => truth(ordering.is_ge()),
+                Operator::Equal => truth(ordering.is_eq()),
+                _ => truth(ordering.is_ne()),
+            }
+        }
+        // Adding to or comparing with money needs more money; anything else needs numbers
+        _ => {
+            let (money, other) = if matches!(left, Value::Money(_)) { (left, right) } else { (right, left) };
+            let additive = matches!(op, Operator::Add | Operator::Subtract) || op.is_comparison();
+            let (expected, found) = if additive && !matches!(other, Value::Money(_)) {
+                ("money", other)
+            } else {
+                ("number", money)
+            };
+            Err(MathError::TypeMismatch {
+                expected: expected.to_string(),
+                found: found.type_name().to_string(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Evaluator, Expr, ImplicitMultiplication, ParserBuilder};
+
+    fn rates(from: &str, to: &str) -> Option<f64> {
+        match (from, to) {
+            ("EUR", "USD") => Some(1.08),
+            ("USD", "EUR") => Some(0.925),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_exact_amounts() {
+        let ctx = Context::new();
+        let value = |input: &str| Evaluator::evaluate_value_with(&Expr::parse(input).unwrap(), &ctx).map(|v| v.to_string());
+        assert_eq!(value("0.10 USD + 0.20 USD").unwrap(), "0.30 USD");
+        assert_eq!(value("19.99 EUR * 3 - 0.125 EUR").unwrap(), "59.845 EUR");
+        assert_eq!(value("-(100 USD / 3)").unwrap(), "-33.333333 USD");
+        assert_eq!(value("100 USD * 15%").unwrap(), "15.00 USD");
+        assert_eq!(value("30 USD / 120 USD").unwrap(), "0.25");
+        assert_eq!(Money::new(0.0000025, "USD").unwrap().to_string(), "0.000002 USD");
+        assert!(matches!(value("1 USD + 1"), Err(MathError::TypeMismatch { ref expected, .. }) if expected == "money"));
+        assert!(matches!(value("1 USD + 1 EUR"), Err(MathError::UnknownRate { .. })));
+        assert_eq!(Expr::parse("2 * 100 USD").unwrap().to_string(), "2 * 100.00 USD");
+
+        // Only known codes are currencies; other capitals are variables
+        assert!(CURRENCY_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(is_currency_code("JPY") && !is_currency_code("ABC") && !is_currency_code("usd"));
+        let implicit = ParserBuilder::new()
+            .implicit_multiplication(ImplicitMultiplication {
+                number_variable: true,
+                ..ImplicitMultiplication::default()
+            })
+            .build();
+        assert_eq!(implicit.parse("2 ABC").unwrap(), Expr::binary(Operator::Multiply, Expr::literal(2.0), Expr::variable("ABC")));
+        assert!(matches!(Money::new(1.0, "ABC"), Err(MathError::InvalidArgument { .. })));
+    }
+
+    #[test]
+    fn test_exchange_rates() {
+        let mut ctx = Context::new();
+        ctx.set_rates(rates);
+        let value = |input: &str| Evaluator::evaluate_value_with(&Expr::parse(input).unwrap(), &ctx).map(|v| v.to_string());
+        assert_eq!(value("100 USD + 50 EUR").unwrap(), "154.00 USD");
+        assert_eq!(value("50 EUR + 100 USD").unwrap(), "142.50 EUR");
+        assert_eq!(value("100 USD > 90 EUR").unwrap(), "1");
+        assert_eq!(
+            value("1 USD + 1 GBP").unwrap_err().to_string(),
+            "No exchange rate from GBP to USD"
+        );
+    }
+}
@@ -0,0 +1,277 @@
+// src/normal.rs
+// A canonical form of expression trees, for caching, deduplicating and comparing formulas: two
+// expressions that differ only in the order of commutative operands, the nesting of sums and
+// products, how signs are written or the notation of their numbers have the same normal form.
+// Where that is not enough, `probably_equal` compares the values at random points.
+use std::cmp::Ordering;
+
+use crate::functions::constant;
+use crate::random::Rng;
+use crate::{Context, Evaluator, Expr, Operator};
+
+// Random points are drawn with this seed, so the answer is the same on every run
+const SEED: u64 = 0x5eed;
+
+// Variables are drawn uniformly from -RANGE to RANGE
+const RANGE: f64 = 10.0;
+
+impl Expr {
+    // The expression with:
+    // - sums and products flattened and their operands sorted (numbers first, then variables,
+    //   calls and everything else), so `b + (a + 1)` and `1 + a + b` are the same. Only the
+    //   numbers of a product move, since `*` of two matrices does not commute.
+    // - subtraction written as the addition of a negated term, and the sign of a product on its
+    //   number, or around the whole product when it has none
+    // - the operands of ==, !=, &, | and xor sorted, and > and >= turned into < and <=
+    // - scientific notation written as plain numbers, and kept parentheses dropped
+    // Regrouping sums and products may change the last bits of a result. Calling it again on a
+    // normal form returns the same expression.
+    pub fn normal_form(&self) -> Expr {
+        let expr = match self {
+            Expr::Group(inner) => return inner.normal_form(),
+            Expr::Scientific { .. } => return Expr::Literal(number(self)),
+            Expr::UnaryMinus(operand) => return negate(operand.normal_form()),
+            _ => self.map_children(Expr::normal_form),
+        };
+        let Expr::BinOp { op, lhs, rhs } = expr else { return expr };
+        match op {
+            Operator::Add | Operator::Subtract => {
+                let mut terms = Vec::new();
+                add_terms(Expr::BinOp { op, lhs, rhs }, false, &mut terms);
+                terms.sort_by(|a, b| order(&unsigned(a), &unsigned(b)).then_with(|| is_negative(a).cmp(&is_negative(b))));
+                let mut terms = terms.into_iter();
+                let first = terms.next().expect("a sum has terms");
+                terms.fold(first, |sum, term| {
+                    if is_negative(&term) {
+                        Expr::binary(Operator::Subtract, sum, negate(term))
+                    } else {
+                        Expr::binary(Operator::Add, sum, term)
+                    }
+                })
+            }
+            Operator::Multiply => {
+                let mut factors = Vec::new();
+                let mut negative = false;
+                for factor in chain(Expr::BinOp { op, lhs, rhs }, &Operator::Multiply) {
+                    negative ^= is_negative(&factor);
+                    factors.push(unsigned(&factor));
+                }
+                // Stable, so the other factors keep their order
+                factors.sort_by_key(|factor| !matches!(factor, Expr::Literal(_)));
+                let product = rebuild(factors, Operator::Multiply);
+                if negative { negate(product) } else { product }
+            }
+            Operator::BitAnd | Operator::BitOr | Operator::BitXor => {
+                let mut operands = chain(Expr::BinOp { op: op.clone(), lhs, rhs }, &op);
+                operands.sort_by(order);
+                rebuild(operands, op)
+            }
+            Operator::Equal | Operator::NotEqual if order(&lhs, &rhs) == Ordering::Greater => Expr::BinOp { op, lhs: rhs, rhs: lhs },
+            Operator::Greater => Expr::BinOp { op: Operator::Less, lhs: rhs, rhs: lhs },
+            Operator::GreaterEqual => Expr::BinOp { op: Operator::LessEqual, lhs: rhs, rhs: lhs },
+            _ => Expr::BinOp { op, lhs, rhs },
+        }
+    }
+}
+
+fn number(expr: &Expr) -> f64 {
+    match expr {
+        Expr::Literal(value) => *value,
+        Expr::Scientific { base, exponent } => base * 10f64.powi(*exponent),
+        _ => f64::NAN,
+    }
+}
+
+// The terms of a sum in normal form, each negated when subtracted
+fn add_terms(expr: Expr, negated: bool, terms: &mut Vec<Expr>) {
+    match expr {
+        Expr::BinOp { op: Operator::Add, lhs, rhs } => {
+            add_terms(*lhs, negated, terms);
+            add_terms(*rhs, negated, terms);
+        }
+        Expr::BinOp { op: Operator::Subtract, lhs, rhs } => {
+            add_terms(*lhs, negated, terms);
+            add_terms(*rhs, !negated, terms);
+        }
+        term if negated => terms.push(negate(term)),
+        term => terms.push(term),
+    }
+}
+
+// The operands of a chain of the same operator, from left to right
+fn chain(expr: Expr, op: &Operator) -> Vec<Expr> {
+    match expr {
+        Expr::BinOp { op: inner, lhs, rhs } if inner == *op => {
+            let mut operands = chain(*lhs, op);
+            operands.extend(chain(*rhs, op));
+            operands
+        }
+        operand => vec![operand],
+    }
+}
+
+fn rebuild(operands: Vec<Expr>, op: Operator) -> Expr {
+    operands
+        .into_iter()
+        .reduce(|chain, operand| Expr::binary(op.clone(), chain, operand))
+        .expect("a chain has operands")
+}
+
+// The first factor of a product (or the expression itself)
+fn leading(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::BinOp { op: Operator::Multiply, lhs, .. } => leading(lhs),
+        _ => expr,
+    }
+}
+
+// Whether a normal form carries a minus sign: a negative number, a negation, or a product whose
+// number is negative
+fn is_negative(expr: &Expr) -> bool {
+    match leading(expr) {
+        Expr::Literal(value) => value.is_sign_negative(),
+        Expr::UnaryMinus(_) => true,
+        _ => false,
+    }
+}
+
+// A normal form negated, keeping it a normal form
+fn negate(expr: Expr) -> Expr {
+    match expr {
+        Expr::Literal(value) => Expr::Literal(-value),
+        Expr::UnaryMinus(inner) => *inner,
+        Expr::BinOp { op: Operator::Multiply, lhs, rhs } if matches!(leading(&lhs), Expr::Literal(_)) => {
+            Expr::BinOp {
+                op: Operator::Multiply,
+                lhs: Box::new(negate(*lhs)),
+                rhs,
+            }
+        }
+        expr => Expr::UnaryMinus(Box::new(expr)),
+    }
+}
+
+fn unsigned(expr: &Expr) -> Expr {
+    if is_negative(expr) { negate(expr.clone()) } else { expr.clone() }
+}
+
+// The order of operands: numbers by value, then variables by name, then calls by name, then
+// everything else by how it displays
+fn order(a: &Expr, b: &Expr) -> Ordering {
+    let rank = |expr: &Expr| match expr {
+        Expr::Literal(_) => 0,
+        Expr::Variable(_) => 1,
+        Expr::Function { .. } => 2,
+        _ => 3,
+    };
+    rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+        (Expr::Literal(a), Expr::Literal(b)) => a.total_cmp(b),
+        (Expr::Variable(a), Expr::Variable(b)) => a.cmp(b),
+        (Expr::Function { name: a, .. }, Expr::Function { name: b, .. }) if a != b => a.cmp(b),
+        _ => a.to_string().cmp(&b.to_string()),
+    })
+}
+
+impl Expr {
+    // Whether the two expressions agree at `trials` random values of their variables, to within a
+    // relative `tolerance`. Points where neither can be evaluated (√x for negative x) are skipped,
+    // while a point where only one can counts as a difference. A quick check where proving the
+    // identity is out of reach: it can be fooled, but rarely, and false if no point is usable.
+    pub fn probably_equal(&self, other: &Expr, trials: usize, tolerance: f64) -> bool {
+        let mut names = Vec::new();
+        variables(self, &mut names);
+        variables(other, &mut names);
+        let mut rng = Rng::seeded(SEED);
+        let mut compared = 0;
+        for _ in 0..trials {
+            let mut ctx = Context::new();
+            for name in &names {
+                ctx.set_variable(name.as_str(), (rng.next_f64() * 2.0 - 1.0) * RANGE);
+            }
+            let value = |expr: &Expr| Evaluator::evaluate_with(expr, &ctx).ok().filter(|value| !value.is_nan());
+            match (value(self), value(other)) {
+                (None, None) => continue,
+                (Some(a), Some(b)) if a == b || (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0) => {
+                    compared += 1
+                }
+                _ => return false,
+            }
+        }
+        compared > 0
+    }
+}
+
+// The free variables of an expression, other than the built-in constants, in order of appearance
+fn variables(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Variable(name) if !names.contains(name) && constant(name).is_none() => names.push(name.clone()),
+        _ => expr.children().into_iter().for_each(|child| variables(child, names)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normal(input: &str) -> String {
+        Expr::parse(input).unwrap().normal_form().to_string()
+    }
+
+    #[test]
+    fn test_normal_form() {
+        assert_eq!(normal("b + (a + 1)"), "1 + a + b");
+        assert_eq!(normal("(1 + a) + b"), normal("b + (a + 1)"));
+        assert_eq!(normal("c - (b - a)"), "a - b + c");
+        assert_eq!(normal("-a + b"), "-a + b");
+        assert_eq!(normal("b - a"), "-a + b");
+        assert_eq!(normal("y * (x * 2)"), "2 * y * x");
+        assert_eq!(normal("-x * 2 * -y"), "2 * x * y");
+        assert_eq!(normal("x * -3"), "-3 * x");
+        assert_eq!(normal("1 - x * y * -2"), "1 + 2 * x * y");
+        assert_eq!(normal("-(y * x)"), "-(y * x)");
+        assert_eq!(normal("1.5e3 + x"), "1500 + x");
+        assert_eq!(normal("max(b, a) + a ^ 2 + f(1)"), "f(1) + max(b, a) + a ^ 2");
+        assert_eq!(normal("x > y"), "y < x");
+        assert_eq!(normal("y == x + 1"), "y == 1 + x");
+        assert_eq!(normal("(b & a) & c"), "a & b & c");
+        // Non-commutative operations keep their order
+        assert_eq!(normal("b ^ a - b / a"), "-(b / a) + b ^ a");
+    }
+
+    #[test]
+    fn test_normal_form_keeps_matrix_products() {
+        let matrix = |input: &str| Evaluator::evaluate_value(&Expr::parse(input).unwrap()).unwrap();
+        let mut ctx = Context::new();
+        ctx.set_variable("A", matrix("[[1, 2], [3, 4]]"));
+        ctx.set_variable("B", matrix("[[0, 1], [1, 0]]"));
+        let value = |input: &str| Evaluator::evaluate_value_with(&Expr::parse(input).unwrap().normal_form(), &ctx).unwrap();
+        assert_ne!(normal("A * B"), normal("B * A"));
+        assert_eq!(value("A * B"), matrix("[[2, 1], [4, 3]]"));
+        assert_eq!(value("B * A"), matrix("[[3, 4], [1, 2]]"));
+        // Numbers still move to the front
+        assert_eq!(normal("B * (A * 2)"), "2 * B * A");
+    }
+
+    #[test]
+    fn test_normal_form_is_stable() {
+        for input in ["b - a * -c + 2", "-(x - y) * 3 + z / (w + v)", "-x * -y * -2", "(a >= b) + (c != b)", "x - -1"] {
+            let normal = Expr::parse(input).unwrap().normal_form();
+            assert_eq!(normal.normal_form(), normal, "{}", input);
+            // And displays as something that parses back to it
+            assert_eq!(Expr::parse(&normal.to_string()).unwrap().normal_form(), normal, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_probably_equal() {
+        let equal = |a: &str, b: &str| Expr::parse(a).unwrap().probably_equal(&Expr::parse(b).unwrap(), 50, 1e-9);
+        assert!(equal("(x + y) ^ 2", "x ^ 2 + 2 * x * y + y ^ 2"));
+        assert!(equal("√(x ^ 2)", "|x|"));
+        assert!(equal("(x ^ 2 - 1) / (x - 1)", "x + 1"));
+        assert!(equal("2 * pi * r", "tau * r"));
+        assert!(!equal("(x + y) ^ 2", "x ^ 2 + y ^ 2"));
+        // √x is only defined for x >= 0, where it agrees; x alone is defined everywhere
+        assert!(!equal("(√x) ^ 2", "x"));
+        assert!(!equal("undefined_function(x)", "undefined_function(x)"));
+    }
+}
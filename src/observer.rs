@@ -0,0 +1,116 @@
+// src/observer.rs
+// Hooks into evaluation for metering, quotas and profiling. An observer set on a context with
+// `Context::set_observer` is told about every node before and after it is evaluated.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Expr, MathError, Result, Value};
+
+pub trait EvalObserver: Send + Sync {
+    // Called before a node is evaluated; an error aborts the evaluation with it
+    fn enter(&self, _expr: &Expr) -> Result<()> {
+        Ok(())
+    }
+
+    // Called after a node is evaluated, with how long it and everything below it took
+    fn exit(&self, _expr: &Expr, _elapsed: Duration, _result: &Result<Value>) {}
+}
+
+// Lets a host keep a handle on the observer it set, to read what it recorded
+impl<O: EvalObserver + ?Sized> EvalObserver for Arc<O> {
+    fn enter(&self, expr: &Expr) -> Result<()> {
+        (**self).enter(expr)
+    }
+
+    fn exit(&self, expr: &Expr, elapsed: Duration, result: &Result<Value>) {
+        (**self).exit(expr, elapsed, result)
+    }
+}
+
+// Counts the nodes evaluated: numbers, variables, operations and calls. With a limit, aborts the
+// evaluation that goes past it with MathError::OperationLimit; the count keeps adding up across
+// evaluations until `reset`.
+#[derive(Debug, Default)]
+pub struct OperationCounter {
+    count: AtomicU64,
+    limit: Option<u64>,
+}
+
+impl OperationCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limit(limit: u64) -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            limit: Some(limit),
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+    }
+}
+
+impl EvalObserver for OperationCounter {
+    fn enter(&self, _expr: &Expr) -> Result<()> {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.limit {
+            Some(limit) if count > limit => Err(MathError::OperationLimit(limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Evaluator};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_operation_counter() {
+        let counter = Arc::new(OperationCounter::new());
+        let mut ctx = Context::new();
+        ctx.set_observer(counter.clone());
+        let expr = Expr::parse("max(1, x) + 2 * 3").unwrap();
+        ctx.set_variable("x", 4.0);
+        assert_eq!(Evaluator::evaluate_with(&expr, &ctx).unwrap(), 10.0);
+        assert_eq!(counter.count(), 7);
+        counter.reset();
+        assert_eq!(counter.count(), 0);
+
+        ctx.set_observer(OperationCounter::with_limit(5));
+        let error = Evaluator::evaluate_with(&expr, &ctx).unwrap_err();
+        assert_eq!(error.to_string(), "Evaluation took more than 5 operations");
+    }
+
+    #[test]
+    fn test_profiling_observer() {
+        // Times each call, to see which dominate
+        #[derive(Default)]
+        struct Profile(Mutex<Vec<(String, bool)>>);
+        impl EvalObserver for Profile {
+            fn exit(&self, expr: &Expr, _elapsed: Duration, result: &Result<Value>) {
+                if let Expr::Function { .. } = expr {
+                    self.0.lock().unwrap().push((expr.to_string(), result.is_ok()));
+                }
+            }
+        }
+        let profile = Arc::new(Profile::default());
+        let mut ctx = Context::new();
+        ctx.set_observer(profile.clone());
+        assert!(Evaluator::evaluate_with(&Expr::parse("min(max(1, 2), 3) + gcd(1.5, 2)").unwrap(), &ctx).is_err());
+        let calls = profile.0.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            [("max(1, 2)".to_string(), true), ("min(max(1, 2), 3)".to_string(), true), ("gcd(1.5, 2)".to_string(), false)]
+        );
+    }
+}
@@ -0,0 +1,263 @@
+// src/operators.rs
+// Operators defined by the host application. Register them in an `OperatorTable`, then hand the
+// table to the tokenizer (through `TokenizerOptions::operators`) so the symbols are recognized,
+// and to the context (`Context::set_operators`) so they can be evaluated.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::Result;
+
+// How a chain of operators with equal precedence groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Associativity {
+    // a ⊕ b ⊕ c is (a ⊕ b) ⊕ c
+    #[default]
+    Left,
+    // a ⊕ b ⊕ c is a ⊕ (b ⊕ c)
+    Right,
+}
+
+// The syntax of a custom infix operator, as carried in tokens and expression trees
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CustomOperator {
+    pub symbol: String,
+    // Compared against the built-in precedences; use e.g. `Operator::Multiply.precedence()`
+    // to bind like multiplication
+    pub precedence: u8,
+    pub associativity: Associativity,
+}
+
+// Which side of its operand a unary operator is written on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fixity {
+    Prefix,  // Binds like unary minus (e.g., √x)
+    Postfix, // Binds like the percent sign (e.g., x!)
+}
+
+// The syntax of a custom unary operator, as carried in tokens and expression trees
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CustomUnaryOperator {
+    pub symbol: String,
+    pub fixity: Fixity,
+}
+
+// The implementation of a custom infix operator
+pub type BinaryFunction = Arc<dyn Fn(f64, f64) -> Result<f64> + Send + Sync>;
+
+// The implementation of a custom unary operator
+pub type UnaryFunction = Arc<dyn Fn(f64) -> Result<f64> + Send + Sync>;
+
+// Custom operators by symbol. One symbol may be registered as a prefix operator and as an infix
+// or postfix one; after an operand it reads as postfix if registered, otherwise as infix.
+#[derive(Clone, Default)]
+pub struct OperatorTable {
+    binary: HashMap<String, (CustomOperator, BinaryFunction)>,
+    prefix: HashMap<String, (CustomUnaryOperator, UnaryFunction)>,
+    postfix: HashMap<String, (CustomUnaryOperator, UnaryFunction)>,
+}
+
+impl OperatorTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registers (or replaces) an infix operator. Symbols are either punctuation (such as "⊕" or
+    // "<>", matched wherever they appear and ahead of built-in operators) or a word (such as
+    // "dot", matched as a whole identifier). Panics if the symbol is empty or starts with a digit
+    // or whitespace, which the tokenizer could not tell apart from numbers and spacing.
+    pub fn register_binary<F>(
+        &mut self,
+        symbol: impl Into<String>,
+        precedence: u8,
+        associativity: Associativity,
+        function: F,
+    ) where
+        F: Fn(f64, f64) -> Result<f64> + Send + Sync + 'static,
+    {
+        let symbol = symbol.into();
+        check_symbol(&symbol);
+        let operator = CustomOperator {
+            symbol: symbol.clone(),
+            precedence,
+            associativity,
+        };
+        self.binary.insert(symbol, (operator, Arc::new(function)));
+    }
+
+    // Registers (or replaces) a prefix operator. Symbols follow the same rules as for infix ones.
+    pub fn register_prefix<F>(&mut self, symbol: impl Into<String>, function: F)
+    where
+        F: Fn(f64) -> Result<f64> + Send + Sync + 'static,
+    {
+        self.register_unary(symbol.into(), Fixity::Prefix, Arc::new(function));
+    }
+
+    // Registers (or replaces) a postfix operator. Symbols follow the same rules as for infix ones.
+    pub fn register_postfix<F>(&mut self, symbol: impl Into<String>, function: F)
+    where
+        F: Fn(f64) -> Result<f64> + Send + Sync + 'static,
+    {
+        self.register_unary(symbol.into(), Fixity::Postfix, Arc::new(function));
+    }
+
+    fn register_unary(&mut self, symbol: String, fixity: Fixity, function: UnaryFunction) {
+        check_symbol(&symbol);
+        let operator = CustomUnaryOperator {
+            symbol: symbol.clone(),
+            fixity,
+        };
+        let operators = match fixity {
+            Fixity::Prefix => &mut self.prefix,
+            Fixity::Postfix => &mut self.postfix,
+        };
+        operators.insert(symbol, (operator, function));
+    }
+
+    // The syntax of the infix operator with this symbol
+    pub fn binary(&self, symbol: &str) -> Option<&CustomOperator> {
+        self.binary.get(symbol).map(|(operator, _)| operator)
+    }
+
+    // The implementation of the infix operator with this symbol
+    pub fn binary_function(&self, symbol: &str) -> Option<&BinaryFunction> {
+        self.binary.get(symbol).map(|(_, function)| function)
+    }
+
+    // The syntax of the unary operator with this symbol and fixity
+    pub fn unary(&self, symbol: &str, fixity: Fixity) -> Option<&CustomUnaryOperator> {
+        self.unary_entry(symbol, fixity).map(|(operator, _)| operator)
+    }
+
+    // The implementation of the unary operator with this symbol and fixity
+    pub fn unary_function(&self, symbol: &str, fixity: Fixity) -> Option<&UnaryFunction> {
+        self.unary_entry(symbol, fixity).map(|(_, function)| function)
+    }
+
+    fn unary_entry(&self, symbol: &str, fixity: Fixity) -> Option<&(CustomUnaryOperator, UnaryFunction)> {
+        match fixity {
+            Fixity::Prefix => self.prefix.get(symbol),
+            Fixity::Postfix => self.postfix.get(symbol),
+        }
+    }
+
+    // The longest registered punctuation symbol that `input` starts with, among those that can
+    // appear after an operand (infix and postfix) or, otherwise, where one is expected (prefix)
+    pub(crate) fn match_symbol(&self, input: &str, after_operand: bool) -> Option<&str> {
+        let symbols: Vec<&String> = if after_operand {
+            self.binary.keys().chain(self.postfix.keys()).collect()
+        } else {
+            self.prefix.keys().collect()
+        };
+        symbols
+            .into_iter()
+            .filter(|symbol| !is_word(symbol) && input.starts_with(symbol.as_str()))
+            .max_by_key(|symbol| symbol.len())
+            .map(String::as_str)
+    }
+
+    // Length in characters of the longest registered symbol
+    pub(crate) fn longest_symbol(&self) -> usize {
+        self.symbols().map(|symbol| symbol.chars().count()).max().unwrap_or(0)
+    }
+
+    fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.binary.keys().chain(self.prefix.keys()).chain(self.postfix.keys())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.binary.is_empty() && self.prefix.is_empty() && self.postfix.is_empty()
+    }
+}
+
+// Panics on symbols the tokenizer cannot match: an empty one would match at every position
+// without consuming anything
+fn check_symbol(symbol: &str) {
+    assert!(
+        symbol.starts_with(|c: char| !c.is_ascii_digit() && !c.is_whitespace()),
+        "operator symbol {:?} must not be empty or start with a digit or whitespace",
+        symbol
+    );
+}
+
+// Whether a symbol is spelled like an identifier
+pub(crate) fn is_word(symbol: &str) -> bool {
+    symbol.starts_with(|c: char| c.is_alphabetic() || c == '_')
+}
+
+impl fmt::Debug for OperatorTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut binary: Vec<_> = self.binary.values().map(|(operator, _)| operator).collect();
+        binary.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        let mut unary: Vec<_> = self.prefix.values().chain(self.postfix.values()).map(|(operator, _)| operator).collect();
+        unary.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        f.debug_struct("OperatorTable").field("binary", &binary).field("unary", &unary).finish()
+    }
+}
+
+// Tables are equal when they define the same operators with the same implementations
+impl PartialEq for OperatorTable {
+    fn eq(&self, other: &Self) -> bool {
+        same_entries(&self.binary, &other.binary)
+            && same_entries(&self.prefix, &other.prefix)
+            && same_entries(&self.postfix, &other.postfix)
+    }
+}
+
+fn same_entries<O: PartialEq, F: ?Sized>(a: &HashMap<String, (O, Arc<F>)>, b: &HashMap<String, (O, Arc<F>)>) -> bool {
+    a.len() == b.len()
+        && a.iter().all(|(symbol, (operator, function))| {
+            b.get(symbol).is_some_and(|(other_operator, other_function)| {
+                operator == other_operator && Arc::ptr_eq(function, other_function)
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_symbol_wins() {
+        let mut table = OperatorTable::new();
+        table.register_binary("<", 3, Associativity::Left, |a, b| Ok(a.min(b)));
+        table.register_binary("<>", 3, Associativity::Left, |a, b| Ok(a.max(b)));
+        table.register_binary("dot", 9, Associativity::Left, |a, b| Ok(a * b));
+        table.register_prefix("<<<", |a| Ok(-a));
+        assert_eq!(table.match_symbol("<> 2", true), Some("<>"));
+        assert_eq!(table.match_symbol("< 2", true), Some("<"));
+        assert_eq!(table.match_symbol("dot 2", true), None); // Words are matched as identifiers
+        // Only prefix operators can start an operand
+        assert_eq!(table.match_symbol("<<< 2", true), Some("<"));
+        assert_eq!(table.match_symbol("<<< 2", false), Some("<<<"));
+        assert_eq!(table.match_symbol("< 2", false), None);
+        assert_eq!(table.binary("dot").unwrap().precedence, 9);
+        assert_eq!(table.binary_function("<>").unwrap()(1.0, 2.0).unwrap(), 2.0);
+
+        assert_eq!(table.clone(), table);
+        assert_ne!(table, OperatorTable::new());
+    }
+
+    // An empty symbol would match everywhere and stop the tokenizer from advancing
+    #[test]
+    #[should_panic(expected = "operator symbol \"\" must not be empty")]
+    fn test_empty_symbol() {
+        OperatorTable::new().register_binary("", 3, Associativity::Left, |a, _| Ok(a));
+    }
+
+    #[test]
+    #[should_panic(expected = "operator symbol \"2x\" must not be empty or start with a digit")]
+    fn test_symbol_starting_with_digit() {
+        OperatorTable::new().register_postfix("2x", Ok);
+    }
+
+    #[test]
+    #[should_panic(expected = "operator symbol \" <>\" must not be empty or start with a digit or whitespace")]
+    fn test_symbol_starting_with_whitespace() {
+        OperatorTable::new().register_prefix(" <>", Ok);
+    }
+}
@@ -1,80 +1,548 @@
 // src/parser.rs
-use crate::{expr::Expr, MathError, Operator, Result, Token};
-// A parser that processes tokens into an expression tree
+use crate::expr::{Expr, Statement};
+use crate::operators::Associativity;
+use crate::money::{self, Money};
+use crate::trace;
+use crate::{MathError, Operator, Result, Span, Token, Tokenizer, TokenizerOptions, UnaryOperator};
+
+// Optional syntax the parser accepts. Everything but implicit multiplication is enabled by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserOptions {
+    // Comparison operators: <, <=, >, >=, ==, !=
+    pub comparisons: bool,
+    // Function calls such as max(1, 2), including if() and piecewise()
+    pub functions: bool,
+    // Where writing operands side by side multiplies them
+    pub implicit_multiplication: ImplicitMultiplication,
+    // Keep parentheses from the input as `Expr::Group` nodes, so the expression displays the way
+    // it was written. Number notation (1.5e3 versus 1500) is always kept.
+    pub preserve_notation: bool,
+    // Deepest nesting of parentheses, calls and operations to accept, so untrusted input cannot
+    // exhaust the stack while parsing or evaluating. Unlimited when `None`.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            comparisons: true,
+            functions: true,
+            implicit_multiplication: ImplicitMultiplication::default(),
+            preserve_notation: false,
+            max_depth: None,
+        }
+    }
+}
+
+// Which juxtapositions imply multiplication. All are off by default, since conventions differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ImplicitMultiplication {
+    // A number before an opening parenthesis: 2(3)
+    pub number_parenthesis: bool,
+    // A number before a variable or function call: 2x, 3 sin(x)
+    pub number_variable: bool,
+    // Variables separated by whitespace: x y
+    pub variable_variable: bool,
+    // A closing parenthesis before an opening one: (a)(b)
+    pub parenthesis_parenthesis: bool,
+    // How tightly the implied multiplication binds
+    pub precedence: ImplicitPrecedence,
+}
+
+impl ImplicitMultiplication {
+    // Every juxtaposition implies multiplication, binding like '*'
+    pub fn all() -> Self {
+        Self {
+            number_parenthesis: true,
+            number_variable: true,
+            variable_variable: true,
+            parenthesis_parenthesis: true,
+            precedence: ImplicitPrecedence::default(),
+        }
+    }
+}
+
+// How tightly an implied multiplication binds, which decides what 1/2x means
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImplicitPrecedence {
+    // Like '*': 1/2x is (1/2)x
+    #[default]
+    SameAsExplicit,
+    // Above '*' and '/' but below '^': 1/2x is 1/(2x)
+    AboveExplicit,
+}
 
+// A parser that processes tokens into an expression tree
 pub struct Parser {
     tokens: Vec<Token>,
+    spans: Vec<Span>, // Where each token is in the input; empty when not known
     current: usize,
+    abs_depth: usize, // Open |...| bars; while nonzero a '|' after an operand closes one
+    depth: usize,     // Nested calls to `parse_expression`, checked against `max_depth`
+    options: ParserOptions,
+    recovered: Option<Vec<MathError>>, // Errors collected so far, when parsing with recovery
+}
+
+// The outcome of `Parser::parse_recovering`: a best-effort tree with `Expr::Error` in place of
+// missing or malformed parts, and every error found on the way
+#[derive(Debug)]
+pub struct PartialParse {
+    pub expr: Expr,
+    pub errors: Vec<MathError>,
+}
+
+impl PartialParse {
+    // Whether the input parsed without errors
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
 }
 
 impl Parser {
     // Creates a new parser from a vector of tokens
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self::with_options(tokens, ParserOptions::default())
+    }
+
+    // Creates a new parser accepting the syntax described by the options
+    pub fn with_options(tokens: Vec<Token>, options: ParserOptions) -> Self {
+        Self {
+            tokens,
+            spans: Vec::new(),
+            current: 0,
+            abs_depth: 0,
+            depth: 0,
+            options,
+            recovered: None,
+        }
+    }
+
+    // Tokenizes and parses a single expression with the default syntax. Use a `Grammar` (from
+    // `ParserBuilder`) for other syntax.
+    pub fn parse_str(input: &str) -> Result<Expr> {
+        let tokens = Tokenizer::tokenize_spanned(input, TokenizerOptions::default())?;
+        Parser::with_spans(tokens, ParserOptions::default()).parse()
+    }
+
+    // Creates a parser from tokens paired with their place in the input (see
+    // `Tokenizer::tokenize_spanned`), so errors can point into the input
+    pub fn with_spans(tokens: Vec<(Token, Span)>, options: ParserOptions) -> Self {
+        let (tokens, spans) = tokens.into_iter().unzip();
+        Self {
+            spans,
+            ..Self::with_options(tokens, options)
+        }
+    }
+
+    // Parses an expression whose operators all bind at least as tightly as `min_bp`. This is a
+    // Pratt parser: the binding power functions below decide how operators group, so this loop
+    // does not change when operators are added.
+    fn parse_expression(&mut self, min_bp: u16) -> Result<Expr> {
+        self.depth += 1;
+        let expr = self.parse_level(min_bp);
+        self.depth -= 1;
+        expr
     }
 
-    // Parse an expression with a minimum precedence level
-    fn parse_expression(&mut self, min_precedence: u8) -> Result<Expr> {
-        let mut lhs = self.parse_primary()?; // Parse the left-hand side of the expression
+    fn parse_level(&mut self, min_bp: u16) -> Result<Expr> {
+        self.check_depth(|| self.depth)?;
+        let lhs = self.parse_prefix()?; // Parse the left-hand side of the expression
+        self.parse_operators(lhs, min_bp)
+    }
+
+    // Fails once nesting goes past `ParserOptions::max_depth`. The depth is only worked out when
+    // there is a limit.
+    fn check_depth(&self, depth: impl FnOnce() -> usize) -> Result<()> {
+        match self.options.max_depth {
+            Some(limit) if depth() > limit => Err(MathError::TooDeep(limit)),
+            _ => Ok(()),
+        }
+    }
 
-        // Loop to parse binary operators
+    // Applies the postfix and binary operators that follow an operand, as long as they bind at
+    // least as tightly as `min_bp`
+    fn parse_operators(&mut self, mut lhs: Expr, min_bp: u16) -> Result<Expr> {
+        // Loop to parse postfix and binary operators
         while let Some(token) = self.peek() {
-            // Peek at the next token
-            if let Token::Operator(op) = token {
-                // Check if the token is an operator
-                let precedence = op.precedence(); // Get the precedence of the operator
-                if precedence < min_precedence {
-                    // If the precedence is less than the minimum, break
+            // Each operator applied adds a level, so stop a long chain before it gets too deep
+            self.check_depth(|| lhs.depth())?;
+            if matches!(token, Token::Operator(Operator::BitOr | Operator::Or)) && self.abs_depth > 0 {
+                // Inside absolute value bars a '|' (or '||') in operator position closes the bars
+                break;
+            }
+
+            if let Some((unit, target)) = self.conversion() {
+                // A conversion binds like a postfix operator: 2 + 5 mi in km converts only the 5
+                if POSTFIX_BP < min_bp {
+                    break;
+                }
+                self.current += 3; // Consume the unit, `in` and the target unit
+                lhs = Expr::Function {
+                    name: "convert".to_string(),
+                    args: vec![lhs, Expr::Text(unit), Expr::Text(target)],
+                };
+            } else if let Some(bp) = postfix_binding_power(&token) {
+                if bp < min_bp {
+                    break;
+                }
+                self.advance(); // Consume the operator token
+                lhs = self.parse_postfix(token, lhs)?;
+            } else if let Token::Operator(op) = token {
+                let (left_bp, right_bp) = infix_binding_power(&op);
+                if left_bp < min_bp {
+                    // The operator belongs to an enclosing expression
                     break;
                 }
+                if op.is_comparison() && !self.options.comparisons {
+                    self.report(MathError::InvalidExpression(format!(
+                        "Comparison operators are not enabled: {}",
+                        op.symbol()
+                    )))?;
+                }
                 self.advance(); // Consume the operator token
-                
-                let rhs = self.parse_expression(precedence + 1)?; // Recursively parse the right-hand side
+
+                let rhs = self.parse_expression(right_bp)?; // Recursively parse the right-hand side
                 lhs = Expr::BinOp {
                     op,
                     lhs: Box::new(lhs),
                     rhs: Box::new(rhs),
                 };
-            } else if matches!(token, Token::RParen) {
-                // If we encounter a right parenthesis, break the loop
+            } else if matches!(token, Token::RParen | Token::RBracket | Token::Comma | Token::Semicolon) {
+                // If we encounter a closing parenthesis or bracket, or a separator, break the loop
                 break;
+            } else if self.implies_multiplication(&token) {
+                // Operands written side by side, as in 2x; nothing is consumed
+                let (left_bp, right_bp) = implicit_binding_power(self.options.implicit_multiplication.precedence);
+                if left_bp < min_bp {
+                    break;
+                }
+                let rhs = self.parse_expression(right_bp)?;
+                lhs = Expr::binary(Operator::Multiply, lhs, rhs);
+            } else if self.recovered.is_some() {
+                // When recovering, skip the token and carry on with the next one
+                self.report(MathError::UnexpectedToken(token.clone()))?;
+                self.advance();
             } else {
-                return Err(MathError::UnexpectedToken(token.clone()));
+                // The expression ends here; whatever encloses it decides if that is an error
+                break;
             }
         }
 
+        self.check_depth(|| lhs.depth())?;
         Ok(lhs) // Return the parsed expression
     }
 
-
-    // Parses the tokens into an expression tree
+    // Parses the tokens into an expression tree. Every token must be part of the expression.
     pub fn parse(&mut self) -> Result<Expr> {
-        self.parse_expression(0)
+        trace::parse(self.tokens.len(), || {
+            self.check_parentheses()?;
+            let expr = self.parse_expression(0)?;
+            self.expect_end()?;
+            Ok(expr)
+        })
+    }
+
+    // Fails on a ')' without a '(' before it, or a '(' that is never closed (the innermost such
+    // one, as that is the next to close)
+    fn check_parentheses(&self) -> Result<()> {
+        let mut open = Vec::new();
+        let mut unmatched = None;
+        for (i, token) in self.tokens.iter().enumerate() {
+            match token {
+                Token::LParen => open.push(i),
+                Token::RParen if open.pop().is_none() && unmatched.is_none() => unmatched = Some((')', i)),
+                _ => {}
+            }
+        }
+        let Some((paren, token)) = unmatched.or_else(|| open.last().map(|&i| ('(', i))) else {
+            return Ok(());
+        };
+        let count = |paren: &Token| self.tokens.iter().filter(|&token| token == paren).count();
+        Err(MathError::UnmatchedParenthesis {
+            paren,
+            token,
+            span: self.spans.get(token).copied(),
+            opened: count(&Token::LParen),
+            closed: count(&Token::RParen),
+        })
+    }
+
+    // Fails if any tokens are left over
+    fn expect_end(&self) -> Result<()> {
+        if self.current >= self.tokens.len() {
+            return Ok(());
+        }
+        let span = match (self.spans.get(self.current), self.spans.last()) {
+            (Some(first), Some(last)) => Some(first.to(*last)),
+            _ => None,
+        };
+        Err(MathError::TrailingInput {
+            leftover: Token::display_slice(&self.tokens[self.current..]).to_string(),
+            span,
+        })
+    }
+
+    // Parses the tokens into an expression tree without stopping at the first error: missing or
+    // malformed operands become `Expr::Error`, missing closing brackets are assumed and stray
+    // tokens are skipped. Meant for editors that show partially typed input.
+    pub fn parse_recovering(&mut self) -> PartialParse {
+        self.recovered = Some(Vec::new());
+        let mut expr = self.parse_expression(0);
+        // Nothing closes the top level, so a ')', ']' or ',' left over there is stray. Skip it
+        // and continue the expression after it.
+        while expr.is_ok() {
+            let Some(token) = self.next() else { break };
+            let _ = self.report(MathError::UnexpectedToken(token));
+            expr = expr.and_then(|lhs| self.parse_operators(lhs, 0));
+        }
+        let mut errors = self.recovered.take().unwrap_or_default();
+        let expr = expr.unwrap_or_else(|error| {
+            errors.push(error);
+            Expr::Error
+        });
+        PartialParse { expr, errors }
+    }
+
+    // Fails with the error, or records it and carries on when parsing with recovery
+    fn report(&mut self, error: MathError) -> Result<()> {
+        match &mut self.recovered {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+
+    // Consumes the closing token of a bracketed construct. When recovering, a missing one is
+    // reported and assumed, leaving the token in its place for the enclosing construct.
+    fn expect_close(&mut self, close: &Token, error: MathError) -> Result<()> {
+        if self.peek().as_ref() == Some(close) {
+            self.advance();
+            return Ok(());
+        }
+        self.report(error)
+    }
+
+    // Parses ';'-separated statements (expressions or `name = expr` assignments).
+    // Empty statements, such as after a trailing ';', are skipped.
+    pub fn parse_statements(&mut self) -> Result<Vec<Statement>> {
+        trace::parse(self.tokens.len(), || {
+            self.check_parentheses()?;
+            let mut statements = Vec::new();
+            while let Some(token) = self.peek() {
+                if token == Token::Semicolon {
+                    self.advance();
+                    continue;
+                }
+
+                statements.push(self.parse_statement()?);
+                match self.peek() {
+                    None => {}
+                    Some(Token::Semicolon) => self.advance(),
+                    Some(_) => self.expect_end()?,
+                }
+            }
+            Ok(statements)
+        })
+    }
+
+    // Parses a single statement
+    fn parse_statement(&mut self) -> Result<Statement> {
+        if let (Some(Token::Identifier(name)), Some(Token::Assign)) =
+            (self.peek(), self.tokens.get(self.current + 1))
+        {
+            self.current += 2; // Consume the name and '='
+            let value = self.parse_expression(0)?;
+            return Ok(Statement::Assign { name, value });
+        }
+        Ok(Statement::Expr(self.parse_expression(0)?))
+    }
+
+
+    // A unit conversion written after an operand, as in 5 mi in km: the unit and the target unit
+    fn conversion(&self) -> Option<(String, String)> {
+        match self.tokens.get(self.current..self.current + 3)? {
+            [Token::Identifier(unit), Token::Identifier(keyword), Token::Identifier(target)] if keyword == "in" => {
+                Some((unit.clone(), target.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    // Whether the operand that just ended and the upcoming token multiply under the policy
+    fn implies_multiplication(&self, next: &Token) -> bool {
+        let policy = &self.options.implicit_multiplication;
+        let Some(previous) = self.current.checked_sub(1).and_then(|i| self.tokens.get(i)) else {
+            return false;
+        };
+        match (previous, next) {
+            (Token::Number(_) | Token::Scientific { .. }, Token::LParen) => policy.number_parenthesis,
+            (Token::Number(_) | Token::Scientific { .. }, Token::Identifier(_)) => policy.number_variable,
+            (Token::Identifier(_), Token::Identifier(_)) => policy.variable_variable,
+            (Token::RParen, Token::LParen) => policy.parenthesis_parenthesis,
+            _ => false,
+        }
     }
 
+    // Parses an operand, starting with any prefix operators (e.g., -x, √x, ~x)
+    fn parse_prefix(&mut self) -> Result<Expr> {
+        let prefix = self.peek().and_then(|token| Some((prefix_binding_power(&token)?, token)));
+        let Some((bp, token)) = prefix else {
+            return self.parse_atom();
+        };
+        self.advance(); // Consume the operator token
+        let operand = self.parse_expression(bp)?;
+        match token {
+            Token::Unary(op) => Ok(Expr::unary(op, operand)),
+            _ => Ok(Expr::UnaryMinus(Box::new(operand))),
+        }
+    }
+
+    // Applies the just-consumed postfix operator (e.g., 15%, x², xs[0]) to its operand
+    fn parse_postfix(&mut self, token: Token, operand: Expr) -> Result<Expr> {
+        match token {
+            Token::LBracket => {
+                let index = self.parse_nested(|parser| parser.parse_expression(0))?;
+                self.expect_close(&Token::RBracket, MathError::InvalidExpression("Expected ']'".to_string()))?;
+                Ok(Expr::index(operand, index))
+            }
+            Token::Percent => Ok(Expr::unary(UnaryOperator::Percent, operand)),
+            Token::Unary(op) => Ok(Expr::unary(op, operand)),
+            Token::Superscript(exponent) => Ok(Expr::binary(Operator::Power, operand, Expr::literal(exponent as f64))),
+            _ => Err(MathError::UnexpectedToken(token)),
+        }
+    }
 
-    // Parses a primary expression (e.g., number, parenthesis, or unary minus etc.)
-    fn parse_primary(&mut self) -> Result<Expr> {
-        let token = self.next().ok_or_else(|| {
-            MathError::InvalidExpression("Unexpected end of input".to_string())
-        })?;
+    // Parses a number, parenthesis, list, variable or function call
+    fn parse_atom(&mut self) -> Result<Expr> {
+        let Some(token) = self.next() else {
+            self.report(MathError::InvalidExpression("Unexpected end of input".to_string()))?;
+            return Ok(Expr::Error);
+        };
 
         match token {
-            Token::Number(n) => Ok(Expr::Literal(n)),
+            // A number followed by a currency code is an amount of money (e.g., 100 USD)
+            Token::Number(n) => match self.peek() {
+                Some(Token::Identifier(code)) if money::is_currency_code(&code) => {
+                    self.advance();
+                    Ok(Expr::Money(Money::new(n, code)?))
+                }
+                _ => Ok(Expr::Literal(n)),
+            },
             Token::Scientific { base, exponent } => Ok(Expr::Scientific { base, exponent }), // If it's a scientific notation, return a scientific expression
-            Token::Operator(Operator::Subtract) => {
-                let expr = self.parse_primary()?; // Recursively parse the expression after the unary minus
-                Ok(Expr::UnaryMinus(Box::new(expr))) // Return a unary minus expression
+            Token::Duration(seconds) => Ok(Expr::Duration(seconds)),
+            Token::Text(text) => Ok(Expr::Text(text)),
+
+            Token::LParen => self.parse_nested(|parser| {
+                let expr = parser.parse_expression(0)?; // Recursively parse the expression inside the parenthesis
+                // A parenthesized, comma-separated group such as (x < 0, -x) is a tuple, held as a list
+                if parser.peek() == Some(Token::Comma) {
+                    parser.advance();
+                    let mut items = vec![expr];
+                    items.extend(parser.parse_list(Token::RParen)?);
+                    return Ok(Expr::List(items));
+                }
+                parser.expect_close(&Token::RParen, MathError::InvalidExpression("Expected ')'".to_string()))?;
+                if parser.options.preserve_notation {
+                    return Ok(Expr::Group(Box::new(expr)));
+                }
+                Ok(expr)
+            }),
+
+            // A '|' where an operand is expected opens absolute value bars
+            Token::Operator(Operator::BitOr) => self.parse_abs(),
+            // ...and a '||' opens two
+            Token::Operator(Operator::Or) => {
+                self.split_double_bar();
+                self.parse_abs()
+            }
+
+            Token::Identifier(name) => {
+                // An identifier followed by '(' is a function call, otherwise a variable
+                if self.peek() == Some(Token::LParen) {
+                    if !self.options.functions {
+                        self.report(MathError::InvalidExpression(format!("Function calls are not enabled: {}", name)))?;
+                    }
+                    self.advance(); // Consume the '('
+                    let args = self.parse_nested(|parser| parser.parse_list(Token::RParen))?;
+                    Ok(Expr::Function { name, args })
+                } else {
+                    Ok(Expr::Variable(name))
+                }
             }
 
-            Token::LParen => {
-                let expr = self.parse_expression(0)?; // Recursively parse the expression inside the parenthesis
-                match self.next() {
-                    Some(Token::RParen) => Ok(expr), // If the next token is a right parenthesis, return the expression
-                    _ => Err(MathError::InvalidExpression("Expected ')'".to_string())),
+            Token::LBracket => {
+                let items = self.parse_nested(|parser| parser.parse_list(Token::RBracket))?;
+                Ok(Expr::List(items))
+            }
+            _ => {
+                // When recovering, leave an operator, closing bracket or separator for the
+                // enclosing expression and stand in for the missing operand
+                if !matches!(token, Token::Assign) {
+                    self.current -= 1;
+                }
+                let error = MathError::UnexpectedToken(token);
+                if self.recovered.is_none() {
+                    return Err(error);
                 }
+                self.report(error)?;
+                Ok(Expr::Error)
             }
-            _ => Err(MathError::UnexpectedToken(token)), // If the token is unexpected, return an error
+        }
+    }
+
+    // Parses the rest of |x| after the opening bar
+    fn parse_abs(&mut self) -> Result<Expr> {
+        self.abs_depth += 1;
+        let expr = self.parse_expression(0);
+        self.abs_depth -= 1;
+        let expr = expr?;
+        // A '||' closes this bar and the enclosing one (e.g., ||x||)
+        if self.peek() == Some(Token::Operator(Operator::Or)) {
+            self.advance();
+            self.split_double_bar();
+            return Ok(Expr::unary(UnaryOperator::Abs, expr));
+        }
+        self.expect_close(
+            &Token::Operator(Operator::BitOr),
+            MathError::InvalidExpression("Expected closing '|'".to_string()),
+        )?;
+        Ok(Expr::unary(UnaryOperator::Abs, expr))
+    }
+
+    // Treats the just-consumed '||' token as a single '|', leaving the second bar to be read next
+    fn split_double_bar(&mut self) {
+        self.current -= 1;
+        self.tokens[self.current] = Token::Operator(Operator::BitOr);
+    }
+
+    // Runs a parse step inside brackets of any kind, where '|' is bitwise OR again even within
+    // absolute value bars
+    fn parse_nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let abs_depth = std::mem::take(&mut self.abs_depth);
+        let result = parse(self);
+        self.abs_depth = abs_depth;
+        result
+    }
+
+    // Parses comma-separated expressions up to and including the closing token (')' or ']')
+    fn parse_list(&mut self, close: Token) -> Result<Vec<Expr>> {
+        let mut items = Vec::new();
+        if self.peek().as_ref() == Some(&close) {
+            self.advance();
+            return Ok(items);
+        }
+
+        loop {
+            items.push(self.parse_expression(0)?);
+            if self.peek() == Some(Token::Comma) {
+                self.advance();
+                continue;
+            }
+            let error = MathError::InvalidExpression(format!("Expected ',' or '{}'", close));
+            self.expect_close(&close, error)?;
+            return Ok(items);
         }
     }
 
@@ -97,10 +565,51 @@ impl Parser {
 }
 
 
+// Binding powers: an operator whose binding power is below the current minimum ends the operand
+// being parsed. Infix operators have one power for each side; the lower power on the right of a
+// left-associative operator stops a following operator of the same precedence from joining its
+// right-hand side. All prefix operators bind tighter than any infix operator (so -2^2 is 4) and
+// postfix operators tighter still (so -15% is -(15%)).
+const PREFIX_BP: u16 = 2 * (u8::MAX as u16 + 1);
+const POSTFIX_BP: u16 = PREFIX_BP + 1;
+
+fn infix_binding_power(op: &Operator) -> (u16, u16) {
+    let bp = 2 * op.precedence() as u16;
+    match op.associativity() {
+        Associativity::Left => (bp, bp + 1),
+        Associativity::Right => (bp, bp),
+    }
+}
+
+fn implicit_binding_power(precedence: ImplicitPrecedence) -> (u16, u16) {
+    let (left_bp, right_bp) = infix_binding_power(&Operator::Multiply);
+    match precedence {
+        ImplicitPrecedence::SameAsExplicit => (left_bp, right_bp),
+        // Between the two sides of '*' and '/', so it wins over them from either side
+        ImplicitPrecedence::AboveExplicit => (right_bp, right_bp + 1),
+    }
+}
+
+fn prefix_binding_power(token: &Token) -> Option<u16> {
+    match token {
+        Token::Operator(Operator::Subtract) => Some(PREFIX_BP),
+        Token::Unary(op) if !op.is_postfix() => Some(PREFIX_BP),
+        _ => None,
+    }
+}
+
+fn postfix_binding_power(token: &Token) -> Option<u16> {
+    match token {
+        // A superscript is a power that binds tighter than any prefix operator
+        Token::LBracket | Token::Percent | Token::Superscript(_) => Some(POSTFIX_BP),
+        Token::Unary(op) if op.is_postfix() => Some(POSTFIX_BP),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*; // Import names from the parent module
-    use crate::Tokenizer; // Import the Tokenizer
 
     #[test]
     fn test_basic_arithmetic() {
@@ -173,4 +682,367 @@ mod tests {
         // Check the string representation
         assert_eq!(expr.to_string(), "2e10 + 3e-2 + 2");
     }
+
+    #[test]
+    fn test_percent() {
+        let input = "200 * -15%";
+        let tokens = Tokenizer::tokenize(input).unwrap();
+        let expr = Parser::new(tokens).parse().unwrap();
+
+        // Expected: 200 * -(15%)
+        assert_eq!(expr, Expr::binary(
+            Operator::Multiply,
+            Expr::literal(200.0),
+            Expr::unary_minus(Expr::unary(UnaryOperator::Percent, Expr::literal(15.0))),
+        ));
+        assert_eq!(expr.to_string(), "200 * -(15%)");
+
+        let tokens = Tokenizer::tokenize("(1 + 2)%").unwrap();
+        assert_eq!(Parser::new(tokens).parse().unwrap().to_string(), "(1 + 2)%");
+    }
+
+    #[test]
+    fn test_superscripts() {
+        let tokens = Tokenizer::tokenize("-x² + 2⁻¹").unwrap();
+        let expr = Parser::new(tokens).parse().unwrap();
+
+        // Expected: -(x ^ 2) + 2 ^ -1
+        assert_eq!(expr, Expr::binary(
+            Operator::Add,
+            Expr::unary_minus(Expr::binary(Operator::Power, Expr::variable("x"), Expr::literal(2.0))),
+            Expr::binary(Operator::Power, Expr::literal(2.0), Expr::literal(-1.0)),
+        ));
+    }
+
+    #[test]
+    fn test_sqrt_and_absolute_value() {
+        let parse = |input: &str| Parser::new(Tokenizer::tokenize(input).unwrap()).parse();
+
+        assert_eq!(
+            parse("√(x + 1)").unwrap(),
+            Expr::unary(UnaryOperator::Sqrt, Expr::binary(Operator::Add, Expr::variable("x"), Expr::literal(1.0)))
+        );
+        assert_eq!(
+            parse("|x - 3|").unwrap(),
+            Expr::unary(UnaryOperator::Abs, Expr::binary(Operator::Subtract, Expr::variable("x"), Expr::literal(3.0)))
+        );
+
+        // Nested bars and bars next to bitwise OR
+        assert_eq!(parse("||x| - 1|").unwrap().to_string(), "||x| - 1|");
+        assert_eq!(parse("|a| | |b|").unwrap().to_string(), "|a| | |b|");
+        assert_eq!(parse("|(a | b)|").unwrap().to_string(), "|(a | b)|");
+        assert_eq!(parse("2 * |x|").unwrap().to_string(), "2 * |x|");
+
+        assert!(parse("|x - 3").is_err());
+        // '||' is logical OR between operands, and two bars elsewhere
+        assert_eq!(parse("||x||").unwrap().to_string(), "||x||");
+        assert_eq!(parse("|x| || |y|").unwrap().to_string(), "|x| || |y|");
+        assert_eq!(parse("|(a || b)|").unwrap().to_string(), "|(a || b)|");
+    }
+
+    #[test]
+    fn test_statements() {
+        let tokens = Tokenizer::tokenize("a = 2; b = a * 3;; a ^ b;").unwrap();
+        let statements = Parser::new(tokens).parse_statements().unwrap();
+        assert_eq!(statements, vec![
+            Statement::Assign { name: "a".to_string(), value: Expr::literal(2.0) },
+            Statement::Assign {
+                name: "b".to_string(),
+                value: Expr::binary(Operator::Multiply, Expr::variable("a"), Expr::literal(3.0)),
+            },
+            Statement::Expr(Expr::binary(Operator::Power, Expr::variable("a"), Expr::variable("b"))),
+        ]);
+        assert_eq!(statements[1].to_string(), "b = a * 3");
+
+        let tokens = Tokenizer::tokenize("1 = 2").unwrap();
+        assert!(Parser::new(tokens).parse_statements().is_err());
+    }
+
+    #[test]
+    fn test_lists_and_indexing() {
+        let parse = |input: &str| Parser::new(Tokenizer::tokenize(input).unwrap()).parse();
+        assert_eq!(
+            parse("[1, x + 2][0]").unwrap(),
+            Expr::index(
+                Expr::list(vec![Expr::literal(1.0), Expr::binary(Operator::Add, Expr::variable("x"), Expr::literal(2.0))]),
+                Expr::literal(0.0),
+            )
+        );
+        assert_eq!(parse("[]").unwrap(), Expr::list(vec![]));
+        assert_eq!(parse("m[1][-1] * 2").unwrap().to_string(), "m[1][-1] * 2");
+        assert_eq!(parse("len([[1, 2], [3]])").unwrap().to_string(), "len([[1, 2], [3]])");
+        assert_eq!(parse("|xs[0]|").unwrap().to_string(), "|xs[0]|");
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("xs[0").is_err());
+    }
+
+    #[test]
+    fn test_comparisons_and_tuples() {
+        let parse = |input: &str| Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+        // Comparisons bind more loosely than arithmetic and bitwise operators
+        assert_eq!(
+            parse("x + 1 <= y | 2"),
+            Expr::binary(
+                Operator::LessEqual,
+                Expr::binary(Operator::Add, Expr::variable("x"), Expr::literal(1.0)),
+                Expr::binary(Operator::BitOr, Expr::variable("y"), Expr::literal(2.0)),
+            )
+        );
+        assert_eq!(
+            parse("(x < 0, -x)"),
+            Expr::list(vec![
+                Expr::binary(Operator::Less, Expr::variable("x"), Expr::literal(0.0)),
+                Expr::unary_minus(Expr::variable("x")),
+            ])
+        );
+        assert_eq!(parse("a < (b == c)").to_string(), "a < (b == c)");
+    }
+
+    #[test]
+    fn test_binding_powers() {
+        let parse = |input: &str| Parser::new(Tokenizer::tokenize(input).unwrap()).parse().unwrap();
+        let (x, two) = (Expr::variable("x"), Expr::literal(2.0));
+
+        // Prefix operators bind tighter than any infix operator, postfix ones tighter still
+        assert_eq!(parse("-2^2"), Expr::binary(Operator::Power, Expr::unary_minus(two.clone()), two.clone()));
+        assert_eq!(
+            parse("-√x²%"),
+            Expr::unary_minus(Expr::unary(
+                UnaryOperator::Sqrt,
+                Expr::unary(UnaryOperator::Percent, Expr::binary(Operator::Power, x.clone(), two.clone()))
+            ))
+        );
+        assert_eq!(
+            parse("x - 2 - x"),
+            Expr::binary(Operator::Subtract, Expr::binary(Operator::Subtract, x.clone(), two.clone()), x.clone())
+        );
+        assert_eq!(
+            parse("x * -2 + x"),
+            Expr::binary(
+                Operator::Add,
+                Expr::binary(Operator::Multiply, x.clone(), Expr::unary_minus(two)),
+                x
+            )
+        );
+    }
+
+    #[test]
+    fn test_implicit_multiplication() {
+        let parse = |input: &str, policy: ImplicitMultiplication| {
+            let options = ParserOptions {
+                implicit_multiplication: policy,
+                ..ParserOptions::default()
+            };
+            Parser::with_options(Tokenizer::tokenize(input).unwrap(), options).parse()
+        };
+        let all = ImplicitMultiplication::all();
+        let (x, y) = (Expr::variable("x"), Expr::variable("y"));
+        let times = |lhs, rhs| Expr::binary(Operator::Multiply, lhs, rhs);
+
+        assert_eq!(parse("2x", all).unwrap(), times(Expr::literal(2.0), x.clone()));
+        assert_eq!(parse("2(3)", all).unwrap(), times(Expr::literal(2.0), Expr::literal(3.0)));
+        assert_eq!(parse("x y", all).unwrap(), times(x.clone(), y.clone()));
+        assert_eq!(parse("(x)(y)", all).unwrap(), times(x.clone(), y.clone()));
+        assert_eq!(parse("2x^2", all).unwrap(), times(Expr::literal(2.0), Expr::binary(Operator::Power, x.clone(), Expr::literal(2.0))));
+        // A function call is still a call
+        assert!(matches!(parse("2 f(x)", all).unwrap(), Expr::BinOp { rhs, .. } if matches!(*rhs, Expr::Function { .. })));
+
+        // 1/2x depends on the precedence
+        let half_x = Expr::binary(Operator::Divide, Expr::literal(1.0), Expr::literal(2.0));
+        assert_eq!(parse("1/2x", all).unwrap(), times(half_x, x.clone()));
+        let tight = ImplicitMultiplication {
+            precedence: ImplicitPrecedence::AboveExplicit,
+            ..all
+        };
+        assert_eq!(
+            parse("1/2x", tight).unwrap(),
+            Expr::binary(Operator::Divide, Expr::literal(1.0), times(Expr::literal(2.0), x.clone()))
+        );
+        assert_eq!(
+            parse("2x/y", tight).unwrap(),
+            Expr::binary(Operator::Divide, times(Expr::literal(2.0), x.clone()), y.clone())
+        );
+
+        // Each juxtaposition is enabled separately, and all are off by default
+        let numbers_only = ImplicitMultiplication {
+            number_variable: true,
+            ..ImplicitMultiplication::default()
+        };
+        assert!(parse("2x", numbers_only).is_ok());
+        assert!(matches!(parse("x y", numbers_only), Err(MathError::TrailingInput { .. })));
+        assert!(parse("2(3)", ImplicitMultiplication::default()).is_err());
+    }
+
+    #[test]
+    fn test_unmatched_parentheses() {
+        let parse = |input: &str| {
+            let tokens = Tokenizer::tokenize_spanned(input, TokenizerOptions::default()).unwrap();
+            Parser::with_spans(tokens, ParserOptions::default()).parse()
+        };
+        for (input, paren, start, opened, closed) in [
+            ("(1 + 2", '(', 0, 1, 0),
+            ("max(1, (2 + 3)", '(', 3, 2, 1),
+            ("1 + (2 * (3", '(', 9, 2, 0),
+            ("1 + 2) * (3", ')', 5, 1, 1),
+            ("(1))", ')', 3, 1, 2),
+        ] {
+            match parse(input) {
+                Err(MathError::UnmatchedParenthesis {
+                    paren: p,
+                    span: Some(span),
+                    opened: o,
+                    closed: c,
+                    ..
+                }) => assert_eq!((p, span.start, o, c), (paren, start, opened, closed), "{}", input),
+                other => panic!("{}: expected an unmatched parenthesis, got {:?}", input, other),
+            }
+        }
+
+        let error = Parser::new(Tokenizer::tokenize("((1)").unwrap()).parse_statements().unwrap_err();
+        assert_eq!(error.to_string(), "Unmatched '(' (2 opened, 1 closed)");
+        assert!(matches!(error, MathError::UnmatchedParenthesis { token: 0, span: None, .. }));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let options = ParserOptions {
+            max_depth: Some(4),
+            ..ParserOptions::default()
+        };
+        let parse = |input: &str| Parser::with_options(Tokenizer::tokenize(input).unwrap(), options).parse();
+        assert_eq!(parse("1 + 2 * 3 - x").unwrap().depth(), 4);
+        assert_eq!(parse("max(1, [2, -3])").unwrap().depth(), 4);
+
+        // Parentheses, operator chains and prefix operators all count
+        for input in ["((((1))))", "1 + 2 + 3 + 4 + 5", "----x", "xs[0][1][2][3]", "max(1, max(2, max(3, max(4, 5))))"] {
+            assert!(matches!(parse(input), Err(MathError::TooDeep(4))), "{}", input);
+        }
+
+        // A long chain stops at the limit rather than building the whole tree
+        let chain = vec!["1"; 100_000].join(" + ");
+        assert!(matches!(parse(&chain), Err(MathError::TooDeep(4))));
+        assert_eq!(Parser::parse_str("1 + 2 + 3 + 4 + 5").unwrap().depth(), 5);
+    }
+
+    #[test]
+    fn test_preserve_notation() {
+        let options = ParserOptions {
+            preserve_notation: true,
+            ..ParserOptions::default()
+        };
+        let parse = |input: &str| Parser::with_options(Tokenizer::tokenize(input).unwrap(), options).parse().unwrap();
+        for input in [
+            "(1 + 2) + 3",
+            "-x * (y)",
+            "1.5e3 + 2",
+            "√(x + 1) - -2",
+            "max(1, (2))",
+            "-(x)",
+            "((x))",
+            "2 * -(3 + 4) ^ 2",
+            "|(a)|",
+        ] {
+            let expr = parse(input);
+            assert_eq!(expr.to_string(), input);
+            assert_eq!(parse(&expr.to_string()), expr);
+        }
+
+        assert_eq!(crate::Evaluator::evaluate(&parse("((1 + 2)) * 3")).unwrap(), 9.0);
+
+        // Without it, redundant parentheses are dropped
+        assert_eq!(Parser::parse_str("(1 + 2) + 3").unwrap().to_string(), "1 + 2 + 3");
+        assert_eq!(Parser::parse_str("-x * (y)").unwrap().to_string(), "-x * y");
+    }
+
+    #[test]
+    fn test_parse_str() {
+        let expected = Expr::binary(Operator::Add, Expr::literal(1.0), Expr::variable("x"));
+        assert_eq!(Parser::parse_str("1 + x").unwrap(), expected);
+        assert_eq!(Expr::parse("1 + x").unwrap(), expected);
+        assert_eq!("1 + x".parse::<Expr>().unwrap(), expected);
+        assert!(matches!(Parser::parse_str("1 + 2 3"), Err(MathError::TrailingInput { span: Some(_), .. })));
+        assert!(matches!(Expr::parse("1 $ 2"), Err(MathError::InvalidExpression(_))));
+    }
+
+    #[test]
+    fn test_trailing_input() {
+        let parse = |input: &str| Parser::new(Tokenizer::tokenize(input).unwrap()).parse();
+        for (input, expected) in [("2 3", "3"), ("(1+2) 5", "5"), ("1 + 2] * 3", "] * 3"), ("1, 2", ", 2"), ("1; 2", "; 2")] {
+            match parse(input) {
+                Err(MathError::TrailingInput { leftover, span: None }) => assert_eq!(leftover, expected, "{}", input),
+                other => panic!("{}: expected trailing input, got {:?}", input, other),
+            }
+        }
+        // Inside brackets the missing closer is the error
+        assert!(matches!(parse("(1 2)"), Err(MathError::InvalidExpression(_))));
+
+        // With spans the error points into the input
+        let tokens = Tokenizer::tokenize_spanned("(1+2) 5 6", TokenizerOptions::default()).unwrap();
+        match Parser::with_spans(tokens, ParserOptions::default()).parse() {
+            Err(MathError::TrailingInput { span, .. }) => assert_eq!(span, Some(Span::new(6, 9))),
+            other => panic!("expected trailing input, got {:?}", other),
+        }
+
+        let statements = Parser::new(Tokenizer::tokenize("a = 1 2; a").unwrap()).parse_statements();
+        assert!(matches!(statements, Err(MathError::TrailingInput { .. })));
+        let tokens = Tokenizer::tokenize_spanned("1 + (2", TokenizerOptions::default()).unwrap();
+        assert_eq!(Parser::with_spans(tokens, ParserOptions::default()).parse().unwrap_err().span(), Some(Span::new(4, 5)));
+        assert_eq!(statements.unwrap_err().span(), None);
+    }
+
+    #[test]
+    fn test_recovering_parse() {
+        let recover = |input: &str| Parser::new(Tokenizer::tokenize(input).unwrap()).parse_recovering();
+
+        let complete = recover("max(1, 2) * 3");
+        assert!(complete.is_complete());
+        assert_eq!(complete.expr.to_string(), "max(1, 2) * 3");
+
+        // Missing operands become placeholders, missing closing brackets are assumed
+        for (input, expected, errors) in [
+            ("2 +", "2 + ?", 1),
+            ("2 + * 3", "2 + ? * 3", 1),
+            ("(1 + 2", "1 + 2", 1),
+            ("max(1, 2", "max(1, 2)", 1),
+            ("[1, |x", "[1, |x|]", 2),
+            ("-", "-?", 1),
+            ("", "?", 1),
+            ("1 + 2) * 3", "(1 + 2) * 3", 1),
+            ("(2 + ) * xs[1", "(2 + ?) * xs[1]", 2),
+        ] {
+            let partial = recover(input);
+            assert_eq!(partial.expr.to_string(), expected, "{}", input);
+            assert_eq!(partial.errors.len(), errors, "{}: {:?}", input, partial.errors);
+        }
+
+        // Disabled syntax is reported but still parsed
+        let options = ParserOptions {
+            comparisons: false,
+            ..ParserOptions::default()
+        };
+        let partial = Parser::with_options(Tokenizer::tokenize("1 < 2").unwrap(), options).parse_recovering();
+        assert_eq!(partial.expr.to_string(), "1 < 2");
+        assert!(matches!(partial.errors[..], [MathError::InvalidExpression(_)]));
+
+        // The plain parse still stops at the first error
+        assert!(Parser::new(Tokenizer::tokenize("(1 + 2").unwrap()).parse().is_err());
+    }
+
+    #[test]
+    fn test_variables_and_functions() {
+        let input = "max(x, 2) * y";
+        let tokens = Tokenizer::tokenize(input).unwrap();
+        let mut parser = Parser::new(tokens);
+        let expr = parser.parse().unwrap();
+
+        // Check the expression tree
+        assert_eq!(expr, Expr::binary(
+            Operator::Multiply,
+            Expr::function("max", vec![Expr::variable("x"), Expr::literal(2.0)]),
+            Expr::variable("y"),
+        ));
+
+        // Check the string representation
+        assert_eq!(expr.to_string(), "max(x, 2) * y");
+    }
 }
\ No newline at end of file
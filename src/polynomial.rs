@@ -0,0 +1,401 @@
+// src/polynomial.rs
+// Polynomials in one variable, taken out of expression trees for the algorithms that only work on
+// them: evaluation by Horner's method, division with remainder, greatest common divisors and the
+// exact root formulas up to cubics. `Polynomial::try_from(&expr)` expands sums, products and whole
+// powers, so (x + 1) ^ 2 becomes x ^ 2 + 2 * x + 1, and `Expr::from(&p)` writes it back.
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::{Evaluator, Expr, MathError, Operator, Result};
+
+// Powers above this are not expanded
+const MAX_POWER: f64 = 64.0;
+
+// Highest degree of a polynomial taken from an expression. Checked before every product and
+// power, since a limit on each exponent alone still lets nested powers multiply: ((x^64)^64)^64
+// would have degree 262144.
+pub(crate) const MAX_DEGREE: usize = 1024;
+
+// Coefficients this small next to the largest one count as zero when dividing
+const TOLERANCE: f64 = 1e-9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polynomial {
+    variable: String,
+    coefficients: Vec<f64>, // From the constant term up, without zero leading coefficients
+}
+
+impl Polynomial {
+    // The polynomial with the coefficients, from the constant term up
+    pub fn new(variable: impl Into<String>, coefficients: Vec<f64>) -> Self {
+        let mut p = Polynomial { variable: variable.into(), coefficients };
+        p.trim();
+        p
+    }
+
+    pub fn constant(variable: impl Into<String>, c: f64) -> Self {
+        Polynomial::new(variable, vec![c])
+    }
+
+    // The polynomial with the given (power, coefficient) terms; repeated powers add up
+    pub fn from_terms(variable: impl Into<String>, terms: impl IntoIterator<Item = (usize, f64)>) -> Self {
+        let mut coefficients = Vec::new();
+        for (power, c) in terms {
+            if coefficients.len() <= power {
+                coefficients.resize(power + 1, 0.0);
+            }
+            coefficients[power] += c;
+        }
+        Polynomial::new(variable, coefficients)
+    }
+
+    // The expression as a polynomial in the variable. Other variables are not allowed, and division
+    // only by numbers: x / 2 is a polynomial, 2 / x is not.
+    pub fn from_expr(expr: &Expr, variable: &str) -> Result<Self> {
+        let of = |expr: &Expr| Polynomial::from_expr(expr, variable);
+        let not_polynomial = || MathError::InvalidArgument {
+            function: "polynomial".to_string(),
+            reason: format!("{} is not a polynomial in {}", expr, variable),
+        };
+        let too_high = || MathError::InvalidArgument {
+            function: "polynomial".to_string(),
+            reason: format!("{} has a degree above {}", expr, MAX_DEGREE),
+        };
+        match expr {
+            Expr::Literal(_) | Expr::Scientific { .. } => {
+                Ok(Polynomial::constant(variable, Evaluator::evaluate(expr)?))
+            }
+            Expr::Variable(name) if name == variable => Ok(Polynomial::new(variable, vec![0.0, 1.0])),
+            Expr::Group(inner) => of(inner),
+            Expr::UnaryMinus(operand) => Ok(-&of(operand)?),
+            Expr::BinOp { op, lhs, rhs } => {
+                let (a, b) = (of(lhs)?, of(rhs)?);
+                match op {
+                    Operator::Add => Ok(&a + &b),
+                    Operator::Subtract => Ok(&a - &b),
+                    Operator::Multiply if a.degree() + b.degree() > MAX_DEGREE => Err(too_high()),
+                    Operator::Multiply => Ok(&a * &b),
+                    Operator::Divide if b.degree() == 0 && !b.is_zero() => Ok(a.scaled(1.0 / b.leading())),
+                    Operator::Divide if b.is_zero() => Err(MathError::DivisionByZero),
+                    Operator::Power => match b.coefficients[..] {
+                        [] => Ok(Polynomial::constant(variable, 1.0)),
+                        [n] if n >= 0.0 && n.fract() == 0.0 && n <= MAX_POWER => match a.degree() * n as usize {
+                            degree if degree > MAX_DEGREE => Err(too_high()),
+                            _ => Ok(a.powi(n as u32)),
+                        },
+                        _ => Err(not_polynomial()),
+                    },
+                    _ => Err(not_polynomial()),
+                }
+            }
+            _ => Err(not_polynomial()),
+        }
+    }
+
+    pub fn variable(&self) -> &str {
+        &self.variable
+    }
+
+    // Every coefficient from the constant term up to the leading one (the dense form)
+    pub fn coefficients(&self) -> &[f64] {
+        &self.coefficients
+    }
+
+    // The (power, coefficient) pairs with nonzero coefficients, from the constant term up (the
+    // sparse form)
+    pub fn terms(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.coefficients.iter().copied().enumerate().filter(|(_, c)| *c != 0.0)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.coefficients.is_empty()
+    }
+
+    // The highest power with a nonzero coefficient; 0 for constants, including zero
+    pub fn degree(&self) -> usize {
+        self.coefficients.len().saturating_sub(1)
+    }
+
+    // The coefficient of the highest power, or 0 for the zero polynomial
+    pub fn leading(&self) -> f64 {
+        self.coefficients.last().copied().unwrap_or(0.0)
+    }
+
+    // The value at x, by Horner's method
+    pub fn evaluate(&self, x: f64) -> f64 {
+        self.coefficients.iter().rev().fold(0.0, |value, c| value * x + c)
+    }
+
+    pub fn powi(&self, n: u32) -> Polynomial {
+        (0..n).fold(Polynomial::constant(&*self.variable, 1.0), |p, _| &p * self)
+    }
+
+    // The quotient and remainder of long division, or None for division by zero
+    pub fn div_rem(&self, divisor: &Polynomial) -> Option<(Polynomial, Polynomial)> {
+        if divisor.is_zero() {
+            return None;
+        }
+        let mut remainder = self.coefficients.clone();
+        let mut quotient = vec![0.0; self.coefficients.len().saturating_sub(divisor.degree())];
+        for power in (0..quotient.len()).rev() {
+            let c = remainder[power + divisor.degree()] / divisor.leading();
+            quotient[power] = c;
+            for (i, d) in divisor.coefficients.iter().enumerate() {
+                remainder[power + i] -= c * d;
+            }
+            // The leading term cancels exactly, whatever the rounding
+            remainder[power + divisor.degree()] = 0.0;
+        }
+        Some((Polynomial::new(&*self.variable, quotient), Polynomial::new(&*self.variable, remainder)))
+    }
+
+    // The monic greatest common divisor, by Euclid's algorithm. Remainders whose coefficients are
+    // all tiny next to the divisor's count as zero, so rounding error does not hide common factors.
+    pub fn gcd(&self, other: &Polynomial) -> Polynomial {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        while !b.is_zero() {
+            let Some((_, mut remainder)) = a.div_rem(&b) else { break };
+            let scale = b.coefficients.iter().fold(0.0f64, |max, c| max.max(c.abs()));
+            if remainder.coefficients.iter().all(|c| c.abs() <= TOLERANCE * scale) {
+                remainder = Polynomial::constant(&*self.variable, 0.0);
+            }
+            (a, b) = (b, remainder);
+        }
+        if a.is_zero() {
+            return a;
+        }
+        a.scaled(1.0 / a.leading())
+    }
+
+    // The distinct real roots in increasing order, by the exact formulas for degrees 1 to 3. None
+    // for higher degrees and for constants, which have no roots or are zero everywhere.
+    pub fn roots(&self) -> Option<Vec<f64>> {
+        let mut roots = match self.coefficients[..] {
+            [b, a] => vec![-b / a],
+            [c, b, a] => {
+                let discriminant = b * b - 4.0 * a * c;
+                if discriminant < 0.0 {
+                    Vec::new()
+                } else {
+                    // Avoids subtracting nearly equal numbers for the smaller root
+                    let q = -(b + b.signum() * discriminant.sqrt()) / 2.0;
+                    if q == 0.0 { vec![0.0] } else { vec![q / a, c / q] }
+                }
+            }
+            [d, c, b, a] => cubic_roots(b / a, c / a, d / a),
+            _ => return None,
+        };
+        // Writes -0 as 0
+        roots.iter_mut().filter(|root| **root == 0.0).for_each(|root| *root = 0.0);
+        roots.sort_by(f64::total_cmp);
+        roots.dedup_by(|x, y| (*x - *y).abs() <= TOLERANCE * x.abs().max(1.0));
+        Some(roots)
+    }
+
+    // How many times (x - a) divides the polynomial, and the quotient. A remainder that is tiny
+    // next to the size of the terms at a counts as zero.
+    pub(crate) fn factor_out(mut self, a: f64) -> (usize, Polynomial) {
+        let mut times = 0;
+        while !self.is_zero() {
+            let scale: f64 = self.terms().map(|(i, c)| (c * a.powi(i as i32)).abs()).sum();
+            // Synthetic division: the quotient's coefficients, with the remainder last
+            let mut quotient: Vec<f64> = self
+                .coefficients
+                .iter()
+                .rev()
+                .scan(0.0, |carry, c| {
+                    *carry = *carry * a + c;
+                    Some(*carry)
+                })
+                .collect();
+            let remainder = quotient.pop().unwrap_or(0.0);
+            if remainder.abs() > 1e-12 * scale {
+                break;
+            }
+            quotient.reverse();
+            self = Polynomial::new(self.variable, quotient);
+            times += 1;
+        }
+        (times, self)
+    }
+
+    fn scaled(&self, factor: f64) -> Polynomial {
+        Polynomial::new(&*self.variable, self.coefficients.iter().map(|c| c * factor).collect())
+    }
+
+    fn trim(&mut self) {
+        while self.coefficients.last() == Some(&0.0) {
+            self.coefficients.pop();
+        }
+    }
+
+    // The variable of a result: a constant takes the other operand's
+    fn variable_with(&self, other: &Polynomial) -> String {
+        if self.degree() == 0 { other.variable.clone() } else { self.variable.clone() }
+    }
+}
+
+// The real roots of x^3 + b x^2 + c x + d: the trigonometric form when there are three, Cardano's
+// formula when there is one
+fn cubic_roots(b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Substituting x = t - b/3 gives t^3 + p t + q
+    let p = c - b * b / 3.0;
+    let q = 2.0 * b * b * b / 27.0 - b * c / 3.0 + d;
+    let shift = -b / 3.0;
+    let discriminant = (q / 2.0).powi(2) + (p / 3.0).powi(3);
+    if p == 0.0 && q == 0.0 {
+        vec![shift]
+    } else if discriminant > 0.0 {
+        let root = discriminant.sqrt();
+        vec![(-q / 2.0 + root).cbrt() + (-q / 2.0 - root).cbrt() + shift]
+    } else {
+        let r = 2.0 * (-p / 3.0).sqrt();
+        let angle = (3.0 * q / (p * r)).clamp(-1.0, 1.0).acos() / 3.0;
+        (0..3).map(|k| r * (angle - 2.0 * std::f64::consts::PI * k as f64 / 3.0).cos() + shift).collect()
+    }
+}
+
+// A polynomial in its only variable; constants are polynomials in x
+impl TryFrom<&Expr> for Polynomial {
+    type Error = MathError;
+
+    fn try_from(expr: &Expr) -> Result<Polynomial> {
+        let mut names = Vec::new();
+        variables(expr, &mut names);
+        match &names[..] {
+            [] => Polynomial::from_expr(expr, "x"),
+            [name] => Polynomial::from_expr(expr, name),
+            _ => Err(MathError::InvalidArgument {
+                function: "polynomial".to_string(),
+                reason: format!("{} has more than one variable: {}", expr, names.join(", ")),
+            }),
+        }
+    }
+}
+
+fn variables(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Variable(name) if !names.contains(name) => names.push(name.clone()),
+        _ => expr.children().into_iter().for_each(|child| variables(child, names)),
+    }
+}
+
+// The terms from the highest power down: 3 * x ^ 2 - x + 1
+impl From<&Polynomial> for Expr {
+    fn from(p: &Polynomial) -> Expr {
+        let mut terms = p.terms().collect::<Vec<_>>().into_iter().rev();
+        let Some((power, c)) = terms.next() else {
+            return Expr::literal(0.0);
+        };
+        let monomial = |power: usize, c: f64| {
+            let x = match power {
+                0 => return Expr::literal(c),
+                1 => Expr::variable(&*p.variable),
+                _ => Expr::binary(Operator::Power, Expr::variable(&*p.variable), Expr::literal(power as f64)),
+            };
+            match c {
+                1.0 => x,
+                -1.0 => Expr::unary_minus(x),
+                c => Expr::binary(Operator::Multiply, Expr::literal(c), x),
+            }
+        };
+        terms.fold(monomial(power, c), |sum, (power, c)| {
+            let op = if c < 0.0 { Operator::Subtract } else { Operator::Add };
+            Expr::binary(op, sum, monomial(power, c.abs()))
+        })
+    }
+}
+
+impl fmt::Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Expr::from(self))
+    }
+}
+
+impl Add for &Polynomial {
+    type Output = Polynomial;
+
+    fn add(self, other: &Polynomial) -> Polynomial {
+        let len = self.coefficients.len().max(other.coefficients.len());
+        let at = |p: &Polynomial, i: usize| p.coefficients.get(i).copied().unwrap_or(0.0);
+        Polynomial::new(self.variable_with(other), (0..len).map(|i| at(self, i) + at(other, i)).collect())
+    }
+}
+
+impl Sub for &Polynomial {
+    type Output = Polynomial;
+
+    fn sub(self, other: &Polynomial) -> Polynomial {
+        self + &-other
+    }
+}
+
+impl Mul for &Polynomial {
+    type Output = Polynomial;
+
+    fn mul(self, other: &Polynomial) -> Polynomial {
+        let variable = self.variable_with(other);
+        if self.is_zero() || other.is_zero() {
+            return Polynomial::new(variable, Vec::new());
+        }
+        let mut product = vec![0.0; self.coefficients.len() + other.coefficients.len() - 1];
+        for (i, a) in self.coefficients.iter().enumerate() {
+            for (j, b) in other.coefficients.iter().enumerate() {
+                product[i + j] += a * b;
+            }
+        }
+        Polynomial::new(variable, product)
+    }
+}
+
+impl Neg for &Polynomial {
+    type Output = Polynomial;
+
+    fn neg(self) -> Polynomial {
+        self.scaled(-1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polynomial(input: &str) -> Polynomial {
+        Polynomial::try_from(&Expr::parse(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_extraction() {
+        let p = polynomial("(x + 1) ^ 2 - 3 * x / 2");
+        assert_eq!(p.coefficients(), &[1.0, 0.5, 1.0]);
+        assert_eq!(p.terms().collect::<Vec<_>>(), vec![(0, 1.0), (1, 0.5), (2, 1.0)]);
+        assert_eq!(p.evaluate(2.0), 6.0);
+        assert_eq!(p.to_string(), "x ^ 2 + 0.5 * x + 1");
+        assert_eq!(polynomial("2 - t ^ 3").to_string(), "-(t ^ 3) + 2");
+        assert_eq!(polynomial("-2 * y * y - y").to_string(), "-2 * y ^ 2 - y");
+        assert_eq!(polynomial("7").variable(), "x");
+        assert!(Polynomial::try_from(&Expr::parse("x * y").unwrap()).is_err());
+        assert!(Polynomial::try_from(&Expr::parse("1 / x").unwrap()).is_err());
+        assert_eq!(Polynomial::try_from(&Expr::from(&p)).unwrap(), p);
+
+        // Nested powers are bounded by the degree they reach
+        assert_eq!(polynomial("(x ^ 32) ^ 32").degree(), 1024);
+        let error = Polynomial::try_from(&Expr::parse("((x ^ 64) ^ 64) ^ 64").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid argument to polynomial: x ^ 64 ^ 64 has a degree above 1024");
+        assert!(Polynomial::try_from(&Expr::parse("(x ^ 32) ^ 32 * x").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_algorithms() {
+        let p = polynomial("x ^ 3 - 6 * x ^ 2 + 11 * x - 6");
+        assert_eq!(p.roots().unwrap().iter().map(|r| r.round()).collect::<Vec<_>>(), vec![1.0, 2.0, 3.0]);
+        assert_eq!(polynomial("x ^ 2 - 4 * x + 4").roots().unwrap(), vec![2.0]);
+        assert_eq!(polynomial("x ^ 2 + 1").roots().unwrap(), Vec::<f64>::new());
+        assert_eq!(polynomial("x ^ 3 - 8").roots().unwrap(), vec![2.0]);
+        let (quotient, remainder) = p.div_rem(&polynomial("x - 1")).unwrap();
+        assert_eq!(quotient.to_string(), "x ^ 2 - 5 * x + 6");
+        assert!(remainder.is_zero());
+        assert_eq!(p.gcd(&polynomial("x ^ 2 - 1")).to_string(), "x - 1");
+        assert_eq!(polynomial("x ^ 2 + 1").gcd(&polynomial("x - 1")).to_string(), "1");
+    }
+}
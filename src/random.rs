@@ -0,0 +1,125 @@
+// src/random.rs
+// A small, self-contained pseudo-random generator (xoshiro256**) for the random built-ins.
+// Implemented here rather than pulled from a crate so a given seed produces the same
+// sequence on every platform and in every future version.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    // A generator whose sequence is fully determined by `seed`
+    pub fn seeded(seed: u64) -> Self {
+        // Expand the seed with SplitMix64, as recommended by the xoshiro authors
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        Self {
+            state: [next(), next(), next(), next()],
+        }
+    }
+
+    // A generator seeded from the process's hash randomness and the clock
+    pub fn from_entropy() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        hasher.write_u64(nanos);
+        Self::seeded(hasher.finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    // Uniform in [0, 1), using the top 53 bits
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform integer in [0, bound) without modulo bias; bound must be non-zero
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % bound;
+            }
+        }
+    }
+
+    // Standard normal sample (Box-Muller)
+    pub fn next_normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64(); // (0, 1], so the log is finite
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+// The generator as stored on a context: lockable through `&Context`, and copied (not shared)
+// when the context is cloned, so a clone replays the same sequence.
+#[derive(Debug)]
+pub(crate) struct RngCell(Mutex<Rng>);
+
+impl RngCell {
+    pub(crate) fn new(rng: Rng) -> Self {
+        Self(Mutex::new(rng))
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Rng> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Clone for RngCell {
+    fn clone(&self) -> Self {
+        Self::new(self.lock().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_sequences_repeat() {
+        let mut a = Rng::seeded(42);
+        let mut b = Rng::seeded(42);
+        let mut c = Rng::seeded(43);
+        let first: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..5).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first, (0..5).map(|_| c.next_u64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::seeded(7);
+        for _ in 0..1000 {
+            let x = rng.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            assert!(rng.below(6) < 6);
+        }
+        let mean = (0..10_000).map(|_| rng.next_normal()).sum::<f64>() / 10_000.0;
+        assert!(mean.abs() < 0.05);
+    }
+}
@@ -0,0 +1,266 @@
+// src/repl.rs
+// Interactive mode of the command-line tool: reads statements line by line, keeping variables
+// between lines. Tab completes function and variable names, `?name` shows help, `:save` /
+// `:load` keep the variables and functions across runs, and `:undo` takes back the last line's
+// changes.
+use std::fs;
+
+use mathexpr::functions::{function_info, FUNCTIONS};
+use mathexpr::{Context, ContextSnapshot, EvaluatorConfig, Workspace};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+
+use crate::{evaluate_line, CliOptions};
+
+// Completes the name before the cursor from the built-in functions and the session's variables
+#[derive(Default)]
+struct NameCompleter {
+    variables: Vec<String>,
+}
+
+impl Completer for NameCompleter {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete_name(line, pos, &self.variables))
+    }
+}
+
+impl Hinter for NameCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for NameCompleter {}
+
+impl Validator for NameCompleter {}
+
+impl Helper for NameCompleter {}
+
+// Where the name ending at `pos` starts, and the names it can be completed to. Functions complete
+// with their opening parenthesis.
+fn complete_name(line: &str, pos: usize, variables: &[String]) -> (usize, Vec<Pair>) {
+    let start = line[..pos].trim_end_matches(|c: char| c.is_alphanumeric() || c == '_').len();
+    let prefix = &line[start..pos];
+    if prefix.is_empty() || prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        return (pos, Vec::new());
+    }
+
+    let functions = FUNCTIONS.iter().map(|info| Pair {
+        display: info.name.to_string(),
+        replacement: format!("{}(", info.name),
+    });
+    let variables = variables.iter().map(|name| Pair {
+        display: name.clone(),
+        replacement: name.clone(),
+    });
+    let mut candidates: Vec<Pair> = functions.chain(variables).filter(|pair| pair.display.starts_with(prefix)).collect();
+    candidates.sort_by(|a, b| a.display.cmp(&b.display));
+    candidates.dedup_by(|a, b| a.display == b.display);
+    (start, candidates)
+}
+
+// The answer to `?name`: a function's signature and description, or a variable's value. A bare
+// `?` lists the functions.
+fn help(name: &str, ctx: &Context) -> String {
+    if name.is_empty() {
+        let names: Vec<&str> = FUNCTIONS.iter().map(|info| info.name).collect();
+        return format!("Functions: {}\nType ?name for help on one", names.join(", "));
+    }
+    match (function_info(name), ctx.value(name)) {
+        (Some(info), _) => format!("{}\n  {}", info.signature, info.description),
+        (None, Some(value)) => format!("{} = {}", name, value),
+        (None, None) => format!("No function or variable named {}", name),
+    }
+}
+
+// Where `:save` and `:load` write and read when no file is given
+const DEFAULT_SESSION: &str = "session.mexpr";
+
+// Writes the session's variables, functions defined by expressions (including those of --load)
+// and settings to a file as a JSON workspace. Returns what was saved.
+fn save_session(ctx: &Context, config: &EvaluatorConfig, path: &str) -> Result<Workspace, String> {
+    let workspace = Workspace::capture(ctx, config);
+    let json = serde_json::to_string_pretty(&workspace).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| format!("cannot write {}: {}", path, e))?;
+    Ok(workspace)
+}
+
+// Defines the variables and functions saved in a file, replacing those of the same name. The
+// session keeps its own settings. Returns what was loaded.
+fn load_session(ctx: &mut Context, path: &str) -> Result<Workspace, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let workspace: Workspace =
+        serde_json::from_str(&json).map_err(|e| format!("{} is not a saved session: {}", path, e))?;
+    workspace.load_into(ctx);
+    Ok(workspace)
+}
+
+// What a saved or loaded workspace holds, as in "2 variable(s) and 1 function(s)"
+fn contents(workspace: &Workspace) -> String {
+    format!("{} variable(s) and {} function(s)", workspace.variables.len(), workspace.functions.len())
+}
+
+// How many lines `:undo` can take back
+const UNDO_LIMIT: usize = 100;
+
+// Snapshots of the session before each line that may have changed it, latest last
+#[derive(Default)]
+struct History(Vec<ContextSnapshot>);
+
+impl History {
+    fn record(&mut self, ctx: &Context) {
+        if self.0.len() == UNDO_LIMIT {
+            self.0.remove(0);
+        }
+        self.0.push(ctx.snapshot());
+    }
+
+    fn undo(&mut self, ctx: &mut Context) -> Result<String, String> {
+        let snapshot = self.0.pop().ok_or("Nothing to undo")?;
+        ctx.restore(snapshot);
+        Ok("Undid the last change".to_string())
+    }
+}
+
+// Runs a `:command`
+fn command(line: &str, ctx: &mut Context, config: &EvaluatorConfig, history: &mut History) -> Result<String, String> {
+    let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let path = match argument.trim() {
+        "" => DEFAULT_SESSION,
+        path => path,
+    };
+    match name {
+        "undo" => history.undo(ctx),
+        "save" => save_session(ctx, config, path).map(|saved| format!("Saved {} to {}", contents(&saved), path)),
+        "load" => {
+            history.record(ctx);
+            load_session(ctx, path).map(|loaded| format!("Loaded {} from {}", contents(&loaded), path))
+        }
+        _ => Err(format!("Unknown command :{} (expected :save, :load or :undo)", name)),
+    }
+}
+
+// Runs the read-evaluate-print loop until end of input (Ctrl-D) or `exit`
+pub fn run(options: &CliOptions, mut ctx: Context) -> rustyline::Result<()> {
+    let mut editor = Editor::new()?;
+    editor.set_helper(Some(NameCompleter {
+        variables: ctx.variable_names().into_iter().map(String::from).collect(),
+    }));
+    println!("Tab completes names, ?name shows help, :save / :load keep variables and functions, :undo takes back a line, exit or Ctrl-D quits.");
+    let mut history = History::default();
+
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue, // Ctrl-C discards the line
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+
+        if line == "exit" || line == "quit" {
+            return Ok(());
+        }
+        if let Some(name) = line.strip_prefix('?') {
+            println!("{}", help(name.trim(), &ctx));
+        } else if let Some(line) = line.strip_prefix(':') {
+            match command(line, &mut ctx, &options.config, &mut history) {
+                Ok(message) => println!("{}", message),
+                Err(e) => println!("{}", options.colors.error(&format!("Error: {}", e))),
+            }
+        } else {
+            // Errors are printed; the session goes on
+            history.record(&ctx);
+            let _ = evaluate_line(line, &mut ctx, options);
+        }
+        if let Some(completer) = editor.helper_mut() {
+            completer.variables = ctx.variable_names().into_iter().map(String::from).collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mathexpr::Value;
+
+    fn completions(line: &str, variables: &[&str]) -> (usize, Vec<String>) {
+        let variables: Vec<String> = variables.iter().map(|name| name.to_string()).collect();
+        let (start, candidates) = complete_name(line, line.len(), &variables);
+        (start, candidates.into_iter().map(|pair| pair.replacement).collect())
+    }
+
+    #[test]
+    fn test_complete_name() {
+        assert_eq!(completions("1 + fa", &[]), (4, vec!["factorize(".to_string()]));
+        assert_eq!(completions("ma", &["mass"]), (0, vec!["mass".to_string(), "max(".to_string()]));
+        assert_eq!(completions("2*(x_", &["x_0", "y"]), (3, vec!["x_0".to_string()]));
+        assert_eq!(completions("1 + ", &["x"]).1, Vec::<String>::new());
+        assert_eq!(completions("2e", &[]).1, Vec::<String>::new());
+        // A variable shadowing a function is offered once
+        assert_eq!(completions("su", &["sum"]).1.len(), 1);
+    }
+
+    #[test]
+    fn test_help() {
+        let mut ctx = Context::new();
+        ctx.set_variable("rate", 0.25);
+        assert_eq!(help("lerp", &ctx), "lerp(a, b, t)\n  Linear interpolation a + (b - a) t");
+        assert_eq!(help("rate", &ctx), "rate = 0.25");
+        assert_eq!(help("nope", &ctx), "No function or variable named nope");
+        assert!(help("", &ctx).starts_with("Functions: acos, asin, atan, atan2, bin"));
+    }
+
+    #[test]
+    fn test_save_and_load_session() {
+        let path = std::env::temp_dir().join(format!("mathexpr-session-{}.mexpr", std::process::id()));
+        let path = path.to_str().unwrap();
+        let config = EvaluatorConfig::default();
+        let save = |ctx: &mut Context| command(&format!("save {}", path), ctx, &config, &mut History::default());
+        let mut ctx = Context::new();
+        // A function as loaded with --load
+        mathexpr::load_source_into("net(x) = x * (1 - a)", ".", &mut ctx).unwrap();
+        evaluate_line("a = 2; m = [[1, 2], [3, a]]", &mut ctx, &CliOptions::default()).unwrap();
+        assert_eq!(save(&mut ctx).unwrap(), format!("Saved 2 variable(s) and 1 function(s) to {}", path));
+
+        let mut resumed = Context::new();
+        resumed.set_variable("a", 5.0);
+        resumed.set_variable("b", 1.0);
+        let loaded = load_session(&mut resumed, path).unwrap();
+        assert_eq!(contents(&loaded), "2 variable(s) and 1 function(s)");
+        assert_eq!(resumed.value("a"), Some(Value::Number(2.0)));
+        assert_eq!(resumed.value("m"), ctx.value("m"));
+        assert_eq!(resumed.value("b"), Some(Value::Number(1.0)));
+        evaluate_line("n = net(10)", &mut resumed, &CliOptions::default()).unwrap();
+        assert_eq!(resumed.variable("n"), Some(-10.0));
+
+        fs::write(path, "not json").unwrap();
+        assert!(load_session(&mut resumed, path).unwrap_err().contains("is not a saved session"));
+        fs::remove_file(path).unwrap();
+        assert!(load_session(&mut resumed, path).unwrap_err().starts_with("cannot read"));
+        assert!(command("quit", &mut resumed, &config, &mut History::default()).is_err());
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut ctx = Context::new();
+        let mut history = History::default();
+        for line in ["a = 2", "a = a * 5; b = 1"] {
+            history.record(&ctx);
+            evaluate_line(line, &mut ctx, &CliOptions::default()).unwrap();
+        }
+        assert_eq!(command("undo", &mut ctx, &EvaluatorConfig::default(), &mut history), Ok("Undid the last change".to_string()));
+        assert_eq!((ctx.variable("a"), ctx.variable("b")), (Some(2.0), None));
+        command("undo", &mut ctx, &EvaluatorConfig::default(), &mut history).unwrap();
+        assert_eq!(ctx.variable("a"), None);
+        assert_eq!(command("undo", &mut ctx, &EvaluatorConfig::default(), &mut history), Err("Nothing to undo".to_string()));
+    }
+}
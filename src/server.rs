@@ -0,0 +1,304 @@
+// src/server.rs
+// A JSON API over HTTP (`mathexpr serve --port 8080`), for running formulas as a service:
+//   POST /eval  {"expression": "price * qty", "variables": {"price": 2.5, "qty": 4}} -> {"result": 10.0}
+//   POST /parse {"expression": "1 + 2"} -> {"ast": {...}}, the expression tree
+// Requests come from untrusted clients, so the body, the expression's length and its nesting
+// are all bounded before anything is evaluated, and evaluating is stopped after a time limit.
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mathexpr::{Context, EvalOptions, Evaluator, EvaluatorConfig, MathError, Parser, Value};
+use serde_json::json;
+use tiny_http::{Header, Response, Server};
+
+// Requests handled at the same time
+const WORKERS: usize = 4;
+
+// Arguments of the serve subcommand
+#[derive(Debug, PartialEq)]
+pub struct ServeArgs {
+    host: String,
+    port: u16,
+    limits: Limits,
+}
+
+// What a single request may ask for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    max_body: usize,   // Bytes in the request body
+    max_length: usize, // Characters in the expression
+    max_depth: usize,  // Nesting of parentheses, calls and operations (see `ParserOptions::max_depth`)
+    timeout: Duration, // Time spent evaluating all the statements (see `EvalOptions::max_duration`)
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_body: 64 * 1024,
+            max_length: 4096,
+            max_depth: 64,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+pub const USAGE: &str =
+    "Usage: mathexpr serve [--host <ADDRESS>] [--port <N>] [--max-length <N>] [--max-depth <N>] [--timeout <MS>]";
+
+// Parses the arguments following `serve`
+pub fn parse_args(args: &[String]) -> Result<ServeArgs, String> {
+    let mut parsed = ServeArgs {
+        host: "127.0.0.1".to_string(),
+        port: 8080,
+        limits: Limits::default(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--host" => parsed.host = value()?.clone(),
+            "-p" | "--port" => parsed.port = number(arg, value()?)?,
+            "--max-length" => parsed.limits.max_length = number(arg, value()?)?,
+            "--max-depth" => parsed.limits.max_depth = number(arg, value()?)?,
+            "--timeout" => parsed.limits.timeout = Duration::from_millis(number(arg, value()?)?),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+// Serves requests until the process is stopped. The configuration's limits apply where they are
+// stricter than the server's.
+pub fn run(args: &ServeArgs, config: &EvaluatorConfig, ctx: &Context) -> Result<(), String> {
+    let address = format!("{}:{}", args.host, args.port);
+    let server = Server::http(&address).map_err(|e| format!("cannot listen on {}: {}", address, e))?;
+    println!("Listening on http://{}", address);
+    thread::scope(|scope| {
+        for _ in 0..WORKERS {
+            scope.spawn(|| serve(&server, &args.limits, config, ctx));
+        }
+    });
+    Ok(())
+}
+
+fn serve(server: &Server, limits: &Limits, config: &EvaluatorConfig, ctx: &Context) {
+    while let Ok(mut request) = server.recv() {
+        // Read one byte past the limit, to tell a body at the limit from one over it
+        let mut body = Vec::new();
+        let read = request.as_reader().take(limits.max_body as u64 + 1).read_to_end(&mut body);
+        let (status, reply) = match read {
+            Ok(_) => respond(request.method().as_str(), request.url(), &body, limits, config, ctx),
+            Err(e) => (400, json!({ "error": e.to_string() })),
+        };
+        eprintln!("{} {} {}", request.method(), request.url(), status);
+        let content_type = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+        let response = Response::from_string(reply.to_string())
+            .with_status_code(status)
+            .with_header(content_type);
+        let _ = request.respond(response); // The client may have gone away
+    }
+}
+
+// The body of a request to either endpoint
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExpressionRequest {
+    expression: String,
+    #[serde(default)]
+    variables: BTreeMap<String, Value>, // Only used by /eval
+}
+
+// Handles one request, returning the status code and the JSON reply
+pub fn respond(
+    method: &str,
+    url: &str,
+    body: &[u8],
+    limits: &Limits,
+    config: &EvaluatorConfig,
+    ctx: &Context,
+) -> (u16, serde_json::Value) {
+    let path = url.split('?').next().unwrap_or_default();
+    if path != "/eval" && path != "/parse" {
+        return (404, json!({ "error": format!("Not found: {}", path) }));
+    }
+    if method != "POST" {
+        return (405, json!({ "error": format!("{} expects POST", path) }));
+    }
+    if body.len() > limits.max_body {
+        return (413, json!({ "error": format!("Request body is over {} bytes", limits.max_body) }));
+    }
+    let request: ExpressionRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return (400, json!({ "error": format!("Invalid request: {}", e) })),
+    };
+    if request.expression.chars().count() > limits.max_length {
+        let error = format!("Expression is over {} characters", limits.max_length);
+        return (422, json!({ "error": error }));
+    }
+
+    let result = if path == "/eval" {
+        evaluate(&request, limits, config, ctx).map(|result| json!({ "result": result }))
+    } else {
+        parser(&request.expression, limits, config)
+            .and_then(|mut parser| parser.parse())
+            .map(|expr| json!({ "ast": expr }))
+    };
+    match result {
+        Ok(reply) => (200, reply),
+        Err(e) => (422, json!({ "error": e.to_string(), "span": e.span() })),
+    }
+}
+
+fn parser(expression: &str, limits: &Limits, config: &EvaluatorConfig) -> Result<Parser, MathError> {
+    let max_depth = config.max_depth.map_or(limits.max_depth, |depth| depth.min(limits.max_depth));
+    let config = EvaluatorConfig {
+        max_depth: Some(max_depth),
+        ..config.clone()
+    };
+    config.grammar().parser(expression)
+}
+
+// Runs the statements of the expression with the request's variables, returning the last result.
+// The statements share the time limit, each getting what the ones before it left.
+fn evaluate(
+    request: &ExpressionRequest,
+    limits: &Limits,
+    config: &EvaluatorConfig,
+    ctx: &Context,
+) -> Result<Value, MathError> {
+    let statements = parser(&request.expression, limits, config)?.parse_statements()?;
+    let mut ctx = ctx.clone();
+    for (name, value) in &request.variables {
+        ctx.set_variable(name.as_str(), value.clone());
+    }
+    let timeout = config.evaluation.max_duration.map_or(limits.timeout, |limit| limit.min(limits.timeout));
+    let start = Instant::now();
+    let mut result = None;
+    for statement in &statements {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Err(MathError::Timeout(timeout));
+        }
+        let options = EvalOptions {
+            max_duration: Some(remaining),
+            ..config.evaluation.clone()
+        };
+        // Reports the request's limit rather than what was left of it
+        let value = Evaluator::execute_with_options(statement, &mut ctx, &options).map_err(|e| match e {
+            MathError::Timeout(_) => MathError::Timeout(timeout),
+            e => e,
+        })?;
+        result = Some(value);
+    }
+    result.ok_or_else(|| MathError::InvalidExpression("Empty expression".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(path: &str, body: &str) -> (u16, serde_json::Value) {
+        respond("POST", path, body.as_bytes(), &Limits::default(), &EvaluatorConfig::default(), &Context::new())
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let body = r#"{"expression": "total = price * qty; total + 1", "variables": {"price": 2.5, "qty": 4}}"#;
+        assert_eq!(post("/eval", body), (200, json!({ "result": 11.0 })));
+        assert_eq!(post("/eval", r#"{"expression": "xs[1]", "variables": {"xs": [1, 2]}}"#).1["result"], 2.0);
+
+        let (status, reply) = post("/parse", r#"{"expression": "1 + x"}"#);
+        assert_eq!(status, 200);
+        let ast: mathexpr::Expr = serde_json::from_value(reply["ast"].clone()).unwrap();
+        assert_eq!(ast, mathexpr::Expr::parse("1 + x").unwrap());
+
+        // Errors in the expression point into it
+        let (status, reply) = post("/eval", r#"{"expression": "1 + (2"}"#);
+        assert_eq!(status, 422);
+        assert_eq!(reply["span"], json!({ "start": 4, "end": 5 }));
+        assert_eq!(post("/eval", r#"{"expression": "y"}"#).1["error"], "Unknown variable: y");
+
+        assert_eq!(post("/eval", "1 + 2").0, 400);
+        assert_eq!(post("/eval", r#"{"expr": "1"}"#).0, 400);
+        assert_eq!(post("/other", "{}").0, 404);
+        assert_eq!(respond("GET", "/eval", b"", &Limits::default(), &EvaluatorConfig::default(), &Context::new()).0, 405);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_body: 100,
+            max_length: 20,
+            max_depth: 3,
+            timeout: Duration::from_secs(1),
+        };
+        let post = |body: &str| respond("POST", "/eval", body.as_bytes(), &limits, &EvaluatorConfig::default(), &Context::new());
+        assert_eq!(post(r#"{"expression": "1 + 2 * 3"}"#).0, 200);
+        assert_eq!(post(&format!(r#"{{"expression": "{}"}}"#, "1".repeat(90))).0, 413);
+        assert_eq!(post(r#"{"expression": "1 + 2 + 3 + 4 + 5 + 6"}"#).0, 422);
+        let (status, reply) = post(r#"{"expression": "1 + 2 + 3 + 4"}"#);
+        assert_eq!((status, reply["error"].as_str()), (422, Some("Expression is nested more than 3 levels deep")));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut ctx = Context::new();
+        ctx.register_function("slow", |args: &[f64]| {
+            std::thread::sleep(Duration::from_millis(60));
+            Ok(args[0])
+        });
+        let limits = Limits {
+            timeout: Duration::from_millis(50),
+            ..Limits::default()
+        };
+        let post = |expression: &str| {
+            let body = json!({ "expression": expression }).to_string();
+            respond("POST", "/eval", body.as_bytes(), &limits, &EvaluatorConfig::default(), &ctx)
+        };
+        let (status, reply) = post("slow(1) + 1");
+        assert_eq!((status, reply["error"].as_str()), (422, Some("Evaluation took longer than 50ms")));
+        // Later statements get only what is left of the limit
+        assert_eq!(post("a = slow(1); a + 1").0, 422);
+        assert_eq!(post("1 + 2").1["result"], 3.0);
+    }
+
+    #[test]
+    fn test_evaluator_config() {
+        let config = EvaluatorConfig {
+            evaluation: EvalOptions {
+                division_by_zero: mathexpr::DivisionByZero::Default(0.0),
+                ..EvalOptions::default()
+            },
+            max_depth: Some(2),
+            ..EvaluatorConfig::default()
+        };
+        let post = |expression: &str| {
+            let body = json!({ "expression": expression }).to_string();
+            respond("POST", "/eval", body.as_bytes(), &Limits::default(), &config, &Context::new())
+        };
+        assert_eq!(post("1 / 0"), (200, json!({ "result": 0.0 })));
+        // The stricter of the two depth limits applies
+        let (status, reply) = post("1 + 2 + 3 + 4");
+        assert_eq!((status, reply["error"].as_str()), (422, Some("Expression is nested more than 2 levels deep")));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args: Vec<String> = ["--port", "9000", "--max-depth", "10", "--timeout", "250"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let parsed = parse_args(&args).unwrap();
+        assert_eq!((parsed.host.as_str(), parsed.port, parsed.limits.max_depth), ("127.0.0.1", 9000, 10));
+        assert_eq!(parsed.limits.timeout, Duration::from_millis(250));
+        assert!(parse_args(&["--port".to_string()]).is_err());
+        assert!(parse_args(&["--port".to_string(), "x".to_string()]).is_err());
+    }
+}
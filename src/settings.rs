@@ -0,0 +1,78 @@
+// src/settings.rs
+// Every setting that changes how an input is parsed, evaluated and printed, in one value that a
+// host can store, compare with another, and hand to each of its front ends alike. Serializable
+// with the `serde` feature, where missing fields take their defaults.
+use crate::{format_value, EvalOptions, Evaluator, FormatOptions, Grammar, ParserBuilder, Value};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct EvaluatorConfig {
+    // Strictness, division by zero, determinism and the time limit
+    pub evaluation: EvalOptions,
+    // Deepest nesting of input to accept (see `ParserOptions::max_depth`)
+    pub max_depth: Option<usize>,
+    // How results are printed
+    pub format: FormatOptions,
+}
+
+impl EvaluatorConfig {
+    // An evaluator with these policies
+    pub fn evaluator(&self) -> Evaluator {
+        Evaluator::builder().options(self.evaluation.clone()).build()
+    }
+
+    // The default syntax, with these limits
+    pub fn grammar(&self) -> Grammar {
+        let builder = ParserBuilder::new();
+        match self.max_depth {
+            Some(depth) => builder.max_depth(depth).build(),
+            None => builder.build(),
+        }
+    }
+
+    // A result as text, with these formatting options
+    pub fn format(&self, value: &Value) -> String {
+        format_value(value, &self.format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, DivisionByZero, Notation};
+
+    #[test]
+    fn test_front_end_settings() {
+        let config = EvaluatorConfig {
+            evaluation: EvalOptions {
+                division_by_zero: DivisionByZero::Default(0.0),
+                ..EvalOptions::default()
+            },
+            max_depth: Some(3),
+            format: FormatOptions::default().notation(Notation::Scientific),
+        };
+        let expr = config.grammar().parse("1500 + 1 / 0").unwrap();
+        let value = config.evaluator().eval_value(&expr, &Context::new()).unwrap();
+        assert_eq!(config.format(&value), "1.5e3");
+        assert!(config.grammar().parse("((((1))))").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let config = EvaluatorConfig {
+            evaluation: EvalOptions {
+                strict: true,
+                max_duration: Some(std::time::Duration::from_millis(50)),
+                ..EvalOptions::default()
+            },
+            format: FormatOptions::decimals(2).locale(crate::Locale::DE),
+            ..EvaluatorConfig::default()
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<EvaluatorConfig>(&json).unwrap(), config);
+
+        let partial: EvaluatorConfig = serde_json::from_str(r#"{ "max_depth": 64 }"#).unwrap();
+        assert_eq!(partial, EvaluatorConfig { max_depth: Some(64), ..EvaluatorConfig::default() });
+    }
+}
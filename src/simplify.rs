@@ -0,0 +1,681 @@
+// src/simplify.rs
+// Algebraic simplification of expression trees, in groups of rules that are turned on separately:
+// the conservative default only removes operations that do nothing and computes the parts that
+// are all numbers, while the aggressive settings also collect like terms and combine powers.
+// Rules treat variables as finite numbers, so `x * 0` becomes 0 although `inf * 0` is NaN, and
+// may drop conditions on them: `x / x` becomes 1 although it is undefined for x = 0.
+use crate::functions::{builtin, ELEMENTARY};
+use crate::{Context, Evaluator, Expr, Operator, UnaryOperator};
+
+// Rewriting stops after this many passes over the tree, even if rules still apply
+const MAX_PASSES: usize = 16;
+
+// Which groups of rules `Expr::simplify` applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimplifyOptions {
+    // Operations that do nothing: x + 0, x * 1, x / 1, x ^ 1, --x, and x * 0, x ^ 0, 1 ^ x
+    pub identities: bool,
+    // Operations on numbers and calls of pure built-in functions with number arguments are
+    // computed: 2 * 3 is 6, max(1, 4) is 4. Results that are not finite are left unevaluated.
+    pub constant_folding: bool,
+    // Sums are collected by term: 2 * x + x - 1 + 4 is 3 * x + 3
+    pub like_terms: bool,
+    // Products are collected by base: x * x ^ 2 is x ^ 3, x / x ^ 3 is 1 / x ^ 2, (x ^ 2) ^ 3 is x ^ 6
+    pub powers: bool,
+    // Identities of the built-in sin, cos and tan, of angles in radians: sin(x) ^ 2 + cos(x) ^ 2 is 1,
+    // sin(-x) is -sin(x), 2 * sin(x) * cos(x) is sin(2 * x), and sin(pi) is 0
+    pub trigonometry: bool,
+    // Identities of the built-in exp and logarithms ln, log, log2 and log10:
+    // ln(a * b) is ln(a) + ln(b), ln(a ^ n) is n * ln(a) and e ^ ln(x) is x, only for a, b and x
+    // known to be positive, and ln(e ^ x) is x
+    pub logarithms: bool,
+}
+
+impl SimplifyOptions {
+    // Identities and constant folding: the result has the same structure, minus the no-ops
+    pub fn conservative() -> Self {
+        Self {
+            identities: true,
+            constant_folding: true,
+            like_terms: false,
+            powers: false,
+            trigonometry: false,
+            logarithms: false,
+        }
+    }
+
+    // Every group of rules
+    pub fn aggressive() -> Self {
+        Self {
+            like_terms: true,
+            powers: true,
+            trigonometry: true,
+            logarithms: true,
+            ..Self::conservative()
+        }
+    }
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        Self::conservative()
+    }
+}
+
+impl Expr {
+    // The expression rewritten by the enabled rules until none applies. Parentheses kept by
+    // `ParserOptions::preserve_notation` are dropped, since the tree already holds the grouping.
+    pub fn simplify(&self, options: &SimplifyOptions) -> Expr {
+        let mut expr = self.clone();
+        for _ in 0..MAX_PASSES {
+            let next = pass(&expr, options);
+            if next == expr {
+                break;
+            }
+            expr = next;
+        }
+        expr
+    }
+}
+
+// Rewrites the children, then the node itself
+fn pass(expr: &Expr, options: &SimplifyOptions) -> Expr {
+    let expr = match expr {
+        Expr::Group(inner) => return pass(inner, options),
+        _ => expr.map_children(|child| pass(child, options)),
+    };
+    if options.constant_folding {
+        if let Some(value) = fold(&expr) {
+            return Expr::Literal(value);
+        }
+    }
+    if options.identities {
+        if let Some(simpler) = identity(&expr) {
+            return simpler;
+        }
+    }
+    if options.trigonometry {
+        if let Some(simpler) = trigonometry(&expr) {
+            return simpler;
+        }
+    }
+    if options.logarithms {
+        if let Some(simpler) = logarithms(&expr) {
+            return simpler;
+        }
+    }
+    match &expr {
+        Expr::BinOp { op: Operator::Add | Operator::Subtract, .. } if options.like_terms => collect_terms(&expr),
+        Expr::BinOp { op: Operator::Multiply | Operator::Divide, .. } if options.powers => {
+            collect_factors(&expr).unwrap_or(expr)
+        }
+        Expr::BinOp { op: Operator::Power, lhs, rhs } if options.powers => match &**lhs {
+            Expr::BinOp { op: Operator::Power, lhs: base, rhs: inner } => {
+                power_of_power(base, inner, rhs).unwrap_or(expr)
+            }
+            _ => expr,
+        },
+        _ => expr,
+    }
+}
+
+// The value of a number node (a literal or scientific notation)
+fn number(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Literal(value) => Some(*value),
+        Expr::Scientific { base, exponent } => Some(base * 10f64.powi(*exponent)),
+        _ => None,
+    }
+}
+
+// The value of an operation whose operands are all numbers, when it can be computed
+fn fold(expr: &Expr) -> Option<f64> {
+    let foldable = match expr {
+        Expr::BinOp { op, lhs, rhs } => !matches!(op, Operator::Custom(_)) && number(lhs).is_some() && number(rhs).is_some(),
+        Expr::UnaryMinus(operand) => number(operand).is_some(),
+        // Square roots that are not whole stay symbolic, so √2 * √2 can still become 2
+        Expr::Unary { op: UnaryOperator::Sqrt, operand } => number(operand).is_some_and(|n| n.sqrt().fract() == 0.0),
+        Expr::Unary { op, operand } => !matches!(op, UnaryOperator::Custom(_)) && number(operand).is_some(),
+        // Not the random functions, nor those that only change how a result is displayed
+        Expr::Function { name, args } => {
+            builtin(name).is_some() && !matches!(name.as_str(), "hex" | "bin" | "oct") && args.iter().all(|arg| number(arg).is_some())
+        }
+        _ => false,
+    };
+    if !foldable {
+        return None;
+    }
+    // Likewise for the results of functions such as ln and sin, so ln(2) is kept exact
+    let whole_only = matches!(expr, Expr::Function { name, .. } if ELEMENTARY.contains(&name.as_str()));
+    Evaluator::evaluate_with(expr, &Context::new())
+        .ok()
+        .filter(|value| value.is_finite() && !(whole_only && value.fract() != 0.0))
+}
+
+fn identity(expr: &Expr) -> Option<Expr> {
+    let is = |expr: &Expr, value: f64| number(expr) == Some(value);
+    let negated = |expr: &Expr| Expr::unary_minus(expr.clone());
+    match expr {
+        Expr::UnaryMinus(operand) => match &**operand {
+            Expr::UnaryMinus(inner) => Some((**inner).clone()),
+            _ => None,
+        },
+        Expr::BinOp { op, lhs, rhs } => match op {
+            Operator::Add if is(rhs, 0.0) => Some((**lhs).clone()),
+            Operator::Add if is(lhs, 0.0) => Some((**rhs).clone()),
+            Operator::Subtract if is(rhs, 0.0) => Some((**lhs).clone()),
+            Operator::Subtract if is(lhs, 0.0) => Some(negated(rhs)),
+            // x + -y is x - y, and x - -y is x + y
+            Operator::Add | Operator::Subtract => {
+                let flipped = if *op == Operator::Add { Operator::Subtract } else { Operator::Add };
+                match (&**rhs, number(rhs)) {
+                    (Expr::UnaryMinus(inner), _) => Some(Expr::binary(flipped, (**lhs).clone(), (**inner).clone())),
+                    (_, Some(value)) if value < 0.0 => Some(Expr::binary(flipped, (**lhs).clone(), Expr::Literal(-value))),
+                    _ => None,
+                }
+            }
+            Operator::Multiply if is(lhs, 0.0) || is(rhs, 0.0) => Some(Expr::Literal(0.0)),
+            Operator::Multiply if is(rhs, 1.0) => Some((**lhs).clone()),
+            Operator::Multiply if is(lhs, 1.0) => Some((**rhs).clone()),
+            Operator::Multiply if is(rhs, -1.0) => Some(negated(lhs)),
+            Operator::Multiply if is(lhs, -1.0) => Some(negated(rhs)),
+            Operator::Divide if is(rhs, 1.0) => Some((**lhs).clone()),
+            Operator::Divide if is(lhs, 0.0) && !is(rhs, 0.0) => Some(Expr::Literal(0.0)),
+            Operator::Power if is(rhs, 1.0) => Some((**lhs).clone()),
+            Operator::Power if is(rhs, 0.0) || is(lhs, 1.0) => Some(Expr::Literal(1.0)),
+            // √x * √x and (√x) ^ 2 are x
+            Operator::Multiply if lhs == rhs => match &**lhs {
+                Expr::Unary { op: UnaryOperator::Sqrt, operand } => Some((**operand).clone()),
+                _ => None,
+            },
+            Operator::Power if is(rhs, 2.0) => match &**lhs {
+                Expr::Unary { op: UnaryOperator::Sqrt, operand } => Some((**operand).clone()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The argument of a call of a one-argument function
+fn argument<'a>(expr: &'a Expr, function: &str) -> Option<&'a Expr> {
+    match expr {
+        Expr::Function { name, args } if name == function && args.len() == 1 => Some(&args[0]),
+        _ => None,
+    }
+}
+
+// The argument of function(x) ^ 2
+fn squared<'a>(expr: &'a Expr, function: &str) -> Option<&'a Expr> {
+    match expr {
+        Expr::BinOp { op: Operator::Power, lhs, rhs } if number(rhs) == Some(2.0) => argument(lhs, function),
+        _ => None,
+    }
+}
+
+// The factors of a product, however it is nested
+fn factors(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinOp { op: Operator::Multiply, lhs, rhs } => {
+            let mut all = factors(lhs);
+            all.extend(factors(rhs));
+            all
+        }
+        _ => vec![expr],
+    }
+}
+
+// The multiple of pi an angle is, for 0, pi, pi / 2 and 2 * pi
+fn multiple_of_pi(expr: &Expr) -> Option<f64> {
+    let is_pi = |expr: &Expr| matches!(expr, Expr::Variable(name) if name == "pi" || name == "π");
+    match expr {
+        _ if number(expr) == Some(0.0) => Some(0.0),
+        _ if is_pi(expr) => Some(1.0),
+        Expr::BinOp { op: Operator::Divide, lhs, rhs } if is_pi(lhs) && number(rhs) == Some(2.0) => Some(0.5),
+        Expr::BinOp { op: Operator::Multiply, lhs, rhs } if number(lhs) == Some(2.0) && is_pi(rhs) => Some(2.0),
+        _ => None,
+    }
+}
+
+fn trigonometry(expr: &Expr) -> Option<Expr> {
+    let call = |name: &str, arg: Expr| Expr::function(name, vec![arg]);
+    let square = |name: &str, arg: &Expr| Expr::binary(Operator::Power, call(name, arg.clone()), Expr::Literal(2.0));
+    match expr {
+        Expr::Function { name, args } if args.len() == 1 => {
+            let arg = &args[0];
+            // Odd and even functions
+            if let Expr::UnaryMinus(inner) = arg {
+                return match name.as_str() {
+                    "sin" | "tan" => Some(Expr::unary_minus(call(name, (**inner).clone()))),
+                    "cos" => Some(call(name, (**inner).clone())),
+                    _ => None,
+                };
+            }
+            let turns = multiple_of_pi(arg)?;
+            let value = match (name.as_str(), turns) {
+                ("sin", 0.5) => 1.0,
+                ("sin", _) => 0.0,
+                ("cos", 0.5) => 0.0,
+                ("cos", 1.0) => -1.0,
+                ("cos", _) => 1.0,
+                ("tan", 0.5) => return None,
+                ("tan", _) => 0.0,
+                _ => return None,
+            };
+            Some(Expr::Literal(value))
+        }
+        Expr::BinOp { op, lhs, rhs } => match op {
+            // sin(x) ^ 2 + cos(x) ^ 2 is 1
+            Operator::Add => {
+                let pair = |a: &Expr, b: &Expr| squared(a, "sin").is_some() && squared(a, "sin") == squared(b, "cos");
+                (pair(lhs, rhs) || pair(rhs, lhs)).then_some(Expr::Literal(1.0))
+            }
+            Operator::Subtract => {
+                // 1 - sin(x) ^ 2 is cos(x) ^ 2, and the other way around
+                if number(lhs) == Some(1.0) {
+                    return match (squared(rhs, "sin"), squared(rhs, "cos")) {
+                        (Some(x), _) => Some(square("cos", x)),
+                        (_, Some(x)) => Some(square("sin", x)),
+                        _ => None,
+                    };
+                }
+                // cos(x) ^ 2 - sin(x) ^ 2 is cos(2 * x)
+                let x = squared(lhs, "cos").filter(|x| squared(rhs, "sin") == Some(*x))?;
+                Some(call("cos", Expr::binary(Operator::Multiply, Expr::Literal(2.0), x.clone())))
+            }
+            // 2 * sin(x) * cos(x) is sin(2 * x)
+            Operator::Multiply => {
+                let mut rest = factors(expr);
+                let two = rest.iter().position(|f| number(f) == Some(2.0))?;
+                rest.remove(two);
+                let [a, b] = rest[..] else { return None };
+                let x = match (argument(a, "sin"), argument(b, "cos")) {
+                    (Some(x), Some(y)) if x == y => x,
+                    _ => argument(b, "sin").filter(|x| argument(a, "cos") == Some(*x))?,
+                };
+                Some(call("sin", Expr::binary(Operator::Multiply, Expr::Literal(2.0), x.clone())))
+            }
+            // sin(x) / cos(x) is tan(x)
+            Operator::Divide => {
+                let x = argument(lhs, "sin").filter(|x| argument(rhs, "cos") == Some(*x))?;
+                Some(call("tan", x.clone()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Whether an expression is positive wherever it is defined: positive numbers, pi and e, exp(x),
+// and sums, products, quotients, powers and square roots of those
+fn positive(expr: &Expr) -> bool {
+    match expr {
+        Expr::Variable(name) => matches!(name.as_str(), "pi" | "π" | "tau" | "τ" | "e"),
+        Expr::Function { name, .. } => name == "exp",
+        Expr::Unary { op: UnaryOperator::Sqrt, operand } => positive(operand),
+        Expr::BinOp { op: Operator::Add | Operator::Multiply | Operator::Divide, lhs, rhs } => {
+            positive(lhs) && positive(rhs)
+        }
+        Expr::BinOp { op: Operator::Power, lhs, .. } => positive(lhs),
+        _ => number(expr).is_some_and(|n| n > 0.0),
+    }
+}
+
+// The base of a logarithm function of one argument: log(x) is the base-10 logarithm, as built in
+fn log_base(name: &str) -> Option<Expr> {
+    match name {
+        "ln" => Some(Expr::variable("e")),
+        "log2" => Some(Expr::Literal(2.0)),
+        "log" | "log10" => Some(Expr::Literal(10.0)),
+        _ => None,
+    }
+}
+
+fn logarithms(expr: &Expr) -> Option<Expr> {
+    let is_log = |name: &str| matches!(name, "ln" | "log" | "log2" | "log10");
+    match expr {
+        Expr::Function { name, args } if is_log(name) && args.len() == 1 => {
+            let log = |arg: &Expr| Expr::function(name, vec![arg.clone()]);
+            match &args[0] {
+                arg if number(arg) == Some(1.0) => Some(Expr::Literal(0.0)),
+                arg if log_base(name).as_ref() == Some(arg) => Some(Expr::Literal(1.0)),
+                // log(b ^ x) is x for the log's own base, and n * log(a) for a positive a
+                Expr::BinOp { op: Operator::Power, lhs, rhs } if log_base(name).as_ref() == Some(&**lhs) => {
+                    Some((**rhs).clone())
+                }
+                Expr::Function { name: exp, args } if name == "ln" && exp == "exp" && args.len() == 1 => {
+                    Some(args[0].clone())
+                }
+                Expr::BinOp { op: Operator::Power, lhs, rhs } if positive(lhs) => {
+                    Some(Expr::binary(Operator::Multiply, (**rhs).clone(), log(lhs)))
+                }
+                Expr::BinOp { op: op @ (Operator::Multiply | Operator::Divide), lhs, rhs }
+                    if positive(lhs) && positive(rhs) =>
+                {
+                    let op = if *op == Operator::Multiply { Operator::Add } else { Operator::Subtract };
+                    Some(Expr::binary(op, log(lhs), log(rhs)))
+                }
+                _ => None,
+            }
+        }
+        // e ^ ln(x), exp(ln(x)) and 10 ^ log10(x) are x, for x where the logarithm is defined
+        Expr::BinOp { op: Operator::Power, lhs, rhs } => match &**rhs {
+            Expr::Function { name, args } if args.len() == 1 && log_base(name).as_ref() == Some(&**lhs) => {
+                positive(&args[0]).then(|| args[0].clone())
+            }
+            _ => None,
+        },
+        Expr::Function { name, args } if name == "exp" && args.len() == 1 => match argument(&args[0], "ln") {
+            Some(x) if positive(x) => Some(x.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// a * b, computed when both are numbers
+// (x ^ a) ^ b as x ^ (a * b), which holds for whole b, and for an odd a since x ^ a keeps the
+// sign of x. For an even a it is |x| ^ (a * b), as (x ^ 2) ^ 0.5 is |x|, not x.
+fn power_of_power(base: &Expr, a: &Expr, b: &Expr) -> Option<Expr> {
+    let whole = |expr: &Expr| number(expr).is_some_and(|n| n.fract() == 0.0);
+    let base = match number(a) {
+        _ if whole(b) => base.clone(),
+        Some(a) if a.fract() == 0.0 && a % 2.0 != 0.0 => base.clone(),
+        Some(a) if a.fract() == 0.0 => Expr::unary(UnaryOperator::Abs, base.clone()),
+        _ => return None,
+    };
+    Some(Expr::binary(Operator::Power, base, product(a, b)))
+}
+
+fn product(a: &Expr, b: &Expr) -> Expr {
+    match (number(a), number(b)) {
+        (Some(a), Some(b)) => Expr::Literal(a * b),
+        _ => Expr::binary(Operator::Multiply, a.clone(), b.clone()),
+    }
+}
+
+// Rebuilds a sum with the terms that differ only in a numeric factor combined, in the order each
+// term first appears. The numbers are summed where the first one was.
+fn collect_terms(expr: &Expr) -> Expr {
+    let mut terms: Vec<(f64, Option<Expr>)> = Vec::new(); // (factor, term), None for the numbers
+    add_terms(expr, 1.0, &mut terms);
+
+    let mut sum: Option<Expr> = None;
+    for (factor, term) in terms {
+        if factor == 0.0 {
+            continue;
+        }
+        let scaled = |factor: f64| match &term {
+            Some(term) if factor == 1.0 => term.clone(),
+            Some(term) => Expr::binary(Operator::Multiply, Expr::Literal(factor), term.clone()),
+            None => Expr::Literal(factor),
+        };
+        sum = Some(match sum {
+            None if factor == -1.0 && term.is_some() => Expr::unary_minus(scaled(1.0)),
+            None => scaled(factor),
+            Some(sum) if factor < 0.0 => Expr::binary(Operator::Subtract, sum, scaled(-factor)),
+            Some(sum) => Expr::binary(Operator::Add, sum, scaled(factor)),
+        });
+    }
+    sum.unwrap_or(Expr::Literal(0.0))
+}
+
+fn add_terms(expr: &Expr, sign: f64, terms: &mut Vec<(f64, Option<Expr>)>) {
+    let (factor, term) = match expr {
+        Expr::BinOp { op: Operator::Add, lhs, rhs } => {
+            add_terms(lhs, sign, terms);
+            return add_terms(rhs, sign, terms);
+        }
+        Expr::BinOp { op: Operator::Subtract, lhs, rhs } => {
+            add_terms(lhs, sign, terms);
+            return add_terms(rhs, -sign, terms);
+        }
+        Expr::UnaryMinus(operand) => return add_terms(operand, -sign, terms),
+        Expr::BinOp { op: Operator::Multiply, lhs, rhs } => match (number(lhs), number(rhs)) {
+            (Some(factor), _) => (factor, Some((**rhs).clone())),
+            (_, Some(factor)) => (factor, Some((**lhs).clone())),
+            _ => (1.0, Some(expr.clone())),
+        },
+        _ => match number(expr) {
+            Some(value) => (value, None),
+            None => (1.0, Some(expr.clone())),
+        },
+    };
+    match terms.iter_mut().find(|(_, known)| *known == term) {
+        Some((total, _)) => *total += sign * factor,
+        None => terms.push((sign * factor, term)),
+    }
+}
+
+// Rebuilds a product with the powers of each base combined, in the order each base first appears,
+// as `numbers * factors / (numbers * factors)`. None when a number divides by zero.
+fn collect_factors(expr: &Expr) -> Option<Expr> {
+    let mut factors = Factors {
+        numerator: 1.0,
+        denominator: 1.0,
+        powers: Vec::new(),
+    };
+    factors.add(expr, false)?;
+    let Factors {
+        mut numerator,
+        mut denominator,
+        powers,
+    } = factors;
+    if (numerator / denominator).fract() == 0.0 {
+        numerator /= denominator;
+        denominator = 1.0;
+    }
+
+    let mut above = Vec::new();
+    let mut below = Vec::new();
+    for (base, exponent) in powers {
+        match number(&exponent) {
+            Some(0.0) => {}
+            Some(value) if value < 0.0 => below.push(power(base, Expr::Literal(-value))),
+            _ => above.push(power(base, exponent)),
+        }
+    }
+    let product = |number: f64, factors: Vec<Expr>| {
+        let number = (number != 1.0 || factors.is_empty()).then_some(Expr::Literal(number));
+        number
+            .into_iter()
+            .chain(factors)
+            .reduce(|product, factor| Expr::binary(Operator::Multiply, product, factor))
+    };
+    let above = product(numerator, above)?;
+    if denominator == 1.0 && below.is_empty() {
+        return Some(above);
+    }
+    Some(Expr::binary(Operator::Divide, above, product(denominator, below)?))
+}
+
+fn power(base: Expr, exponent: Expr) -> Expr {
+    match number(&exponent) {
+        Some(1.0) => base,
+        _ => Expr::binary(Operator::Power, base, exponent),
+    }
+}
+
+// The factors of a product: numbers multiplied into the numerator or denominator, and each base
+// with the sum of its exponents
+struct Factors {
+    numerator: f64,
+    denominator: f64,
+    powers: Vec<(Expr, Expr)>,
+}
+
+impl Factors {
+    fn add(&mut self, expr: &Expr, inverted: bool) -> Option<()> {
+        let (base, exponent) = match expr {
+            Expr::BinOp { op: Operator::Multiply, lhs, rhs } => {
+                self.add(lhs, inverted)?;
+                return self.add(rhs, inverted);
+            }
+            Expr::BinOp { op: Operator::Divide, lhs, rhs } => {
+                self.add(lhs, inverted)?;
+                return self.add(rhs, !inverted);
+            }
+            Expr::BinOp { op: Operator::Power, lhs, rhs } => ((**lhs).clone(), (**rhs).clone()),
+            _ => match number(expr) {
+                Some(0.0) if inverted => return None,
+                Some(value) if inverted => {
+                    self.denominator *= value;
+                    return Some(());
+                }
+                Some(value) => {
+                    self.numerator *= value;
+                    return Some(());
+                }
+                None => (expr.clone(), Expr::Literal(1.0)),
+            },
+        };
+        let exponent = if inverted { negate(&exponent) } else { exponent };
+        match self.powers.iter_mut().find(|(known, _)| *known == base) {
+            Some((_, total)) => *total = sum(total, &exponent),
+            None => self.powers.push((base, exponent)),
+        }
+        Some(())
+    }
+}
+
+fn negate(expr: &Expr) -> Expr {
+    match number(expr) {
+        Some(value) => Expr::Literal(-value),
+        None => Expr::unary_minus(expr.clone()),
+    }
+}
+
+fn sum(a: &Expr, b: &Expr) -> Expr {
+    match (number(a), number(b)) {
+        (Some(a), Some(b)) => Expr::Literal(a + b),
+        _ => Expr::binary(Operator::Add, a.clone(), b.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simplify(input: &str, options: SimplifyOptions) -> String {
+        Expr::parse(input).unwrap().simplify(&options).to_string()
+    }
+
+    #[test]
+    fn test_rule_groups() {
+        let conservative = SimplifyOptions::conservative();
+        assert_eq!(simplify("x * 1 + 0 * y - (2 * 3 - 6)", conservative), "x");
+        assert_eq!(simplify("--x ^ 1 / 1 + max(1, 4)", conservative), "x + 4");
+        assert_eq!(simplify("x + (1 - 4)", conservative), "x - 3");
+        // Division by zero and random numbers are left for the evaluator
+        assert_eq!(simplify("1 / 0 + rand() * 2 + hex(255)", conservative), "1 / 0 + rand() * 2 + hex(255)");
+        // Conservative settings keep the structure
+        assert_eq!(simplify("2 * x + x", conservative), "2 * x + x");
+        assert_eq!(simplify("x * x", conservative), "x * x");
+
+        let none = SimplifyOptions {
+            identities: false,
+            constant_folding: false,
+            like_terms: false,
+            powers: false,
+            trigonometry: false,
+            logarithms: false,
+        };
+        assert_eq!(simplify("x * 1 + 2 * 3", none), "x * 1 + 2 * 3");
+        let folding = SimplifyOptions { constant_folding: true, ..none };
+        assert_eq!(simplify("x * 1 + 2 * 3", folding), "x * 1 + 6");
+    }
+
+    #[test]
+    fn test_aggressive() {
+        let aggressive = SimplifyOptions::aggressive();
+        assert_eq!(simplify("2 * x + x - 1 + 4", aggressive), "3 * x + 3");
+        assert_eq!(simplify("x - y - x * 2 + y", aggressive), "-x");
+        assert_eq!(simplify("1 + a - 3 * a", aggressive), "1 - 2 * a");
+        assert_eq!(simplify("x - x", aggressive), "0");
+        assert_eq!(simplify("x * x ^ 2 * 3", aggressive), "3 * x ^ 3");
+        assert_eq!(simplify("x / x ^ 3", aggressive), "1 / x ^ 2");
+        assert_eq!(simplify("(x ^ 2) ^ 3 * y / y", aggressive), "x ^ 6");
+        // Powers of powers only combine where that keeps the sign
+        assert_eq!(simplify("(x ^ 2) ^ 0.5", aggressive), "|x|");
+        assert_eq!(simplify("(x ^ 4) ^ 0.25 * 2", aggressive), "2 * |x|");
+        assert_eq!(simplify("(x ^ 3) ^ (1 / 3)", aggressive), "x");
+        assert_eq!(simplify("(x ^ 0.5) ^ 4", aggressive), "x ^ 2");
+        assert_eq!(simplify("(x ^ 0.5) ^ 0.5", aggressive), "x ^ 0.5 ^ 0.5");
+        assert_eq!(simplify("6 * x / 3", aggressive), "2 * x");
+        assert_eq!(simplify("x ^ a * x ^ b", aggressive), "x ^ (a + b)");
+        assert_eq!(simplify("x / 0", aggressive), "x / 0");
+
+        // Simplifying does not change the value
+        let mut ctx = Context::new();
+        ctx.set_variable("x", 1.7);
+        ctx.set_variable("y", -0.6);
+        for input in ["2 * x + x - 1 + 4", "x * x ^ 2 * 3 / y", "(x + y) * (x + y) - 2 * (y + x)", "x ^ 2 / x - x / 2"] {
+            let expr = Expr::parse(input).unwrap();
+            let expected = Evaluator::evaluate_with(&expr, &ctx).unwrap();
+            let simplified = Evaluator::evaluate_with(&expr.simplify(&aggressive), &ctx).unwrap();
+            assert!((expected - simplified).abs() < 1e-12, "{}: {} != {}", input, expected, simplified);
+        }
+    }
+
+    #[test]
+    fn test_trigonometry() {
+        let trigonometry = SimplifyOptions { trigonometry: true, ..SimplifyOptions::conservative() };
+        assert_eq!(simplify("sin(x) ^ 2 + cos(x) ^ 2", trigonometry), "1");
+        assert_eq!(simplify("cos(2 * y) ^ 2 + sin(2 * y) ^ 2 + 1", trigonometry), "2");
+        assert_eq!(simplify("sin(x) ^ 2 + cos(y) ^ 2", trigonometry), "sin(x) ^ 2 + cos(y) ^ 2");
+        assert_eq!(simplify("1 - cos(x) ^ 2", trigonometry), "sin(x) ^ 2");
+        assert_eq!(simplify("sin(-x) + cos(-x) + tan(-(x + 1))", trigonometry), "-sin(x) + cos(x) - tan(x + 1)");
+        assert_eq!(simplify("2 * sin(x) * cos(x)", trigonometry), "sin(2 * x)");
+        assert_eq!(simplify("cos(a) * 2 * sin(a)", trigonometry), "sin(2 * a)");
+        assert_eq!(simplify("cos(t) ^ 2 - sin(t) ^ 2", trigonometry), "cos(2 * t)");
+        assert_eq!(simplify("sin(x) / cos(x)", trigonometry), "tan(x)");
+        assert_eq!(simplify("sin(pi) + cos(pi / 2) + cos(0) + tan(π)", trigonometry), "1");
+        assert_eq!(simplify("cos(pi) + cos(2 * pi) + sin(pi / 2)", trigonometry), "1");
+        assert_eq!(simplify("sin(x) ^ 2 + cos(x) ^ 2", SimplifyOptions::conservative()), "sin(x) ^ 2 + cos(x) ^ 2");
+        // The exact values agree with the built-ins
+        for input in ["sin(pi)", "cos(pi / 2)", "cos(2 * pi)", "tan(pi)", "sin(pi / 2)"] {
+            let exact = Evaluator::evaluate(&Expr::parse(&simplify(input, trigonometry)).unwrap()).unwrap();
+            assert!((Evaluator::evaluate(&Expr::parse(input).unwrap()).unwrap() - exact).abs() < 1e-15, "{}", input);
+        }
+        // Numbers whose sines are not whole stay symbolic
+        assert_eq!(simplify("sin(1) + sqrt(16)", SimplifyOptions::conservative()), "sin(1) + 4");
+    }
+
+    #[test]
+    fn test_logarithms() {
+        let logarithms = SimplifyOptions { logarithms: true, ..SimplifyOptions::conservative() };
+        assert_eq!(simplify("ln(2 * pi)", logarithms), "ln(2) + ln(pi)");
+        assert_eq!(simplify("log10(1000 / √e)", logarithms), "3 - log10(√e)");
+        assert_eq!(simplify("ln(3 ^ x) + log2(2 ^ y)", logarithms), "x * ln(3) + y");
+        assert_eq!(simplify("ln(e ^ x) + ln(exp(y)) + ln(e) + log(1)", logarithms), "x + y + 1");
+        assert_eq!(simplify("e ^ ln(√pi) + exp(ln(5))", logarithms), "√pi + 5");
+        // Only for operands known to be positive: ln(x * y) is defined for x = y = -1
+        assert_eq!(simplify("ln(x * y) + ln(x ^ 2)", logarithms), "ln(x * y) + ln(x ^ 2)");
+        assert_eq!(simplify("e ^ ln(x)", logarithms), "e ^ ln(x)");
+        // log is the built-in base-10 logarithm; with a base given it is left alone
+        assert_eq!(simplify("log(10 ^ x) + log(10) + 10 ^ log(pi)", logarithms), "x + 1 + pi");
+        assert_eq!(simplify("log(10 ^ x, 2)", logarithms), "log(10 ^ x, 2)");
+        for input in ["ln(2 * pi)", "log(pi ^ 3)", "log2(e / 3)", "exp(ln(5))"] {
+            let rewritten = Expr::parse(&simplify(input, logarithms)).unwrap();
+            let difference = Evaluator::evaluate(&Expr::parse(input).unwrap()).unwrap() - Evaluator::evaluate(&rewritten).unwrap();
+            assert!(difference.abs() < 1e-12, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_symbolic_constants() {
+        let conservative = SimplifyOptions::conservative();
+        assert_eq!(simplify("√2 * √2 + √9", conservative), "5");
+        assert_eq!(simplify("(√(x + 1)) ^ 2", conservative), "x + 1");
+        assert_eq!(simplify("2 * pi * (3 - 2) + √8", conservative), "2 * pi + √8");
+        assert_eq!(simplify("e ^ 0 + pi * 0", conservative), "1");
+        assert_eq!(simplify("pi - pi + 2 * e - e", SimplifyOptions::aggressive()), "e");
+        // Only evaluation rounds them
+        let value = Evaluator::evaluate(&Expr::parse("2 * pi + e * 0 - tau").unwrap()).unwrap();
+        assert_eq!(value, 0.0);
+        let mut ctx = Context::new();
+        ctx.set_variable("e", 5.0);
+        assert_eq!(Evaluator::evaluate_with(&Expr::parse("e").unwrap(), &ctx).unwrap(), 5.0);
+    }
+}
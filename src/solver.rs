@@ -0,0 +1,464 @@
+// src/solver.rs
+// Equations: `solve_for` rearranges one to isolate a variable, and `solve_linear` solves systems of
+// linear equations in several unknowns, such as solve([x + y == 3, x - y == 1], [x, y]).
+// Each side of an equation must be linear in the unknowns; anything else that appears in it (other
+// variables, function calls) is evaluated as a constant. Coefficients are kept as exact fractions
+// while they fit, so 1/3 stays a third through the elimination, with a fallback to floating point
+// elimination for a square system whose fractions grow too large.
+use crate::functions::gcd;
+use crate::{matrix, Context, Evaluator, Expr, MathError, Operator, Result, SimplifyOptions, UnaryOperator};
+
+// The variable isolated from `lhs == rhs` (or from an expression equal to zero), as an expression in
+// the other variables: y == 2 * x + 3 gives (y - 3) / 2 for x. The variable must appear once, after
+// collecting like terms, inside operations that can be undone. Even powers and √ are undone by the
+// principal root and square, so x ^ 2 == y gives y ^ 0.5 and not -y ^ 0.5. Built-in functions with
+// an inverse are undone by it, taking principal values: sin(x) == y gives asin(y).
+pub fn solve_for(equation: &Expr, variable: &str) -> Result<Expr> {
+    let (lhs, rhs) = match equation {
+        Expr::BinOp { op: Operator::Equal, lhs, rhs } => ((**lhs).clone(), (**rhs).clone()),
+        _ => (equation.clone(), Expr::literal(0.0)),
+    };
+    let cannot = |reason: String| MathError::InvalidArgument {
+        function: "solve_for".to_string(),
+        reason,
+    };
+    let (lhs, rhs) = match occurrences(&lhs, variable) + occurrences(&rhs, variable) {
+        0 => return Err(cannot(format!("{} does not appear in {}", variable, equation))),
+        1 => (lhs, rhs),
+        _ => (lhs.simplify(&SimplifyOptions::aggressive()), rhs.simplify(&SimplifyOptions::aggressive())),
+    };
+    let (mut target, mut other) = match (occurrences(&lhs, variable), occurrences(&rhs, variable)) {
+        (1, 0) => (lhs, rhs),
+        (0, 1) => (rhs, lhs),
+        _ => return Err(cannot(format!("{} appears more than once in {}", variable, equation))),
+    };
+    let has = |expr: &Expr| occurrences(expr, variable) > 0;
+    // Undo the outermost operation around the variable until only the variable is left
+    loop {
+        other = match target {
+            Expr::Variable(ref name) if name == variable => break,
+            Expr::Group(inner) => {
+                target = *inner;
+                continue;
+            }
+            Expr::UnaryMinus(operand) => {
+                target = *operand;
+                Expr::unary_minus(other)
+            }
+            Expr::Unary { op, operand } => {
+                target = *operand;
+                match op {
+                    UnaryOperator::Percent => Expr::binary(Operator::Multiply, other, Expr::literal(100.0)),
+                    UnaryOperator::Sqrt => Expr::binary(Operator::Power, other, Expr::literal(2.0)),
+                    UnaryOperator::BitNot => Expr::unary(UnaryOperator::BitNot, other),
+                    op => return Err(cannot(format!("{} cannot be undone", op.symbol()))),
+                }
+            }
+            Expr::BinOp { op, lhs, rhs } => {
+                let left = has(&lhs);
+                let (inner, known) = if left { (*lhs, *rhs) } else { (*rhs, *lhs) };
+                target = inner;
+                match (op, left) {
+                    (Operator::Add, _) => Expr::binary(Operator::Subtract, other, known),
+                    (Operator::Subtract, true) => Expr::binary(Operator::Add, other, known),
+                    (Operator::Subtract, false) => Expr::binary(Operator::Subtract, known, other),
+                    (Operator::Multiply, _) => Expr::binary(Operator::Divide, other, known),
+                    (Operator::Divide, true) => Expr::binary(Operator::Multiply, other, known),
+                    (Operator::Divide, false) => Expr::binary(Operator::Divide, known, other),
+                    (Operator::Power, true) => Expr::binary(
+                        Operator::Power,
+                        other,
+                        Expr::binary(Operator::Divide, Expr::literal(1.0), known),
+                    ),
+                    (op, _) => return Err(cannot(format!("{} cannot be undone for {}", op.symbol(), variable))),
+                }
+            }
+            Expr::Function { ref name, ref args } if args.len() == 1 || name == "log" && args.len() == 2 => {
+                let undone = match (name.as_str(), args.get(1)) {
+                    (_, Some(base)) if !has(base) => Expr::binary(Operator::Power, base.clone(), other),
+                    ("exp", None) => Expr::function("ln", vec![other]),
+                    ("ln", None) => Expr::function("exp", vec![other]),
+                    ("log" | "log10", None) => Expr::binary(Operator::Power, Expr::literal(10.0), other),
+                    ("log2", None) => Expr::binary(Operator::Power, Expr::literal(2.0), other),
+                    ("sqrt", None) => Expr::binary(Operator::Power, other, Expr::literal(2.0)),
+                    ("cbrt", None) => Expr::binary(Operator::Power, other, Expr::literal(3.0)),
+                    ("sin" | "cos" | "tan", None) => Expr::function(format!("a{}", name), vec![other]),
+                    ("asin" | "acos" | "atan", None) => Expr::function(&name[1..], vec![other]),
+                    _ => return Err(cannot(format!("{} cannot be undone for {}", name, variable))),
+                };
+                target = args[0].clone();
+                undone
+            }
+            target => return Err(cannot(format!("{} cannot be undone for {}", target, variable))),
+        };
+    }
+    Ok(other.simplify(&SimplifyOptions::conservative()))
+}
+
+// How many times the variable appears in the expression
+fn occurrences(expr: &Expr, variable: &str) -> usize {
+    match expr {
+        Expr::Variable(name) => usize::from(name == variable),
+        _ => expr.children().into_iter().map(|child| occurrences(child, variable)).sum(),
+    }
+}
+
+// Solves the equations for the unknowns, in the order given. Each equation is `lhs == rhs` or an
+// expression equal to zero, with the context's variables and functions as constants.
+pub fn solve_linear(equations: &[Expr], unknowns: &[&str], ctx: &Context) -> Result<Vec<f64>> {
+    solve_linear_by(equations, unknowns, &|expr| Evaluator::evaluate_with(expr, ctx))
+}
+
+// Whether the arguments of solve are a list of equations and a list of unknowns, rather than a
+// matrix and a right-hand side
+pub(crate) fn is_system(args: &[Expr]) -> bool {
+    matches!(
+        args,
+        [Expr::List(equations), Expr::List(unknowns)]
+            if equations.iter().all(|e| matches!(e, Expr::BinOp { op: Operator::Equal, .. }))
+                && unknowns.iter().all(|u| matches!(u, Expr::Variable(_)))
+                && !unknowns.is_empty()
+    )
+}
+
+// Solves with `constant` evaluating the parts of the equations that do not involve the unknowns
+pub(crate) fn solve_linear_by(
+    equations: &[Expr],
+    unknowns: &[&str],
+    constant: &dyn Fn(&Expr) -> Result<f64>,
+) -> Result<Vec<f64>> {
+    let system = System { unknowns, constant };
+    let exact: Option<Vec<Linear<Fraction>>> = equations.iter().map(|e| system.equation(e)).collect::<Result<_>>()?;
+    if let Some(rows) = exact {
+        if let Some(solution) = eliminate(rows, unknowns.len()) {
+            return solution.map(|xs| xs.into_iter().map(Fraction::to_f64).collect());
+        }
+    }
+    let rows: Vec<Linear<f64>> = equations
+        .iter()
+        .map(|e| system.equation(e)?.ok_or_else(|| MathError::InvalidNumber(e.to_string())))
+        .collect::<Result<_>>()?;
+    if rows.len() != unknowns.len() {
+        return Err(invalid("the equations are too large to solve exactly unless there is one per unknown"));
+    }
+    let a = rows.iter().map(|row| row.coefficients.clone()).collect();
+    let b = rows.iter().map(|row| vec![-row.constant]).collect();
+    let x = matrix::solve(a, b).ok_or_else(|| invalid("the equations have no unique solution"))?;
+    Ok(x.into_iter().flatten().collect())
+}
+
+fn invalid(reason: &str) -> MathError {
+    MathError::InvalidArgument {
+        function: "solve".to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+// The numbers elimination works in. Arithmetic returns None when the result is not representable.
+trait Scalar: Copy + PartialEq {
+    const ZERO: Self;
+    const ONE: Self;
+    fn from_f64(x: f64) -> Option<Self>;
+    fn add(self, other: Self) -> Option<Self>;
+    fn mul(self, other: Self) -> Option<Self>;
+    fn div(self, other: Self) -> Option<Self>;
+    fn neg(self) -> Option<Self>;
+
+    fn sub(self, other: Self) -> Option<Self> {
+        self.add(other.neg()?)
+    }
+}
+
+impl Scalar for f64 {
+    const ZERO: f64 = 0.0;
+    const ONE: f64 = 1.0;
+
+    fn from_f64(x: f64) -> Option<f64> {
+        x.is_finite().then_some(x)
+    }
+
+    fn add(self, other: f64) -> Option<f64> {
+        f64::from_f64(self + other)
+    }
+
+    fn mul(self, other: f64) -> Option<f64> {
+        f64::from_f64(self * other)
+    }
+
+    fn div(self, other: f64) -> Option<f64> {
+        f64::from_f64(self / other)
+    }
+
+    fn neg(self) -> Option<f64> {
+        Some(-self)
+    }
+}
+
+// An exact fraction in lowest terms, with a positive denominator
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Fraction {
+    numerator: i128,
+    denominator: i128,
+}
+
+impl Fraction {
+    fn new(numerator: i128, denominator: i128) -> Option<Fraction> {
+        if denominator == 0 {
+            return None;
+        }
+        // The divisor is at most 2^127, which only i128::MIN over itself or 0 reaches
+        let divisor = i128::try_from(gcd(numerator, denominator)).ok()?.max(1);
+        let sign = denominator.signum();
+        Some(Fraction {
+            numerator: (numerator / divisor).checked_mul(sign)?,
+            denominator: (denominator / divisor).checked_mul(sign)?,
+        })
+    }
+
+    fn to_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+}
+
+impl Scalar for Fraction {
+    const ZERO: Fraction = Fraction { numerator: 0, denominator: 1 };
+    const ONE: Fraction = Fraction { numerator: 1, denominator: 1 };
+
+    // The decimal the number displays as, so 0.1 is 1/10
+    fn from_f64(x: f64) -> Option<Fraction> {
+        let (digits, places) = crate::money::decimal(x)?;
+        Fraction::new(digits, 10i128.checked_pow(places)?)
+    }
+
+    fn add(self, other: Fraction) -> Option<Fraction> {
+        let numerator = self
+            .numerator
+            .checked_mul(other.denominator)?
+            .checked_add(other.numerator.checked_mul(self.denominator)?)?;
+        Fraction::new(numerator, self.denominator.checked_mul(other.denominator)?)
+    }
+
+    fn mul(self, other: Fraction) -> Option<Fraction> {
+        Fraction::new(
+            self.numerator.checked_mul(other.numerator)?,
+            self.denominator.checked_mul(other.denominator)?,
+        )
+    }
+
+    fn div(self, other: Fraction) -> Option<Fraction> {
+        Fraction::new(
+            self.numerator.checked_mul(other.denominator)?,
+            self.denominator.checked_mul(other.numerator)?,
+        )
+    }
+
+    fn neg(self) -> Option<Fraction> {
+        Some(Fraction {
+            numerator: self.numerator.checked_neg()?,
+            denominator: self.denominator,
+        })
+    }
+}
+
+// coefficients · unknowns + constant
+#[derive(Debug, Clone)]
+struct Linear<T> {
+    coefficients: Vec<T>,
+    constant: T,
+}
+
+impl<T: Scalar> Linear<T> {
+    fn constant(n: usize, c: T) -> Self {
+        Linear { coefficients: vec![T::ZERO; n], constant: c }
+    }
+
+    fn is_constant(&self) -> bool {
+        self.coefficients.iter().all(|c| *c == T::ZERO)
+    }
+
+    fn combine(&self, other: &Linear<T>, f: impl Fn(T, T) -> Option<T>) -> Option<Linear<T>> {
+        Some(Linear {
+            coefficients: self
+                .coefficients
+                .iter()
+                .zip(&other.coefficients)
+                .map(|(a, b)| f(*a, *b))
+                .collect::<Option<_>>()?,
+            constant: f(self.constant, other.constant)?,
+        })
+    }
+
+    fn map(&self, f: impl Fn(T) -> Option<T>) -> Option<Linear<T>> {
+        Some(Linear {
+            coefficients: self.coefficients.iter().map(|a| f(*a)).collect::<Option<_>>()?,
+            constant: f(self.constant)?,
+        })
+    }
+}
+
+struct System<'a> {
+    unknowns: &'a [&'a str],
+    constant: &'a dyn Fn(&Expr) -> Result<f64>,
+}
+
+impl System<'_> {
+    // An equation as a linear form equal to zero. Ok(None) means the numbers do not fit in T.
+    fn equation<T: Scalar>(&self, equation: &Expr) -> Result<Option<Linear<T>>> {
+        match equation {
+            Expr::BinOp { op: Operator::Equal, lhs, rhs } => {
+                let (Some(lhs), Some(rhs)) = (self.linear::<T>(lhs)?, self.linear::<T>(rhs)?) else {
+                    return Ok(None);
+                };
+                Ok(lhs.combine(&rhs, T::sub))
+            }
+            _ => self.linear(equation),
+        }
+    }
+
+    fn involves_unknowns(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Variable(name) => self.unknowns.contains(&name.as_str()),
+            _ => expr.children().into_iter().any(|child| self.involves_unknowns(child)),
+        }
+    }
+
+    // Arithmetic is followed down to the unknowns, so 1/3 is the fraction and not its nearest f64
+    fn linear<T: Scalar>(&self, expr: &Expr) -> Result<Option<Linear<T>>> {
+        let n = self.unknowns.len();
+        let not_linear = || invalid(&format!("{} is not linear in {}", expr, self.unknowns.join(", ")));
+        Ok(match expr {
+            Expr::Literal(x) => T::from_f64(*x).map(|c| Linear::constant(n, c)),
+            Expr::Variable(name) if self.unknowns.contains(&name.as_str()) => {
+                let mut form = Linear::constant(n, T::ZERO);
+                form.coefficients[self.unknowns.iter().position(|u| u == name).unwrap_or(0)] = T::ONE;
+                Some(form)
+            }
+            Expr::Group(inner) => self.linear(inner)?,
+            Expr::UnaryMinus(operand) => self.linear::<T>(operand)?.and_then(|form| form.map(T::neg)),
+            Expr::BinOp { op, lhs, rhs }
+                if matches!(op, Operator::Add | Operator::Subtract | Operator::Multiply | Operator::Divide) =>
+            {
+                let (Some(left), Some(right)) = (self.linear::<T>(lhs)?, self.linear::<T>(rhs)?) else {
+                    return Ok(None);
+                };
+                match op {
+                    Operator::Add => left.combine(&right, T::add),
+                    Operator::Subtract => left.combine(&right, T::sub),
+                    Operator::Multiply if right.is_constant() => left.map(|a| a.mul(right.constant)),
+                    Operator::Multiply if left.is_constant() => right.map(|b| left.constant.mul(b)),
+                    Operator::Divide if right.is_constant() && right.constant == T::ZERO => {
+                        return Err(MathError::DivisionByZero)
+                    }
+                    Operator::Divide if right.is_constant() => left.map(|a| a.div(right.constant)),
+                    _ => return Err(not_linear()),
+                }
+            }
+            _ if self.involves_unknowns(expr) => return Err(not_linear()),
+            _ => T::from_f64((self.constant)(expr)?).map(|c| Linear::constant(n, c)),
+        })
+    }
+}
+
+// Gauss-Jordan elimination on the equations coefficients · x + constant = 0. Returns None when the
+// numbers outgrow T, and an error unless there is exactly one solution.
+fn eliminate<T: Scalar>(rows: Vec<Linear<T>>, n: usize) -> Option<Result<Vec<T>>> {
+    let mut rows: Vec<Vec<T>> = rows
+        .into_iter()
+        .map(|row| Some(row.coefficients.into_iter().chain([row.constant.neg()?]).collect()))
+        .collect::<Option<_>>()?;
+    let mut rank = 0;
+    for col in 0..n {
+        let Some(pivot) = (rank..rows.len()).find(|&i| rows[i][col] != T::ZERO) else {
+            continue;
+        };
+        rows.swap(rank, pivot);
+        let p = rows[rank][col];
+        rows[rank] = rows[rank].iter().map(|x| x.div(p)).collect::<Option<_>>()?;
+        let pivot_row = rows[rank].clone();
+        for (i, row) in rows.iter_mut().enumerate() {
+            let factor = row[col];
+            if i != rank && factor != T::ZERO {
+                for (x, p) in row.iter_mut().zip(&pivot_row) {
+                    *x = x.sub(factor.mul(*p)?)?;
+                }
+            }
+        }
+        rank += 1;
+    }
+    // A leftover row 0 = c with c nonzero contradicts itself
+    if rows[rank..].iter().any(|row| row[n] != T::ZERO) {
+        return Some(Err(invalid("the equations are inconsistent")));
+    }
+    if rank < n {
+        return Some(Err(invalid("the equations do not determine every unknown")));
+    }
+    Some(Ok(rows.into_iter().take(n).map(|row| row[n]).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(equations: &[&str], unknowns: &[&str]) -> Result<Vec<f64>> {
+        let equations: Vec<Expr> = equations.iter().map(|e| Expr::parse(e).unwrap()).collect();
+        solve_linear(&equations, unknowns, &Context::new())
+    }
+
+    #[test]
+    fn test_solve_for() {
+        let solve_for = |input: &str| super::solve_for(&Expr::parse(input).unwrap(), "x").map(|e| e.to_string());
+        assert_eq!(solve_for("y == 2 * x + 3").unwrap(), "(y - 3) / 2");
+        assert_eq!(solve_for("10 - x / 4 == y").unwrap(), "(10 - y) * 4");
+        assert_eq!(solve_for("√(x - 1) == r").unwrap(), "r ^ 2 + 1");
+        assert_eq!(solve_for("(a / x) ^ 3 == 8").unwrap(), "a / 2");
+        assert_eq!(solve_for("x + x == 6").unwrap(), "3");
+        assert!(solve_for("x * x + x == 6").is_err());
+        assert_eq!(
+            solve_for("2 ^ x == y").unwrap_err().to_string(),
+            "Invalid argument to solve_for: ^ cannot be undone for x"
+        );
+        // Built-in functions with an inverse
+        assert_eq!(solve_for("exp(2 * x) == y").unwrap(), "ln(y) / 2");
+        assert_eq!(solve_for("ln(x - 1) == 3").unwrap(), "exp(3) + 1");
+        assert_eq!(solve_for("log(x) == y").unwrap(), "10 ^ y");
+        assert_eq!(solve_for("log(x, 2) == 8").unwrap(), "256");
+        assert_eq!(solve_for("sin(x / 2) == y").unwrap(), "asin(y) * 2");
+        assert_eq!(solve_for("atan(x) == 1").unwrap(), "tan(1)");
+        assert_eq!(
+            solve_for("log(8, x) == 3").unwrap_err().to_string(),
+            "Invalid argument to solve_for: log cannot be undone for x"
+        );
+        assert!(solve_for("sinh(x) == 1").is_err());
+    }
+
+    #[test]
+    fn test_linear_systems() {
+        assert_eq!(solve(&["x + y == 3", "x - y == 1"], &["x", "y"]).unwrap(), vec![2.0, 1.0]);
+        assert_eq!(solve(&["3 * x == 1"], &["x"]).unwrap(), vec![1.0 / 3.0]);
+        assert_eq!(
+            solve(&["x + y + z == 6", "2 * y - z == 1", "(x - z) / 2 == -1", "x + y == 3"], &["x", "y", "z"]).unwrap(),
+            vec![1.0, 2.0, 3.0]
+        );
+        assert_eq!(solve(&["0.1 * x + 0.2 * y == 0.3", "x == y"], &["x", "y"]).unwrap(), vec![1.0, 1.0]);
+        assert!(solve(&["x + y == 1", "2 * x + 2 * y == 2"], &["x", "y"]).is_err());
+        assert!(solve(&["x + y == 1", "x + y == 2"], &["x", "y"]).is_err());
+        assert_eq!(
+            solve(&["x * y == 1"], &["x", "y"]).unwrap_err().to_string(),
+            "Invalid argument to solve: x * y is not linear in x, y"
+        );
+        // Fractions whose common divisor does not fit are not representable
+        assert_eq!(Fraction::new(i128::MIN, -2), Some(Fraction { numerator: 1 << 126, denominator: 1 }));
+        assert_eq!(Fraction::new(i128::MIN, i128::MIN), None);
+        assert_eq!(Fraction::new(i128::MIN, 0), None);
+    }
+
+    #[test]
+    fn test_solve_in_expressions() {
+        let mut ctx = Context::new();
+        ctx.set_variable("a", 2.0);
+        let value =
+            |input: &str| Evaluator::evaluate_value_with(&Expr::parse(input).unwrap(), &ctx).unwrap().to_string();
+        assert_eq!(value("solve([a * x + y == 5, x - y == max(1, 0)], [x, y])"), "[2, 1]");
+        assert_eq!(value("solve([[2, 0], [0, 4]], [2, 4])"), "[1, 1]");
+    }
+}
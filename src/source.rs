@@ -0,0 +1,117 @@
+// src/source.rs
+// Files of formulas: statements one or more per line (separated by ';'), with '#' line comments
+// and '/* ... */' block comments. This is what the command-line tool's --watch mode, language
+// server and `mathexpr fmt` read. Formula libraries (see `loader`) add `include "file"` lines and
+// function definitions such as `f(x) = x ^ 2`, which are formatted too.
+use crate::loader::parse_line;
+use crate::{Grammar, MathError, Result};
+
+// Formats a file of formulas with the default syntax (see `format_source_with`)
+pub fn format_source(source: &str) -> Result<String> {
+    format_source_with(source, &Grammar::default())
+}
+
+// Formats a file of formulas the way expressions display: one space around binary operators and
+// after commas, only the parentheses that precedence needs, and numbers written plainly (1.50 as
+// 1.5, .5 as 0.5, 1.50e03 as 1.5e3). Comments are kept, after the code with one space before
+// them; lines with block comments are kept as they are. Trailing whitespace and trailing blank
+// lines are removed. Fails on the first line that does not parse, leaving nothing formatted.
+pub fn format_source_with(source: &str, grammar: &Grammar) -> Result<String> {
+    let mut formatted = String::new();
+    let mut in_comment = false; // Inside a block comment that started on an earlier line
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim_end();
+        if in_comment || line.contains("/*") {
+            formatted.push_str(line);
+            formatted.push('\n');
+            in_comment = ends_in_comment(line, in_comment);
+            continue;
+        }
+
+        let (code, comment) = match line.find('#') {
+            Some(start) => (&line[..start], Some(&line[start..])),
+            None => (line, None),
+        };
+        let definitions = parse_line(code, grammar).map_err(|error| MathError::Line {
+            line: number + 1,
+            error: Box::new(error),
+        })?;
+        let code: Vec<String> = definitions.iter().map(ToString::to_string).collect();
+        let code = code.join("; ");
+        match comment {
+            Some(comment) if !code.is_empty() => formatted.push_str(&format!("{} {}", code, comment)),
+            Some(comment) => formatted.push_str(comment),
+            None => formatted.push_str(&code),
+        }
+        formatted.push('\n');
+    }
+    while formatted.ends_with("\n\n") {
+        formatted.pop();
+    }
+    if formatted == "\n" {
+        formatted.clear();
+    }
+    Ok(formatted)
+}
+
+// Whether a block comment is still open at the end of the line, given whether one was open at
+// its start. A '#' outside block comments ends the line's code, and with it any '/*'.
+pub(crate) fn ends_in_comment(line: &str, mut open: bool) -> bool {
+    let mut rest = line;
+    loop {
+        if open {
+            match rest.find("*/") {
+                Some(end) => {
+                    rest = &rest[end + 2..];
+                    open = false;
+                }
+                None => return true,
+            }
+        } else {
+            match (rest.find("/*"), rest.find('#')) {
+                (Some(start), hash) if hash.is_none_or(|hash| start < hash) => {
+                    rest = &rest[start + 2..];
+                    open = true;
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_source() {
+        let source = "  rate=0.050   # yearly\n\ntotal = ((price)*(1+rate));  total*2\n# Done\n/* kept  as\n   it  is */\nx=1.50e03+.5\n\n\n";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted,
+            "rate = 0.05 # yearly\n\ntotal = price * (1 + rate); total * 2\n# Done\n/* kept  as\n   it  is */\nx = 1.5e3 + 0.5\n"
+        );
+        // Formatting again changes nothing
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        // Formula libraries
+        assert_eq!(
+            format_source("include   \"common.mexpr\"\nnet( gross )=gross*(1-rate) ;fee=2\n").unwrap(),
+            "include \"common.mexpr\"\nnet(gross) = gross * (1 - rate); fee = 2\n"
+        );
+        assert_eq!(format_source("\n\n").unwrap(), "");
+
+        let error = format_source("a = 1\nb = (2\n").unwrap_err();
+        assert!(matches!(error, MathError::Line { line: 2, .. }));
+        assert_eq!(error.to_string(), "line 2: Unmatched '(' (1 opened, 0 closed)");
+        assert_eq!(error.span(), Some(crate::Span::new(4, 5)));
+    }
+
+    #[test]
+    fn test_block_comments() {
+        assert!(ends_in_comment("x /* open", false));
+        assert!(!ends_in_comment("x /* closed */ + 1", false));
+        assert!(!ends_in_comment("still */ x", true));
+        assert!(!ends_in_comment("x # not /* a block comment", false));
+        assert!(ends_in_comment("*/ x /* again", true));
+    }
+}
@@ -0,0 +1,159 @@
+// src/template.rs
+// Expression templates for report and invoice generators: an expression with named placeholders,
+// such as `price * (1 + {rate})`, parsed once and filled in with numbers or sub-expressions for
+// each use. Placeholders are kept in the tree as variables named with their braces (`{rate}`), so
+// they can never be confused with variables of the context, and display as they were written.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::token::{ends_operand, paren_depth_after};
+use crate::{Context, Evaluator, Expr, Grammar, MathError, Parser, Result, Span, Token, Tokenizer, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    expr: Expr,
+    placeholders: Vec<String>, // Without braces, in order of first appearance
+}
+
+impl Template {
+    // Parses a template with the default syntax
+    pub fn new(source: &str) -> Result<Self> {
+        Self::with_grammar(source, &Grammar::default())
+    }
+
+    // Parses a template with the grammar. A placeholder stands anywhere an operand can.
+    pub fn with_grammar(source: &str, grammar: &Grammar) -> Result<Self> {
+        let mut tokens = Vec::new();
+        let mut placeholders = Vec::new();
+        let mut depth = 0;
+        let mut rest = 0; // Where the text after the last placeholder starts
+        loop {
+            let (text_end, placeholder) = match source[rest..].find('{') {
+                Some(open) => {
+                    let open = rest + open;
+                    let close = source[open..].find('}').map(|close| open + close).ok_or_else(|| {
+                        MathError::InvalidExpression(format!("Unclosed placeholder: {}", &source[open..]))
+                    })?;
+                    (open, Some(open..close + 1))
+                }
+                None => (source.len(), None),
+            };
+
+            // A placeholder is an operand, like the identifier it becomes
+            let after_operand = tokens.last().is_some_and(|(token, _)| ends_operand(token));
+            let mut tokenizer =
+                Tokenizer::resume(&source[rest..text_end], grammar.tokenizer_options().clone(), depth, after_operand);
+            while let Some((token, span)) = tokenizer.next_spanned()? {
+                depth = paren_depth_after(depth, &token);
+                tokens.push((token, Span::new(span.start + rest, span.end + rest)));
+            }
+
+            let Some(placeholder) = placeholder else { break };
+            let name = source[placeholder.start + 1..placeholder.end - 1].trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                let text = &source[placeholder.clone()];
+                return Err(MathError::InvalidExpression(format!("Invalid placeholder: {}", text)));
+            }
+            if !placeholders.iter().any(|known| known == name) {
+                placeholders.push(name.to_string());
+            }
+            let span = Span::new(placeholder.start, placeholder.end);
+            tokens.push((Token::Identifier(format!("{{{}}}", name)), span));
+            rest = placeholder.end;
+        }
+        let expr = Parser::with_spans(tokens, *grammar.parser_options()).parse()?;
+        Ok(Self { expr, placeholders })
+    }
+
+    // The names of the placeholders, without braces, in the order they first appear
+    pub fn placeholders(&self) -> &[String] {
+        &self.placeholders
+    }
+
+    // The parsed template, with each placeholder as a variable named `{name}`
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    // The expression with every placeholder replaced by its value: a number, or an expression that
+    // keeps its grouping wherever it lands (filling `{x}` in `2 * {x}` with `a + b` gives
+    // `2 * (a + b)`). Every placeholder must be filled, and only placeholders.
+    pub fn fill<K, V>(&self, values: impl IntoIterator<Item = (K, V)>) -> Result<Expr>
+    where
+        K: AsRef<str>,
+        V: Into<Expr>,
+    {
+        let mut filled = HashMap::new();
+        for (name, value) in values {
+            let name = name.as_ref();
+            if !self.placeholders.iter().any(|known| known == name) {
+                return Err(MathError::UnknownPlaceholder(name.to_string()));
+            }
+            filled.insert(format!("{{{}}}", name), value.into());
+        }
+        if let Some(missing) = self.placeholders.iter().find(|name| !filled.contains_key(&format!("{{{}}}", name))) {
+            return Err(MathError::UnfilledPlaceholder(missing.clone()));
+        }
+        Ok(substitute(&self.expr, &filled))
+    }
+
+    // Fills the placeholders and evaluates the result against the context
+    pub fn evaluate<K, V>(&self, values: impl IntoIterator<Item = (K, V)>, ctx: &Context) -> Result<Value>
+    where
+        K: AsRef<str>,
+        V: Into<Expr>,
+    {
+        Evaluator::evaluate_value_with(&self.fill(values)?, ctx)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.expr, f)
+    }
+}
+
+// The expression with the variables named in `values` replaced
+fn substitute(expr: &Expr, values: &HashMap<String, Expr>) -> Expr {
+    match expr {
+        Expr::Variable(name) => values.get(name).cloned().unwrap_or_else(|| expr.clone()),
+        _ => expr.map_children(|child| substitute(child, values)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let template = Template::new("price * (1 + {rate}) - {discount} * {rate}").unwrap();
+        assert_eq!(template.placeholders(), ["rate", "discount"]);
+        assert_eq!(template.to_string(), "price * (1 + {rate}) - {discount} * {rate}");
+
+        let mut ctx = Context::new();
+        ctx.set_variable("price", 100.0);
+        let values = [("rate", Expr::from(0.5)), ("discount", Expr::parse("price / 10").unwrap())];
+        assert_eq!(template.evaluate(values.clone(), &ctx).unwrap(), Value::Number(145.0));
+        // Sub-expressions keep their grouping
+        let filled = template.fill([("rate", Expr::parse("a + b").unwrap()), ("discount", 2.0.into())]).unwrap();
+        assert_eq!(filled.to_string(), "price * (1 + (a + b)) - 2 * (a + b)");
+
+        assert!(matches!(template.fill([("rate", 1.0)]), Err(MathError::UnfilledPlaceholder(name)) if name == "discount"));
+        let extra = template.fill([("rate", 1.0), ("discount", 1.0), ("tax", 1.0)]);
+        assert_eq!(extra.unwrap_err().to_string(), "Template has no placeholder {tax}");
+    }
+
+    #[test]
+    fn test_placeholder_syntax() {
+        // Placeholders are operands wherever they appear, and may be spaced inside their braces
+        let template = Template::new("-{ x }^2 + max({x}, [{y}][0])").unwrap();
+        assert_eq!(template.evaluate([("x", 3.0), ("y", 4.0)], &Context::new()).unwrap(), Value::Number(13.0));
+
+        assert!(Template::new("1 + {rate").is_err());
+        assert!(Template::new("1 + {}").is_err());
+        assert!(Template::new("1 + {a b}").is_err());
+        let error = Template::new("1 + {x} {y}").unwrap_err();
+        assert_eq!(error.span(), Some(Span::new(8, 11)));
+    }
+}
@@ -0,0 +1,143 @@
+// src/testing.rs
+// Helpers for crates that embed the evaluator to test their integrations: comparing results
+// within a tolerance, printing trees in a stable form for snapshot tests, and checking that
+// expressions display as text that parses back to them.
+use std::fmt::Write;
+
+use crate::duration::format_duration;
+use crate::{Context, Evaluator, Expr};
+
+// Asserts that an input evaluates to a number within `epsilon` of the expected one (1e-9 when
+// not given), against a new context or the one given:
+//
+//     assert_eval_eq!("0.1 + 0.2", 0.3);
+//     assert_eval_eq!("rate * 100", 5.0, 1e-12, &ctx);
+#[macro_export]
+macro_rules! assert_eval_eq {
+    ($input:expr, $expected:expr $(,)?) => {
+        $crate::assert_eval_eq!($input, $expected, 1e-9)
+    };
+    ($input:expr, $expected:expr, $epsilon:expr $(,)?) => {
+        $crate::testing::assert_eval_eq($input, $expected, $epsilon, &$crate::Context::new())
+    };
+    ($input:expr, $expected:expr, $epsilon:expr, $ctx:expr $(,)?) => {
+        $crate::testing::assert_eval_eq($input, $expected, $epsilon, $ctx)
+    };
+}
+
+// Whether the input evaluates within `epsilon` of the expected number, or why not. Infinities
+// match themselves, and NaN matches NaN.
+pub fn check_eval(input: &str, expected: f64, epsilon: f64, ctx: &Context) -> Result<(), String> {
+    let expr = Expr::parse(input).map_err(|e| format!("`{}` does not parse: {}", input, e))?;
+    let actual = Evaluator::evaluate_with(&expr, ctx).map_err(|e| format!("`{}` does not evaluate: {}", input, e))?;
+    if actual == expected || (actual - expected).abs() <= epsilon || (actual.is_nan() && expected.is_nan()) {
+        Ok(())
+    } else {
+        Err(format!("`{}` evaluates to {}, expected {} (within {})", input, actual, expected, epsilon))
+    }
+}
+
+// Panics unless the input evaluates within `epsilon` of the expected number; what
+// `assert_eval_eq!` calls
+#[track_caller]
+pub fn assert_eval_eq(input: &str, expected: f64, epsilon: f64, ctx: &Context) {
+    if let Err(message) = check_eval(input, expected, epsilon, ctx) {
+        panic!("{}", message);
+    }
+}
+
+// Whether the input parses, displays, and parses back to the same tree, displaying the same way
+// again, or why not
+pub fn check_round_trip(input: &str) -> Result<(), String> {
+    let expr = Expr::parse(input).map_err(|e| format!("`{}` does not parse: {}", input, e))?;
+    let shown = expr.to_string();
+    let reparsed = Expr::parse(&shown).map_err(|e| format!("`{}` displays as `{}`, which does not parse: {}", input, shown, e))?;
+    if reparsed != expr {
+        return Err(format!(
+            "`{}` displays as `{}`, which parses to a different tree:\n{}\ninstead of:\n{}",
+            input,
+            shown,
+            ast_snapshot(&reparsed),
+            ast_snapshot(&expr)
+        ));
+    }
+    if reparsed.to_string() != shown {
+        return Err(format!("`{}` displays as `{}`, then as `{}`", input, shown, reparsed));
+    }
+    Ok(())
+}
+
+// Panics unless the input passes `check_round_trip`, showing both trees when they differ
+#[track_caller]
+pub fn assert_round_trip(input: &str) {
+    if let Err(message) = check_round_trip(input) {
+        panic!("{}", message);
+    }
+}
+
+// The tree as indented lines, one node per line with its children two spaces further in, for
+// comparing with stored snapshots. The format only changes when the tree does.
+//
+//     BinOp(+)
+//       Literal(1)
+//       Function(max)
+//         Variable(x)
+//         Literal(2)
+pub fn ast_snapshot(expr: &Expr) -> String {
+    let mut snapshot = String::new();
+    write_node(expr, 0, &mut snapshot);
+    snapshot
+}
+
+fn write_node(expr: &Expr, indent: usize, out: &mut String) {
+    let label = match expr {
+        Expr::Literal(value) => format!("Literal({})", value),
+        Expr::Scientific { base, exponent } => format!("Scientific({}e{})", base, exponent),
+        Expr::Duration(seconds) => format!("Duration({})", format_duration(*seconds)),
+        Expr::Money(money) => format!("Money({})", money),
+        Expr::Text(text) => format!("Text({:?})", text),
+        Expr::BinOp { op, .. } => format!("BinOp({})", op.symbol()),
+        Expr::UnaryMinus(_) => "UnaryMinus".to_string(),
+        Expr::Unary { op, .. } => format!("Unary({})", op.symbol()),
+        Expr::Variable(name) => format!("Variable({})", name),
+        Expr::Function { name, .. } => format!("Function({})", name),
+        Expr::List(_) => "List".to_string(),
+        Expr::Index { .. } => "Index".to_string(),
+        Expr::Group(_) => "Group".to_string(),
+        Expr::Error => "Error".to_string(),
+    };
+    let _ = writeln!(out, "{:indent$}{}", "", label, indent = indent);
+    for child in expr.children() {
+        write_node(child, indent + 2, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_eval_eq() {
+        let mut ctx = Context::new();
+        ctx.set_variable("rate", 0.05);
+        crate::assert_eval_eq!("0.1 + 0.2", 0.3);
+        crate::assert_eval_eq!("1 / 3", 0.333, 1e-3);
+        crate::assert_eval_eq!("rate * 100", 5.0, 1e-12, &ctx);
+
+        let check = |input: &str, expected: f64| check_eval(input, expected, 1e-9, &ctx).unwrap_err();
+        assert_eq!(check("1 + 1", 3.0), "`1 + 1` evaluates to 2, expected 3 (within 0.000000001)");
+        assert_eq!(check("1 +", 1.0), "`1 +` does not parse: Invalid operator: Unexpected end of input");
+        assert_eq!(check("tax", 1.0), "`tax` does not evaluate: Unknown variable: tax");
+    }
+
+    #[test]
+    fn test_round_trip_and_snapshot() {
+        assert_round_trip("max(x, 2) - -(1 + y) ^ 2");
+        assert_round_trip("1.5e3 + [1, 2][0]");
+        assert_eq!(
+            ast_snapshot(&Expr::parse("1 + max(x, 2)").unwrap()),
+            "BinOp(+)\n  Literal(1)\n  Function(max)\n    Variable(x)\n    Literal(2)\n"
+        );
+        assert_eq!(check_round_trip("1 +").unwrap_err(), "`1 +` does not parse: Invalid operator: Unexpected end of input");
+    }
+}
@@ -2,47 +2,188 @@
 
 use std::fmt;
 mod tokenizer;
-pub use tokenizer::Tokenizer;
+pub use tokenizer::{highlight, PercentSign, Tokenizer, TokenizerOptions};
+pub(crate) use tokenizer::{ends_operand, paren_depth_after};
 
-use crate::Expr;
+use crate::duration::format_duration;
+use crate::expr::{Expr, Statement};
+use crate::format::pad;
+use crate::operators::{is_word, Associativity, CustomOperator, CustomUnaryOperator, Fixity};
+
+// A byte range of the input, such as where a token was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize, // Exclusive
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    // The smallest span covering both
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
 
 // Token definition 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Token {
     Number(f64),
     Operator(Operator),
     LParen,
     RParen,
+    LBracket, // Opens a list literal or an index
+    RBracket,
+    Comma,
+    Semicolon, // Separates statements
+    Assign,    // '=' in an assignment statement
+    Identifier(String),
+    Unary(UnaryOperator),
+    Percent,
+    Superscript(i32), // A superscript exponent such as ² or ⁻¹
     Scientific { base: f64, exponent: i32 },
+    Duration(f64), // A duration literal such as 1h30m, in seconds
+    Text(String),  // A quoted name such as "km", without the quotes
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Operator {
     Add,
     Subtract,
     Multiply,
     Divide,
     Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Modulo,
+    Less,         // <
+    LessEqual,    // <=
+    Greater,      // >
+    GreaterEqual, // >=
+    Equal,        // ==
+    NotEqual,     // !=
+    And,          // && or `and`
+    Or,           // || or `or`
+    Custom(CustomOperator), // Registered by the host application in an OperatorTable
+}
+
+// Unary operators other than unary minus
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOperator {
+    BitNot,  // ~x
+    Percent, // x% (x / 100)
+    Sqrt,    // √x
+    Abs,     // |x|
+    Not,     // !x or `not x`
+    Custom(CustomUnaryOperator), // Registered by the host application in an OperatorTable
+}
+
+impl UnaryOperator {
+    // Pure function to get the operator symbol
+    pub fn symbol(&self) -> &str {
+        match self {
+            UnaryOperator::BitNot => "~",
+            UnaryOperator::Percent => "%",
+            UnaryOperator::Sqrt => "√",
+            UnaryOperator::Abs => "|",
+            UnaryOperator::Not => "!",
+            UnaryOperator::Custom(custom) => &custom.symbol,
+        }
+    }
+
+    // Whether the operator is written after its operand
+    pub fn is_postfix(&self) -> bool {
+        match self {
+            UnaryOperator::Percent => true,
+            UnaryOperator::Custom(custom) => custom.fixity == Fixity::Postfix,
+            _ => false,
+        }
+    }
 }
 
 impl Operator {
     // Pure function to get operator precedence
     pub fn precedence(&self) -> u8 {
         match self {
-            Operator:: Add | Operator::Subtract => 1,
-            Operator::Multiply | Operator::Divide => 2,
-            Operator::Power => 3,
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Less
+            | Operator::LessEqual
+            | Operator::Greater
+            | Operator::GreaterEqual
+            | Operator::Equal
+            | Operator::NotEqual => 3,
+            Operator::BitOr => 4,
+            Operator::BitXor => 5,
+            Operator::BitAnd => 6,
+            Operator::ShiftLeft | Operator::ShiftRight => 7,
+            Operator:: Add | Operator::Subtract => 8,
+            Operator::Multiply | Operator::Divide | Operator::Modulo => 9,
+            Operator::Power => 10,
+            Operator::Custom(custom) => custom.precedence,
+        }
+    }
+
+    // Whether this is one of <, <=, >, >=, == and !=
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Operator::Less
+                | Operator::LessEqual
+                | Operator::Greater
+                | Operator::GreaterEqual
+                | Operator::Equal
+                | Operator::NotEqual
+        )
+    }
+
+    // How chains of operators with the same precedence group. All built-ins are left-associative.
+    pub fn associativity(&self) -> Associativity {
+        match self {
+            Operator::Custom(custom) => custom.associativity,
+            _ => Associativity::Left,
         }
     }
 
     // Pure function to get operato symbol
-    pub fn symbol(&self) -> char {
+    pub fn symbol(&self) -> &str {
         match self {
-            Operator::Add => '+',
-            Operator::Subtract => '-',
-            Operator::Multiply => '*',
-            Operator::Divide => '/',
-            Operator::Power => '^',
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Power => "^",
+            Operator::BitAnd => "&",
+            Operator::BitOr => "|",
+            Operator::BitXor => "xor",
+            Operator::ShiftLeft => "<<",
+            Operator::ShiftRight => ">>",
+            Operator::Modulo => "%",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Custom(custom) => &custom.symbol,
         }
     }
 }
@@ -54,39 +195,267 @@ impl fmt::Display for Token {
             Token::Operator(op) => write!(f, "{}", op.symbol()),
             Token::LParen => write!(f, "("),
             Token::RParen => write!(f, ")"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+            Token::Comma => write!(f, ","),
+            Token::Semicolon => write!(f, ";"),
+            Token::Assign => write!(f, "="),
+            Token::Identifier(name) => write!(f, "{}", name),
+            Token::Unary(op) => write!(f, "{}", op.symbol()),
+            Token::Percent => write!(f, "%"),
+            Token::Superscript(exponent) => {
+                const DIGITS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+                if *exponent < 0 {
+                    write!(f, "⁻")?;
+                }
+                for digit in exponent.unsigned_abs().to_string().bytes() {
+                    write!(f, "{}", DIGITS[(digit - b'0') as usize])?;
+                }
+                Ok(())
+            }
             Token::Scientific { base, exponent } => write!(f, "{}e{}", base, exponent),
+            Token::Duration(seconds) => write!(f, "{}", format_duration(*seconds)),
+            Token::Text(text) => write!(f, "\"{}\"", text),
         }
     }
 }
 
+// What kind of source text a token is, for syntax highlighting (see `highlight`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenClass {
+    Number,     // Including scientific notation and superscript exponents
+    Operator,   // Including word operators (and, not, ...), '%', '=' and the ',' and ';' separators
+    Paren,      // Parentheses and brackets
+    Identifier, // Variable and function names, and quoted names
+    Error,      // Text the tokenizer rejects
+}
+
+impl Token {
+    pub fn class(&self) -> TokenClass {
+        match self {
+            Token::Number(_) | Token::Scientific { .. } | Token::Superscript(_) | Token::Duration(_) => TokenClass::Number,
+            Token::LParen | Token::RParen | Token::LBracket | Token::RBracket => TokenClass::Paren,
+            Token::Identifier(_) | Token::Text(_) => TokenClass::Identifier,
+            Token::Operator(_)
+            | Token::Unary(_)
+            | Token::Percent
+            | Token::Assign
+            | Token::Comma
+            | Token::Semicolon => TokenClass::Operator,
+        }
+    }
+
+    // A displayable view of a token sequence (see `Tokens`)
+    pub fn display_slice(tokens: &[Token]) -> Tokens<'_> {
+        Tokens(tokens)
+    }
+}
+
+// Displays a token sequence as readable source, such as the remaining input quoted in an error
+// message. Spacing is reconstructed, so "2*(x+1)" shows as "2 * (x + 1)".
+#[derive(Debug, Clone, Copy)]
+pub struct Tokens<'a>(pub &'a [Token]);
+
+impl fmt::Display for Tokens<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rendered = String::new();
+        let mut after_operand = false;
+        let mut glue_next = true; // No space before the next token
+        let mut open_bars = 0usize;
+        for token in self.0 {
+            // (no space before, no space after, ends an operand)
+            let (glue_before, glue_after, operand) = match token {
+                Token::Number(_) | Token::Scientific { .. } | Token::Duration(_) | Token::Identifier(_) | Token::Text(_) => {
+                    (false, false, true)
+                }
+                // A call, index or implicit multiplication follows its operand directly
+                Token::LParen | Token::LBracket => (after_operand, true, false),
+                Token::RParen | Token::RBracket | Token::Percent | Token::Superscript(_) => (true, false, true),
+                Token::Comma | Token::Semicolon => (true, false, false),
+                Token::Assign => (false, false, false),
+                Token::Unary(op) if op.is_postfix() => (true, false, true),
+                Token::Unary(op) => (false, !is_word(op.symbol()), false),
+                // Bars in operand position open an absolute value, and close one after an operand
+                Token::Operator(Operator::BitOr) if !after_operand => {
+                    open_bars += 1;
+                    (false, true, false)
+                }
+                Token::Operator(Operator::Or) if !after_operand => {
+                    open_bars += 2;
+                    (false, true, false)
+                }
+                Token::Operator(Operator::BitOr) if open_bars >= 1 => {
+                    open_bars -= 1;
+                    (true, false, true)
+                }
+                Token::Operator(Operator::Or) if open_bars >= 2 => {
+                    open_bars -= 2;
+                    (true, false, true)
+                }
+                Token::Operator(Operator::Add | Operator::Subtract) if !after_operand => (false, true, false),
+                Token::Operator(_) => (false, false, false),
+            };
+            if !glue_next && !glue_before {
+                rendered.push(' ');
+            }
+            rendered.push_str(&token.to_string());
+            glue_next = glue_after;
+            after_operand = operand;
+        }
+        pad(f, &rendered)
+    }
+}
+
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::Expr(expr) => fmt::Display::fmt(expr, f),
+            Statement::Assign { name, value } => {
+                let style = ExprStyle::of(f);
+                pad(f, &format!("{} = {}", name, style.apply(value)))
+            }
+        }
+    }
+}
+
+// How to display an expression: `{:#}` parenthesizes every operation, a precision (`{:.2}`)
+// applies to every number, and a width pads the whole expression
+#[derive(Debug, Clone, Copy)]
+struct ExprStyle {
+    parenthesize: bool,
+    precision: Option<usize>,
+}
+
+impl ExprStyle {
+    fn of(f: &fmt::Formatter) -> Self {
+        Self {
+            parenthesize: f.alternate(),
+            precision: f.precision(),
+        }
+    }
+
+    fn apply(self, expr: &Expr) -> Styled<'_> {
+        Styled(expr, self)
+    }
+}
+
+struct Styled<'a>(&'a Expr, ExprStyle);
+
+impl fmt::Display for Styled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write_styled(f, self.1)
+    }
+}
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let style = ExprStyle::of(f);
+        if f.width().is_some() {
+            pad(f, &style.apply(self).to_string())
+        } else {
+            self.write_styled(f, style)
+        }
+    }
+}
+
+impl Expr {
+    fn write_styled(&self, f: &mut fmt::Formatter, style: ExprStyle) -> fmt::Result {
+        let s = |expr| style.apply(expr);
         match self {
-            Expr::Literal(value) => write!(f, "{}", value),
-            Expr::Scientific { base, exponent } => write!(f, "{}e{}", base, exponent),
-            Expr::UnaryMinus(expr) => write!(f, "-({})", expr),
+            Expr::Literal(value) => write_number(f, *value, style.precision),
+            Expr::Scientific { base, exponent } => {
+                write_number(f, *base, style.precision)?;
+                write!(f, "e{}", exponent)
+            }
+            Expr::Duration(seconds) => write!(f, "{}", format_duration(*seconds)),
+            Expr::Text(text) => write!(f, "\"{}\"", text),
+            Expr::Money(money) => write!(f, "{}", money),
+            Expr::UnaryMinus(expr) if expr.is_atomic() => write!(f, "-{}", s(expr)),
+            Expr::UnaryMinus(expr) => write!(f, "-({})", s(expr)),
+            Expr::Unary { op, operand } if op.is_postfix() => {
+                match **operand {
+                    Expr::BinOp { .. } | Expr::UnaryMinus(_) | Expr::Unary { .. } => write!(f, "({})", s(operand))?,
+                    _ => write!(f, "{}", s(operand))?,
+                }
+                // Keep a word operator apart from the operand (x fact, not xfact)
+                let separator = if is_word(op.symbol()) { " " } else { "" };
+                write!(f, "{}{}", separator, op.symbol())
+            }
+            Expr::Unary {
+                op: UnaryOperator::Abs,
+                operand,
+            } => match **operand {
+                // Keep a bitwise or logical OR from reading as a closing bar
+                Expr::BinOp {
+                    op: Operator::BitOr | Operator::Or,
+                    ..
+                } => write!(f, "|({})|", s(operand)),
+                _ => write!(f, "|{}|", s(operand)),
+            },
+            Expr::Unary { op, operand } if operand.is_atomic() => {
+                let separator = if is_word(op.symbol()) { " " } else { "" };
+                write!(f, "{}{}{}", op.symbol(), separator, s(operand))
+            }
+            Expr::Unary { op, operand } => write!(f, "{}({})", op.symbol(), s(operand)),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::Group(expr) => write!(f, "({})", s(expr)),
+            Expr::Error => write!(f, "?"),
+            Expr::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", s(item))?;
+                }
+                write!(f, "]")
+            }
+            Expr::Index { target, index } => match **target {
+                Expr::BinOp { .. } | Expr::UnaryMinus(_) | Expr::Unary { .. } => {
+                    write!(f, "({})[{}]", s(target), s(index))
+                }
+                _ => write!(f, "{}[{}]", s(target), s(index)),
+            },
+            Expr::Function { name, args } => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", s(arg))?;
+                }
+                write!(f, ")")
+            }
             Expr::BinOp { op, lhs, rhs } => {
                 // Handle operator precedence for proper parentheses
+                // An operand at the same precedence only needs them on the side it does not group towards
                 let need_parens_left = match (&**lhs, op) {
+                    (Expr::BinOp { .. }, _) if style.parenthesize => true,
                     (Expr::BinOp { op: inner_op, .. }, outer_op) => {
                         inner_op.precedence() < outer_op.precedence()
+                            || (inner_op.precedence() == outer_op.precedence()
+                                && outer_op.associativity() == Associativity::Right)
                     }
                     _ => false,
                 };
 
                 let need_parens_right = match (&**rhs, op) {
+                    (Expr::BinOp { .. }, _) if style.parenthesize => true,
                     (Expr::BinOp { op: inner_op, .. }, outer_op) => {
-                        inner_op.precedence() <= outer_op.precedence()
+                        inner_op.precedence() < outer_op.precedence()
+                            || (inner_op.precedence() == outer_op.precedence()
+                                && outer_op.associativity() == Associativity::Left)
                     }
                     _ => false,
                 };
 
                 // Write left expression with optional parentheses
                 if need_parens_left {
-                    write!(f, "({})", lhs)?;
+                    write!(f, "({})", s(lhs))?;
                 } else {
-                    write!(f, "{}", lhs)?;
+                    write!(f, "{}", s(lhs))?;
                 }
 
                 // Write operator
@@ -94,11 +463,18 @@ impl fmt::Display for Expr {
 
                 // Write right expression with optional parentheses
                 if need_parens_right {
-                    write!(f, "({})", rhs)
+                    write!(f, "({})", s(rhs))
                 } else {
-                    write!(f, "{}", rhs)
+                    write!(f, "{}", s(rhs))
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn write_number(f: &mut fmt::Formatter, value: f64, precision: Option<usize>) -> fmt::Result {
+    match precision {
+        Some(precision) => write!(f, "{:.*}", precision, value),
+        None => write!(f, "{}", value),
+    }
+}
@@ -1,19 +1,77 @@
 //src/token/tokenizer.rs
 
-use crate::{MathError, Operator, Result, Token};
+use crate::duration::{self, UNITS};
+use crate::operators::{is_word, Fixity, OperatorTable};
+use crate::trace;
+use crate::{MathError, Operator, Result, Span, Token, TokenClass, UnaryOperator};
+
+// What '%' means
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PercentSign {
+    // Postfix percent: 15% is 0.15
+    #[default]
+    Percent,
+    // Binary remainder: 7 % 3 is 1
+    Modulo,
+}
+
+// Settings for the syntax the tokenizer accepts
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenizerOptions {
+    // Read ',' as the decimal point (3,14) and ';' inside parentheses as the argument separator
+    // (max(1,5; 2)). Outside parentheses ';' still separates statements.
+    pub decimal_comma: bool,
+    // Accept this character between groups of three integer digits (1,000,000 or 1.000.000).
+    // When it is ',', ';' becomes the argument separator. '_' is always accepted between digits.
+    pub thousands_separator: Option<char>,
+    // Accept SI magnitude suffixes on numbers: f p n u µ m k M G T P (4.7k, 220n, 10M).
+    // Exa is not available since E already marks an exponent.
+    pub si_suffixes: bool,
+    // Accept duration literals: a number followed by d, h, m or s, such as 1h30m or 90s.
+    // Without this, 2m is 2 times the variable m.
+    pub durations: bool,
+    // Whether '%' is the postfix percent or the remainder operator
+    pub percent_sign: PercentSign,
+    // Custom operators to recognize. Their symbols take priority over built-in operators.
+    pub operators: OperatorTable,
+}
+
+// Built-in operators longer than one character
+const BUILTIN_SYMBOLS: [&str; 8] = ["<<", ">>", "<=", ">=", "==", "!=", "&&", "||"];
 
 // A function tokenizer that processes input characters into tokens
 pub struct Tokenizer<'a> {
-    chars: std::iter::Peekable<std::str::Chars<'a>>, // iterate over the characters of the input string
+    chars: Chars<'a>, // iterate over the characters of the input string
+    input_len: usize,
+    options: TokenizerOptions,
+    paren_depth: usize, // Open parentheses and brackets, to tell argument from statement separators
+    after_operand: bool, // Whether the last token ended an operand, to tell prefix custom operators from the rest
 }
 
 impl<'a> Tokenizer<'a> {
     // Create a new tokenizer from input string
     pub fn new(input: &'a str) -> Self {
+        Self::with_options(input, TokenizerOptions::default())
+    }
+
+    // Create a new tokenizer accepting the syntax described by the options
+    pub fn with_options(input: &'a str, options: TokenizerOptions) -> Self {
+        Self {
+            chars: Chars::new(input),
+            input_len: input.len(),
+            options,
+            paren_depth: 0,
+            after_operand: false,
+        }
+    }
+
+    // Creates a tokenizer that carries on from a point in a longer input where the given number of
+    // parentheses and brackets are open, and the previous token did (or did not) end an operand
+    pub(crate) fn resume(input: &'a str, options: TokenizerOptions, paren_depth: usize, after_operand: bool) -> Self {
         Self {
-            // input,
-            // index: 0,
-            chars: input.chars().peekable(),
+            paren_depth,
+            after_operand,
+            ..Self::with_options(input, options)
         }
     }
 
@@ -23,36 +81,140 @@ impl<'a> Tokenizer<'a> {
         tokenizer.tokenize_all()
     }
 
+    // Static method to tokenize an entire string with the given options
+    pub fn tokenize_with(input: &'a str, options: TokenizerOptions) -> Result<Vec<Token>> {
+        let mut tokenizer = Self::with_options(input, options);
+        tokenizer.tokenize_all()
+    }
+
+    // Static method to tokenize an entire string, with where in it each token was found
+    pub fn tokenize_spanned(input: &'a str, options: TokenizerOptions) -> Result<Vec<(Token, Span)>> {
+        let mut tokenizer = Self::with_options(input, options);
+        tokenizer.tokenize_all_spanned()
+    }
+
     // Pure function to tokenize the entire input
     pub fn tokenize_all(&mut self) -> Result<Vec<Token>> {
-        let mut tokens = Vec::new();
-        while let Some(token) = self.next_token()? {
-            tokens.push(token);
+        let tokens = self.tokenize_all_spanned()?;
+        Ok(tokens.into_iter().map(|(token, _)| token).collect())
+    }
+
+    // Tokenizes the entire input, recording the byte range each token came from
+    pub fn tokenize_all_spanned(&mut self) -> Result<Vec<(Token, Span)>> {
+        trace::tokenize(self.input_len, || {
+            let mut tokens = Vec::new();
+            while let Some(token) = self.next_spanned()? {
+                tokens.push(token);
+            }
+            Ok(tokens)
+        })
+    }
+
+    // The next token and the byte range it came from
+    pub(crate) fn next_spanned(&mut self) -> Result<Option<(Token, Span)>> {
+        self.skip_whitespace_and_comments()?;
+        let start = self.offset();
+        let Some(token) = self.next_token()? else { return Ok(None) };
+        self.after_operand = ends_operand(&token);
+        Ok(Some((token, Span::new(start, self.offset()))))
+    }
+
+    // Classifies the entire input for syntax highlighting. Unlike tokenizing, this does not stop
+    // at an error: the rejected text is classed as `TokenClass::Error` and the rest is classified
+    // after it. Whitespace and comments are not included.
+    pub fn classify_all(&mut self) -> Vec<(Span, TokenClass)> {
+        let mut classes = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let start = self.offset();
+            if self.skip_whitespace_and_comments().is_err() {
+                // An unterminated block comment runs to the end of the input
+                classes.push((Span::new(start, self.offset()), TokenClass::Error));
+                break;
+            }
+            let start = self.offset();
+            match self.next_token() {
+                Ok(Some(token)) => {
+                    self.after_operand = ends_operand(&token);
+                    classes.push((Span::new(start, self.offset()), token.class()));
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    // Skip at least the character that could not start a token
+                    if self.offset() == start {
+                        self.chars.next();
+                    }
+                    classes.push((Span::new(start, self.offset()), TokenClass::Error));
+                }
+            }
         }
-        Ok(tokens)
+        classes
+    }
+
+    // Byte offset of the next character
+    fn offset(&self) -> usize {
+        self.input_len - self.chars.rest.len()
     }
 
     // Gets the next token from the input stream
     fn next_token(&mut self) -> Result<Option<Token>> {
-        self.skip_whitespace();
+        self.skip_whitespace_and_comments()?;
+        if let Some(token) = self.tokenize_custom_operator() {
+            return Ok(Some(token));
+        }
 
         match self.chars.peek() {
             None => Ok(None),
             Some(&ch) => match ch {
                 '0'..='9' | '.' => self.tokenize_number(), // Delegates number parsing
+                ',' if self.options.decimal_comma => self.tokenize_number(),
+                ';' => {
+                    self.chars.next();
+                    // With decimal commas, ';' inside parentheses or brackets separates arguments
+                    if self.comma_in_numbers() && self.paren_depth > 0 {
+                        Ok(Some(Token::Comma))
+                    } else {
+                        Ok(Some(Token::Semicolon))
+                    }
+                }
+                '=' => {
+                    self.chars.next();
+                    if self.chars.next_if_eq(&'=').is_some() {
+                        Ok(Some(Token::Operator(Operator::Equal)))
+                    } else {
+                        Ok(Some(Token::Assign))
+                    }
+                }
+                '!' => {
+                    self.chars.next();
+                    match self.chars.next_if_eq(&'=') {
+                        Some(_) => Ok(Some(Token::Operator(Operator::NotEqual))),
+                        None => Ok(Some(Token::Unary(UnaryOperator::Not))),
+                    }
+                }
+                '≤' | '≥' | '≠' => {
+                    self.chars.next();
+                    let op = match ch {
+                        '≤' => Operator::LessEqual,
+                        '≥' => Operator::GreaterEqual,
+                        _ => Operator::NotEqual,
+                    };
+                    Ok(Some(Token::Operator(op)))
+                }
                 '+' => {
                     self.chars.next();
                     Ok(Some(Token::Operator(Operator::Add)))
                 }
-                '-' => {
+                // Typographic variants pasted from documents: minus sign, times, dot operators, division
+                '-' | '\u{2212}' => {
                     self.chars.next();
                     Ok(Some(Token::Operator(Operator::Subtract)))
                 }
-                '*' => {
+                '*' | '×' | '·' | '\u{22C5}' => {
                     self.chars.next();
                     Ok(Some(Token::Operator(Operator::Multiply)))
                 }
-                '/' => {
+                '/' | '÷' => {
                     self.chars.next();
                     Ok(Some(Token::Operator(Operator::Divide)))
                 }
@@ -60,14 +222,67 @@ impl<'a> Tokenizer<'a> {
                     self.chars.next();
                     Ok(Some(Token::Operator(Operator::Power)))
                 }
+                '&' => {
+                    self.chars.next();
+                    match self.chars.next_if_eq(&'&') {
+                        Some(_) => Ok(Some(Token::Operator(Operator::And))),
+                        None => Ok(Some(Token::Operator(Operator::BitAnd))),
+                    }
+                }
+                // '||' is logical OR; the parser splits it back into two bars where
+                // absolute value bars are expected (e.g., ||x| - 1|)
+                '|' => {
+                    self.chars.next();
+                    match self.chars.next_if_eq(&'|') {
+                        Some(_) => Ok(Some(Token::Operator(Operator::Or))),
+                        None => Ok(Some(Token::Operator(Operator::BitOr))),
+                    }
+                }
+                '%' => {
+                    self.chars.next();
+                    match self.options.percent_sign {
+                        PercentSign::Percent => Ok(Some(Token::Percent)),
+                        PercentSign::Modulo => Ok(Some(Token::Operator(Operator::Modulo))),
+                    }
+                }
+                _ if superscript_digit(ch).is_some() || ch == '⁻' || ch == '⁺' => {
+                    self.tokenize_superscript()
+                }
+                '~' => {
+                    self.chars.next();
+                    Ok(Some(Token::Unary(UnaryOperator::BitNot)))
+                }
+                '√' => {
+                    self.chars.next();
+                    Ok(Some(Token::Unary(UnaryOperator::Sqrt)))
+                }
+                '<' | '>' => self.tokenize_angle(ch),
                 '(' => {
                     self.chars.next();
+                    self.paren_depth += 1;
                     Ok(Some(Token::LParen))
                 }
                 ')' => {
                     self.chars.next();
+                    self.paren_depth = self.paren_depth.saturating_sub(1);
                     Ok(Some(Token::RParen))
                 }
+                '[' => {
+                    self.chars.next();
+                    self.paren_depth += 1;
+                    Ok(Some(Token::LBracket))
+                }
+                ']' => {
+                    self.chars.next();
+                    self.paren_depth = self.paren_depth.saturating_sub(1);
+                    Ok(Some(Token::RBracket))
+                }
+                ',' => {
+                    self.chars.next();
+                    Ok(Some(Token::Comma))
+                }
+                '"' => self.tokenize_text(),
+                _ if ch.is_alphabetic() || ch == '_' => self.tokenize_identifier(),
                 _ => Err(MathError::InvalidExpression(format!(
                     "Unexpected character: {}",
                     ch
@@ -76,26 +291,61 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    // Reads a quoted name such as "km", which may hold anything but a quote
+    fn tokenize_text(&mut self) -> Result<Option<Token>> {
+        self.chars.next(); // The opening quote
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(Some(Token::Text(text))),
+                Some(ch) => text.push(ch),
+                None => return Err(MathError::InvalidExpression(format!("Unterminated quoted name: \"{}", text))),
+            }
+        }
+    }
+
     // Pure function to tokenize a number, handling both regular and scientific notation
     fn tokenize_number(&mut self) -> Result<Option<Token>> {
         let mut number = String::new();
         let mut is_scientific = false;
         let mut has_decimal = false;
+        let mut grouped = false; // Whether a thousands separator has been seen
+        let mut group_len = 0; // Integer digits since the last thousands separator
+        let mut suffix_exponent = None; // Power of ten from an SI suffix
+        let mut is_duration = false; // Whether a unit of time follows, as in 1h30m
 
         while let Some(&ch) = self.chars.peek() {
             match ch {
                 '0'..='9' => {
+                    if !has_decimal && !is_scientific {
+                        group_len += 1;
+                    }
                     number.push(ch);
                     self.chars.next();
                 }
-                '.' => {
+                '_' => self.skip_digit_separator(&number, ch)?,
+                _ if Some(ch) == self.thousands_separator() => {
+                    // Only between integer digit groups: 1-3 digits first, exactly 3 after that
+                    let misplaced = has_decimal
+                        || is_scientific
+                        || (grouped && group_len != 3)
+                        || (!grouped && group_len > 3);
+                    if misplaced {
+                        return Err(MathError::InvalidNumber(format!("{}{}", number, ch)));
+                    }
+                    self.skip_digit_separator(&number, ch)?;
+                    grouped = true;
+                    group_len = 0;
+                }
+                '.' | ',' if ch == '.' || self.options.decimal_comma => {
                     if has_decimal {
                         return Err(MathError::InvalidExpression(
                             "Multiple decimal points in number".to_string(),
                         ));
                     }
+                    Self::check_last_group(&number, grouped, group_len)?;
                     has_decimal = true;
-                    number.push(ch);
+                    number.push('.'); // Normalize a decimal comma for parsing
                     self.chars.next();
                 }
                 'e' | 'E' => {
@@ -104,6 +354,9 @@ impl<'a> Tokenizer<'a> {
                             "Multiple scientific notation in number".to_string(),
                         ));
                     }
+                    if !has_decimal {
+                        Self::check_last_group(&number, grouped, group_len)?;
+                    }
                     is_scientific = true;
                     number.push(ch);
                     self.chars.next();
@@ -116,15 +369,43 @@ impl<'a> Tokenizer<'a> {
                         }
                     }
                 }
-                _ if ch.is_whitespace() || "+-*/^()".contains(ch) => break,
-                _ => return Err(MathError::InvalidNumber(number)),
+                _ if !is_scientific && self.si_suffix() => {
+                    suffix_exponent = si_exponent(ch);
+                    self.chars.next();
+                    break;
+                }
+                _ if !is_scientific && self.duration_unit() => {
+                    is_duration = true;
+                    break;
+                }
+                // Anything else starts the next token; the parser decides whether 2x is a product
+                _ => break,
             }
         }
 
+        if !has_decimal && !is_scientific {
+            Self::check_last_group(&number, grouped, group_len)?;
+        }
+
         if number.is_empty() {
             return Err(MathError::InvalidExpression("Empty number".to_string()));
         }
 
+        if is_duration {
+            let count = number
+                .parse::<f64>()
+                .map_err(|_| MathError::InvalidNumber(number.clone()))?;
+            return self.tokenize_duration(count, number);
+        }
+
+        // A magnitude suffix scales the number like an exponent would
+        if let Some(exponent) = suffix_exponent {
+            let base = number
+                .parse::<f64>()
+                .map_err(|_| MathError::InvalidNumber(number.clone()))?;
+            return Ok(Some(Token::Scientific { base, exponent }));
+        }
+
         // If it's scientific notation, parse it as such
         if is_scientific {
             self.parse_scientific_notation(&number)
@@ -138,6 +419,133 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    // Tokenize a run of superscript digits with an optional sign (², ⁻¹) as an exponent
+    fn tokenize_superscript(&mut self) -> Result<Option<Token>> {
+        let negative = self.chars.next_if(|&c| c == '⁻' || c == '⁺') == Some('⁻');
+        let mut exponent: i32 = 0;
+        let mut has_digits = false;
+        while let Some(digit) = self.chars.peek().copied().and_then(superscript_digit) {
+            self.chars.next();
+            has_digits = true;
+            exponent = exponent
+                .checked_mul(10)
+                .and_then(|e| e.checked_add(digit as i32))
+                .ok_or_else(|| MathError::InvalidExpression("Superscript exponent too large".to_string()))?;
+        }
+        if !has_digits {
+            return Err(MathError::InvalidExpression(
+                "Expected superscript digits after sign".to_string(),
+            ));
+        }
+        Ok(Some(Token::Superscript(if negative { -exponent } else { exponent })))
+    }
+
+    // Tokenize an operator starting with < or > (a shift or a comparison)
+    fn tokenize_angle(&mut self, ch: char) -> Result<Option<Token>> {
+        self.chars.next();
+        let op = if self.chars.next_if_eq(&ch).is_some() {
+            if ch == '<' { Operator::ShiftLeft } else { Operator::ShiftRight }
+        } else if self.chars.next_if_eq(&'=').is_some() {
+            if ch == '<' { Operator::LessEqual } else { Operator::GreaterEqual }
+        } else if ch == '<' {
+            Operator::Less
+        } else {
+            Operator::Greater
+        };
+        Ok(Some(Token::Operator(op)))
+    }
+
+    // Whether the next character is an SI suffix ending the number (so 2min stays an error)
+    fn si_suffix(&self) -> bool {
+        if !self.options.si_suffixes {
+            return false;
+        }
+        let mut ahead = self.chars.clone();
+        let is_suffix = ahead.next().and_then(si_exponent).is_some();
+        is_suffix && !ahead.peek().is_some_and(|c| c.is_alphanumeric() || *c == '_')
+    }
+
+    // Whether the next character is a unit of time ending the number, or followed by the next
+    // number of a duration (so 2min stays an error)
+    fn duration_unit(&self) -> bool {
+        if !self.options.durations {
+            return false;
+        }
+        let mut ahead = self.chars.clone();
+        let is_unit = ahead.next().and_then(duration::unit).is_some();
+        is_unit && !ahead.peek().is_some_and(|c| (c.is_alphanumeric() && !c.is_ascii_digit()) || *c == '_')
+    }
+
+    // Tokenize the units and numbers of a duration literal after its first number, which need
+    // to come in order from days to seconds (1h30m, not 30m1h)
+    fn tokenize_duration(&mut self, first: f64, mut text: String) -> Result<Option<Token>> {
+        let mut seconds = 0.0;
+        let mut count = first;
+        let mut previous = None;
+        while let Some(symbol) = self.chars.next() {
+            text.push(symbol);
+            let unit = duration::unit(symbol)
+                .filter(|unit| previous.is_none_or(|previous| *unit > previous))
+                .ok_or_else(|| MathError::InvalidNumber(text.clone()))?;
+            seconds += count * UNITS[unit].1;
+            previous = Some(unit);
+            if !self.chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                break;
+            }
+            let start = text.len();
+            while let Some(ch) = self.chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                text.push(ch);
+            }
+            count = text[start..].parse().map_err(|_| MathError::InvalidNumber(text.clone()))?;
+            if self.chars.peek().and_then(|c| duration::unit(*c)).is_none() {
+                return Err(MathError::InvalidNumber(text));
+            }
+        }
+        if let Some(&ch) = self.chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+            text.push(ch);
+            return Err(MathError::InvalidNumber(text));
+        }
+        Ok(Some(Token::Duration(seconds)))
+    }
+
+    // Consumes a digit separator, which must sit between two digits
+    fn skip_digit_separator(&mut self, number: &str, separator: char) -> Result<()> {
+        let after_digit = number.ends_with(|c: char| c.is_ascii_digit());
+        self.chars.next();
+        let before_digit = self.chars.peek().is_some_and(|c| c.is_ascii_digit());
+        if after_digit && before_digit {
+            Ok(())
+        } else {
+            Err(MathError::InvalidNumber(format!("{}{}", number, separator)))
+        }
+    }
+
+    // After thousands separators the final integer group must have exactly three digits
+    fn check_last_group(number: &str, grouped: bool, group_len: usize) -> Result<()> {
+        if grouped && group_len != 3 {
+            return Err(MathError::InvalidNumber(number.to_string()));
+        }
+        Ok(())
+    }
+
+    // The thousands separator in effect (a decimal comma takes precedence over a ',' separator)
+    fn thousands_separator(&self) -> Option<char> {
+        self.options
+            .thousands_separator
+            .filter(|&c| !(c == ',' && self.options.decimal_comma))
+    }
+
+    // Whether ',' belongs to numbers, making ';' the argument separator
+    fn comma_in_numbers(&self) -> bool {
+        self.options.decimal_comma || self.thousands_separator() == Some(',')
+    }
+
+    // Whether the open parentheses change how later text is tokenized (a ';' between them
+    // separates arguments when ',' is part of numbers)
+    pub(crate) fn nesting_matters(&self) -> bool {
+        self.comma_in_numbers()
+    }
+
     // Pure function to parse scientific notation
     fn parse_scientific_notation(&self, number: &str) -> Result<Option<Token>> {
         let parts: Vec<&str> = number.split('e').collect();
@@ -152,10 +560,74 @@ impl<'a> Tokenizer<'a> {
             .parse::<i32>()
             .map_err(|_| MathError::InvalidNumber(number.to_string()))?;
 
-        Ok(Some(Token::Scientific {
-            base,
-            exponent: exponent as i32,
-        }))
+        Ok(Some(Token::Scientific { base, exponent }))
+    }
+
+    // Tokenize an identifier (variable or function name)
+    fn tokenize_identifier(&mut self) -> Result<Option<Token>> {
+        let mut name = String::new();
+        while let Some(&ch) = self.chars.peek() {
+            if !(ch.is_alphanumeric() || ch == '_') || superscript_digit(ch).is_some() {
+                break;
+            }
+            name.push(ch);
+            self.chars.next();
+        }
+
+        // Non-finite literals, accepted in the spellings f64's Display produces
+        match name.to_ascii_lowercase().as_str() {
+            "inf" | "infinity" => return Ok(Some(Token::Number(f64::INFINITY))),
+            "nan" => return Ok(Some(Token::Number(f64::NAN))),
+            _ => {}
+        }
+
+        // Operators spelled as words
+        if is_word(&name) {
+            if let Some(token) = self.custom_token(&name) {
+                return Ok(Some(token));
+            }
+        }
+        match name.as_str() {
+            "xor" => Ok(Some(Token::Operator(Operator::BitXor))),
+            "and" => Ok(Some(Token::Operator(Operator::And))),
+            "or" => Ok(Some(Token::Operator(Operator::Or))),
+            "not" => Ok(Some(Token::Unary(UnaryOperator::Not))),
+            _ => Ok(Some(Token::Identifier(name))),
+        }
+    }
+
+    // Consumes a registered punctuation operator at the current position, if there is one
+    fn tokenize_custom_operator(&mut self) -> Option<Token> {
+        if self.options.operators.is_empty() {
+            return None;
+        }
+        let ahead: String = self.chars.clone().take(self.options.operators.longest_symbol()).collect();
+        let symbol = self.options.operators.match_symbol(&ahead, self.after_operand)?;
+        // A longer built-in operator still wins, so a postfix '!' leaves '!=' alone
+        if BUILTIN_SYMBOLS.iter().any(|builtin| builtin.len() > symbol.len() && ahead.starts_with(builtin)) {
+            return None;
+        }
+        let token = self.custom_token(symbol)?;
+        for _ in symbol.chars() {
+            self.chars.next();
+        }
+        Some(token)
+    }
+
+    // The custom operator a symbol stands for here: after an operand a postfix operator, or else
+    // an infix one; anywhere else a prefix operator
+    fn custom_token(&self, symbol: &str) -> Option<Token> {
+        let operators = &self.options.operators;
+        if self.after_operand {
+            match operators.unary(symbol, Fixity::Postfix) {
+                Some(custom) => Some(Token::Unary(UnaryOperator::Custom(custom.clone()))),
+                None => operators.binary(symbol).map(|custom| Token::Operator(Operator::Custom(custom.clone()))),
+            }
+        } else {
+            operators
+                .unary(symbol, Fixity::Prefix)
+                .map(|custom| Token::Unary(UnaryOperator::Custom(custom.clone())))
+        }
     }
 
     // Skip whitespace characters
@@ -167,12 +639,146 @@ impl<'a> Tokenizer<'a> {
             self.chars.next();
         }
     }
+
+    // Skip whitespace, '#' line comments and '/* ... */' block comments
+    fn skip_whitespace_and_comments(&mut self) -> Result<()> {
+        loop {
+            self.skip_whitespace();
+            match self.chars.peek().copied() {
+                Some('#') => {
+                    while self.chars.next_if(|&c| c != '\n').is_some() {}
+                }
+                Some('/') if self.chars.clone().nth(1) == Some('*') => {
+                    self.chars.next();
+                    self.chars.next();
+                    let mut previous = None;
+                    loop {
+                        match self.chars.next() {
+                            Some('/') if previous == Some('*') => break,
+                            Some(c) => previous = Some(c),
+                            None => {
+                                return Err(MathError::InvalidExpression(
+                                    "Unterminated block comment".to_string(),
+                                ))
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+// The class of every token in the input, with the default syntax (see `Tokenizer::classify_all`)
+pub fn highlight(input: &str) -> Vec<(Span, TokenClass)> {
+    Tokenizer::new(input).classify_all()
+}
+
+// Whether the token ends an operand, so a custom operator after it is not a prefix one
+pub(crate) fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Number(_)
+            | Token::Scientific { .. }
+            | Token::Duration(_)
+            | Token::Identifier(_)
+            | Token::Text(_)
+            | Token::RParen
+            | Token::RBracket
+            | Token::Percent
+            | Token::Superscript(_)
+    ) || matches!(token, Token::Unary(op) if op.is_postfix())
+}
+
+// The parentheses and brackets open after the token, given how many were open before it
+pub(crate) fn paren_depth_after(depth: usize, token: &Token) -> usize {
+    match token {
+        Token::LParen | Token::LBracket => depth + 1,
+        Token::RParen | Token::RBracket => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
+
+// The value of a superscript digit (⁰ to ⁹)
+pub(crate) fn superscript_digit(ch: char) -> Option<u32> {
+    match ch {
+        '⁰' => Some(0),
+        '¹' => Some(1),
+        '²' => Some(2),
+        '³' => Some(3),
+        '⁴'..='⁹' => Some(ch as u32 - '⁴' as u32 + 4),
+        _ => None,
+    }
+}
+
+// The power of ten an SI magnitude suffix stands for
+fn si_exponent(suffix: char) -> Option<i32> {
+    match suffix {
+        'f' => Some(-15),
+        'p' => Some(-12),
+        'n' => Some(-9),
+        'u' | 'µ' => Some(-6),
+        'm' => Some(-3),
+        'k' => Some(3),
+        'M' => Some(6),
+        'G' => Some(9),
+        'T' => Some(12),
+        'P' => Some(15),
+        _ => None,
+    }
+}
+
+// The characters of the input, like `Peekable<Chars>` but also telling how much is left
+#[derive(Clone)]
+struct Chars<'a> {
+    rest: &'a str,      // The unconsumed input, starting with `next`
+    next: Option<char>, // The next character, if any
+}
+
+impl<'a> Chars<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            rest: input,
+            next: input.chars().next(),
+        }
+    }
+
+    fn peek(&self) -> Option<&char> {
+        self.next.as_ref()
+    }
+
+    // Consumes the next character if it matches
+    fn next_if(&mut self, matches: impl FnOnce(&char) -> bool) -> Option<char> {
+        if matches(self.next.as_ref()?) {
+            self.next()
+        } else {
+            None
+        }
+    }
+
+    fn next_if_eq(&mut self, expected: &char) -> Option<char> {
+        self.next_if(|ch| ch == expected)
+    }
+}
+
+impl Iterator for Chars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.next?;
+        self.rest = &self.rest[ch.len_utf8()..];
+        self.next = self.rest.chars().next();
+        Some(ch)
+    }
 }
 
 // Unit tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::operators::{Associativity, Fixity};
+    use crate::token::Tokens;
 
     #[test]
     fn test_basic_tokenization() {
@@ -214,6 +820,412 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_identifiers_and_commas() {
+        let input = "max(x_1, 2)";
+        let tokens = Tokenizer::tokenize(input).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("max".to_string()),
+                Token::LParen,
+                Token::Identifier("x_1".to_string()),
+                Token::Comma,
+                Token::Number(2.0),
+                Token::RParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_finite_literals() {
+        let tokens = Tokenizer::tokenize("-inf + Infinity * NaN").unwrap();
+        assert_eq!(tokens[0], Token::Operator(Operator::Subtract));
+        assert_eq!(tokens[1], Token::Number(f64::INFINITY));
+        assert_eq!(tokens[3], Token::Number(f64::INFINITY));
+        assert!(matches!(tokens[5], Token::Number(n) if n.is_nan()));
+
+        // Longer names are still identifiers
+        assert_eq!(
+            Tokenizer::tokenize("info").unwrap(),
+            vec![Token::Identifier("info".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_decimal_comma() {
+        let options = TokenizerOptions {
+            decimal_comma: true,
+            ..TokenizerOptions::default()
+        };
+        let tokens = Tokenizer::tokenize_with("max(2,75; ,5)", options.clone()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("max".to_string()),
+                Token::LParen,
+                Token::Number(2.75),
+                Token::Comma,
+                Token::Number(0.5),
+                Token::RParen,
+            ]
+        );
+
+        // ...and list elements
+        let tokens = Tokenizer::tokenize_with("[1,5; 2]; 3", options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LBracket,
+                Token::Number(1.5),
+                Token::Comma,
+                Token::Number(2.0),
+                Token::RBracket,
+                Token::Semicolon,
+                Token::Number(3.0),
+            ]
+        );
+
+        // Without the option a comma still separates arguments
+        let tokens = Tokenizer::tokenize("3,14").unwrap();
+        assert_eq!(tokens, vec![Token::Number(3.0), Token::Comma, Token::Number(14.0)]);
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        assert_eq!(Tokenizer::tokenize("1_000_000").unwrap(), vec![Token::Number(1e6)]);
+        assert_eq!(Tokenizer::tokenize("0.000_1").unwrap(), vec![Token::Number(0.0001)]);
+
+        // Trailing, doubled, or next to the decimal point
+        for input in ["1_", "1__0", "1_.5", "1._5", "1_e3"] {
+            assert!(
+                matches!(Tokenizer::tokenize(input), Err(MathError::InvalidNumber(_))),
+                "{} should be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_thousands_separators() {
+        let english = TokenizerOptions {
+            thousands_separator: Some(','),
+            ..TokenizerOptions::default()
+        };
+        assert_eq!(
+            Tokenizer::tokenize_with("max(1,234,567.5; 12)", english.clone()).unwrap(),
+            vec![
+                Token::Identifier("max".to_string()),
+                Token::LParen,
+                Token::Number(1234567.5),
+                Token::Comma,
+                Token::Number(12.0),
+                Token::RParen,
+            ]
+        );
+        for input in ["1,23", "1234,567", "1,2345", "1.5,000", "1,,000"] {
+            assert!(
+                Tokenizer::tokenize_with(input, english.clone()).is_err(),
+                "{} should be rejected",
+                input
+            );
+        }
+
+        let german = TokenizerOptions {
+            decimal_comma: true,
+            thousands_separator: Some('.'),
+            ..TokenizerOptions::default()
+        };
+        assert_eq!(
+            Tokenizer::tokenize_with("1.234.567,89", german).unwrap(),
+            vec![Token::Number(1234567.89)]
+        );
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let tokens = Tokenizer::tokenize("~1&2|3 xor 4<<5>>6").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Unary(UnaryOperator::BitNot),
+                Token::Number(1.0),
+                Token::Operator(Operator::BitAnd),
+                Token::Number(2.0),
+                Token::Operator(Operator::BitOr),
+                Token::Number(3.0),
+                Token::Operator(Operator::BitXor),
+                Token::Number(4.0),
+                Token::Operator(Operator::ShiftLeft),
+                Token::Number(5.0),
+                Token::Operator(Operator::ShiftRight),
+                Token::Number(6.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        let ops = |input: &str| -> Vec<Operator> {
+            Tokenizer::tokenize(input)
+                .unwrap()
+                .into_iter()
+                .filter_map(|token| match token {
+                    Token::Operator(op) => Some(op),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(
+            ops("a<b<=c>d>=e==f!=g<<h"),
+            vec![
+                Operator::Less,
+                Operator::LessEqual,
+                Operator::Greater,
+                Operator::GreaterEqual,
+                Operator::Equal,
+                Operator::NotEqual,
+                Operator::ShiftLeft,
+            ]
+        );
+        assert_eq!(ops("a ≤ b ≥ c ≠ d"), vec![Operator::LessEqual, Operator::GreaterEqual, Operator::NotEqual]);
+        assert_eq!(Tokenizer::tokenize("x = 1").unwrap()[1], Token::Assign);
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = Tokenizer::tokenize_spanned("√x² + 1.5 /* c */ ≤ 2", TokenizerOptions::default()).unwrap();
+        let spans: Vec<Span> = tokens.into_iter().map(|(_, span)| span).collect();
+        // Byte offsets: √ and ≤ take three bytes, ² two
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 3),
+                Span::new(3, 4),
+                Span::new(4, 6),
+                Span::new(7, 8),
+                Span::new(9, 12),
+                Span::new(21, 24),
+                Span::new(25, 26),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_operators() {
+        let mut operators = OperatorTable::new();
+        operators.register_binary("⊕", 8, Associativity::Left, |a, b| Ok(a.hypot(b)));
+        operators.register_binary("~", 3, Associativity::Left, |a, b| Ok((a - b).abs()));
+        operators.register_binary("dot", 9, Associativity::Left, |a, b| Ok(a * b));
+        let options = TokenizerOptions {
+            operators: operators.clone(),
+            ..TokenizerOptions::default()
+        };
+        let custom = |symbol: &str| Token::Operator(Operator::Custom(operators.binary(symbol).unwrap().clone()));
+
+        let tokens = Tokenizer::tokenize_with("3⊕4 ~ x dot dots", options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Number(3.0),
+                custom("⊕"),
+                Token::Number(4.0),
+                custom("~"), // Takes priority over the built-in prefix '~'
+                Token::Identifier("x".to_string()),
+                custom("dot"),
+                Token::Identifier("dots".to_string()),
+            ]
+        );
+        assert!(Tokenizer::tokenize("3⊕4").is_err());
+    }
+
+    #[test]
+    fn test_custom_unary_operators() {
+        let mut operators = OperatorTable::new();
+        operators.register_postfix("!", |x| Ok(libm::tgamma(x + 1.0)));
+        operators.register_prefix("∛", |x| Ok(x.cbrt()));
+        operators.register_prefix("--", |x| Ok(x - 1.0));
+        operators.register_binary("--", 8, Associativity::Left, |a, b| Ok(a - b - 1.0));
+        let options = TokenizerOptions {
+            operators: operators.clone(),
+            ..TokenizerOptions::default()
+        };
+        let prefix = |symbol: &str| Token::Unary(UnaryOperator::Custom(operators.unary(symbol, Fixity::Prefix).unwrap().clone()));
+        let postfix = |symbol: &str| Token::Unary(UnaryOperator::Custom(operators.unary(symbol, Fixity::Postfix).unwrap().clone()));
+        let infix = |symbol: &str| Token::Operator(Operator::Custom(operators.binary(symbol).unwrap().clone()));
+
+        let tokens = Tokenizer::tokenize_with("--3! -- ∛x != !1", options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                prefix("--"),
+                Token::Number(3.0),
+                postfix("!"),
+                infix("--"), // The same symbol after an operand
+                prefix("∛"),
+                Token::Identifier("x".to_string()),
+                Token::Operator(Operator::NotEqual), // The longer built-in wins
+                Token::Unary(UnaryOperator::Not), // Only registered as postfix
+                Token::Number(1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let tokens = Tokenizer::tokenize("!a && b || not c and d or e & f | g").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Unary(UnaryOperator::Not),
+                Token::Identifier("a".to_string()),
+                Token::Operator(Operator::And),
+                Token::Identifier("b".to_string()),
+                Token::Operator(Operator::Or),
+                Token::Unary(UnaryOperator::Not),
+                Token::Identifier("c".to_string()),
+                Token::Operator(Operator::And),
+                Token::Identifier("d".to_string()),
+                Token::Operator(Operator::Or),
+                Token::Identifier("e".to_string()),
+                Token::Operator(Operator::BitAnd),
+                Token::Identifier("f".to_string()),
+                Token::Operator(Operator::BitOr),
+                Token::Identifier("g".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_si_suffixes() {
+        let options = TokenizerOptions {
+            si_suffixes: true,
+            ..TokenizerOptions::default()
+        };
+        let tokens = Tokenizer::tokenize_with("4.7k + 220n * 10M - 3.3µ", options.clone()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Scientific { base: 4.7, exponent: 3 },
+                Token::Operator(Operator::Add),
+                Token::Scientific { base: 220.0, exponent: -9 },
+                Token::Operator(Operator::Multiply),
+                Token::Scientific { base: 10.0, exponent: 6 },
+                Token::Operator(Operator::Subtract),
+                Token::Scientific { base: 3.3, exponent: -6 },
+            ]
+        );
+
+        // A suffix must end the number, and cannot follow an exponent. The letters then start an
+        // identifier, which the parser rejects unless implicit multiplication is enabled.
+        let parse = |input: &str, options: TokenizerOptions| {
+            Tokenizer::tokenize_with(input, options).and_then(|tokens| crate::Parser::new(tokens).parse())
+        };
+        assert_eq!(
+            Tokenizer::tokenize_with("2min", options.clone()).unwrap(),
+            vec![Token::Number(2.0), Token::Identifier("min".to_string())]
+        );
+        assert!(parse("2min", options.clone()).is_err());
+        assert!(parse("1e3k", options).is_err());
+
+        // Disabled by default
+        assert!(parse("4.7k", TokenizerOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_percent_sign() {
+        assert_eq!(
+            Tokenizer::tokenize("15%").unwrap(),
+            vec![Token::Number(15.0), Token::Percent]
+        );
+        let modulo = TokenizerOptions {
+            percent_sign: PercentSign::Modulo,
+            ..TokenizerOptions::default()
+        };
+        assert_eq!(
+            Tokenizer::tokenize_with("7 % 3", modulo).unwrap(),
+            vec![
+                Token::Number(7.0),
+                Token::Operator(Operator::Modulo),
+                Token::Number(3.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unicode_operators() {
+        let tokens = Tokenizer::tokenize("6×2 ÷ 3 − 1·2⋅1").unwrap();
+        assert_eq!(tokens, Tokenizer::tokenize("6*2 / 3 - 1*2*1").unwrap());
+    }
+
+    #[test]
+    fn test_superscripts() {
+        let tokens = Tokenizer::tokenize("x² + 10³ - 2⁻¹").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("x".to_string()),
+                Token::Superscript(2),
+                Token::Operator(Operator::Add),
+                Token::Number(10.0),
+                Token::Superscript(3),
+                Token::Operator(Operator::Subtract),
+                Token::Number(2.0),
+                Token::Superscript(-1),
+            ]
+        );
+        assert_eq!(Tokenizer::tokenize("2¹⁰").unwrap()[1], Token::Superscript(10));
+        assert!(Tokenizer::tokenize("2⁻").is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "1 + /* the rate */ 2 # trailing note\n * 3 /**/";
+        assert_eq!(
+            Tokenizer::tokenize(input).unwrap(),
+            Tokenizer::tokenize("1 + 2 * 3").unwrap()
+        );
+        assert_eq!(Tokenizer::tokenize("# only a comment").unwrap(), vec![]);
+        assert_eq!(
+            Tokenizer::tokenize("6/2").unwrap(),
+            vec![Token::Number(6.0), Token::Operator(Operator::Divide), Token::Number(2.0)]
+        );
+        assert!(Tokenizer::tokenize("1 /* never closed").is_err());
+        assert!(Tokenizer::tokenize("1 /* almost */").is_ok());
+    }
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(
+            Tokenizer::tokenize("a = 2; a").unwrap(),
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Assign,
+                Token::Number(2.0),
+                Token::Semicolon,
+                Token::Identifier("a".to_string()),
+            ]
+        );
+
+        let options = TokenizerOptions {
+            decimal_comma: true,
+            ..TokenizerOptions::default()
+        };
+        assert_eq!(
+            Tokenizer::tokenize_with("f(1;2); 3", options).unwrap(),
+            vec![
+                Token::Identifier("f".to_string()),
+                Token::LParen,
+                Token::Number(1.0),
+                Token::Comma,
+                Token::Number(2.0),
+                Token::RParen,
+                Token::Semicolon,
+                Token::Number(3.0),
+            ]
+        );
+    }
+
     #[test]
     fn test_scientific_notation_2() {
         let input = "1.23e-4 + 5.67e+8";
@@ -233,6 +1245,71 @@ mod tests {
             ]
         );
     }
+    #[test]
+    fn test_highlight() {
+        let input = "max(x, 2e3) + y² $ 1 # total";
+        let classes: Vec<(&str, TokenClass)> = highlight(input)
+            .into_iter()
+            .map(|(span, class)| (&input[span.start..span.end], class))
+            .collect();
+        assert_eq!(
+            classes,
+            [
+                ("max", TokenClass::Identifier),
+                ("(", TokenClass::Paren),
+                ("x", TokenClass::Identifier),
+                (",", TokenClass::Operator),
+                ("2e3", TokenClass::Number),
+                (")", TokenClass::Paren),
+                ("+", TokenClass::Operator),
+                ("y", TokenClass::Identifier),
+                ("²", TokenClass::Number),
+                // Classification carries on after text the tokenizer rejects
+                ("$", TokenClass::Error),
+                ("1", TokenClass::Number),
+            ]
+        );
+        assert_eq!(highlight("1 /* open"), [(Span::new(0, 1), TokenClass::Number), (Span::new(2, 9), TokenClass::Error)]);
+    }
+
+    #[test]
+    fn test_display_slice() {
+        let show = |input: &str| Token::display_slice(&Tokenizer::tokenize(input).unwrap()).to_string();
+        assert_eq!(show("2*(x+1)"), "2 * (x + 1)");
+        assert_eq!(show("-max(1,-2)[0]^2"), "-max(1, -2)[0] ^ 2");
+        assert_eq!(show("a=√x²;15%"), "a = √x²; 15%");
+        assert_eq!(show("||x|-1| || y"), "||x| - 1| || y");
+        assert_eq!(show("[1, 2] < 3e2 and not 1"), "[1, 2] < 3e2 && !1");
+        assert_eq!(show(""), "");
+        assert_eq!(format!("[{:>7}]", Tokens(&Tokenizer::tokenize("1+2").unwrap())), "[  1 + 2]");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use crate::{MathError, Parser};
+
+        let mut operators = OperatorTable::new();
+        operators.register_binary("⊕", 8, Associativity::Right, |a, b| Ok(a.hypot(b)));
+        let options = TokenizerOptions {
+            operators,
+            ..TokenizerOptions::default()
+        };
+        let tokens = Tokenizer::tokenize_spanned("max(1, 2e3) ⊕ √x%", options).unwrap();
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<(Token, Span)>>(&json).unwrap(), tokens);
+
+        let expr = Parser::parse_str("-[1, 2][0] ^ 2 < 3 && x").unwrap();
+        let json = serde_json::to_string(&expr).unwrap();
+        assert_eq!(serde_json::from_str::<crate::Expr>(&json).unwrap(), expr);
+
+        let error = Parser::parse_str("(1 + 2").unwrap_err();
+        let json = serde_json::to_string(&error).unwrap();
+        match serde_json::from_str::<MathError>(&json).unwrap() {
+            MathError::UnmatchedParenthesis { paren: '(', opened: 1, closed: 0, .. } => {}
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
 }
 
 /*
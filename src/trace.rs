@@ -0,0 +1,186 @@
+// src/trace.rs
+// Spans around tokenizing, parsing and evaluating with the `tracing` feature, at debug level. Each
+// records the size of what the stage was given and produced, how long it took in microseconds
+// (`duration_us`), and the kind of error it failed with (`error`, see `MathError::kind`). Without
+// the feature the stages just run.
+#![cfg_attr(not(feature = "tracing"), allow(dead_code))] // Sizes are only measured for spans
+
+use crate::{Expr, Result, Statement};
+
+// A tokenizer run over `input_len` bytes, producing `tokens`
+pub(crate) fn tokenize<T>(input_len: usize, run: impl FnOnce() -> Result<Vec<T>>) -> Result<Vec<T>> {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::debug_span!("tokenize", input_len, tokens = EMPTY, duration_us = EMPTY, error = EMPTY);
+        enabled::record(span, run, |tokens| Some(("tokens", tokens.len())))
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = input_len;
+        run()
+    }
+}
+
+// A parse of `tokens` tokens, producing an expression of `nodes` nodes or `statements` statements
+pub(crate) fn parse<T: Parsed>(tokens: usize, run: impl FnOnce() -> Result<T>) -> Result<T> {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::debug_span!(
+            "parse",
+            tokens,
+            nodes = EMPTY,
+            statements = EMPTY,
+            duration_us = EMPTY,
+            error = EMPTY
+        );
+        enabled::record(span, run, |parsed| Some((T::FIELD, parsed.size())))
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = tokens;
+        run()
+    }
+}
+
+// An evaluation of an expression of `nodes` nodes
+pub(crate) fn evaluate<T>(expr: &Expr, run: impl FnOnce() -> Result<T>) -> Result<T> {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::debug_span!("evaluate", nodes = node_count(expr), duration_us = EMPTY, error = EMPTY);
+        enabled::record(span, run, |_| None)
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = expr;
+        run()
+    }
+}
+
+// What a parse produces: its size, and the field that is recorded in
+pub(crate) trait Parsed {
+    const FIELD: &'static str;
+    fn size(&self) -> usize;
+}
+
+impl Parsed for Expr {
+    const FIELD: &'static str = "nodes";
+
+    fn size(&self) -> usize {
+        node_count(self)
+    }
+}
+
+impl Parsed for Vec<Statement> {
+    const FIELD: &'static str = "statements";
+
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+fn node_count(expr: &Expr) -> usize {
+    1 + expr.children().into_iter().map(node_count).sum::<usize>()
+}
+
+#[cfg(feature = "tracing")]
+const EMPTY: tracing::field::Empty = tracing::field::Empty;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use std::time::Instant;
+
+    use crate::Result;
+
+    // Runs a stage in its span, then records how long it took, and the size of its output or the
+    // kind of error
+    pub(super) fn record<T>(
+        span: tracing::Span,
+        run: impl FnOnce() -> Result<T>,
+        size: impl FnOnce(&T) -> Option<(&'static str, usize)>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let result = span.in_scope(run);
+        span.record("duration_us", start.elapsed().as_micros() as u64);
+        match &result {
+            Ok(output) => {
+                if let Some((field, size)) = size(output) {
+                    span.record(field, size);
+                }
+            }
+            Err(error) => {
+                span.record("error", error.kind());
+            }
+        }
+        result
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::{Context, Evaluator, Expr};
+
+    // Keeps the name and fields of each span, in the order they were opened
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+    #[derive(Clone)]
+    struct Fields(String, Vec<String>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() != "duration_us" {
+                self.1.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            let mut fields = Fields(span.metadata().name().to_string(), Vec::new());
+            span.record(&mut fields);
+            spans.push(fields);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record) {
+            let mut spans = self.0.lock().unwrap();
+            values.record(&mut spans[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_spans() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let expr = Expr::parse("1 + max(2, x)").unwrap();
+            assert!(Evaluator::evaluate_with(&expr, &Context::new()).is_err());
+        });
+        let spans = recorder.0.lock().unwrap().clone();
+        let spans: Vec<String> = spans.iter().map(|Fields(name, fields)| format!("{} {}", name, fields.join(" "))).collect();
+        assert_eq!(
+            spans,
+            [
+                "tokenize input_len=13 tokens=8",
+                "parse tokens=8 nodes=5",
+                "evaluate nodes=5 error=\"unknown_variable\"",
+            ]
+        );
+    }
+}
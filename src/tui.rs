@@ -0,0 +1,243 @@
+// src/tui.rs
+// Full-screen terminal calculator (`mathexpr tui`): an input line checked as it is typed, the
+// scrolling history of inputs and results, and the variables defined so far
+use std::io;
+use std::mem;
+
+use mathexpr::{Context, MathError, Statement};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::{render_result, CliOptions};
+
+const HELP: &str = "Enter evaluates · ↑↓ recall · PgUp/PgDn scroll · Esc quits";
+
+// An evaluated input and its result or error
+struct Entry {
+    input: String,
+    output: Result<String, String>,
+}
+
+struct App<'a> {
+    options: &'a CliOptions,
+    ctx: Context,
+    input: String,
+    history: Vec<Entry>,
+    scroll: usize,           // Entries hidden below the bottom of the history pane
+    recalled: Option<usize>, // The history entry shown in the input line
+}
+
+impl<'a> App<'a> {
+    fn new(options: &'a CliOptions, ctx: Context) -> Self {
+        Self {
+            options,
+            ctx,
+            input: String::new(),
+            history: Vec::new(),
+            scroll: 0,
+            recalled: None,
+        }
+    }
+
+    fn parse(&self, line: &str) -> Result<Vec<Statement>, MathError> {
+        self.options.config.grammar().parse_statements(line)
+    }
+
+    // The error in the input as typed so far
+    fn live_error(&self) -> Option<MathError> {
+        if self.input.trim().is_empty() {
+            return None;
+        }
+        self.parse(&self.input).err()
+    }
+
+    // Evaluates the input line and moves it to the history
+    fn submit(&mut self) {
+        let input = mem::take(&mut self.input);
+        if input.trim().is_empty() {
+            return;
+        }
+        let output = self.evaluate(&input);
+        self.history.push(Entry { input, output });
+        self.scroll = 0;
+        self.recalled = None;
+    }
+
+    fn evaluate(&mut self, line: &str) -> Result<String, String> {
+        let statements = self.parse(line).map_err(|e| e.to_string())?;
+        let evaluator = self.options.config.evaluator();
+        let mut results = Vec::new();
+        for statement in &statements {
+            let value = evaluator.run(statement, &mut self.ctx).map_err(|e| e.to_string())?;
+            results.push(render_result(statement, &value, self.options)?);
+        }
+        Ok(results.join("; "))
+    }
+
+    // Shows an earlier input in the input line, going back (older) or forward
+    fn recall(&mut self, back: bool) {
+        let Some(last) = self.history.len().checked_sub(1) else { return };
+        let index = match (self.recalled, back) {
+            (None, true) => last,
+            (Some(index), true) => index.saturating_sub(1),
+            (Some(index), false) if index < last => index + 1,
+            (_, false) => {
+                if self.recalled.take().is_some() {
+                    self.input.clear();
+                }
+                return;
+            }
+        };
+        self.recalled = Some(index);
+        self.input = self.history[index].input.clone();
+    }
+
+    // Handles a key press. Returns false to quit.
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match code {
+            KeyCode::Esc => return false,
+            KeyCode::Char('c' | 'd') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => self.submit(),
+            KeyCode::Up => self.recall(true),
+            KeyCode::Down => self.recall(false),
+            KeyCode::PageUp => self.scroll = (self.scroll + 5).min(self.history.len().saturating_sub(1)),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(5),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, input_area, status_area] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3), Constraint::Length(1)]).areas(frame.area());
+        let [history_area, variables_area] =
+            Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)]).areas(main);
+        let error_style = Style::default().fg(Color::Red);
+
+        // The newest entries at the bottom, moved up by the scroll position
+        let shown = self.history.len() - self.scroll.min(self.history.len());
+        let mut lines: Vec<Line> = Vec::new();
+        for entry in &self.history[..shown] {
+            lines.push(Line::from(format!("> {}", entry.input)));
+            lines.push(match &entry.output {
+                Ok(result) => Line::styled(format!("  = {}", result), Style::default().fg(Color::Cyan)),
+                Err(e) => Line::styled(format!("  {}", e), error_style),
+            });
+        }
+        let height = history_area.height.saturating_sub(2) as usize;
+        let lines = lines.split_off(lines.len().saturating_sub(height));
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("History")), history_area);
+
+        let variable = |name: &str| {
+            let value = self.options.config.format(&self.ctx.value(name)?);
+            Some(Line::from(format!("{} = {}", name, value)))
+        };
+        let variables: Vec<Line> = self.ctx.variable_names().into_iter().filter_map(variable).collect();
+        frame.render_widget(Paragraph::new(variables).block(Block::bordered().title("Variables")), variables_area);
+
+        // The part of the input an error points at is marked as it is typed
+        let error = self.live_error();
+        let input = match error.as_ref().and_then(MathError::span) {
+            Some(span) if span.end <= self.input.len() => Line::from(vec![
+                Span::raw(&self.input[..span.start]),
+                Span::styled(&self.input[span.start..span.end], error_style.add_modifier(Modifier::UNDERLINED)),
+                Span::raw(&self.input[span.end..]),
+            ]),
+            _ => Line::from(self.input.as_str()),
+        };
+        frame.render_widget(Paragraph::new(input).block(Block::bordered().title("Input")), input_area);
+        let cursor = input_area.x + 1 + self.input.chars().count() as u16;
+        frame.set_cursor_position((cursor.min(input_area.right().saturating_sub(2)), input_area.y + 1));
+
+        let status = match error {
+            Some(e) => Line::styled(e.to_string(), error_style),
+            None => Line::styled(HELP, Style::default().add_modifier(Modifier::DIM)),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle_key(key.code, key.modifiers) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+// Runs the calculator until Esc, restoring the terminal afterwards
+pub fn run(options: &CliOptions, ctx: Context) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(options, ctx).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn type_line(app: &mut App, line: &str) {
+        for c in line.chars() {
+            app.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn test_app() {
+        let options = CliOptions::default();
+        let mut app = App::new(&options, Context::new());
+        type_line(&mut app, "a = 2; a * 3");
+        app.handle_key(KeyCode::Enter, KeyModifiers::NONE);
+        type_line(&mut app, "a / 0");
+        app.handle_key(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(app.history[0].output, Ok("2; 6".to_string()));
+        assert!(app.history[1].output.is_err());
+        assert_eq!(app.ctx.variable("a"), Some(2.0));
+
+        // Errors are found while typing
+        type_line(&mut app, "(1 + 2");
+        assert!(matches!(app.live_error(), Some(MathError::UnmatchedParenthesis { .. })));
+        app.handle_key(KeyCode::Char(')'), KeyModifiers::NONE);
+        assert!(app.live_error().is_none());
+
+        // Recalling earlier inputs
+        app.recall(true);
+        assert_eq!(app.input, "a / 0");
+        app.recall(true);
+        app.recall(true);
+        assert_eq!(app.input, "a = 2; a * 3");
+        app.recall(false);
+        app.recall(false);
+        assert_eq!(app.input, "");
+        assert!(!app.handle_key(KeyCode::Esc, KeyModifiers::NONE));
+    }
+
+    #[test]
+    fn test_draw() {
+        let options = CliOptions::default();
+        let mut app = App::new(&options, Context::new());
+        type_line(&mut app, "rate = 0.5");
+        app.submit();
+        type_line(&mut app, "1 + 2 3");
+        let mut terminal = Terminal::new(TestBackend::new(60, 10)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("> rate = 0.5"));
+        assert!(screen.contains("rate = 0.5"));
+        assert!(screen.contains("Unexpected input after the expression: 3"));
+    }
+}
@@ -0,0 +1,474 @@
+// src/units.rs
+// Units of measurement for conversions: `convert(5, "mi", "km")`, or inline as `5 mi in km`.
+// A built-in table covers length, mass, time, data sizes and temperatures; `UnitTable::define` adds units (or
+// replaces built-in ones) for a context, set with `Context::set_units`. With `EvalOptions::units`,
+// unit names also stand for quantities (3 m is three metres), which keep track of their dimensions
+// so that `3 m + 4 s` is an error rather than 7.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Div, Mul};
+
+use crate::{MathError, Operator, Result, UnaryOperator, Value};
+
+// What a unit measures. Units convert only into units of the same dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dimension {
+    Length, // In metres
+    Mass,   // In kilograms
+    Time,   // In seconds
+    Data,   // In bytes
+    // A temperature on a scale, such as 20 degC (in degrees Celsius). Converting one adds or removes the
+    // offset between the scales' zeros: 20 degC is 68 degF.
+    Temperature,
+    // A change of temperature, such as a rise of 20 ΔdegC (in kelvin, the same as ΔdegC). Converting one only
+    // scales it: a rise of 20 ΔdegC is a rise of 36 ΔdegF.
+    TemperatureDifference,
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Dimension::Length => "length",
+            Dimension::Mass => "mass",
+            Dimension::Time => "time",
+            Dimension::Data => "data",
+            Dimension::Temperature => "temperature",
+            Dimension::TemperatureDifference => "temperature difference",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A unit, as its size in the base unit of its dimension (e.g., a kilometre is 1000 metres) and,
+// for temperature scales, where its zero is in the base unit (0 K is -273.15 degC)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Unit {
+    pub dimension: Dimension,
+    pub factor: f64,
+    pub offset: f64,
+}
+
+impl Unit {
+    pub const fn new(dimension: Dimension, factor: f64) -> Self {
+        Self::affine(dimension, factor, 0.0)
+    }
+
+    // A unit whose zero is not the base unit's: x in it is `x * factor + offset` in the base unit
+    pub const fn affine(dimension: Dimension, factor: f64, offset: f64) -> Self {
+        Self {
+            dimension,
+            factor,
+            offset,
+        }
+    }
+}
+
+// The size of a Fahrenheit or Rankine degree in kelvin
+const FAHRENHEIT_DEGREE: f64 = 5.0 / 9.0;
+
+// The built-in units by name
+const BUILTIN: &[(&str, Unit)] = &[
+    ("m", Unit::new(Dimension::Length, 1.0)),
+    ("km", Unit::new(Dimension::Length, 1e3)),
+    ("cm", Unit::new(Dimension::Length, 1e-2)),
+    ("mm", Unit::new(Dimension::Length, 1e-3)),
+    ("um", Unit::new(Dimension::Length, 1e-6)),
+    ("µm", Unit::new(Dimension::Length, 1e-6)),
+    ("nm", Unit::new(Dimension::Length, 1e-9)),
+    ("inch", Unit::new(Dimension::Length, 0.0254)), // Not "in", which reads as the conversion keyword
+    ("ft", Unit::new(Dimension::Length, 0.3048)),
+    ("yd", Unit::new(Dimension::Length, 0.9144)),
+    ("mi", Unit::new(Dimension::Length, 1609.344)),
+    ("nmi", Unit::new(Dimension::Length, 1852.0)),
+    ("kg", Unit::new(Dimension::Mass, 1.0)),
+    ("g", Unit::new(Dimension::Mass, 1e-3)),
+    ("mg", Unit::new(Dimension::Mass, 1e-6)),
+    ("t", Unit::new(Dimension::Mass, 1e3)),
+    ("oz", Unit::new(Dimension::Mass, 0.028349523125)),
+    ("lb", Unit::new(Dimension::Mass, 0.45359237)),
+    ("st", Unit::new(Dimension::Mass, 6.35029318)),
+    ("s", Unit::new(Dimension::Time, 1.0)),
+    ("ms", Unit::new(Dimension::Time, 1e-3)),
+    ("us", Unit::new(Dimension::Time, 1e-6)),
+    ("µs", Unit::new(Dimension::Time, 1e-6)),
+    ("ns", Unit::new(Dimension::Time, 1e-9)),
+    ("min", Unit::new(Dimension::Time, 60.0)),
+    ("h", Unit::new(Dimension::Time, 3_600.0)),
+    ("day", Unit::new(Dimension::Time, 86_400.0)),
+    ("week", Unit::new(Dimension::Time, 604_800.0)),
+    ("year", Unit::new(Dimension::Time, 31_557_600.0)), // A Julian year of 365.25 days
+    ("bit", Unit::new(Dimension::Data, 0.125)),
+    ("B", Unit::new(Dimension::Data, 1.0)),
+    ("kB", Unit::new(Dimension::Data, 1e3)),
+    ("MB", Unit::new(Dimension::Data, 1e6)),
+    ("GB", Unit::new(Dimension::Data, 1e9)),
+    ("TB", Unit::new(Dimension::Data, 1e12)),
+    ("KiB", Unit::new(Dimension::Data, 1_024.0)),
+    ("MiB", Unit::new(Dimension::Data, 1_048_576.0)),
+    ("GiB", Unit::new(Dimension::Data, 1_073_741_824.0)),
+    ("TiB", Unit::new(Dimension::Data, 1_099_511_627_776.0)),
+    ("degC", Unit::new(Dimension::Temperature, 1.0)),
+    ("°C", Unit::new(Dimension::Temperature, 1.0)),
+    ("K", Unit::affine(Dimension::Temperature, 1.0, -273.15)),
+    ("degF", Unit::affine(Dimension::Temperature, FAHRENHEIT_DEGREE, -32.0 * FAHRENHEIT_DEGREE)),
+    ("°F", Unit::affine(Dimension::Temperature, FAHRENHEIT_DEGREE, -32.0 * FAHRENHEIT_DEGREE)),
+    ("degR", Unit::affine(Dimension::Temperature, FAHRENHEIT_DEGREE, -273.15)),
+    ("ΔK", Unit::new(Dimension::TemperatureDifference, 1.0)),
+    ("delta_K", Unit::new(Dimension::TemperatureDifference, 1.0)),
+    ("ΔdegC", Unit::new(Dimension::TemperatureDifference, 1.0)),
+    ("delta_degC", Unit::new(Dimension::TemperatureDifference, 1.0)),
+    ("ΔdegF", Unit::new(Dimension::TemperatureDifference, FAHRENHEIT_DEGREE)),
+    ("delta_degF", Unit::new(Dimension::TemperatureDifference, FAHRENHEIT_DEGREE)),
+];
+
+// The units available to conversions: the built-in ones, and any defined on top of them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitTable {
+    defined: HashMap<String, Unit>,
+}
+
+impl UnitTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds (or replaces) a unit
+    pub fn define(&mut self, name: impl Into<String>, unit: Unit) {
+        self.defined.insert(name.into(), unit);
+    }
+
+    // Adds (or replaces) a unit as an amount of a known one (e.g., a furlong is 220 yd). A unit
+    // defined from a temperature scale keeps its zero.
+    pub fn define_as(&mut self, name: impl Into<String>, amount: f64, of: &str) -> Result<()> {
+        let unit = self.unit(of)?;
+        self.define(name, Unit::affine(unit.dimension, amount * unit.factor, unit.offset));
+        Ok(())
+    }
+
+    // Looks up a unit by name, defined units first
+    pub fn get(&self, name: &str) -> Option<Unit> {
+        self.defined.get(name).copied().or_else(|| {
+            BUILTIN
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|&(_, unit)| unit)
+        })
+    }
+
+    // A value in the unit `from` as a number of the unit `to`. Temperatures on different scales
+    // convert through degrees Celsius, so 100 degC is 212 degF.
+    pub fn convert(&self, value: f64, from: &str, to: &str) -> Result<f64> {
+        let (source, target) = (self.unit(from)?, self.unit(to)?);
+        if source.dimension != target.dimension {
+            return Err(MathError::InvalidArgument {
+                function: "convert".to_string(),
+                reason: format!(
+                    "cannot convert {} ({}) to {} ({})",
+                    from, source.dimension, to, target.dimension
+                ),
+            });
+        }
+        Ok((value * source.factor + source.offset - target.offset) / target.factor)
+    }
+
+    fn unit(&self, name: &str) -> Result<Unit> {
+        self.get(name).ok_or_else(|| MathError::UnknownUnit(name.to_string()))
+    }
+
+    // One of a unit as a quantity, for a unit name used as a variable. Temperatures on a scale
+    // have none.
+    pub(crate) fn quantity(&self, name: &str) -> Option<Value> {
+        let unit = self.get(name)?;
+        let dimensions = Dimensions::of(unit.dimension)?;
+        Some(Quantity::new(unit.factor, dimensions).into_value())
+    }
+}
+
+// The dimensions quantities are built from, with the symbol of each one's base unit
+const BASE: [(Dimension, &str); 5] = [
+    (Dimension::Length, "m"),
+    (Dimension::Mass, "kg"),
+    (Dimension::Time, "s"),
+    (Dimension::Data, "B"),
+    (Dimension::TemperatureDifference, "K"),
+];
+
+// Powers of the base dimensions, such as length / time for a speed. Temperatures on a scale are
+// not among them: they neither add up nor scale, so only their differences form quantities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dimensions([i8; 5]);
+
+impl Dimensions {
+    // Those of a plain number
+    pub const NONE: Dimensions = Dimensions([0; 5]);
+
+    // A single dimension, or None for temperatures on a scale
+    pub fn of(dimension: Dimension) -> Option<Dimensions> {
+        let position = BASE.iter().position(|(base, _)| *base == dimension)?;
+        let mut powers = [0; 5];
+        powers[position] = 1;
+        Some(Dimensions(powers))
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        *self == Self::NONE
+    }
+
+    // The dimensions of a quantity raised to a power, if every power stays whole (the square root
+    // of an area is a length, that of a length is nothing)
+    pub fn powf(self, power: f64) -> Option<Dimensions> {
+        let mut powers = [0; 5];
+        for (scaled, exponent) in powers.iter_mut().zip(self.0) {
+            let product = exponent as f64 * power;
+            if product.fract() != 0.0 || product.abs() > i8::MAX as f64 {
+                return None;
+            }
+            *scaled = product as i8;
+        }
+        Some(Dimensions(powers))
+    }
+
+    // Names each base dimension with a power, as in m*kg/s^2
+    fn describe(&self, name: impl Fn(usize) -> String) -> String {
+        let term = |position: usize, power: i8| match power.abs() {
+            1 => name(position),
+            power => format!("{}^{}", name(position), power),
+        };
+        let side = |positive: bool| {
+            let terms: Vec<String> = (0..BASE.len())
+                .filter(|&position| self.0[position] != 0 && (self.0[position] > 0) == positive)
+                .map(|position| term(position, self.0[position]))
+                .collect();
+            (terms.len(), terms.join("*"))
+        };
+        match (side(true), side(false)) {
+            ((_, numerator), (0, _)) => numerator,
+            ((0, _), (1, denominator)) => format!("1/{}", denominator),
+            ((0, _), (_, denominator)) => format!("1/({})", denominator),
+            ((_, numerator), (1, denominator)) => format!("{}/{}", numerator, denominator),
+            ((_, numerator), (_, denominator)) => format!("{}/({})", numerator, denominator),
+        }
+    }
+}
+
+impl Mul for Dimensions {
+    type Output = Dimensions;
+
+    fn mul(self, other: Dimensions) -> Dimensions {
+        Dimensions(std::array::from_fn(|i| self.0[i].saturating_add(other.0[i])))
+    }
+}
+
+impl Div for Dimensions {
+    type Output = Dimensions;
+
+    fn div(self, other: Dimensions) -> Dimensions {
+        Dimensions(std::array::from_fn(|i| self.0[i].saturating_sub(other.0[i])))
+    }
+}
+
+// By name: length/time^2, or dimensionless
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_dimensionless() {
+            return write!(f, "dimensionless");
+        }
+        write!(f, "{}", self.describe(|position| BASE[position].0.to_string()))
+    }
+}
+
+// An amount with dimensions, in the base units of its dimensions (e.g., 1 km is 1000 m)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quantity {
+    pub value: f64,
+    pub dimensions: Dimensions,
+}
+
+impl Quantity {
+    pub fn new(value: f64, dimensions: Dimensions) -> Self {
+        Self { value, dimensions }
+    }
+
+    // The base units it is in, as in m/s^2
+    pub fn units(&self) -> String {
+        self.dimensions.describe(|position| BASE[position].1.to_string())
+    }
+
+    // As a value: a plain number once the dimensions cancel out (1 km / 1 m is 1000)
+    pub(crate) fn into_value(self) -> Value {
+        if self.dimensions.is_dimensionless() {
+            Value::Number(self.value)
+        } else {
+            Value::Quantity(self)
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.units())
+    }
+}
+
+// The error for dimensions an operation cannot combine
+fn mismatch(operation: &str, left: Dimensions, right: Dimensions) -> MathError {
+    MathError::DimensionMismatch {
+        operation: operation.to_string(),
+        left: left.to_string(),
+        right: right.to_string(),
+    }
+}
+
+// The error for a quantity passed to a function of plain numbers
+pub(crate) fn not_dimensionless(function: &str, quantity: &Quantity) -> MathError {
+    mismatch(function, quantity.dimensions, Dimensions::NONE)
+}
+
+// Applies a binary operator where at least one operand is a quantity: `number` works out the
+// value in base units, and the dimensions follow from the operator. Sums, remainders and
+// comparisons need equal dimensions; exponents must be plain numbers.
+pub(crate) fn apply<F>(op: &Operator, left: &Value, right: &Value, number: F) -> Result<Value>
+where
+    F: Fn(&Operator, f64, f64) -> Result<f64>,
+{
+    let (a, b) = (quantity(left)?, quantity(right)?);
+    let same = || match a.dimensions == b.dimensions {
+        true => Ok(a.dimensions),
+        false => Err(mismatch(op.symbol(), a.dimensions, b.dimensions)),
+    };
+    let dimensions = match op {
+        Operator::Multiply => a.dimensions * b.dimensions,
+        Operator::Divide => a.dimensions / b.dimensions,
+        Operator::Power if b.dimensions.is_dimensionless() => a
+            .dimensions
+            .powf(b.value)
+            .ok_or_else(|| mismatch(op.symbol(), a.dimensions, b.dimensions))?,
+        Operator::Add | Operator::Subtract | Operator::Modulo => same()?,
+        op if op.is_comparison() => same().map(|_| Dimensions::NONE)?,
+        _ => return Err(mismatch(op.symbol(), a.dimensions, b.dimensions)),
+    };
+    Ok(Quantity::new(number(op, a.value, b.value)?, dimensions).into_value())
+}
+
+// Applies a prefix or postfix operator to a quantity: a percentage or absolute value keeps its
+// dimensions and a square root halves them; the rest need plain numbers
+pub(crate) fn unary<F>(op: &UnaryOperator, quantity: &Quantity, number: F) -> Result<Value>
+where
+    F: Fn(f64) -> Result<f64>,
+{
+    let dimensions = match op {
+        UnaryOperator::Percent | UnaryOperator::Abs => Some(quantity.dimensions),
+        UnaryOperator::Sqrt => quantity.dimensions.powf(0.5),
+        _ => None,
+    };
+    let dimensions = dimensions.ok_or_else(|| not_dimensionless(op.symbol(), quantity))?;
+    Ok(Quantity::new(number(quantity.value)?, dimensions).into_value())
+}
+
+// An operand as a quantity, counting numbers as dimensionless ones
+fn quantity(value: &Value) -> Result<Quantity> {
+    match value {
+        Value::Number(n) => Ok(Quantity::new(*n, Dimensions::NONE)),
+        Value::Quantity(quantity) => Ok(*quantity),
+        _ => Err(MathError::TypeMismatch {
+            expected: "number".to_string(),
+            found: value.type_name().to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Evaluator, Expr, ImplicitMultiplication, ImplicitPrecedence, ParserBuilder};
+
+    #[test]
+    fn test_conversions() {
+        let mut ctx = Context::new();
+        let mut units = UnitTable::new();
+        units.define_as("furlong", 220.0, "yd").unwrap();
+        ctx.set_units(units);
+        ctx.set_variable("x", 3.0);
+        let value = |input: &str| Evaluator::evaluate_value_with(&Expr::parse(input).unwrap(), &ctx);
+        assert_eq!(value("convert(5, \"km\", \"m\")").unwrap(), 5_000.0);
+        assert_eq!(value("5 mi in km").unwrap(), 8.04672);
+        assert_eq!(value("2 + 12 inch in cm").unwrap(), 32.48);
+        assert_eq!(value("(x + 1) GiB in MiB").unwrap(), 4_096.0);
+        assert_eq!(value("[1, 2] furlong in m").unwrap().to_string(), "[201.168, 402.336]");
+        assert_eq!(value("90 min in h").unwrap(), 1.5);
+        assert_eq!(
+            value("5 kg in m").unwrap_err().to_string(),
+            "Invalid argument to convert: cannot convert kg (mass) to m (length)"
+        );
+        assert!(matches!(value("5 parsec in m"), Err(MathError::UnknownUnit(ref unit)) if unit == "parsec"));
+        assert_eq!(Expr::parse("5 mi in km").unwrap().to_string(), "convert(5, \"mi\", \"km\")");
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let mut ctx = Context::new();
+        ctx.set_variable("m", 2.0); // Variables take priority over units
+        let evaluator = Evaluator::builder().units(true).build();
+        // 3 km reads as 3 * km, and 100 km / 2 h divides by 2 h
+        let grammar = ParserBuilder::new()
+            .implicit_multiplication(ImplicitMultiplication {
+                number_variable: true,
+                precedence: ImplicitPrecedence::AboveExplicit,
+                ..ImplicitMultiplication::default()
+            })
+            .build();
+        let value = |input: &str| evaluator.eval_value(&grammar.parse(input).unwrap(), &ctx);
+        assert_eq!(value("3 km + 500 mm").unwrap().to_string(), "3000.5 m");
+        assert_eq!(value("100 km / 2 h").unwrap().to_string(), "13.88888888888889 m/s");
+        assert_eq!(value("(3 km)^2 / 1 kg / 2 s").unwrap().to_string(), "4500000 m^2/(kg*s)");
+        assert_eq!(value("√(9 km * 4 km)").unwrap().to_string(), "6000 m");
+        assert_eq!(value("1 KiB / 1 B").unwrap(), 1_024.0);
+        assert_eq!(value("1 mi > 1 km").unwrap(), 1.0);
+        assert_eq!(value("3 m").unwrap(), 6.0);
+        assert_eq!(
+            value("3 km + 4 s").unwrap_err().to_string(),
+            "Dimension mismatch in +: length and time"
+        );
+        assert_eq!(
+            value("floor(5 kg)").unwrap_err().to_string(),
+            "Dimension mismatch in floor: mass and dimensionless"
+        );
+        assert_eq!(
+            value("sin(5 kg)").unwrap_err().to_string(),
+            "Dimension mismatch in sin: mass and dimensionless"
+        );
+        // Angles and ratios of equal dimensions are plain numbers
+        assert_eq!(value("sin(0 km / 1 mm)").unwrap(), 0.0);
+        assert!(matches!(value("exp(2 s)"), Err(MathError::DimensionMismatch { .. })));
+        assert!(matches!(value("√(2 km)"), Err(MathError::DimensionMismatch { .. })));
+        assert!(matches!(value("2 ^ (1 s)"), Err(MathError::DimensionMismatch { .. })));
+        // Without the option, unit names are only names
+        let plain = Evaluator::evaluate_with(&grammar.parse("3 kg").unwrap(), &ctx);
+        assert!(matches!(plain, Err(MathError::UnknownVariable(_))));
+    }
+
+    #[test]
+    fn test_temperatures() {
+        let units = UnitTable::new();
+        let close = |value: f64, from: &str, to: &str, expected: f64| {
+            let converted = units.convert(value, from, to).unwrap();
+            let message = format!("{} {} is {} {}, not {}", value, from, converted, to, expected);
+            assert!((converted - expected).abs() < 1e-9, "{}", message);
+        };
+        close(100.0, "degC", "degF", 212.0);
+        close(-40.0, "degF", "°C", -40.0);
+        close(98.6, "degF", "degC", 37.0);
+        close(0.0, "degC", "K", 273.15);
+        close(0.0, "K", "degR", 0.0);
+        close(491.67, "degR", "degC", 0.0);
+        // A difference of 10 degrees C is 18 degrees F, though 10 degC is 50 degF
+        close(10.0, "ΔdegC", "ΔdegF", 18.0);
+        close(10.0, "degC", "degF", 50.0);
+        close(5.0, "ΔK", "delta_degC", 5.0);
+        assert!(matches!(units.convert(1.0, "degC", "ΔdegF"), Err(MathError::InvalidArgument { .. })));
+    }
+}
@@ -0,0 +1,176 @@
+// src/value.rs
+use std::fmt;
+
+use crate::duration::format_duration;
+use crate::format::pad;
+use crate::{Date, MathError, Money, Quantity, Result};
+
+// The result of evaluating an expression: a number, a date, a duration, an amount of money, a
+// quantity with units or a (possibly nested) list of values
+#[derive(Debug, Clone, PartialEq)]
+// Serialized as a plain number, a "YYYY-MM-DD" string, a duration literal string such as "1h30m",
+// an amount string such as "150.00 USD", a quantity object or a (nested) array
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(untagged))]
+pub enum Value {
+    Number(f64),
+    List(Vec<Value>),
+    Date(Date),
+    // In seconds
+    Duration(#[cfg_attr(feature = "serde", serde(with = "crate::duration::literal"))] f64),
+    Money(Money),
+    Quantity(Quantity),
+}
+
+impl Value {
+    // Name of the value's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::List(_) => "list",
+            Value::Date(_) => "date",
+            Value::Duration(_) => "duration",
+            Value::Money(_) => "money",
+            Value::Quantity(_) => "quantity",
+        }
+    }
+
+    // The number, if this is one
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // The elements, if this is a list
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    // The number, or a type mismatch error for any other value
+    pub fn to_number(&self) -> Result<f64> {
+        self.as_number().ok_or_else(|| self.mismatch("number"))
+    }
+
+    // The elements, or a type mismatch error for any other value
+    pub fn to_list(&self) -> Result<&[Value]> {
+        self.as_list().ok_or_else(|| self.mismatch("list"))
+    }
+
+    fn mismatch(&self, expected: &str) -> MathError {
+        MathError::TypeMismatch {
+            expected: expected.to_string(),
+            found: self.type_name().to_string(),
+        }
+    }
+}
+
+// Resolves a list index (negative counts from the end) to a position within `len` items
+pub(crate) fn position(index: f64, len: usize) -> Result<usize> {
+    if index.fract() != 0.0 || !index.is_finite() {
+        return Err(MathError::NotAnInteger(index));
+    }
+    let index = index as i64;
+    let position = if index < 0 { index + len as i64 } else { index };
+    if position < 0 || position >= len as i64 {
+        return Err(MathError::IndexOutOfRange { index, len });
+    }
+    Ok(position as usize)
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<Vec<f64>> for Value {
+    fn from(items: Vec<f64>) -> Self {
+        Value::List(items.into_iter().map(Value::Number).collect())
+    }
+}
+
+impl From<Date> for Value {
+    fn from(date: Date) -> Self {
+        Value::Date(date)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Self {
+        Value::List(items)
+    }
+}
+
+// Lets results be compared against plain numbers, e.g. `assert_eq!(value, 2.0)`
+impl PartialEq<f64> for Value {
+    fn eq(&self, other: &f64) -> bool {
+        self.as_number() == Some(*other)
+    }
+}
+
+// A precision (`{:.2}`) applies to every number and a width pads the whole value
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.width().is_some() {
+            let mut rendered = String::new();
+            self.write_rounded(&mut rendered, f.precision())?;
+            pad(f, &rendered)
+        } else {
+            self.write_rounded(f, f.precision())
+        }
+    }
+}
+
+impl Value {
+    fn write_rounded(&self, out: &mut impl fmt::Write, precision: Option<usize>) -> fmt::Result {
+        match self {
+            Value::Number(n) => match precision {
+                Some(precision) => write!(out, "{:.*}", precision, n),
+                None => write!(out, "{}", n),
+            },
+            Value::List(items) => {
+                write!(out, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ", ")?;
+                    }
+                    item.write_rounded(out, precision)?;
+                }
+                write!(out, "]")
+            }
+            Value::Date(date) => write!(out, "{}", date),
+            Value::Duration(seconds) => write!(out, "{}", format_duration(*seconds)),
+            Value::Money(money) => write!(out, "{}", money),
+            Value::Quantity(quantity) => {
+                Value::Number(quantity.value).write_rounded(out, precision)?;
+                write!(out, " {}", quantity.units())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions_and_display() {
+        let list = Value::from(vec![1.0, 2.5]);
+        assert_eq!(list.to_string(), "[1, 2.5]");
+        assert_eq!(Value::List(vec![list.clone(), Value::List(vec![])]).to_string(), "[[1, 2.5], []]");
+        assert_eq!(list.as_list().unwrap().len(), 2);
+        assert_eq!(Value::from(4.0).to_number().unwrap(), 4.0);
+        assert_eq!(Value::from(4.0), 4.0);
+        assert_ne!(list, 1.0);
+        assert_eq!(format!("{:.2}", list), "[1.00, 2.50]");
+        assert_eq!(format!("{:>12.1}|{:<4}|", list, Value::from(3.0)), "  [1.0, 2.5]|3   |");
+        assert!(matches!(
+            list.to_number(),
+            Err(MathError::TypeMismatch { ref expected, ref found }) if expected == "number" && found == "list"
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+// src/watch.rs
+// Watch mode (`mathexpr --watch formulas.txt`): evaluates a file of statements, then again each
+// time it changes, so a formula can be tweaked in an editor while its results stay in view
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use mathexpr::{Context, Statement};
+
+use crate::{render_result, CliOptions};
+
+// How often the file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+// Evaluates the file on every change until interrupted. Fails if it cannot be read at first;
+// later, while it is missing (as when an editor replaces it), the last results stay shown.
+pub fn run(path: &str, options: &CliOptions, ctx: &Context) -> Result<(), String> {
+    let mut shown = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    print_results(path, &shown, options, ctx);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        match fs::read_to_string(path) {
+            Ok(text) if text != shown => {
+                print_results(path, &text, options, ctx);
+                shown = text;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn print_results(path: &str, text: &str, options: &CliOptions, ctx: &Context) {
+    println!("--- {} ---", path);
+    for line in evaluate_file(text, options, ctx) {
+        println!("{}", line);
+    }
+}
+
+// The result of every statement in the file, one per line. Each line is evaluated after the ones
+// before it, so later lines see earlier assignments; an error only affects its own line.
+fn evaluate_file(text: &str, options: &CliOptions, ctx: &Context) -> Vec<String> {
+    let mut ctx = ctx.clone();
+    let (grammar, evaluator) = (options.config.grammar(), options.config.evaluator());
+    let mut results = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let statements = match grammar.parse_statements(line) {
+            Ok(statements) => statements,
+            Err(e) => {
+                results.push(format!("line {}: Error: {}", number + 1, e));
+                continue;
+            }
+        };
+        for statement in &statements {
+            let result = evaluator.run(statement, &mut ctx).map_err(|e| e.to_string());
+            match result.and_then(|result| render_result(statement, &result, options)) {
+                Ok(text) => match statement {
+                    Statement::Expr(expr) => results.push(format!("{} = {}", expr, text)),
+                    Statement::Assign { name, .. } => results.push(format!("{} = {}", name, text)),
+                },
+                Err(e) => {
+                    results.push(format!("line {}: Error: {}", number + 1, e));
+                    break;
+                }
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_file() {
+        let text = "rate = 0.5\n\n# Comment lines are skipped\nprice = 10; price * (1+rate)\n1 +\nmissing * 2\nprice / 4\n";
+        assert_eq!(
+            evaluate_file(text, &CliOptions::default(), &Context::new()),
+            [
+                "rate = 0.5",
+                "price = 10",
+                "price * (1 + rate) = 15",
+                "line 5: Error: Invalid operator: Unexpected end of input",
+                "line 6: Error: Unknown variable: missing",
+                "price / 4 = 2.5",
+            ]
+        );
+    }
+}
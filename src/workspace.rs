@@ -0,0 +1,122 @@
+// src/workspace.rs
+// A user's workspace as plain data: the variables, the functions defined by expressions and the
+// settings, to save with serde (in JSON, TOML or any other format) and load into a context later.
+//
+//     let saved = serde_json::to_string(&Workspace::capture(&ctx, &config))?;
+//     let (ctx, config) = serde_json::from_str::<Workspace>(&saved)?.into_context();
+//
+// Functions registered as closures cannot be saved and are left out; register them again after
+// loading. The resolver, observer, metrics, operators and random state are left out too.
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{Context, EvaluatorConfig, Expr, FunctionDefinition, Value};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct Workspace {
+    pub variables: BTreeMap<String, Value>,
+    pub functions: Vec<FunctionDefinition>, // Sorted by name
+    pub config: EvaluatorConfig,
+}
+
+impl Workspace {
+    // What the context binds in any scope, innermost first, with the settings
+    pub fn capture(ctx: &Context, config: &EvaluatorConfig) -> Self {
+        let variables = ctx
+            .variable_names()
+            .into_iter()
+            .filter_map(|name| Some((name.to_string(), ctx.value(name)?)))
+            .collect();
+        let functions = ctx
+            .function_names()
+            .into_iter()
+            .filter_map(|name| ctx.function_definition(name).cloned())
+            .collect();
+        Self {
+            variables,
+            functions,
+            config: config.clone(),
+        }
+    }
+
+    // Sets the variables in the context, then defines the functions, each after those it calls.
+    // Functions therefore see the saved variables, even ones changed after they were first defined.
+    pub fn load_into(&self, ctx: &mut Context) {
+        for (name, value) in &self.variables {
+            ctx.set_variable(name.as_str(), value.clone());
+        }
+        let mut pending: Vec<&FunctionDefinition> = self.functions.iter().collect();
+        while !pending.is_empty() {
+            let waiting: HashSet<&str> = pending.iter().map(|definition| definition.name.as_str()).collect();
+            let ready = |definition: &&FunctionDefinition| {
+                let mut calls = Vec::new();
+                called(&definition.body, &mut calls);
+                calls.iter().all(|name| *name == definition.name || !waiting.contains(name))
+            };
+            // Definitions calling each other cannot be ordered; define them as they come
+            let (now, later): (Vec<_>, Vec<_>) = pending.into_iter().partition(ready);
+            let (now, later) = if now.is_empty() { (later, Vec::new()) } else { (now, later) };
+            for definition in now {
+                ctx.define_function(definition.clone());
+            }
+            pending = later;
+        }
+    }
+
+    // A new context holding the workspace, and its settings
+    pub fn into_context(self) -> (Context, EvaluatorConfig) {
+        let mut ctx = Context::new();
+        self.load_into(&mut ctx);
+        (ctx, self.config)
+    }
+}
+
+// The names of the functions an expression calls
+fn called<'a>(expr: &'a Expr, calls: &mut Vec<&'a str>) {
+    if let Expr::Function { name, .. } = expr {
+        calls.push(name);
+    }
+    for child in expr.children() {
+        called(child, calls);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_source_into, Evaluator, FormatOptions};
+
+    #[test]
+    fn test_capture_and_load() {
+        let mut ctx = Context::new();
+        load_source_into("rate = 0.05\nnet(x) = x * (1 - rate)\ntotal(a, b) = net(a) + net(b)\nm = [1, 2]", ".", &mut ctx).unwrap();
+        ctx.register_function("native", |_: &[f64]| Ok(1.0));
+        let config = EvaluatorConfig {
+            format: FormatOptions::decimals(2),
+            ..EvaluatorConfig::default()
+        };
+        let workspace = Workspace::capture(&ctx, &config);
+        assert_eq!(workspace.functions.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["net", "total"]);
+        assert_eq!(workspace.variables.len(), 2);
+
+        let (loaded, loaded_config) = workspace.into_context();
+        assert_eq!(loaded_config, config);
+        let expr = Expr::parse("total(100, 200)").unwrap();
+        assert_eq!(Evaluator::evaluate_with(&expr, &loaded).unwrap(), 285.0);
+        assert!(loaded.function("native").is_none());
+        assert_eq!(loaded.value("m"), ctx.value("m"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut ctx = Context::new();
+        load_source_into("k = 3\nsq(x) = x^2 * k", ".", &mut ctx).unwrap();
+        let workspace = Workspace::capture(&ctx, &EvaluatorConfig::default());
+        let json = serde_json::to_string(&workspace).unwrap();
+        let loaded: Workspace = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, workspace);
+        let (ctx, _) = loaded.into_context();
+        assert_eq!(Evaluator::evaluate_with(&Expr::parse("sq(2)").unwrap(), &ctx).unwrap(), 12.0);
+    }
+}